@@ -1,8 +1,66 @@
 #version 450
 
-layout(location = 0) in vec4 col;
+layout(location = 0) in vec2 uv;
 layout(location = 0) out vec4 target;
 
+layout(set = 0, binding = 0) uniform ViewUniform {
+    mat4 view;
+    mat4 projection;
+    mat4 viewProjection;
+    vec4 cameraPosition;
+    uint outputEncoding;
+    float sdrWhiteNits;
+    vec2 _padding;
+};
+
+layout(set = 2, binding = 0) uniform sampler2D baseColor;
+layout(set = 2, binding = 1) uniform MaterialParameters {
+    // TintParameters
+    vec4 tint;
+};
+
+// OutputEncoding
+const uint OUTPUT_LINEAR = 0u;
+const uint OUTPUT_SRGB = 1u;
+const uint OUTPUT_HDR10 = 2u;
+
+vec3 encodeSrgb(vec3 linear) {
+    return mix(
+        linear * 12.92,
+        1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055,
+        greaterThan(linear, vec3(0.0031308))
+    );
+}
+
+vec3 encodePq(vec3 linear) {
+    const float m1 = 2610.0 / 16384.0;
+    const float m2 = 2523.0 / 4096.0 * 128.0;
+    const float c1 = 3424.0 / 4096.0;
+    const float c2 = 2413.0 / 4096.0 * 32.0;
+    const float c3 = 2392.0 / 4096.0 * 32.0;
+
+    vec3 y = pow(max(linear * sdrWhiteNits / 10000.0, 0.0), vec3(m1));
+    return pow((c1 + c2 * y) / (1.0 + c3 * y), vec3(m2));
+}
+
+// linear sRGB -> back buffer(same as OutputEncoding::encode)
+vec4 encodeOutput(vec4 linear) {
+    if (outputEncoding == OUTPUT_SRGB) {
+        return vec4(encodeSrgb(linear.rgb), linear.a);
+    }
+    if (outputEncoding == OUTPUT_HDR10) {
+        // BT.709 -> BT.2020(columns)
+        const mat3 toBt2020 = mat3(
+            0.6274, 0.0691, 0.0164,
+            0.3293, 0.9195, 0.0880,
+            0.0433, 0.0114, 0.8956
+        );
+        return vec4(encodePq(toBt2020 * linear.rgb), linear.a);
+    }
+
+    return linear;
+}
+
 void main() {
-    target = col;
+    target = encodeOutput(texture(baseColor, uv) * tint);
 }
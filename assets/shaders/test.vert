@@ -1,19 +1,27 @@
 #version 450
 
-layout(location = 0) in vec4 pos;
-layout(location = 1) in vec4 col;
-layout(location = 0) out vec4 ocol;
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in vec2 uv;
+layout(location = 3) in vec4 tangent;
+layout(location = 0) out vec2 ouv;
 out gl_PerVertex { out vec4 gl_Position; };
 
-layout(push_constant) uniform ScreenProperties {
-    vec2 screenSize;
+layout(set = 0, binding = 0) uniform ViewUniform {
+    mat4 view;
+    mat4 projection;
+    mat4 viewProjection;
+    vec4 cameraPosition;
+    uint outputEncoding;
+    float sdrWhiteNits;
+    vec2 _padding;
 };
 
-layout(set = 0, binding = 0) uniform ObjectTransform {
+layout(set = 1, binding = 0) uniform ObjectTransform {
     mat4 objectTransform;
 };
 
 void main() {
-    gl_Position = objectTransform * pos * vec4(1.0f / screenSize.x, -1.0f / screenSize.y, 1.0f, 1.0f);
-    ocol = col;
+    gl_Position = viewProjection * objectTransform * vec4(position, 1.0);
+    ouv = uv;
 }
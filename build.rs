@@ -1,5 +1,47 @@
+#[allow(dead_code)]
+#[path = "src/shader/compiler.rs"]
+mod shader_compiler;
+
+/// Release builds embed shaders precompiled here(listed in `$OUT_DIR/shaders.rs`).
+/// Debug builds compile them at runtime(hot reload), so the list is left empty.
+fn compile_shaders() {
+    let sources = shader_compiler::sources(std::path::Path::new("assets/shaders"))
+        .expect("Failed to enumerate shader sources");
+    // Note: 実行時コンパイル(デバッグビルド)の出力先なのでディレクトリ単位では監視しない
+    for s in &sources {
+        println!("cargo:rerun-if-changed={}", s.display());
+    }
+
+    let out_dir = std::path::PathBuf::from(std::env::var_os("OUT_DIR").expect("no OUT_DIR"));
+    let mut list = String::from("&[\n");
+    if std::env::var("PROFILE").as_deref() == Ok("release") {
+        let output_dir = out_dir.join("shaders");
+        std::fs::create_dir_all(&output_dir).expect("Failed to create shader output directory");
+
+        for s in sources {
+            let spirv = shader_compiler::spirv_path(&s).expect("not a shader source");
+            let output = output_dir.join(spirv.file_name().expect("no file name"));
+            if let Err(e) = shader_compiler::compile(&s, &output, true) {
+                panic!("Failed to compile {}: {e}", s.display());
+            }
+
+            let name = s
+                .file_name()
+                .and_then(|n| n.to_str())
+                .expect("invalid source name");
+            let output = output.to_str().expect("invalid output path");
+            list.push_str(&format!("    ({name:?}, include_bytes!({output:?})),\n"));
+        }
+    }
+    list.push(']');
+    std::fs::write(out_dir.join("shaders.rs"), list)
+        .expect("Failed to write the precompiled shader list");
+}
+
 #[cfg(target_os = "macos")]
 fn main() {
+    compile_shaders();
+
     let vk_sdk_base = std::path::PathBuf::from(env!("VULKAN_SDK"))
         .parent()
         .expect("Failed to calc parent for VULKAN_SDK")
@@ -20,10 +62,14 @@ fn main() {
 }
 
 #[cfg(target_os = "linux")]
-fn main() {}
+fn main() {
+    compile_shaders();
+}
 
 #[cfg(target_os = "windows")]
 fn main() {
+    compile_shaders();
+
     let vk_sdk_base = std::path::PathBuf::from(env!("VULKAN_SDK"));
 
     println!(
@@ -0,0 +1,974 @@
+//! glTF 2.0 loader(`.gltf` with external/embedded buffers and `.glb`).
+//!
+//! Meshes are converted to the engine vertex layout([`MeshVertex`]) with missing normals and
+//! tangents generated. Each primitive becomes an engine mesh since a scene node refers one mesh and
+//! one material.
+
+use std::path::{Path, PathBuf};
+
+use bedrock as br;
+
+use crate::{
+    error::EngineError,
+    math::{Quat, Vec3},
+    render::{
+        memory::MemoryAllocator,
+        mesh::{MeshData, MeshVertex},
+        scene_renderer::SceneRenderer,
+        upload::UploadQueue,
+    },
+    scene::{MaterialId, MeshId, MeshInstance, NodeId, Scene, Transform},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlphaMode {
+    Opaque,
+    /// Alpha tested with [`GltfMaterial::alpha_cutoff`]
+    Mask,
+    Blend,
+}
+
+/// Reference to an image with the texture coordinate set used for sampling it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TextureRef {
+    /// Index of [`GltfAsset::images`]
+    pub image: usize,
+    pub tex_coord: u32,
+}
+
+/// Metallic-roughness PBR parameters of a material.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GltfMaterial {
+    pub name: Option<String>,
+    pub base_color_factor: [f32; 4],
+    pub base_color_texture: Option<TextureRef>,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    /// Metalness in B, roughness in G
+    pub metallic_roughness_texture: Option<TextureRef>,
+    pub normal_texture: Option<TextureRef>,
+    pub normal_scale: f32,
+    pub occlusion_texture: Option<TextureRef>,
+    pub occlusion_strength: f32,
+    pub emissive_factor: [f32; 3],
+    pub emissive_texture: Option<TextureRef>,
+    pub alpha_mode: AlphaMode,
+    pub alpha_cutoff: f32,
+    pub double_sided: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GltfImage {
+    /// Path to the image file
+    File(PathBuf),
+    /// Image file contents stored in a buffer or a data URI
+    Embedded {
+        mime_type: Option<String>,
+        data: Vec<u8>,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct GltfPrimitive {
+    pub data: MeshData,
+    /// Index of [`GltfAsset::materials`], or None for the default material
+    pub material: Option<usize>,
+}
+
+#[derive(Clone, Debug)]
+pub struct GltfMesh {
+    pub name: Option<String>,
+    pub primitives: Vec<GltfPrimitive>,
+}
+
+#[derive(Clone, Debug)]
+pub struct GltfNode {
+    pub name: Option<String>,
+    pub transform: Transform,
+    /// Index of [`GltfAsset::meshes`]
+    pub mesh: Option<usize>,
+    pub children: Vec<usize>,
+}
+
+pub struct GltfAsset {
+    pub meshes: Vec<GltfMesh>,
+    pub materials: Vec<GltfMaterial>,
+    pub images: Vec<GltfImage>,
+    pub nodes: Vec<GltfNode>,
+    /// Root nodes of the default scene(or the first scene if not specified)
+    pub roots: Vec<usize>,
+}
+impl GltfAsset {
+    /// Loads a `.gltf` or `.glb` file at a path relative to the asset directory.
+    pub fn load(relative_path: impl AsRef<Path>) -> Result<Self, EngineError> {
+        let path = super::path(relative_path);
+        let bytes = std::fs::read(&path).map_err(|e| EngineError::io(&path, e))?;
+
+        Self::from_slice(&path, &bytes)
+    }
+
+    /// `path` is used for resolving external files and error reports.
+    pub fn from_slice(path: &Path, bytes: &[u8]) -> Result<Self, EngineError> {
+        let ::gltf::Gltf { document, mut blob } =
+            ::gltf::Gltf::from_slice(bytes).map_err(|e| EngineError::invalid_asset(path, e))?;
+        let base_dir = path.parent().unwrap_or(Path::new(""));
+
+        let buffers = document
+            .buffers()
+            .map(|b| {
+                let mut data = match b.source() {
+                    ::gltf::buffer::Source::Bin => blob
+                        .take()
+                        .ok_or_else(|| EngineError::invalid_asset(path, "no binary chunk"))?,
+                    ::gltf::buffer::Source::Uri(uri) => read_uri(path, base_dir, uri)?,
+                };
+                if data.len() < b.length() {
+                    return Err(EngineError::invalid_asset(
+                        path,
+                        format!("buffer {} is shorter than declared", b.index()),
+                    ));
+                }
+                // GLB binary chunks may be padded
+                data.truncate(b.length());
+
+                Ok(data)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let images = document
+            .images()
+            .map(|i| match i.source() {
+                ::gltf::image::Source::View { view, mime_type } => {
+                    let data = buffers[view.buffer().index()]
+                        .get(view.offset()..view.offset() + view.length())
+                        .ok_or_else(|| {
+                            EngineError::invalid_asset(path, "image view out of the buffer")
+                        })?;
+
+                    Ok(GltfImage::Embedded {
+                        mime_type: Some(mime_type.to_owned()),
+                        data: data.to_vec(),
+                    })
+                }
+                ::gltf::image::Source::Uri { uri, mime_type } => {
+                    if uri.starts_with("data:") {
+                        Ok(GltfImage::Embedded {
+                            mime_type: mime_type.map(ToOwned::to_owned),
+                            data: read_uri(path, base_dir, uri)?,
+                        })
+                    } else {
+                        Ok(GltfImage::File(base_dir.join(percent_decode(uri))))
+                    }
+                }
+            })
+            .collect::<Result<Vec<_>, EngineError>>()?;
+
+        let meshes = document
+            .meshes()
+            .map(|m| {
+                let primitives = m
+                    .primitives()
+                    .filter_map(|p| load_primitive(path, &buffers, &m, &p).transpose())
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                Ok(GltfMesh {
+                    name: m.name().map(ToOwned::to_owned),
+                    primitives,
+                })
+            })
+            .collect::<Result<Vec<_>, EngineError>>()?;
+
+        let materials = document.materials().map(load_material).collect();
+
+        let nodes = document
+            .nodes()
+            .map(|n| {
+                let (translation, [x, y, z, w], scale) = n.transform().decomposed();
+
+                GltfNode {
+                    name: n.name().map(ToOwned::to_owned),
+                    transform: Transform {
+                        translation: Vec3::from(translation),
+                        rotation: Quat::from_xyzw(x, y, z, w),
+                        scale: Vec3::from(scale),
+                    },
+                    mesh: n.mesh().map(|m| m.index()),
+                    children: n.children().map(|c| c.index()).collect(),
+                }
+            })
+            .collect();
+
+        let roots = document
+            .default_scene()
+            .or_else(|| document.scenes().next())
+            .map_or_else(Vec::new, |s| s.nodes().map(|n| n.index()).collect());
+
+        Ok(Self {
+            meshes,
+            materials,
+            images,
+            nodes,
+            roots,
+        })
+    }
+
+    /// Uploads every primitive and registers them to the renderer.
+    ///
+    /// Returns mesh ids indexed by `[mesh][primitive]`.
+    pub fn upload_meshes<'d, Device: br::Device + ?Sized + 'd>(
+        &self,
+        allocator: &MemoryAllocator<'d, Device>,
+        upload_queue: &UploadQueue<'d, Device>,
+        renderer: &mut SceneRenderer<'d, Device>,
+    ) -> Result<Vec<Vec<MeshId>>, EngineError> {
+        self.meshes
+            .iter()
+            .map(|m| {
+                m.primitives
+                    .iter()
+                    .map(|p| Ok(renderer.add_mesh(p.data.upload(allocator, upload_queue)?)))
+                    .collect::<Result<Vec<_>, EngineError>>()
+            })
+            .collect()
+    }
+
+    /// Adds the node hierarchy of the asset under `parent`.
+    ///
+    /// `meshes` is the result of [`Self::upload_meshes`]. `material` maps material indices(None
+    /// for the default material) to registered materials. Meshes with multiple primitives are
+    /// expanded to a child node per primitive. Returns the nodes added for the roots, or None if
+    /// `parent` is not a node of `scene`(nothing is added in that case).
+    pub fn instantiate(
+        &self,
+        scene: &mut Scene,
+        parent: Option<NodeId>,
+        meshes: &[Vec<MeshId>],
+        material: impl Fn(Option<usize>) -> MaterialId,
+    ) -> Option<Vec<NodeId>> {
+        let mut roots = Vec::with_capacity(self.roots.len());
+        let mut stack = self
+            .roots
+            .iter()
+            .rev()
+            .map(|&n| (n, parent, true))
+            .collect::<Vec<_>>();
+        while let Some((index, parent, is_root)) = stack.pop() {
+            let source = &self.nodes[index];
+            let id = scene.add_node(source.name.clone().unwrap_or_default(), parent)?;
+            scene.node_mut(id).expect("no node").transform = source.transform;
+            if is_root {
+                roots.push(id);
+            }
+
+            if let Some(mesh) = source.mesh {
+                let primitives = &self.meshes[mesh].primitives;
+                let instances =
+                    primitives
+                        .iter()
+                        .zip(&meshes[mesh])
+                        .map(|(p, &mesh)| MeshInstance {
+                            mesh,
+                            material: material(p.material),
+                        });
+
+                if primitives.len() == 1 {
+                    scene.node_mut(id).expect("no node").mesh = instances.last();
+                } else {
+                    for (n, instance) in instances.enumerate() {
+                        let child = scene.add_node(format!("primitive{n}"), Some(id))?;
+                        scene.node_mut(child).expect("no node").mesh = Some(instance);
+                    }
+                }
+            }
+
+            stack.extend(source.children.iter().map(|&c| (c, Some(id), false)));
+        }
+
+        Some(roots)
+    }
+}
+
+fn load_primitive(
+    path: &Path,
+    buffers: &[Vec<u8>],
+    mesh: &::gltf::Mesh,
+    primitive: &::gltf::Primitive,
+) -> Result<Option<GltfPrimitive>, EngineError> {
+    if primitive.mode() != ::gltf::mesh::Mode::Triangles {
+        eprintln!(
+            "gltf {}: skipping non-triangle primitive {} of mesh {}",
+            path.display(),
+            primitive.index(),
+            mesh.index()
+        );
+        return Ok(None);
+    }
+
+    let reader = primitive.reader(|b| buffers.get(b.index()).map(|d| &d[..]));
+    let Some(positions) = reader.read_positions() else {
+        eprintln!(
+            "gltf {}: skipping primitive {} of mesh {} without positions",
+            path.display(),
+            primitive.index(),
+            mesh.index()
+        );
+        return Ok(None);
+    };
+    let mut vertices = positions
+        .map(|position| MeshVertex {
+            position,
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+    if vertices.is_empty() {
+        return Ok(None);
+    }
+
+    let indices = reader
+        .read_indices()
+        .map(|i| i.into_u32().collect::<Vec<_>>());
+    if let Some(i) = indices.as_ref().and_then(|i| i.iter().max()) {
+        if *i as usize >= vertices.len() {
+            return Err(EngineError::invalid_asset(
+                path,
+                format!("index out of range in mesh {}", mesh.index()),
+            ));
+        }
+    }
+
+    // Note: 頂点数が合わない属性は壊れたファイルとみなす
+    let attribute_mismatch = || {
+        EngineError::invalid_asset(
+            path,
+            format!("attribute count mismatch in mesh {}", mesh.index()),
+        )
+    };
+    let normals = reader.read_normals().map(Iterator::collect::<Vec<_>>);
+    let uvs = reader
+        .read_tex_coords(0)
+        .map(|t| t.into_f32().collect::<Vec<_>>());
+    let tangents = reader.read_tangents().map(Iterator::collect::<Vec<_>>);
+    let (has_normals, has_tangents) = (normals.is_some(), tangents.is_some());
+    if let Some(normals) = normals {
+        if normals.len() != vertices.len() {
+            return Err(attribute_mismatch());
+        }
+        for (v, n) in vertices.iter_mut().zip(normals) {
+            v.normal = n;
+        }
+    }
+    if let Some(uvs) = uvs {
+        if uvs.len() != vertices.len() {
+            return Err(attribute_mismatch());
+        }
+        for (v, uv) in vertices.iter_mut().zip(uvs) {
+            v.uv = uv;
+        }
+    }
+    if let Some(tangents) = tangents {
+        if tangents.len() != vertices.len() {
+            return Err(attribute_mismatch());
+        }
+        for (v, t) in vertices.iter_mut().zip(tangents) {
+            v.tangent = t;
+        }
+    }
+
+    let mut data = MeshData { vertices, indices };
+    if !has_normals {
+        data.generate_normals();
+    }
+    if !has_tangents {
+        data.generate_tangents();
+    }
+
+    Ok(Some(GltfPrimitive {
+        data,
+        material: primitive.material().index(),
+    }))
+}
+
+fn texture_ref(info: Option<::gltf::texture::Info>) -> Option<TextureRef> {
+    info.map(|i| TextureRef {
+        image: i.texture().source().index(),
+        tex_coord: i.tex_coord(),
+    })
+}
+
+fn load_material(material: ::gltf::Material) -> GltfMaterial {
+    let pbr = material.pbr_metallic_roughness();
+    let normal = material.normal_texture();
+    let occlusion = material.occlusion_texture();
+
+    GltfMaterial {
+        name: material.name().map(ToOwned::to_owned),
+        base_color_factor: pbr.base_color_factor(),
+        base_color_texture: texture_ref(pbr.base_color_texture()),
+        metallic_factor: pbr.metallic_factor(),
+        roughness_factor: pbr.roughness_factor(),
+        metallic_roughness_texture: texture_ref(pbr.metallic_roughness_texture()),
+        normal_texture: normal.as_ref().map(|t| TextureRef {
+            image: t.texture().source().index(),
+            tex_coord: t.tex_coord(),
+        }),
+        normal_scale: normal.as_ref().map_or(1.0, |t| t.scale()),
+        occlusion_texture: occlusion.as_ref().map(|t| TextureRef {
+            image: t.texture().source().index(),
+            tex_coord: t.tex_coord(),
+        }),
+        occlusion_strength: occlusion.as_ref().map_or(1.0, |t| t.strength()),
+        emissive_factor: material.emissive_factor(),
+        emissive_texture: texture_ref(material.emissive_texture()),
+        alpha_mode: match material.alpha_mode() {
+            ::gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+            ::gltf::material::AlphaMode::Mask => AlphaMode::Mask,
+            ::gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+        },
+        alpha_cutoff: material.alpha_cutoff().unwrap_or(0.5),
+        double_sided: material.double_sided(),
+    }
+}
+
+/// Reads an external file relative to the glTF file, or decodes a base64 data URI.
+fn read_uri(path: &Path, base_dir: &Path, uri: &str) -> Result<Vec<u8>, EngineError> {
+    if let Some(data) = uri.strip_prefix("data:") {
+        let Some((_, encoded)) = data.split_once(";base64,") else {
+            return Err(EngineError::invalid_asset(path, "non-base64 data uri"));
+        };
+
+        return decode_base64(encoded)
+            .ok_or_else(|| EngineError::invalid_asset(path, "malformed base64 data uri"));
+    }
+    if uri.contains("://") {
+        return Err(EngineError::invalid_asset(
+            path,
+            format!("unsupported uri: {uri}"),
+        ));
+    }
+
+    let file_path = base_dir.join(percent_decode(uri));
+    std::fs::read(&file_path).map_err(|e| EngineError::io(file_path, e))
+}
+
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                decoded.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn decode_base64(encoded: &str) -> Option<Vec<u8>> {
+    fn value(c: u8) -> Option<u32> {
+        match c {
+            b'A'..=b'Z' => Some((c - b'A') as _),
+            b'a'..=b'z' => Some((c - b'a' + 26) as _),
+            b'0'..=b'9' => Some((c - b'0' + 52) as _),
+            b'+' | b'-' => Some(62),
+            b'/' | b'_' => Some(63),
+            _ => None,
+        }
+    }
+
+    let encoded = encoded.trim_end_matches('=').as_bytes();
+    let mut decoded = Vec::with_capacity(encoded.len() * 3 / 4);
+    for chunk in encoded.chunks(4) {
+        if chunk.len() == 1 {
+            return None;
+        }
+
+        let mut bits = 0u32;
+        for (n, &c) in chunk.iter().enumerate() {
+            bits |= value(c)? << (18 - 6 * n);
+        }
+        decoded.extend_from_slice(&bits.to_be_bytes()[1..chunk.len()]);
+    }
+
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Vec4;
+
+    /// Attributes of a single triangle-list primitive
+    #[derive(Default)]
+    struct Primitive<'a> {
+        positions: &'a [[f32; 3]],
+        normals: Option<&'a [[f32; 3]]>,
+        uvs: Option<&'a [[f32; 2]]>,
+        indices: Option<&'a [u16]>,
+    }
+    impl Primitive<'_> {
+        /// JSON part of the document(without `buffers`) and the buffer contents
+        fn encode(&self) -> (String, Vec<u8>) {
+            let mut bin = Vec::new();
+            let mut views = Vec::new();
+            let mut accessors = Vec::new();
+            let mut attributes = Vec::new();
+            let mut push = |bytes: Vec<u8>, accessor: String| {
+                views.push(format!(
+                    r#"{{"buffer":0,"byteOffset":{},"byteLength":{}}}"#,
+                    bin.len(),
+                    bytes.len()
+                ));
+                accessors.push(format!(
+                    r#"{{"bufferView":{},{accessor}}}"#,
+                    views.len() - 1
+                ));
+                bin.extend(bytes);
+
+                accessors.len() - 1
+            };
+            let floats = |xs: &[f32]| xs.iter().flat_map(|x| x.to_le_bytes()).collect::<Vec<_>>();
+
+            let (min, max) =
+                self.positions
+                    .iter()
+                    .fold(([f32::MAX; 3], [f32::MIN; 3]), |(min, max), p| {
+                        (
+                            [0, 1, 2].map(|n| min[n].min(p[n])),
+                            [0, 1, 2].map(|n| max[n].max(p[n])),
+                        )
+                    });
+            let position = push(
+                floats(self.positions.concat().as_slice()),
+                format!(
+                    r#""componentType":5126,"count":{},"type":"VEC3","min":{min:?},"max":{max:?}"#,
+                    self.positions.len()
+                ),
+            );
+            attributes.push(format!(r#""POSITION":{position}"#));
+            if let Some(normals) = self.normals {
+                let normal = push(
+                    floats(normals.concat().as_slice()),
+                    format!(
+                        r#""componentType":5126,"count":{},"type":"VEC3""#,
+                        normals.len()
+                    ),
+                );
+                attributes.push(format!(r#""NORMAL":{normal}"#));
+            }
+            if let Some(uvs) = self.uvs {
+                let uv = push(
+                    floats(uvs.concat().as_slice()),
+                    format!(
+                        r#""componentType":5126,"count":{},"type":"VEC2""#,
+                        uvs.len()
+                    ),
+                );
+                attributes.push(format!(r#""TEXCOORD_0":{uv}"#));
+            }
+            let indices = self.indices.map(|indices| {
+                let index = push(
+                    indices.iter().flat_map(|i| i.to_le_bytes()).collect(),
+                    format!(
+                        r#""componentType":5123,"count":{},"type":"SCALAR""#,
+                        indices.len()
+                    ),
+                );
+
+                format!(r#","indices":{index}"#)
+            });
+
+            let json = format!(
+                r#""asset":{{"version":"2.0"}},"scene":0,"scenes":[{{"nodes":[0]}}],
+                "nodes":[{{"name":"root","mesh":0,"translation":[1.0,2.0,3.0]}}],
+                "meshes":[{{"primitives":[{{"attributes":{{{}}}{}}}]}}],
+                "accessors":[{}],"bufferViews":[{}]"#,
+                attributes.join(","),
+                indices.unwrap_or_default(),
+                accessors.join(","),
+                views.join(",")
+            );
+
+            (json, bin)
+        }
+
+        /// `.gltf` with the buffer embedded as a data uri
+        fn gltf(&self) -> Vec<u8> {
+            let (json, bin) = self.encode();
+            let uri = format!(
+                "data:application/octet-stream;base64,{}",
+                encode_base64(&bin)
+            );
+
+            format!(
+                r#"{{{json},"buffers":[{{"byteLength":{},"uri":"{uri}"}}]}}"#,
+                bin.len()
+            )
+            .into_bytes()
+        }
+
+        /// `.glb` with the buffer in the binary chunk. `declared_length` overrides `byteLength`
+        fn glb(&self, declared_length: Option<usize>) -> Vec<u8> {
+            let (json, bin) = self.encode();
+            let json = format!(
+                r#"{{{json},"buffers":[{{"byteLength":{}}}]}}"#,
+                declared_length.unwrap_or(bin.len())
+            );
+
+            let chunk = |ty: &[u8; 4], mut data: Vec<u8>, padding: u8| {
+                data.resize((data.len() + 3) / 4 * 4, padding);
+                let mut chunk = (data.len() as u32).to_le_bytes().to_vec();
+                chunk.extend(ty);
+                chunk.extend(data);
+
+                chunk
+            };
+            let chunks = [
+                chunk(b"JSON", json.into_bytes(), b' '),
+                chunk(b"BIN\0", bin, 0),
+            ]
+            .concat();
+
+            let mut glb = b"glTF".to_vec();
+            glb.extend(2u32.to_le_bytes());
+            glb.extend((12 + chunks.len() as u32).to_le_bytes());
+            glb.extend(chunks);
+
+            glb
+        }
+    }
+
+    fn encode_base64(bytes: &[u8]) -> String {
+        const TABLE: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+        let mut encoded = String::new();
+        for chunk in bytes.chunks(3) {
+            let mut bits = [0u8; 4];
+            bits[1..=chunk.len()].copy_from_slice(chunk);
+            let bits = u32::from_be_bytes(bits);
+            for n in 0..4 {
+                if n <= chunk.len() {
+                    encoded.push(TABLE[(bits >> (18 - 6 * n) & 0x3f) as usize] as char);
+                } else {
+                    encoded.push('=');
+                }
+            }
+        }
+
+        encoded
+    }
+
+    const TRIANGLE: [[f32; 3]; 3] = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+    const TRIANGLE_UVS: [[f32; 2]; 3] = [[0.0, 1.0], [1.0, 1.0], [0.0, 0.0]];
+
+    fn load(name: &str, bytes: &[u8]) -> Result<GltfAsset, EngineError> {
+        GltfAsset::from_slice(Path::new(name), bytes)
+    }
+
+    fn single_primitive(asset: &GltfAsset) -> &MeshData {
+        assert_eq!(asset.meshes.len(), 1);
+        assert_eq!(asset.meshes[0].primitives.len(), 1);
+
+        &asset.meshes[0].primitives[0].data
+    }
+
+    #[test]
+    fn base64_is_decoded() {
+        assert_eq!(decode_base64("TWFu").as_deref(), Some(&b"Man"[..]));
+        assert_eq!(decode_base64("TWE=").as_deref(), Some(&b"Ma"[..]));
+        assert_eq!(decode_base64("TQ==").as_deref(), Some(&b"M"[..]));
+        assert_eq!(decode_base64("").as_deref(), Some(&b""[..]));
+        // url-safe alphabet
+        assert_eq!(decode_base64("-_8=").as_deref(), Some(&[0xfb, 0xff][..]));
+        assert_eq!(decode_base64("+/8=").as_deref(), Some(&[0xfb, 0xff][..]));
+
+        let bytes = (0..=255).collect::<Vec<u8>>();
+        assert_eq!(decode_base64(&encode_base64(&bytes)), Some(bytes));
+    }
+
+    #[test]
+    fn malformed_base64_is_rejected() {
+        assert_eq!(decode_base64("TWFuT"), None);
+        assert_eq!(decode_base64("TW*u"), None);
+        assert_eq!(decode_base64("TW u"), None);
+    }
+
+    #[test]
+    fn percent_encoded_uris_are_decoded() {
+        assert_eq!(percent_decode("a%20b.bin"), "a b.bin");
+        assert_eq!(percent_decode("%E3%81%82.png"), "あ.png");
+        assert_eq!(percent_decode("plain/path.bin"), "plain/path.bin");
+        // malformed escapes are kept as is
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%2"), "%2");
+        assert_eq!(percent_decode("%zz"), "%zz");
+    }
+
+    #[test]
+    fn embedded_buffers_are_loaded() {
+        let normals = [[0.0, 0.0, 1.0]; 3];
+        let asset = load(
+            "test.gltf",
+            &Primitive {
+                positions: &TRIANGLE,
+                normals: Some(&normals),
+                uvs: Some(&TRIANGLE_UVS),
+                indices: Some(&[0, 1, 2]),
+            }
+            .gltf(),
+        )
+        .expect("failed to load");
+
+        let data = single_primitive(&asset);
+        let positions = data.vertices.iter().map(|v| v.position).collect::<Vec<_>>();
+        let uvs = data.vertices.iter().map(|v| v.uv).collect::<Vec<_>>();
+        assert_eq!(positions, TRIANGLE);
+        assert_eq!(uvs, TRIANGLE_UVS);
+        assert_eq!(data.indices.as_deref(), Some(&[0, 1, 2][..]));
+        assert_eq!(asset.meshes[0].primitives[0].material, None);
+
+        assert_eq!(asset.roots, [0]);
+        assert_eq!(asset.nodes[0].name.as_deref(), Some("root"));
+        assert_eq!(asset.nodes[0].mesh, Some(0));
+        assert_eq!(
+            asset.nodes[0].transform.translation,
+            Vec3::new(1.0, 2.0, 3.0)
+        );
+    }
+
+    #[test]
+    fn missing_normals_and_tangents_are_generated() {
+        let asset = load(
+            "test.gltf",
+            &Primitive {
+                positions: &TRIANGLE,
+                uvs: Some(&TRIANGLE_UVS),
+                ..Default::default()
+            }
+            .gltf(),
+        )
+        .expect("failed to load");
+
+        // counter-clockwise in XY plane, U along +X and V along -Y
+        for v in &single_primitive(&asset).vertices {
+            assert_eq!(v.normal, [0.0, 0.0, 1.0]);
+            assert_eq!(v.tangent, [1.0, 0.0, 0.0, -1.0]);
+        }
+    }
+
+    #[test]
+    fn provided_normals_are_kept() {
+        let normals = [[0.0, 1.0, 0.0]; 3];
+        let asset = load(
+            "test.gltf",
+            &Primitive {
+                positions: &TRIANGLE,
+                normals: Some(&normals),
+                uvs: Some(&TRIANGLE_UVS),
+                ..Default::default()
+            }
+            .gltf(),
+        )
+        .expect("failed to load");
+
+        for v in &single_primitive(&asset).vertices {
+            assert_eq!(v.normal, [0.0, 1.0, 0.0]);
+            // tangents are still generated(perpendicular to the provided normal)
+            assert_eq!(v.tangent, [1.0, 0.0, 0.0, 1.0]);
+        }
+    }
+
+    #[test]
+    fn attribute_count_mismatch_is_rejected() {
+        let short_normals = [[0.0, 0.0, 1.0]; 2];
+        let short_uvs = [[0.0, 0.0]; 2];
+
+        for primitive in [
+            Primitive {
+                positions: &TRIANGLE,
+                normals: Some(&short_normals),
+                ..Default::default()
+            },
+            Primitive {
+                positions: &TRIANGLE,
+                uvs: Some(&short_uvs),
+                ..Default::default()
+            },
+        ] {
+            assert!(matches!(
+                load("test.gltf", &primitive.gltf()),
+                Err(EngineError::InvalidAsset { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn out_of_range_indices_are_rejected() {
+        let primitive = Primitive {
+            positions: &TRIANGLE,
+            indices: Some(&[0, 1, 3]),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            load("test.gltf", &primitive.gltf()),
+            Err(EngineError::InvalidAsset { .. })
+        ));
+    }
+
+    #[test]
+    fn padded_glb_blob_is_loaded() {
+        // 36 bytes of positions + 6 bytes of indices: the binary chunk is padded to 44 bytes
+        let primitive = Primitive {
+            positions: &TRIANGLE,
+            indices: Some(&[2, 1, 0]),
+            ..Default::default()
+        };
+        let glb = primitive.glb(None);
+        assert_eq!(glb.len() % 4, 0);
+
+        let asset = load("test.glb", &glb).expect("failed to load");
+        let data = single_primitive(&asset);
+        assert_eq!(data.vertices.len(), 3);
+        assert_eq!(data.indices.as_deref(), Some(&[2, 1, 0][..]));
+    }
+
+    #[test]
+    fn glb_blob_shorter_than_declared_is_rejected() {
+        let primitive = Primitive {
+            positions: &TRIANGLE,
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            load("test.glb", &primitive.glb(Some(64))),
+            Err(EngineError::InvalidAsset { .. })
+        ));
+    }
+
+    #[test]
+    fn primitives_are_expanded_to_child_nodes() {
+        let primitive = |material| GltfPrimitive {
+            data: MeshData::default(),
+            material,
+        };
+        let node = |name: &str, translation, mesh, children| GltfNode {
+            name: Some(name.into()),
+            transform: Transform {
+                translation,
+                ..Transform::IDENTITY
+            },
+            mesh,
+            children,
+        };
+        let asset = GltfAsset {
+            meshes: vec![
+                GltfMesh {
+                    name: None,
+                    primitives: vec![primitive(Some(0))],
+                },
+                GltfMesh {
+                    name: None,
+                    primitives: vec![primitive(None), primitive(Some(0))],
+                },
+            ],
+            materials: Vec::new(),
+            images: Vec::new(),
+            nodes: vec![
+                node("single", Vec3::new(1.0, 0.0, 0.0), Some(0), vec![1]),
+                node("multi", Vec3::new(0.0, 2.0, 0.0), Some(1), vec![]),
+            ],
+            roots: vec![0],
+        };
+        let meshes = [vec![MeshId(10)], vec![MeshId(11), MeshId(12)]];
+
+        let mut scene = Scene::new();
+        let parent = scene.add_node("parent", None).expect("no parent");
+        scene
+            .node_mut(parent)
+            .expect("no node")
+            .transform
+            .translation = Vec3::new(0.0, 0.0, 4.0);
+        let roots = asset
+            .instantiate(&mut scene, Some(parent), &meshes, |m| {
+                MaterialId(m.map_or(0, |m| m as u32 + 1))
+            })
+            .expect("no parent");
+
+        assert_eq!(roots.len(), 1);
+        let single = scene.node(roots[0]).expect("no node");
+        assert_eq!(single.name, "single");
+
+        scene.update_world_transforms();
+        let mut visible = Vec::new();
+        scene.collect_visible_meshes(&mut visible);
+        visible.sort_by_key(|v| v.instance.mesh);
+        let visible = visible
+            .iter()
+            .map(|v| (v.instance, v.world_transform.cols[3]))
+            .collect::<Vec<_>>();
+        // the multi-primitive mesh has no mesh itself, its children inherit the transform
+        assert_eq!(
+            visible,
+            [
+                (
+                    MeshInstance {
+                        mesh: MeshId(10),
+                        material: MaterialId(1),
+                    },
+                    Vec4::new(1.0, 0.0, 4.0, 1.0)
+                ),
+                (
+                    MeshInstance {
+                        mesh: MeshId(11),
+                        material: MaterialId(0),
+                    },
+                    Vec4::new(1.0, 2.0, 4.0, 1.0)
+                ),
+                (
+                    MeshInstance {
+                        mesh: MeshId(12),
+                        material: MaterialId(1),
+                    },
+                    Vec4::new(1.0, 2.0, 4.0, 1.0)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn instantiating_under_foreign_node_adds_nothing() {
+        let asset = GltfAsset {
+            meshes: Vec::new(),
+            materials: Vec::new(),
+            images: Vec::new(),
+            nodes: vec![GltfNode {
+                name: None,
+                transform: Transform::IDENTITY,
+                mesh: None,
+                children: Vec::new(),
+            }],
+            roots: vec![0],
+        };
+        let mut other = Scene::new();
+        other.add_node("a", None).expect("no node");
+        let foreign = other.add_node("b", None).expect("no node");
+
+        let mut scene = Scene::new();
+        assert_eq!(
+            asset.instantiate(&mut scene, Some(foreign), &[], |_| MaterialId(0)),
+            None
+        );
+        assert!(scene.node(foreign).is_none());
+    }
+}
@@ -0,0 +1,23 @@
+//! Loading assets from the `assets/` directory.
+
+use std::path::{Path, PathBuf};
+
+use crate::error::EngineError;
+
+pub mod gltf;
+pub mod texture;
+
+/// Assets are resolved relative to the working directory
+pub const ASSET_ROOT: &str = "assets";
+
+/// Resolves a path relative to [`ASSET_ROOT`].
+pub fn path(relative: impl AsRef<Path>) -> PathBuf {
+    Path::new(ASSET_ROOT).join(relative)
+}
+
+/// Reads the whole file at a path relative to [`ASSET_ROOT`].
+pub fn read(relative: impl AsRef<Path>) -> Result<Vec<u8>, EngineError> {
+    let path = path(relative);
+
+    std::fs::read(&path).map_err(|e| EngineError::io(path, e))
+}
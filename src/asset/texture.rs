@@ -0,0 +1,524 @@
+//! Texture decoding(PNG and KTX2).
+
+use std::path::Path;
+
+use bedrock as br;
+
+use crate::error::EngineError;
+
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+const PNG_SIGNATURE: [u8; 8] = [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// How 8-bit color values of PNG images are interpreted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureColorSpace {
+    /// Color textures(base color, emissive)
+    Srgb,
+    /// Non-color data(normal maps, metallic-roughness)
+    Linear,
+}
+
+/// Decoded texels of a 2D texture.
+#[derive(Clone, Debug)]
+pub struct TextureData {
+    pub format: br::vk::VkFormat,
+    pub extent: br::vk::VkExtent2D,
+    /// Tightly packed texels of each mip level, from the base level
+    pub levels: Vec<Vec<u8>>,
+    /// true if the rest of the mip chain should be generated from the base level
+    pub generate_mips: bool,
+}
+impl TextureData {
+    /// Loads a PNG or KTX2 file at a path relative to the asset directory.
+    ///
+    /// `color_space` is ignored for KTX2 files(the format is stored in the file).
+    pub fn load(
+        relative_path: impl AsRef<Path>,
+        color_space: TextureColorSpace,
+    ) -> Result<Self, EngineError> {
+        let path = super::path(relative_path);
+        let bytes = std::fs::read(&path).map_err(|e| EngineError::io(&path, e))?;
+
+        Self::from_slice(&path, &bytes, color_space)
+    }
+
+    /// Decodes by the file signature. `path` is used for error reports.
+    pub fn from_slice(
+        path: &Path,
+        bytes: &[u8],
+        color_space: TextureColorSpace,
+    ) -> Result<Self, EngineError> {
+        if bytes.starts_with(&KTX2_IDENTIFIER) {
+            Self::from_ktx2(path, bytes)
+        } else if bytes.starts_with(&PNG_SIGNATURE) {
+            Self::from_png(path, bytes, color_space)
+        } else {
+            Err(EngineError::invalid_asset(path, "unknown texture format"))
+        }
+    }
+
+    /// Decodes a PNG image into RGBA8.
+    pub fn from_png(
+        path: &Path,
+        bytes: &[u8],
+        color_space: TextureColorSpace,
+    ) -> Result<Self, EngineError> {
+        let (width, height, pixels) =
+            decode_png_rgba8(bytes).map_err(|e| EngineError::invalid_asset(path, e))?;
+
+        Ok(Self {
+            format: match color_space {
+                TextureColorSpace::Srgb => br::vk::VK_FORMAT_R8G8B8A8_SRGB,
+                TextureColorSpace::Linear => br::vk::VK_FORMAT_R8G8B8A8_UNORM,
+            },
+            extent: br::vk::VkExtent2D { width, height },
+            levels: vec![pixels],
+            generate_mips: true,
+        })
+    }
+
+    /// Reads a KTX2 container. Only 2D textures without supercompression are supported.
+    ///
+    /// Each level must have exactly the size implied by the format and the extent of the level.
+    pub fn from_ktx2(path: &Path, bytes: &[u8]) -> Result<Self, EngineError> {
+        let invalid = |reason: &str| EngineError::invalid_asset(path, reason);
+        let u32_at = |offset: usize| {
+            bytes
+                .get(offset..offset + 4)
+                .map(|b| u32::from_le_bytes(b.try_into().expect("size mismatch")))
+                .ok_or_else(|| invalid("truncated header"))
+        };
+        let u64_at = |offset: usize| {
+            bytes
+                .get(offset..offset + 8)
+                .map(|b| u64::from_le_bytes(b.try_into().expect("size mismatch")))
+                .ok_or_else(|| invalid("truncated header"))
+        };
+
+        let format = u32_at(12)? as br::vk::VkFormat;
+        let (width, height, depth) = (u32_at(20)?, u32_at(24)?, u32_at(28)?);
+        let (layer_count, face_count, level_count) = (u32_at(32)?, u32_at(36)?, u32_at(40)?);
+        let supercompression_scheme = u32_at(44)?;
+        if format == br::vk::VK_FORMAT_UNDEFINED {
+            return Err(invalid("basis universal textures are not supported"));
+        }
+        if supercompression_scheme != 0 {
+            return Err(invalid("supercompressed textures are not supported"));
+        }
+        if width == 0 || height == 0 || depth != 0 || layer_count > 1 || face_count != 1 {
+            return Err(invalid("only 2D textures are supported"));
+        }
+        let (block_width, block_height, block_size) =
+            block_extent_and_size(format).ok_or_else(|| invalid("unsupported format"))?;
+        let full_mip_levels = 32 - width.max(height).leading_zeros();
+        if level_count > full_mip_levels {
+            return Err(invalid("more levels than the full mip chain"));
+        }
+
+        // level index follows the header(48 bytes) and the index of data blocks(32 bytes)
+        let levels = (0..level_count.max(1) as usize)
+            .map(|n| {
+                let (offset, length) = (u64_at(80 + n * 24)?, u64_at(80 + n * 24 + 8)?);
+                let range = offset.checked_add(length).and_then(|end| {
+                    Some(usize::try_from(offset).ok()?..usize::try_from(end).ok()?)
+                });
+
+                let data = range
+                    .and_then(|r| bytes.get(r))
+                    .ok_or_else(|| invalid("level data out of the file"))?;
+
+                // copies from the staged data read exactly this size
+                let (w, h) = ((width >> n).max(1), (height >> n).max(1));
+                let expected = u64::from(w.div_ceil(block_width))
+                    * u64::from(h.div_ceil(block_height))
+                    * block_size;
+                if length != expected {
+                    return Err(invalid(&format!(
+                        "level {n} has {length} bytes, \
+                         but {w}x{h} texels require {expected} bytes"
+                    )));
+                }
+
+                Ok(data.to_vec())
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            format,
+            extent: br::vk::VkExtent2D { width, height },
+            levels,
+            // levelCount = 0 requests mip generation
+            generate_mips: level_count == 0,
+        })
+    }
+
+    /// Number of levels of the full mip chain
+    pub fn full_mip_levels(&self) -> u32 {
+        32 - self.extent.width.max(self.extent.height).leading_zeros()
+    }
+}
+
+/// (block width, block height, bytes per block) of formats which can be loaded from KTX2 files.
+/// Uncompressed formats have 1x1 blocks.
+fn block_extent_and_size(format: br::vk::VkFormat) -> Option<(u32, u32, u64)> {
+    match format {
+        br::vk::VK_FORMAT_R8_UNORM | br::vk::VK_FORMAT_R8_SRGB => Some((1, 1, 1)),
+        br::vk::VK_FORMAT_R8G8_UNORM
+        | br::vk::VK_FORMAT_R8G8_SRGB
+        | br::vk::VK_FORMAT_R16_SFLOAT
+        | br::vk::VK_FORMAT_R16_UNORM => Some((1, 1, 2)),
+        br::vk::VK_FORMAT_R8G8B8_UNORM | br::vk::VK_FORMAT_R8G8B8_SRGB => Some((1, 1, 3)),
+        br::vk::VK_FORMAT_R8G8B8A8_UNORM
+        | br::vk::VK_FORMAT_R8G8B8A8_SRGB
+        | br::vk::VK_FORMAT_B8G8R8A8_UNORM
+        | br::vk::VK_FORMAT_B8G8R8A8_SRGB
+        | br::vk::VK_FORMAT_A2B10G10R10_UNORM_PACK32
+        | br::vk::VK_FORMAT_B10G11R11_UFLOAT_PACK32
+        | br::vk::VK_FORMAT_E5B9G9R9_UFLOAT_PACK32
+        | br::vk::VK_FORMAT_R16G16_SFLOAT
+        | br::vk::VK_FORMAT_R16G16_UNORM
+        | br::vk::VK_FORMAT_R32_SFLOAT => Some((1, 1, 4)),
+        br::vk::VK_FORMAT_R16G16B16A16_SFLOAT
+        | br::vk::VK_FORMAT_R16G16B16A16_UNORM
+        | br::vk::VK_FORMAT_R32G32_SFLOAT => Some((1, 1, 8)),
+        br::vk::VK_FORMAT_R32G32B32A32_SFLOAT => Some((1, 1, 16)),
+        br::vk::VK_FORMAT_BC1_RGB_UNORM_BLOCK
+        | br::vk::VK_FORMAT_BC1_RGB_SRGB_BLOCK
+        | br::vk::VK_FORMAT_BC1_RGBA_UNORM_BLOCK
+        | br::vk::VK_FORMAT_BC1_RGBA_SRGB_BLOCK
+        | br::vk::VK_FORMAT_BC4_UNORM_BLOCK
+        | br::vk::VK_FORMAT_BC4_SNORM_BLOCK
+        | br::vk::VK_FORMAT_ETC2_R8G8B8_UNORM_BLOCK
+        | br::vk::VK_FORMAT_ETC2_R8G8B8_SRGB_BLOCK => Some((4, 4, 8)),
+        br::vk::VK_FORMAT_BC2_UNORM_BLOCK
+        | br::vk::VK_FORMAT_BC2_SRGB_BLOCK
+        | br::vk::VK_FORMAT_BC3_UNORM_BLOCK
+        | br::vk::VK_FORMAT_BC3_SRGB_BLOCK
+        | br::vk::VK_FORMAT_BC5_UNORM_BLOCK
+        | br::vk::VK_FORMAT_BC5_SNORM_BLOCK
+        | br::vk::VK_FORMAT_BC6H_UFLOAT_BLOCK
+        | br::vk::VK_FORMAT_BC6H_SFLOAT_BLOCK
+        | br::vk::VK_FORMAT_BC7_UNORM_BLOCK
+        | br::vk::VK_FORMAT_BC7_SRGB_BLOCK
+        | br::vk::VK_FORMAT_ETC2_R8G8B8A8_UNORM_BLOCK
+        | br::vk::VK_FORMAT_ETC2_R8G8B8A8_SRGB_BLOCK
+        | br::vk::VK_FORMAT_ASTC_4x4_UNORM_BLOCK
+        | br::vk::VK_FORMAT_ASTC_4x4_SRGB_BLOCK => Some((4, 4, 16)),
+        _ => None,
+    }
+}
+
+/// Decodes a PNG image. Grayscale/RGB/palette images are expanded into RGBA.
+///
+/// Returns (width, height, pixels).
+pub fn decode_png_rgba8(
+    reader: impl std::io::Read,
+) -> Result<(u32, u32, Vec<u8>), png::DecodingError> {
+    let mut decoder = png::Decoder::new(reader);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf)?;
+    buf.truncate(info.buffer_size());
+
+    let pixels = match info.color_type {
+        png::ColorType::Rgba => buf,
+        png::ColorType::Rgb => buf
+            .chunks_exact(3)
+            .flat_map(|x| [x[0], x[1], x[2], 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => buf
+            .chunks_exact(2)
+            .flat_map(|x| [x[0], x[0], x[0], x[1]])
+            .collect(),
+        png::ColorType::Grayscale => buf.iter().flat_map(|&x| [x, x, x, 255]).collect(),
+        png::ColorType::Indexed => unreachable!("palette is expanded by the decoder"),
+    };
+
+    Ok((info.width, info.height, pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// KTX2 file with the level index and the level data following it
+    fn ktx2(format: br::vk::VkFormat, (width, height): (u32, u32), levels: &[Vec<u8>]) -> Vec<u8> {
+        ktx2_with_level_count(format, (width, height), levels.len() as u32, levels)
+    }
+
+    fn ktx2_with_level_count(
+        format: br::vk::VkFormat,
+        (width, height): (u32, u32),
+        level_count: u32,
+        levels: &[Vec<u8>],
+    ) -> Vec<u8> {
+        let mut header = KTX2_IDENTIFIER.to_vec();
+        // vkFormat, typeSize, pixel size(w, h, d), layerCount, faceCount, levelCount and
+        // supercompressionScheme
+        for x in [format as u32, 1, width, height, 0, 0, 1, level_count, 0] {
+            header.extend(x.to_le_bytes());
+        }
+        // index of data format descriptor, key/value data and supercompression global data
+        header.resize(80, 0);
+
+        let mut offset = 80 + levels.len() * 24;
+        for l in levels {
+            for x in [offset, l.len(), l.len()] {
+                header.extend((x as u64).to_le_bytes());
+            }
+            offset += l.len();
+        }
+        header.extend(levels.concat());
+
+        header
+    }
+
+    fn load_ktx2(bytes: &[u8]) -> Result<TextureData, EngineError> {
+        TextureData::from_ktx2(Path::new("test.ktx2"), bytes)
+    }
+
+    fn is_invalid(result: Result<TextureData, EngineError>) -> bool {
+        matches!(result, Err(EngineError::InvalidAsset { .. }))
+    }
+
+    fn encode_png(
+        width: u32,
+        height: u32,
+        color: png::ColorType,
+        depth: png::BitDepth,
+        palette: Option<Vec<u8>>,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut bytes, width, height);
+            encoder.set_color(color);
+            encoder.set_depth(depth);
+            if let Some(palette) = palette {
+                encoder.set_palette(palette);
+            }
+            let mut writer = encoder.write_header().expect("failed to write header");
+            writer.write_image_data(data).expect("failed to write data");
+        }
+
+        bytes
+    }
+
+    #[test]
+    fn ktx2_mip_chain_is_loaded() {
+        let levels = vec![vec![1; 4 * 4 * 4], vec![2; 2 * 2 * 4], vec![3; 4]];
+        let data = load_ktx2(&ktx2(br::vk::VK_FORMAT_R8G8B8A8_SRGB, (4, 4), &levels))
+            .expect("failed to load");
+
+        assert!(data.format == br::vk::VK_FORMAT_R8G8B8A8_SRGB);
+        assert_eq!((data.extent.width, data.extent.height), (4, 4));
+        assert_eq!(data.levels, levels);
+        assert!(!data.generate_mips);
+    }
+
+    #[test]
+    fn ktx2_without_levels_requests_mip_generation() {
+        let base = vec![vec![0; 8 * 2 * 4]];
+        let data = load_ktx2(&ktx2_with_level_count(
+            br::vk::VK_FORMAT_R8G8B8A8_UNORM,
+            (8, 2),
+            0,
+            &base,
+        ))
+        .expect("failed to load");
+
+        assert_eq!(data.levels, base);
+        assert!(data.generate_mips);
+        assert_eq!(data.full_mip_levels(), 4);
+    }
+
+    #[test]
+    fn compressed_levels_are_sized_by_blocks() {
+        // 8x6 -> 4x3 -> 2x1 -> 1x1: 2x2, 1x1, 1x1 and 1x1 blocks
+        let levels = vec![vec![0; 4 * 8], vec![0; 8], vec![0; 8], vec![0; 8]];
+
+        assert!(load_ktx2(&ktx2(
+            br::vk::VK_FORMAT_BC1_RGB_UNORM_BLOCK,
+            (8, 6),
+            &levels
+        ))
+        .is_ok());
+        assert!(is_invalid(load_ktx2(&ktx2(
+            br::vk::VK_FORMAT_BC7_UNORM_BLOCK,
+            (8, 6),
+            &levels
+        ))));
+    }
+
+    #[test]
+    fn truncated_levels_are_rejected() {
+        let levels = vec![vec![0; 4 * 4 * 4], vec![0; 2 * 2 * 4 - 1], vec![0; 4]];
+
+        assert!(is_invalid(load_ktx2(&ktx2(
+            br::vk::VK_FORMAT_R8G8B8A8_UNORM,
+            (4, 4),
+            &levels
+        ))));
+    }
+
+    #[test]
+    fn levels_beyond_the_full_mip_chain_are_rejected() {
+        let levels = vec![vec![0; 2 * 2 * 4], vec![0; 4], vec![0; 4]];
+
+        assert!(is_invalid(load_ktx2(&ktx2(
+            br::vk::VK_FORMAT_R8G8B8A8_UNORM,
+            (2, 2),
+            &levels
+        ))));
+    }
+
+    #[test]
+    fn level_ranges_out_of_the_file_are_rejected() {
+        let mut bytes = ktx2(br::vk::VK_FORMAT_R8_UNORM, (1, 1), &[vec![0]]);
+        // offset + length overflows
+        bytes[80..88].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(is_invalid(load_ktx2(&bytes)));
+
+        let mut bytes = ktx2(br::vk::VK_FORMAT_R8_UNORM, (1, 1), &[vec![0]]);
+        bytes.pop();
+        assert!(is_invalid(load_ktx2(&bytes)));
+    }
+
+    #[test]
+    fn unsupported_ktx2_files_are_rejected() {
+        let rgba = || vec![vec![0; 4]];
+
+        // truncated header
+        assert!(is_invalid(load_ktx2(&KTX2_IDENTIFIER)));
+        // basis universal
+        assert!(is_invalid(load_ktx2(&ktx2(
+            br::vk::VK_FORMAT_UNDEFINED,
+            (1, 1),
+            &rgba()
+        ))));
+
+        let mut supercompressed = ktx2(br::vk::VK_FORMAT_R8G8B8A8_UNORM, (1, 1), &rgba());
+        supercompressed[44] = 2;
+        assert!(is_invalid(load_ktx2(&supercompressed)));
+
+        let mut volume = ktx2(br::vk::VK_FORMAT_R8G8B8A8_UNORM, (1, 1), &rgba());
+        volume[28] = 1;
+        assert!(is_invalid(load_ktx2(&volume)));
+
+        let mut cube = ktx2(br::vk::VK_FORMAT_R8G8B8A8_UNORM, (1, 1), &rgba());
+        cube[36] = 6;
+        assert!(is_invalid(load_ktx2(&cube)));
+
+        assert!(is_invalid(load_ktx2(&ktx2(
+            br::vk::VK_FORMAT_D32_SFLOAT,
+            (1, 1),
+            &rgba()
+        ))));
+    }
+
+    #[test]
+    fn png_is_expanded_into_rgba() {
+        let decode = |bytes: Vec<u8>| decode_png_rgba8(&bytes[..]).expect("failed to decode");
+
+        let rgba = [1, 2, 3, 4, 5, 6, 7, 8];
+        assert_eq!(
+            decode(encode_png(
+                2,
+                1,
+                png::ColorType::Rgba,
+                png::BitDepth::Eight,
+                None,
+                &rgba
+            )),
+            (2, 1, rgba.to_vec())
+        );
+        assert_eq!(
+            decode(encode_png(
+                1,
+                2,
+                png::ColorType::Rgb,
+                png::BitDepth::Eight,
+                None,
+                &[1, 2, 3, 4, 5, 6]
+            )),
+            (1, 2, vec![1, 2, 3, 255, 4, 5, 6, 255])
+        );
+        assert_eq!(
+            decode(encode_png(
+                1,
+                1,
+                png::ColorType::GrayscaleAlpha,
+                png::BitDepth::Eight,
+                None,
+                &[7, 8]
+            )),
+            (1, 1, vec![7, 7, 7, 8])
+        );
+        assert_eq!(
+            decode(encode_png(
+                2,
+                1,
+                png::ColorType::Grayscale,
+                png::BitDepth::Eight,
+                None,
+                &[9, 10]
+            )),
+            (2, 1, vec![9, 9, 9, 255, 10, 10, 10, 255])
+        );
+    }
+
+    #[test]
+    fn png_palette_and_16bit_images_are_normalized() {
+        let decode = |bytes: Vec<u8>| decode_png_rgba8(&bytes[..]).expect("failed to decode");
+
+        assert_eq!(
+            decode(encode_png(
+                2,
+                1,
+                png::ColorType::Indexed,
+                png::BitDepth::Eight,
+                Some(vec![10, 20, 30, 40, 50, 60]),
+                &[1, 0]
+            )),
+            (2, 1, vec![40, 50, 60, 255, 10, 20, 30, 255])
+        );
+        // only the upper bytes are kept
+        assert_eq!(
+            decode(encode_png(
+                1,
+                1,
+                png::ColorType::Rgb,
+                png::BitDepth::Sixteen,
+                None,
+                &[0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc]
+            )),
+            (1, 1, vec![0x12, 0x56, 0x9a, 255])
+        );
+    }
+
+    #[test]
+    fn malformed_png_is_rejected() {
+        let mut bytes = encode_png(
+            1,
+            1,
+            png::ColorType::Rgba,
+            png::BitDepth::Eight,
+            None,
+            &[0; 4],
+        );
+        bytes.truncate(bytes.len() / 2);
+
+        assert!(decode_png_rgba8(&bytes[..]).is_err());
+        assert!(matches!(
+            TextureData::from_slice(Path::new("test.png"), &bytes, TextureColorSpace::Srgb),
+            Err(EngineError::InvalidAsset { .. })
+        ));
+        assert!(matches!(
+            TextureData::from_slice(Path::new("test.bin"), &[0; 16], TextureColorSpace::Srgb),
+            Err(EngineError::InvalidAsset { .. })
+        ));
+    }
+}
@@ -0,0 +1,120 @@
+//! Error type of the engine.
+
+use std::path::PathBuf;
+
+use bedrock as br;
+
+#[derive(Debug)]
+pub enum EngineError {
+    /// Vulkan API call failed
+    Vulkan(br::VkResult),
+    /// `VK_ERROR_DEVICE_LOST`: every object created from the device is unusable
+    DeviceLost,
+    /// Failed to read or write a file(e.g. shader blobs)
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// Asset file is malformed or uses unsupported features
+    InvalidAsset { path: PathBuf, reason: String },
+    /// GLSL compilation failed and no previously compiled SPIR-V is available
+    ShaderCompilation { path: PathBuf, diagnostic: String },
+    /// Shader interfaces disagree with each other or with Rust side layouts
+    ShaderInterfaceMismatch(String),
+    /// No Vulkan implementations or physical devices found
+    NoVulkanDevice,
+    /// Every adapter has been rejected(see the log for reasons)
+    NoSuitableAdapter,
+    /// No adapter matched to the user-specified adapter override
+    AdapterOverrideNotMatched(String),
+    /// The selected adapter has no graphics queue family(capable of presentation to the surface)
+    NoGraphicsQueue,
+    /// The surface supports no formats
+    NoSuitableFormat,
+    /// No memory type satisfies the requirements of the resource
+    NoSuitableMemoryType(&'static str),
+    /// Per-frame data does not fit in the dynamic buffer segment of the frame
+    DynamicBufferExhausted,
+    /// Capture requested before any frames are presented
+    NoPresentedFrame,
+    /// The operation is not supported in current configuration
+    Unsupported(&'static str),
+    /// Neither of supported window servers is available
+    NoWindowServer,
+    /// The window server lacks a global interface required by the engine(e.g. `xdg_wm_base`)
+    MissingWindowServerInterface(&'static str),
+    /// A call to the window system failed(e.g. creating windows or display links)
+    WindowSystem(&'static str),
+}
+impl EngineError {
+    pub fn io(path: impl Into<PathBuf>, source: std::io::Error) -> Self {
+        Self::Io {
+            path: path.into(),
+            source,
+        }
+    }
+
+    pub fn invalid_asset(path: impl Into<PathBuf>, reason: impl std::fmt::Display) -> Self {
+        Self::InvalidAsset {
+            path: path.into(),
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn is_device_lost(&self) -> bool {
+        matches!(self, Self::DeviceLost)
+    }
+}
+impl From<br::VkResult> for EngineError {
+    fn from(value: br::VkResult) -> Self {
+        if value == br::vk::VK_ERROR_DEVICE_LOST {
+            Self::DeviceLost
+        } else {
+            Self::Vulkan(value)
+        }
+    }
+}
+impl std::fmt::Display for EngineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Vulkan(r) => write!(f, "vulkan error: {r:?}"),
+            Self::DeviceLost => f.write_str("device lost"),
+            Self::Io { path, source } => write!(f, "io error on {}: {source}", path.display()),
+            Self::InvalidAsset { path, reason } => {
+                write!(f, "invalid asset {}: {reason}", path.display())
+            }
+            Self::ShaderCompilation { path, diagnostic } => {
+                write!(f, "failed to compile {}:\n{diagnostic}", path.display())
+            }
+            Self::ShaderInterfaceMismatch(reason) => {
+                write!(f, "shader interface mismatch: {reason}")
+            }
+            Self::NoVulkanDevice => f.write_str("no vulkan devices"),
+            Self::NoSuitableAdapter => f.write_str("no suitable vulkan devices"),
+            Self::AdapterOverrideNotMatched(o) => {
+                write!(f, "no adapter matched to the override {o}")
+            }
+            Self::NoGraphicsQueue => f.write_str("no graphics queue family"),
+            Self::NoSuitableFormat => f.write_str("no suitable surface format"),
+            Self::NoSuitableMemoryType(purpose) => {
+                write!(f, "no suitable memory type for {purpose}")
+            }
+            Self::DynamicBufferExhausted => f.write_str("dynamic buffer exhausted"),
+            Self::NoPresentedFrame => f.write_str("no frames have been presented yet"),
+            Self::Unsupported(what) => write!(f, "unsupported: {what}"),
+            Self::NoWindowServer => f.write_str("no window server available"),
+            Self::MissingWindowServerInterface(name) => {
+                write!(f, "window server does not provide {name}")
+            }
+            Self::WindowSystem(what) => write!(f, "window system error: failed to {what}"),
+        }
+    }
+}
+impl std::error::Error for EngineError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -1,21 +1,417 @@
 use std::sync::Arc;
 
 use bedrock::{
-    self as br, CommandBufferMut, CommandPoolMut, DescriptorPoolMut, DeviceChild, DeviceMemory,
-    Fence, FenceMut, GraphicsPipelineBuilder, ImageSubresourceSlice, MemoryBound,
-    PipelineShaderStageProvider, QueueMut, RenderPass, SemaphoreMut, ShaderModule, Status,
-    Swapchain, VulkanStructure,
+    self as br, CommandBufferMut, CommandPoolMut, Fence, FenceMut, ImageSubresourceSlice,
+    PhysicalDevice, QueueMut, SemaphoreMut, Status, Swapchain, VkHandle, VulkanStructure,
 };
 use futures_util::FutureExt;
 
+use crate::{
+    asset::{
+        self,
+        gltf::GltfAsset,
+        texture::{TextureColorSpace, TextureData},
+    },
+    error::EngineError,
+    math::{std140_struct, Quat, Vec3, Vec4},
+    render::{
+        camera::{Camera, Projection, ViewUniform},
+        capture::CapturedImage,
+        depth::{self, DepthBuffer},
+        descriptor::DescriptorAllocator,
+        frame::FrameRing,
+        graph::{
+            BufferAccess, ImageAccess, ImageHandle, ImportedImage, RenderGraph, RenderGraphDesc,
+        },
+        headless::{HeadlessConfig, HeadlessTarget, OffscreenImage, OffscreenImageView},
+        material::{
+            BlendMode, CullMode, MaterialDesc, MaterialParameters, PipelineState, ShaderPair,
+        },
+        memory::{MemoryAllocator, MemoryLocation},
+        mesh::{MeshData, MeshVertex},
+        pipeline_cache::{PersistentPipelineCache, PipelineCacheIdentity},
+        scene_renderer::{
+            ObjectUniform, PipelineTarget, SceneRenderer, MATERIAL_SET, OBJECT_SET, VIEW_SET,
+        },
+        surface_format::{OutputColorSpace, OutputEncoding},
+        texture::{SamplerDesc, Texture},
+        upload::{UploadProcessor, UploadQueue, DEFAULT_STAGING_BUFFER_SIZE},
+    },
+    scene::{MeshInstance, Scene},
+    shader::ShaderLibrary,
+};
+
+pub type EngineSurface<Device> = br::SurfaceObject<<Device as br::Device>::ConcreteInstance>;
+pub type EngineSwapchain<'d, Device> =
+    br::SurfaceSwapchainObject<&'d Device, Arc<EngineSurface<Device>>>;
+pub type BackBufferView<'d, Device> =
+    br::ImageViewObject<br::SwapchainImage<Arc<EngineSwapchain<'d, Device>>>>;
+/// Framebuffer for a back buffer(and the depth buffer if enabled).
+pub type MainFramebuffer<'d, Device> =
+    br::FramebufferObject<&'d Device, MainAttachmentView<'d, Device>>;
+
+/// Attachment of the main framebuffers: a back buffer of either presentation target,
+/// or the depth buffer.
+pub enum MainAttachmentView<'d, Device: br::Device + ?Sized + 'd> {
+    Swapchain(BackBufferView<'d, Device>),
+    Offscreen(OffscreenImageView<'d, Device>),
+}
+impl<'d, Device: br::Device + ?Sized + 'd> br::VkHandle for MainAttachmentView<'d, Device> {
+    type Handle = br::vk::VkImageView;
+
+    fn native_ptr(&self) -> Self::Handle {
+        match self {
+            Self::Swapchain(x) => x.native_ptr(),
+            Self::Offscreen(x) => x.native_ptr(),
+        }
+    }
+}
+
+/// Image of a back buffer, kept for reading back its contents.
+enum BackBufferImage<'d, Device: br::Device + ?Sized + 'd> {
+    Swapchain(br::SwapchainImage<Arc<EngineSwapchain<'d, Device>>>),
+    Offscreen(Arc<OffscreenImage<'d, Device>>),
+}
+impl<'d, Device: br::Device + ?Sized + 'd> br::VkHandle for BackBufferImage<'d, Device> {
+    type Handle = br::vk::VkImage;
+
+    fn native_ptr(&self) -> Self::Handle {
+        match self {
+            Self::Swapchain(x) => x.native_ptr(),
+            Self::Offscreen(x) => x.native_ptr(),
+        }
+    }
+}
+
+/// Parameters used for (re)creating the swapchain.
+#[derive(Clone)]
+pub struct SwapchainConfig {
+    pub format: br::vk::VkSurfaceFormatKHR,
+    pub present_mode: br::PresentMode,
+    pub back_buffer_count: u32,
+    pub frames_in_flight: u32,
+}
+
+enum PresentationTarget<'d, Device: br::Device + ?Sized + 'd> {
+    Swapchain {
+        surface: Arc<EngineSurface<Device>>,
+        config: SwapchainConfig,
+        // Note: rebuild時に古いswapchainを先に破棄する必要があるのでOptionで持つ
+        swapchain: Option<Arc<EngineSwapchain<'d, Device>>>,
+    },
+    Headless(HeadlessTarget<'d, Device>),
+}
+
 pub struct Engine<'d, Device: br::Device + ?Sized + 'd> {
     pub graphics_queue_family_index: u32,
     pub q: br::QueueObject<&'d Device>,
-    pub swapchain:
-        Arc<br::SurfaceSwapchainObject<&'d Device, br::SurfaceObject<Device::ConcreteInstance>>>,
-    pub memory_properties: br::MemoryProperties,
+    device: &'d Device,
+    adapter: br::PhysicalDeviceObject<Device::ConcreteInstance>,
+    memory_allocator: MemoryAllocator<'d, Device>,
+    uploader: UploadProcessor<'d, Device>,
+    // Note: framebuffersがtargetのイメージを参照しているので先に破棄されるようにする
+    main_framebuffers: Vec<MainFramebuffer<'d, Device>>,
+    depth_buffer: Option<DepthBuffer<'d, Device>>,
+    depth_format: Option<br::vk::VkFormat>,
+    back_buffer_images: Vec<BackBufferImage<'d, Device>>,
+    target: PresentationTarget<'d, Device>,
+    swapchain_generation: u64,
+    back_buffer_size: br::vk::VkExtent2D,
+    main_render_pass: br::RenderPassObject<&'d Device>,
+    pipeline_cache: PersistentPipelineCache<'d, Device>,
 }
 impl<'d, Device: br::Device + ?Sized + 'd> Engine<'d, Device> {
+    pub fn new(
+        device: &'d Device,
+        adapter: br::PhysicalDeviceObject<Device::ConcreteInstance>,
+        graphics_queue_family_index: u32,
+        q: br::QueueObject<&'d Device>,
+        surface: EngineSurface<Device>,
+        swapchain_config: SwapchainConfig,
+        depth_format: Option<br::vk::VkFormat>,
+        initial_extent: br::vk::VkExtent2D,
+        memory_properties: br::MemoryProperties,
+    ) -> Result<Self, EngineError> {
+        let main_render_pass =
+            Self::create_main_render_pass(device, swapchain_config.format.format, depth_format)?;
+
+        Self::with_target(
+            device,
+            adapter,
+            graphics_queue_family_index,
+            q,
+            PresentationTarget::Swapchain {
+                surface: Arc::new(surface),
+                config: swapchain_config,
+                swapchain: None,
+            },
+            main_render_pass,
+            depth_format,
+            initial_extent,
+            memory_properties,
+        )
+    }
+
+    /// Creates an engine rendering into offscreen images instead of a swapchain.
+    ///
+    /// Back buffers are left in `TRANSFER_SRC_OPTIMAL` layout at the end of each frame.
+    pub fn new_headless(
+        device: &'d Device,
+        adapter: br::PhysicalDeviceObject<Device::ConcreteInstance>,
+        graphics_queue_family_index: u32,
+        q: br::QueueObject<&'d Device>,
+        config: HeadlessConfig,
+        depth_format: Option<br::vk::VkFormat>,
+        extent: br::vk::VkExtent2D,
+        memory_properties: br::MemoryProperties,
+    ) -> Result<Self, EngineError> {
+        let main_render_pass = Self::create_main_render_pass(device, config.format, depth_format)?;
+
+        Self::with_target(
+            device,
+            adapter,
+            graphics_queue_family_index,
+            q,
+            PresentationTarget::Headless(HeadlessTarget::new(config)),
+            main_render_pass,
+            depth_format,
+            extent,
+            memory_properties,
+        )
+    }
+
+    fn with_target(
+        device: &'d Device,
+        adapter: br::PhysicalDeviceObject<Device::ConcreteInstance>,
+        graphics_queue_family_index: u32,
+        q: br::QueueObject<&'d Device>,
+        target: PresentationTarget<'d, Device>,
+        main_render_pass: br::RenderPassObject<&'d Device>,
+        depth_format: Option<br::vk::VkFormat>,
+        initial_extent: br::vk::VkExtent2D,
+        memory_properties: br::MemoryProperties,
+    ) -> Result<Self, EngineError> {
+        let adapter_properties = adapter.properties();
+        let memory_allocator = MemoryAllocator::new(
+            device,
+            memory_properties,
+            adapter_properties.limits.bufferImageGranularity,
+        );
+        let uploader = UploadProcessor::new(
+            device,
+            graphics_queue_family_index,
+            memory_allocator.clone(),
+            DEFAULT_STAGING_BUFFER_SIZE,
+        )?;
+        let pipeline_cache = PersistentPipelineCache::load(
+            device,
+            PipelineCacheIdentity {
+                vendor_id: adapter_properties.vendorID,
+                device_id: adapter_properties.deviceID,
+                driver_version: adapter_properties.driverVersion,
+                uuid: adapter_properties.pipelineCacheUUID,
+            },
+        )?;
+
+        let mut this = Self {
+            graphics_queue_family_index,
+            q,
+            device,
+            adapter,
+            memory_allocator,
+            uploader,
+            main_framebuffers: Vec::new(),
+            depth_buffer: None,
+            depth_format,
+            back_buffer_images: Vec::new(),
+            target,
+            swapchain_generation: 0,
+            back_buffer_size: initial_extent,
+            main_render_pass,
+            pipeline_cache,
+        };
+        this.rebuild_swapchain(initial_extent)?;
+
+        Ok(this)
+    }
+
+    /// Attachments are kept in the attachment-optimal layouts: transitions from/to the presentation
+    /// layout are done by the render graph(see [`Self::import_back_buffer`]).
+    /// The depth attachment(if enabled) is cleared and discarded after the pass.
+    fn create_main_render_pass(
+        device: &'d Device,
+        format: br::vk::VkFormat,
+        depth_format: Option<br::vk::VkFormat>,
+    ) -> br::Result<br::RenderPassObject<&'d Device>> {
+        let mut attachments = vec![br::AttachmentDescription::new(
+            format,
+            br::ImageLayout::ColorAttachmentOpt,
+            br::ImageLayout::ColorAttachmentOpt,
+        )
+        .color_memory_op(br::LoadOp::Clear, br::StoreOp::Store)];
+        if let Some(depth_format) = depth_format {
+            let mut depth_attachment = br::AttachmentDescription::new(
+                depth_format,
+                br::ImageLayout::DepthStencilAttachmentOpt,
+                br::ImageLayout::DepthStencilAttachmentOpt,
+            )
+            .depth_memory_op(br::LoadOp::Clear, br::StoreOp::DontCare);
+            if depth::has_stencil(depth_format) {
+                depth_attachment =
+                    depth_attachment.stencil_memory_op(br::LoadOp::Clear, br::StoreOp::DontCare);
+            }
+            attachments.push(depth_attachment);
+        }
+        let depth_ref = br::AttachmentReference::new(1, br::ImageLayout::DepthStencilAttachmentOpt);
+
+        let mut subpass = br::SubpassDescription::new().color_attachments(
+            &[br::AttachmentReference::new(
+                0,
+                br::ImageLayout::ColorAttachmentOpt,
+            )],
+            &[],
+        );
+        if depth_format.is_some() {
+            subpass = subpass.depth_stencil(&depth_ref);
+        }
+
+        br::RenderPassBuilder::new(&attachments, &[subpass], &[]).create(device)
+    }
+
+    /// Recreates the swapchain(or offscreen images) and every resource depending on it
+    /// (back buffer views and main framebuffers).
+    ///
+    /// `requested_extent` is used only if the surface does not decide its own size.
+    /// If the resulting extent has zero area(e.g. minimized window), swapchain is left destroyed and
+    /// [`Self::is_presentable`] returns false until next successful rebuild.
+    pub fn rebuild_swapchain(
+        &mut self,
+        requested_extent: br::vk::VkExtent2D,
+    ) -> Result<(), EngineError> {
+        self.q.wait()?;
+
+        // back buffer images are referenced from the framebuffers: release them first
+        self.main_framebuffers.clear();
+        self.depth_buffer = None;
+        self.back_buffer_images.clear();
+
+        let (extent, color_views) = match &mut self.target {
+            PresentationTarget::Swapchain {
+                surface,
+                config,
+                swapchain,
+            } => {
+                *swapchain = None;
+
+                let surface_caps = self.adapter.surface_capabilities(&**surface)?;
+                let extent = swapchain_extent(&surface_caps, requested_extent);
+                if extent.width == 0 || extent.height == 0 {
+                    println!("swapchain rebuild deferred: zero-sized surface");
+                    return Ok(());
+                }
+
+                let new_swapchain = br::SwapchainBuilder::new(
+                    surface.clone(),
+                    config.back_buffer_count,
+                    config.format.clone(),
+                    extent,
+                    br::ImageUsageFlags::COLOR_ATTACHMENT,
+                )
+                .present_mode(config.present_mode)
+                .pre_transform(br::SurfaceTransform::Identity)
+                .composite_alpha(br::CompositeAlpha::Opaque)
+                .create(self.device)?;
+                let new_swapchain = Arc::new(new_swapchain);
+
+                let images = new_swapchain.get_images()?;
+                let views = images
+                    .iter()
+                    .map(|bb| {
+                        bb.clone_parent()
+                            .subresource_range(br::AspectMask::COLOR, 0..1, 0..1)
+                            .view_builder()
+                            .create()
+                            .map(MainAttachmentView::Swapchain)
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.back_buffer_images =
+                    images.into_iter().map(BackBufferImage::Swapchain).collect();
+                *swapchain = Some(new_swapchain);
+
+                (extent, views)
+            }
+            PresentationTarget::Headless(target) => {
+                if requested_extent.width == 0 || requested_extent.height == 0 {
+                    target.release();
+                    println!("offscreen rebuild deferred: zero-sized extent");
+                    return Ok(());
+                }
+
+                target.rebuild(&self.memory_allocator, requested_extent)?;
+                self.back_buffer_images = target
+                    .images()
+                    .iter()
+                    .cloned()
+                    .map(BackBufferImage::Offscreen)
+                    .collect();
+                let views = target
+                    .create_views()?
+                    .into_iter()
+                    .map(MainAttachmentView::Offscreen)
+                    .collect();
+
+                (requested_extent, views)
+            }
+        };
+
+        self.depth_buffer = self
+            .depth_format
+            .map(|f| DepthBuffer::new(&self.memory_allocator, f, extent))
+            .transpose()?;
+        self.main_framebuffers = color_views
+            .into_iter()
+            .map(|view| {
+                let mut builder =
+                    br::FramebufferBuilder::new_with_attachment(&self.main_render_pass, view);
+                if let Some(depth_buffer) = &self.depth_buffer {
+                    builder = builder.with_attachment(MainAttachmentView::Offscreen(
+                        depth_buffer.create_view()?,
+                    ));
+                }
+
+                builder.create()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        self.back_buffer_size = extent;
+        self.swapchain_generation += 1;
+
+        Ok(())
+    }
+
+    /// Rebuilds the swapchain with the current size(for recovering from `VK_ERROR_OUT_OF_DATE_KHR`).
+    pub fn refresh_swapchain(&mut self) -> Result<(), EngineError> {
+        self.rebuild_swapchain(self.back_buffer_size)
+    }
+
+    /// false while the surface has zero area
+    pub fn is_presentable(&self) -> bool {
+        match &self.target {
+            PresentationTarget::Swapchain { swapchain, .. } => swapchain.is_some(),
+            PresentationTarget::Headless(target) => !target.images().is_empty(),
+        }
+    }
+
+    /// true if rendering into offscreen images(no window server)
+    pub fn is_headless(&self) -> bool {
+        matches!(self.target, PresentationTarget::Headless(_))
+    }
+
+    /// Incremented on every successful swapchain rebuild.
+    /// Resources recorded against the back buffers should be recreated when this changes.
+    pub fn swapchain_generation(&self) -> u64 {
+        self.swapchain_generation
+    }
+
     pub fn command_pool_builder_for_graphics_works(&self) -> br::CommandPoolBuilder {
         br::CommandPoolBuilder::new(self.graphics_queue_family_index)
     }
@@ -38,604 +434,923 @@ impl<'d, Device: br::Device + ?Sized + 'd> Engine<'d, Device> {
         Ok(())
     }
 
+    /// Acquires next back buffer. `ready` is signaled when the back buffer becomes available.
+    pub fn acquire_next_back_buffer(
+        &mut self,
+        ready: &mut br::SemaphoreObject<&'d Device>,
+    ) -> br::Result<u32> {
+        match &mut self.target {
+            PresentationTarget::Swapchain { swapchain, .. } => swapchain
+                .as_ref()
+                .expect("swapchain is not available(surface has zero area)")
+                .acquire_next(
+                    None,
+                    br::CompletionHandlerMut::Queue(ready.as_transparent_mut_ref()),
+                ),
+            PresentationTarget::Headless(target) => {
+                let index = target.acquire_next();
+                // offscreen images are always available: just signal the semaphore in queue order
+                self.q.submit_alt3(
+                    &[br::SubmissionBatch3::new_wait_semaphore_array(
+                        &[],
+                        &[],
+                        &[],
+                        &[ready.as_transparent_ref()],
+                    )],
+                    None,
+                )?;
+
+                Ok(index)
+            }
+        }
+    }
+
+    /// Presents the back buffer after `wait` is signaled.
+    ///
+    /// Headless targets only consume the semaphore(contents are kept in the offscreen image).
     pub fn queue_present(
         &mut self,
         back_buffer_index: u32,
-        wait_semaphores: &[impl br::Transparent<Target = br::vk::VkSemaphore>],
+        wait: &br::SemaphoreObject<&'d Device>,
     ) -> br::Result<()> {
-        br::PresentInfo::new(
-            wait_semaphores,
-            &[self.swapchain.as_transparent_ref()],
-            &[back_buffer_index],
-        )
-        .submit(&mut self.q)
-        .map(drop)
+        match &self.target {
+            PresentationTarget::Swapchain { swapchain, .. } => {
+                let swapchain = swapchain
+                    .as_ref()
+                    .expect("swapchain is not available(surface has zero area)");
+
+                br::PresentInfo::new(
+                    &[wait.as_transparent_ref()],
+                    &[swapchain.as_transparent_ref()],
+                    &[back_buffer_index],
+                )
+                .submit(&mut self.q)
+                .map(drop)
+            }
+            PresentationTarget::Headless(_) => self.q.submit_alt3(
+                &[br::SubmissionBatch3::new_wait_semaphore_array(
+                    &[wait.as_transparent_ref()],
+                    &[br::PipelineStageFlags::ALL_COMMANDS],
+                    &[],
+                    &[],
+                )],
+                None,
+            ),
+        }
     }
 
     pub fn device(&self) -> &'d Device {
-        *self.swapchain.device()
+        self.device
+    }
+
+    fn back_buffer_surface_format(&self) -> br::vk::VkSurfaceFormatKHR {
+        match &self.target {
+            PresentationTarget::Swapchain { config, .. } => config.format.clone(),
+            PresentationTarget::Headless(target) => br::vk::VkSurfaceFormatKHR {
+                format: target.config().format,
+                colorSpace: br::vk::VK_COLOR_SPACE_SRGB_NONLINEAR_KHR,
+            },
+        }
     }
 
     pub fn back_buffer_format(&self) -> br::vk::VkFormat {
-        self.swapchain.format()
+        self.back_buffer_surface_format().format
+    }
+
+    pub fn back_buffer_color_space(&self) -> OutputColorSpace {
+        OutputColorSpace::from_vk(self.back_buffer_surface_format().colorSpace)
+    }
+
+    /// How shaders(and clear colors) have to encode colors written to the back buffer
+    pub fn output_encoding(&self) -> OutputEncoding {
+        OutputEncoding::new(self.back_buffer_color_space(), self.back_buffer_format())
+    }
+
+    /// Converts a linear sRGB color into the value to be written to the back buffer
+    /// (e.g. clear colors). Alpha is kept as is.
+    pub fn encode_output_color(&self, linear: [f32; 4]) -> [f32; 4] {
+        self.output_encoding().encode(linear)
+    }
+
+    pub fn back_buffer_size(&self) -> br::vk::VkExtent2D {
+        self.back_buffer_size
+    }
+
+    pub fn main_render_pass(&self) -> &br::RenderPassObject<&'d Device> {
+        &self.main_render_pass
+    }
+
+    /// Pipelines rendering in the main render pass
+    pub fn main_pipeline_target(&self) -> PipelineTarget<'_, 'd, Device> {
+        PipelineTarget {
+            render_pass: &self.main_render_pass,
+            subpass: 0,
+            depth: self.depth_format.is_some(),
+            cache: Some(self.pipeline_cache.object()),
+        }
+    }
+
+    /// Shared by every pipeline creation, so that compiled pipelines are reused across launches.
+    pub fn pipeline_cache(&self) -> &br::PipelineCacheObject<&'d Device> {
+        self.pipeline_cache.object()
+    }
+
+    /// Writes the pipeline cache back to the cache directory.
+    pub fn save_pipeline_cache(&self) -> Result<(), EngineError> {
+        self.pipeline_cache.save()
+    }
+
+    pub fn main_framebuffers(&self) -> &[MainFramebuffer<'d, Device>] {
+        &self.main_framebuffers
+    }
+
+    /// Imports the acquired back buffer into the render graph of the frame.
+    ///
+    /// The first access waits for `COLOR_ATTACHMENT_OUTPUT`(the wait stage of the acquisition semaphore)
+    /// and the image is transitioned into [`Self::back_buffer_final_layout`] at the end.
+    pub fn import_back_buffer(
+        &self,
+        graph: &mut RenderGraphDesc,
+        back_buffer_index: u32,
+    ) -> ImageHandle {
+        graph.import_image(ImportedImage {
+            image: self.back_buffer_images[back_buffer_index as usize].native_ptr(),
+            format: self.back_buffer_format(),
+            extent: self.back_buffer_size,
+            aspect: br::vk::VK_IMAGE_ASPECT_COLOR_BIT,
+            initial_layout: br::ImageLayout::Undefined,
+            initial_stages: br::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            // availability of the previous writes is guaranteed by the acquisition
+            initial_access: 0,
+            final_layout: Some(self.back_buffer_final_layout()),
+        })
+    }
+
+    /// Format of the depth attachment of the main render pass. None if the depth buffer is disabled
+    pub fn depth_format(&self) -> Option<br::vk::VkFormat> {
+        self.depth_format
+    }
+
+    /// Imports the depth buffer into the render graph of the frame, if enabled.
+    /// Should be used as [`ImageAccess::ExternalDepthAttachment`] of the main render pass.
+    pub fn import_depth_buffer(&self, graph: &mut RenderGraphDesc) -> Option<ImageHandle> {
+        self.depth_buffer.as_ref().map(|d| d.import(graph))
+    }
+
+    /// Creates caches for compiling render graphs, sized for the frames in flight.
+    pub fn create_render_graph(&self) -> RenderGraph<'d, Device> {
+        RenderGraph::new(
+            self.device,
+            self.memory_allocator.clone(),
+            self.frames_in_flight(),
+        )
+    }
+
+    /// Layout of the back buffers at the end of each frame.
+    pub fn back_buffer_final_layout(&self) -> br::ImageLayout {
+        match self.target {
+            PresentationTarget::Swapchain { .. } => br::ImageLayout::PresentSrc,
+            PresentationTarget::Headless(_) => br::ImageLayout::TransferSrcOpt,
+        }
+    }
+
+    /// Reads back the contents of the back buffer, after waiting for every submitted work.
+    ///
+    /// Fails with [`EngineError::Unsupported`] if the back buffer format cannot be converted into RGBA8,
+    /// or the back buffer is a swapchain image(presented images are owned by the presentation
+    /// engine until they are acquired again).
+    pub fn capture_back_buffer(
+        &mut self,
+        back_buffer_index: u32,
+    ) -> Result<CapturedImage, EngineError> {
+        let format = self.back_buffer_format();
+        let Some(texel_size) = CapturedImage::texel_size(format) else {
+            return Err(EngineError::Unsupported(
+                "capturing back buffers of this format",
+            ));
+        };
+        if !self.is_headless() {
+            return Err(EngineError::Unsupported(
+                "capturing presented swapchain images",
+            ));
+        }
+        let image = &self.back_buffer_images[back_buffer_index as usize];
+        let extent = self.back_buffer_size;
+        let size = extent.width as usize * extent.height as usize * texel_size;
+
+        let buffer = self.memory_allocator.create_buffer(
+            br::BufferDesc::new(size, br::BufferUsage::TRANSFER_DEST),
+            MemoryLocation::HostVisible,
+        )?;
+
+        let final_layout = self.back_buffer_final_layout();
+        let color_range = br::vk::VkImageSubresourceRange {
+            aspectMask: br::vk::VK_IMAGE_ASPECT_COLOR_BIT,
+            baseMipLevel: 0,
+            levelCount: 1,
+            baseArrayLayer: 0,
+            layerCount: 1,
+        };
+        let mut cp = self
+            .command_pool_builder_for_graphics_works()
+            .transient()
+            .create(self.device)?;
+        let [mut cb] = cp.alloc_array::<1>(true)?;
+        unsafe { cb.begin_once(self.device)? }
+            .pipeline_barrier(
+                br::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                br::PipelineStageFlags::TRANSFER,
+                false,
+                &[],
+                &[],
+                &[br::vk::VkImageMemoryBarrier {
+                    sType: br::vk::VkImageMemoryBarrier::TYPE,
+                    pNext: core::ptr::null(),
+                    srcAccessMask: br::AccessFlags::COLOR_ATTACHMENT.write,
+                    dstAccessMask: br::AccessFlags::TRANSFER.read,
+                    oldLayout: final_layout as _,
+                    newLayout: br::ImageLayout::TransferSrcOpt as _,
+                    srcQueueFamilyIndex: br::vk::VK_QUEUE_FAMILY_IGNORED,
+                    dstQueueFamilyIndex: br::vk::VK_QUEUE_FAMILY_IGNORED,
+                    image: image.native_ptr(),
+                    subresourceRange: color_range.clone(),
+                }],
+            )
+            .copy_image_to_buffer(
+                image,
+                br::ImageLayout::TransferSrcOpt,
+                buffer.buffer(),
+                &[br::vk::VkBufferImageCopy {
+                    bufferOffset: 0,
+                    // tightly packed
+                    bufferRowLength: 0,
+                    bufferImageHeight: 0,
+                    imageSubresource: br::vk::VkImageSubresourceLayers {
+                        aspectMask: br::vk::VK_IMAGE_ASPECT_COLOR_BIT,
+                        mipLevel: 0,
+                        baseArrayLayer: 0,
+                        layerCount: 1,
+                    },
+                    imageOffset: br::vk::VkOffset3D { x: 0, y: 0, z: 0 },
+                    imageExtent: br::vk::VkExtent3D {
+                        width: extent.width,
+                        height: extent.height,
+                        depth: 1,
+                    },
+                }],
+            )
+            .pipeline_barrier(
+                br::PipelineStageFlags::TRANSFER,
+                br::PipelineStageFlags::HOST,
+                false,
+                &[br::vk::VkMemoryBarrier {
+                    sType: br::vk::VkMemoryBarrier::TYPE,
+                    pNext: core::ptr::null(),
+                    srcAccessMask: br::AccessFlags::TRANSFER.write,
+                    dstAccessMask: br::AccessFlags::HOST.read,
+                }],
+                &[],
+                &[br::vk::VkImageMemoryBarrier {
+                    sType: br::vk::VkImageMemoryBarrier::TYPE,
+                    pNext: core::ptr::null(),
+                    srcAccessMask: br::AccessFlags::TRANSFER.read,
+                    dstAccessMask: 0,
+                    oldLayout: br::ImageLayout::TransferSrcOpt as _,
+                    newLayout: final_layout as _,
+                    srcQueueFamilyIndex: br::vk::VK_QUEUE_FAMILY_IGNORED,
+                    dstQueueFamilyIndex: br::vk::VK_QUEUE_FAMILY_IGNORED,
+                    image: image.native_ptr(),
+                    subresourceRange: color_range,
+                }],
+            )
+            .end()?;
+        // also waits for the rendering of the back buffer
+        self.submit_graphics_work_and_wait(&[br::SubmissionBatch3::new_wait_semaphore_array(
+            &[],
+            &[],
+            &[cb.as_transparent_ref()],
+            &[],
+        )])?;
+
+        let texels = unsafe { buffer.read_mapped_bytes(0..size) };
+
+        CapturedImage::from_texels(format, extent.width, extent.height, texels).ok_or(
+            EngineError::Unsupported("capturing back buffers of this format"),
+        )
     }
 
-    pub fn back_buffers<'s>(
-        &'s self,
-    ) -> br::Result<
-        Vec<
-            br::SwapchainImage<
-                &'s Arc<
-                    br::SurfaceSwapchainObject<
-                        &'d Device,
-                        br::SurfaceObject<Device::ConcreteInstance>,
-                    >,
-                >,
-            >,
-        >,
-    > {
-        self.swapchain.get_images()
+    /// Allocator for buffers and images used by the game.
+    pub fn memory_allocator(&self) -> &MemoryAllocator<'d, Device> {
+        &self.memory_allocator
     }
 
-    pub fn find_matching_device_local_memory_index(&self, index_mask: u32) -> Option<u32> {
-        self.memory_properties.find_device_local_index(index_mask)
+    /// Depth of the frames-in-flight ring requested by the presentation config
+    pub fn frames_in_flight(&self) -> u32 {
+        match &self.target {
+            PresentationTarget::Swapchain { config, .. } => config.frames_in_flight,
+            PresentationTarget::Headless(target) => target.config().frames_in_flight,
+        }
     }
 
-    pub fn find_matching_host_visible_memory_index(&self, index_mask: u32) -> Option<u32> {
-        self.memory_properties.find_host_visible_index(index_mask)
+    /// Creates a frames-in-flight ring with the configured depth.
+    /// Each frame can use `dynamic_buffer_size` bytes of per-draw uniform/storage data.
+    pub fn create_frame_ring(
+        &self,
+        dynamic_buffer_size: u64,
+    ) -> Result<FrameRing<'d, Device>, EngineError> {
+        let limits = self.adapter.properties().limits;
+
+        FrameRing::new(
+            self.device,
+            self.graphics_queue_family_index,
+            &self.memory_allocator,
+            self.frames_in_flight(),
+            dynamic_buffer_size,
+            limits
+                .minUniformBufferOffsetAlignment
+                .max(limits.minStorageBufferOffsetAlignment),
+        )
+    }
+
+    /// Request side of the upload service. Clone it to request uploads from other tasks.
+    pub fn upload_queue(&self) -> &UploadQueue<'d, Device> {
+        self.uploader.queue()
+    }
+
+    /// Creates a sampled texture and requests uploads of the texels(visible after the next
+    /// [`Self::flush_uploads`]). Mip chains are generated only if the format supports linear blits.
+    pub fn create_texture(&self, data: TextureData) -> Result<Texture<'d, Device>, EngineError> {
+        let features = self
+            .adapter
+            .format_properties(data.format)
+            .optimalTilingFeatures;
+        if (features & br::vk::VK_FORMAT_FEATURE_SAMPLED_IMAGE_BIT) == 0 {
+            return Err(EngineError::Unsupported("sampling images of this format"));
+        }
+        let blit_features = br::vk::VK_FORMAT_FEATURE_BLIT_SRC_BIT
+            | br::vk::VK_FORMAT_FEATURE_BLIT_DST_BIT
+            | br::vk::VK_FORMAT_FEATURE_SAMPLED_IMAGE_FILTER_LINEAR_BIT;
+
+        Texture::new(
+            &self.memory_allocator,
+            self.upload_queue(),
+            data,
+            (features & blit_features) == blit_features,
+        )
+    }
+
+    /// Submits pending uploads in one batch(without waiting) and retires completed ones.
+    ///
+    /// Later submissions on the graphics queue observe the uploaded contents.
+    pub fn flush_uploads(&mut self) -> Result<(), EngineError> {
+        self.uploader.flush(&mut self.q)
     }
 }
 
+/// Decides swapchain extent from the surface capabilities.
+/// `0xffff_ffff` in currentExtent means the surface size is determined by the swapchain.
+fn swapchain_extent(
+    caps: &br::vk::VkSurfaceCapabilitiesKHR,
+    requested: br::vk::VkExtent2D,
+) -> br::vk::VkExtent2D {
+    if caps.currentExtent.width != 0xffff_ffff && caps.currentExtent.height != 0xffff_ffff {
+        return caps.currentExtent;
+    }
+
+    br::vk::VkExtent2D {
+        width: requested
+            .width
+            .clamp(caps.minImageExtent.width, caps.maxImageExtent.width),
+        height: requested
+            .height
+            .clamp(caps.minImageExtent.height, caps.maxImageExtent.height),
+    }
+}
+
+/// Window states reported along with resize events.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WindowState {
+    Maximized,
+    Fullscreen,
+    /// Being resized interactively
+    Resizing,
+    Activated,
+    TiledLeft,
+    TiledRight,
+    TiledTop,
+    TiledBottom,
+    /// Not visible to the user(e.g. minimized or occluded)
+    Suspended,
+}
+
 pub enum EngineEvents {
     Shutdown,
+    /// Window content area has been resized.
+    Resized {
+        width: u32,
+        height: u32,
+        states: Vec<WindowState>,
+    },
+    /// Requests the contents of the last presented frame.
+    /// Replies [`EngineError::NoPresentedFrame`] if no frames have been presented since last swapchain rebuild.
+    CaptureLastFrame(async_std::channel::Sender<Result<CapturedImage, EngineError>>),
 }
 
-#[repr(C)]
-#[derive(Clone)]
-pub struct Vertex {
-    pub pos: [f32; 4],
-    pub col: [f32; 4],
+/// A request for rendering next frame, emitted by platform-specific frame pacing sources
+/// (e.g. `wl_surface.frame`, CVDisplayLink or busy loop).
+#[derive(Clone, Copy, Debug)]
+pub struct FrameRequest {
+    /// Presentation timestamp of the frame, if the source provides it.
+    /// Only differences between timestamps are meaningful(the base is platform-defined).
+    pub timestamp: Option<std::time::Duration>,
 }
 
-#[repr(C)]
+pub type FrameRequestReceiver = async_std::channel::Receiver<FrameRequest>;
+
+/// Sending side of the frame request bus.
+///
+/// Requests are coalesced: if previous request has not been consumed yet, new request is dropped.
 #[derive(Clone)]
-pub struct UniformData {
-    pub object_matrix: [f32; 16],
+pub struct FrameRequestSender(async_std::channel::Sender<FrameRequest>);
+impl FrameRequestSender {
+    /// Returns false if the receiver(game loop) has gone.
+    pub fn request(&self, timestamp: Option<std::time::Duration>) -> bool {
+        match self.0.try_send(FrameRequest { timestamp }) {
+            Ok(_) | Err(async_std::channel::TrySendError::Full(_)) => true,
+            Err(async_std::channel::TrySendError::Closed(_)) => false,
+        }
+    }
+
+    /// Sends a request without coalescing(waits until the previous one is consumed).
+    /// Returns false if the receiver(game loop) has gone.
+    pub async fn send(&self, request: FrameRequest) -> bool {
+        self.0.send(request).await.is_ok()
+    }
+
+    /// Waits until the pending request is taken by the game loop.
+    pub async fn wait_consumed(&self) {
+        while !self.0.is_empty() && !self.0.is_closed() {
+            async_std::task::yield_now().await;
+        }
+    }
+}
+
+pub fn frame_request_bus() -> (FrameRequestSender, FrameRequestReceiver) {
+    let (sender, receiver) = async_std::channel::bounded(1);
+
+    (FrameRequestSender(sender), receiver)
+}
+
+std140_struct! {
+    /// Parameter block of the sample material(`test.frag`).
+    struct TintParameters {
+        /// Multiplied to the texture color(premultiplied)
+        tint: Vec4,
+    }
 }
 
 pub async fn game_main<'d, Device: br::Device + 'd>(
     mut engine: Engine<'d, Device>,
     event_bus: async_std::channel::Receiver<EngineEvents>,
-    frame_request_bus: async_std::channel::Receiver<()>,
-) {
+    frame_request_bus: FrameRequestReceiver,
+) -> Result<(), EngineError> {
     println!("mainloop ready");
 
-    let render_pass = {
-        let main_attachment = br::AttachmentDescription::new(
-            engine.back_buffer_format(),
-            br::ImageLayout::Undefined,
-            br::ImageLayout::PresentSrc,
-        )
-        .color_memory_op(br::LoadOp::Clear, br::StoreOp::Store);
-        let enter_dependency = br::vk::VkSubpassDependency {
-            srcSubpass: br::vk::VK_SUBPASS_EXTERNAL,
-            dstSubpass: 0,
-            srcStageMask: br::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT.0,
-            dstStageMask: br::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT.0,
-            srcAccessMask: 0,
-            dstAccessMask: br::AccessFlags::COLOR_ATTACHMENT.write,
-            dependencyFlags: br::vk::VK_DEPENDENCY_BY_REGION_BIT,
-        };
-        let leave_dependency = br::vk::VkSubpassDependency {
-            srcSubpass: 0,
-            dstSubpass: br::vk::VK_SUBPASS_EXTERNAL,
-            srcStageMask: br::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT.0,
-            dstStageMask: br::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT.0,
-            srcAccessMask: br::AccessFlags::COLOR_ATTACHMENT.write,
-            dstAccessMask: 0,
-            dependencyFlags: br::vk::VK_DEPENDENCY_BY_REGION_BIT,
-        };
-
-        br::RenderPassBuilder::new(
-            &[main_attachment],
-            &[br::SubpassDescription::new().color_attachments(
-                &[br::AttachmentReference::new(
-                    0,
-                    br::ImageLayout::ColorAttachmentOpt,
-                )],
-                &[],
-            )],
-            &[enter_dependency, leave_dependency],
-        )
-        .create(engine.device())
-        .expect("Failed to create render pass")
+    let quad = MeshData {
+        vertices: [
+            ([-1.0, 1.0], [0.0, 0.0]),
+            ([1.0, 1.0], [1.0, 0.0]),
+            ([-1.0, -1.0], [0.0, 1.0]),
+            ([1.0, -1.0], [1.0, 1.0]),
+        ]
+        .map(|([x, y], uv)| MeshVertex {
+            position: [x * 0.8, y * 0.8, 0.0],
+            normal: [0.0, 0.0, 1.0],
+            uv,
+            tangent: [1.0, 0.0, 0.0, 1.0],
+        })
+        .to_vec(),
+        indices: Some(vec![0, 2, 1, 1, 2, 3]),
     };
+    let mut scene_renderer = SceneRenderer::new();
+    let quad_mesh =
+        scene_renderer.add_mesh(quad.upload(engine.memory_allocator(), engine.upload_queue())?);
+    let model = GltfAsset::load("models/pyramid.gltf")?;
+    let model_meshes = model.upload_meshes(
+        engine.memory_allocator(),
+        engine.upload_queue(),
+        &mut scene_renderer,
+    )?;
+    let texture = engine.create_texture(TextureData::load(
+        "textures/checker.png",
+        TextureColorSpace::Srgb,
+    )?)?;
+    let sampler = SamplerDesc::default().create(engine.device())?;
+    // Note: 完了を待たなくても後続のsubmitから見えるのでcompletionは捨てる
+    engine.flush_uploads()?;
+    for h in engine.memory_allocator().heap_stats() {
+        if h.block_count == 0 {
+            continue;
+        }
 
-    let back_buffers = engine
-        .back_buffers()
-        .expect("Failed to acquire back buffer resources");
-    let framebuffers = back_buffers
-        .into_iter()
-        .map(|bb| {
-            let view = bb
-                .clone_parent()
-                .subresource_range(br::AspectMask::COLOR, 0..1, 0..1)
-                .view_builder()
-                .create()?;
-            br::FramebufferBuilder::new_with_attachment(&render_pass, view).create()
-        })
-        .collect::<Result<Vec<_>, _>>()
-        .expect("Failed to create main framebuffers");
-    let back_buffer_size = framebuffers[0].size().clone();
-
-    let full_scissor_rect = back_buffer_size.into_rect(br::vk::VkOffset2D::ZERO);
-    let full_viewport = full_scissor_rect.make_viewport(0.0..1.0);
-
-    #[repr(C)]
-    #[derive(Clone)]
-    struct BufferInitializationContents {
-        pub vertices: [Vertex; 3],
-        pub uniform: UniformData,
-    }
-    let mut vertex_buffer = br::BufferDesc::new(
-        core::mem::size_of::<[Vertex; 3]>(),
-        br::BufferUsage::VERTEX_BUFFER.transfer_dest(),
-    )
-    .create(engine.device())
-    .expect("Failed to create vertex buffer");
-    let mut uniform_buffer = br::BufferDesc::new(
-        core::mem::size_of::<UniformData>(),
-        br::BufferUsage::UNIFORM_BUFFER.transfer_dest(),
-    )
-    .create(engine.device())
-    .expect("Failed to create uniform buffer");
-    let vertex_buffer_requirements = vertex_buffer.requirements();
-    let uniform_buffer_requirements = uniform_buffer.requirements();
-    let buffer_memory_index = engine
-        .find_matching_device_local_memory_index(
-            vertex_buffer_requirements.memoryTypeBits & uniform_buffer_requirements.memoryTypeBits,
-        )
-        .expect("No suitable memory index for device local buffer");
-    let uniform_buffer_offset = (vertex_buffer_requirements.size
-        + (uniform_buffer_requirements.alignment - 1))
-        & !(uniform_buffer_requirements.alignment - 1);
-    let buffer_memory = br::DeviceMemoryRequest::allocate(
-        (uniform_buffer_offset + uniform_buffer_requirements.size) as _,
-        buffer_memory_index,
-    )
-    .execute(engine.device())
-    .expect("Failed to allocate device local memory");
-    vertex_buffer
-        .bind(&buffer_memory, 0)
-        .expect("Failed to bind vertex buffer memory");
-    uniform_buffer
-        .bind(&buffer_memory, uniform_buffer_offset as _)
-        .expect("Failed to bind uniform buffer memory");
-    let mut upload_buffer = br::BufferDesc::new(
-        core::mem::size_of::<BufferInitializationContents>(),
-        br::BufferUsage::TRANSFER_SRC,
-    )
-    .create(engine.device())
-    .expect("Failed to create upload buffer");
-    let upload_buffer_requirements = upload_buffer.requirements();
-    let upload_memory_index = engine
-        .find_matching_host_visible_memory_index(upload_buffer_requirements.memoryTypeBits)
-        .expect("no suitable memory index for upload");
-    let mut upload_buffer_memory = br::DeviceMemoryRequest::allocate(
-        upload_buffer_requirements.size as _,
-        upload_memory_index,
-    )
-    .execute(engine.device())
-    .expect("Failed to allocate upload memory");
-    upload_buffer
-        .bind(&upload_buffer_memory, 0)
-        .expect("Failed to bind upload buffer memory");
-    unsafe {
-        let ptr = upload_buffer_memory
-            .map(0..core::mem::size_of::<BufferInitializationContents>())
-            .expect("Failed to map upload memory");
-        ptr.clone_at(
-            0,
-            &BufferInitializationContents {
-                vertices: [
-                    Vertex {
-                        pos: [
-                            100.0 * 0.0f32.to_radians().cos(),
-                            100.0 * 0.0f32.to_radians().sin(),
-                            0.5,
-                            1.0,
-                        ],
-                        col: [1.0, 1.0, 1.0, 1.0],
-                    },
-                    Vertex {
-                        pos: [
-                            100.0 * 120.0f32.to_radians().cos(),
-                            100.0 * 120.0f32.to_radians().sin(),
-                            0.5,
-                            1.0,
-                        ],
-                        col: [1.0, 1.0, 1.0, 1.0],
-                    },
-                    Vertex {
-                        pos: [
-                            100.0 * 240.0f32.to_radians().cos(),
-                            100.0 * 240.0f32.to_radians().sin(),
-                            0.5,
-                            1.0,
-                        ],
-                        col: [1.0, 1.0, 1.0, 1.0],
-                    },
-                ],
-                uniform: UniformData {
-                    object_matrix: [
-                        1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0,
-                        1.0,
-                    ],
-                },
-            },
+        println!(
+            "memory heap {}{}: {} blocks, {} bytes in {} allocations ({:.1}% of the heap)",
+            h.heap_index,
+            if h.device_local { "(device local)" } else { "" },
+            h.block_count,
+            h.allocated_bytes,
+            h.allocation_count,
+            h.budget_usage() * 100.0,
         );
-        upload_buffer_memory.unmap();
-    }
-
-    let mut tmp_cp = engine
-        .command_pool_builder_for_graphics_works()
-        .transient()
-        .create(engine.device())
-        .expect("Failed to create temporary cb pool");
-    let [mut tmp_cb] = tmp_cp
-        .alloc_array::<1>(true)
-        .expect("Failed to create temporary cb");
-    unsafe {
-        tmp_cb
-            .begin_once(engine.device())
-            .expect("Failed to begin temporary cb")
-    }
-    .copy_buffer(
-        &upload_buffer,
-        &vertex_buffer,
-        &[br::BufferCopy::copy_data::<[Vertex; 3]>(
-            core::mem::offset_of!(BufferInitializationContents, vertices) as _,
-            0,
-        )],
-    )
-    .copy_buffer(
-        &upload_buffer,
-        &uniform_buffer,
-        &[br::BufferCopy::copy_data::<UniformData>(
-            core::mem::offset_of!(BufferInitializationContents, uniform) as _,
-            0,
-        )],
-    )
-    .pipeline_barrier(
-        br::PipelineStageFlags::TRANSFER,
-        br::PipelineStageFlags::VERTEX_INPUT.vertex_shader(),
-        false,
-        &[br::vk::VkMemoryBarrier {
-            sType: br::vk::VkMemoryBarrier::TYPE,
-            pNext: core::ptr::null(),
-            srcAccessMask: br::AccessFlags::TRANSFER.write,
-            dstAccessMask: br::AccessFlags::VERTEX_ATTRIBUTE_READ | br::AccessFlags::UNIFORM_READ,
-        }],
-        &[],
-        &[],
-    )
-    .end()
-    .expect("Failed to record init commands");
-    engine
-        .submit_graphics_work_and_wait(&[br::SubmissionBatch3::new_wait_semaphore_array(
-            &[],
-            &[],
-            &[tmp_cb.as_transparent_ref()],
-            &[],
-        )])
-        .expect("Failed to submit init commands");
-    drop(tmp_cp);
-    drop(upload_buffer);
-
-    let vert_shader_blob =
-        std::fs::read("assets/shaders/test.vspv").expect("Failed to read vertex shader blob");
-    let frag_shader_blob =
-        std::fs::read("assets/shaders/test.fspv").expect("Failed to read fragment shader blob");
-    let vert_shader = engine
-        .device()
-        .new_shader_module_ref(&vert_shader_blob)
-        .expect("Failed to create vert shader module");
-    let frag_shader = engine
-        .device()
-        .new_shader_module_ref(&frag_shader_blob)
-        .expect("Failed to create frag shader module");
-    let dsl_ub1 = br::DescriptorSetLayoutBuilder::new(&[br::DescriptorType::UniformBuffer
-        .make_binding(0, 1)
-        .only_for_vertex()])
-    .create(engine.device())
-    .expect("Failed to create descriptor set layout");
-    let pl = br::PipelineLayoutBuilder::new(
-        &[br::DescriptorSetLayoutObjectRef::new(&dsl_ub1)],
-        &[br::PushConstantRange::for_type::<[f32; 2]>(
-            br::ShaderStage::VERTEX,
-            0,
-        )],
-    )
-    .create(engine.device())
-    .expect("Failed to create pipellne layout");
-    let vbind = [br::VertexInputBindingDescription::per_vertex_typed::<Vertex>(0)];
-    let vattr = [
-        br::vk::VkVertexInputAttributeDescription {
-            location: 0,
-            binding: 0,
-            format: br::vk::VK_FORMAT_R32G32B32A32_SFLOAT,
-            offset: core::mem::offset_of!(Vertex, pos) as _,
-        },
-        br::vk::VkVertexInputAttributeDescription {
-            location: 1,
-            binding: 0,
-            format: br::vk::VK_FORMAT_R32G32B32A32_SFLOAT,
-            offset: core::mem::offset_of!(Vertex, col) as _,
-        },
-    ];
-    let pipeline = {
-        let mut builder = br::NonDerivedGraphicsPipelineBuilder::new(
-            &pl,
-            render_pass.subpass(0),
-            br::VertexProcessingStages::new(
-                br::VertexShaderStage::new(vert_shader.with_entry_point(c"main"))
-                    .with_fragment_shader_stage(frag_shader.with_entry_point(c"main")),
-                &vbind,
-                &vattr,
-                br::vk::VK_PRIMITIVE_TOPOLOGY_TRIANGLE_LIST,
-            ),
-        );
-        builder
-            .viewport_scissors(
-                br::DynamicArrayState::Static(&[full_viewport]),
-                br::DynamicArrayState::Static(&[full_scissor_rect]),
-            )
-            .add_attachment_blend(br::AttachmentColorBlendState::premultiplied())
-            .multisample_state(Some(br::MultisampleState::new()));
+    }
 
-        builder
-            .create(engine.device(), None::<&br::PipelineCacheObject<Device>>)
-            .expect("Failed to create render pipeline")
+    let mut shaders = ShaderLibrary::new();
+    shaders.load("test.vert")?;
+    shaders.load("test.frag")?;
+    let textured_state = PipelineState {
+        // the quad is visible from both sides while spinning
+        cull: CullMode::None,
+        blend: BlendMode::Premultiplied,
+        ..PipelineState::new(ShaderPair::new("test.vert", "test.frag"))
     };
+    let mut frames = engine.create_frame_ring(64 << 10)?;
+    let mut render_commands = Vec::with_capacity(frames.depth() as _);
+    for f in frames.frames_mut() {
+        let [cb] = f.command_pool_mut().alloc_array::<1>(true)?;
+        render_commands.push(cb);
+    }
 
-    let mut descriptor_pool =
-        br::DescriptorPoolBuilder::new(1, &[br::DescriptorType::UniformBuffer.make_size(1)])
-            .create(engine.device())
-            .expect("Failed to create descriptor pool");
-    let [object_descriptor] = descriptor_pool
-        .alloc_array(&[br::DescriptorSetLayoutObjectRef::new(&dsl_ub1)])
-        .expect("Failed to allocate descriptor set");
+    let mut descriptors = DescriptorAllocator::new(engine.device());
+    let textured_layout =
+        scene_renderer.shader_layout(&mut descriptors, &shaders, &textured_state.shaders)?;
+    // view and object sets are compatible across the shader pairs(same interface)
+    let view_signature = textured_layout.set_signature(VIEW_SET).clone();
+    let object_signature = textured_layout.set_signature(OBJECT_SET).clone();
+    // shared by the materials: parameters are selected by the dynamic offset
+    let texture_descriptor = descriptors.allocate(textured_layout.set_signature(MATERIAL_SET))?;
+    let dynamic_buffer = frames
+        .frames_mut()
+        .next()
+        .expect("no frames")
+        .dynamic_buffer_mut()
+        .buffer();
     engine.device().update_descriptor_sets(
-        &[object_descriptor
-            .binding_at(0)
-            .write(br::DescriptorContents::uniform_buffer(
-                &uniform_buffer,
-                0..core::mem::size_of::<UniformData>() as u64,
-            ))],
+        &[
+            texture_descriptor
+                .binding_at(0)
+                .write(br::DescriptorContents::combined_image_sampler(
+                    &sampler,
+                    texture.view(),
+                    br::ImageLayout::ShaderReadOnlyOpt,
+                )),
+            texture_descriptor
+                .binding_at(1)
+                .write(br::DescriptorContents::uniform_buffer_dynamic(
+                    dynamic_buffer,
+                    0..core::mem::size_of::<TintParameters>() as u64,
+                )),
+        ],
         &[],
     );
 
-    let mut cp = engine
-        .command_pool_builder_for_graphics_works()
-        .create(engine.device())
-        .expect("Failed to create command pool");
-    let mut render_cb = cp
-        .alloc(framebuffers.len() as _, true)
-        .expect("Failed to allocate command buffers");
-    for (cb, fb) in render_cb.iter_mut().zip(framebuffers.iter()) {
-        unsafe {
-            cb.begin(engine.device())
-                .expect("Failed to begin recording")
-        }
-        .begin_render_pass(
-            &render_pass,
-            fb,
-            back_buffer_size.into_rect(br::vk::VkOffset2D::ZERO),
-            &[br::ClearValue::color_f32([0.0, 0.0, 0.0, 1.0])],
-            true,
-        )
-        .bind_graphics_pipeline(&pipeline)
-        .push_constant(
-            &pl,
-            br::ShaderStage::VERTEX,
-            0,
-            &[back_buffer_size.width as f32, back_buffer_size.height as _],
-        )
-        .bind_graphics_descriptor_sets(&pl, 0, &[object_descriptor], &[])
-        .bind_vertex_buffers(0, &[br::BufferObjectRef::new(&vertex_buffer)], &[0])
-        .draw(3, 1, 0, 0)
-        .end_render_pass()
-        .end()
-        .expect("Command error");
-    }
-
-    let mut render_ready = br::SemaphoreBuilder::new()
-        .create(engine.device())
-        .expect("Failed to create render ready semaphore");
-    let updated = br::SemaphoreBuilder::new()
-        .create(engine.device())
-        .expect("Failed to create updated semaphore");
-    let present_ready = br::SemaphoreBuilder::new()
-        .create(engine.device())
-        .expect("Failed to create present ready semaphore");
-    let mut last_render_fence = br::FenceBuilder::new()
-        .create(engine.device())
-        .expect("Failed to create last render fence");
-    let mut last_render_occured = false;
-
-    let mut dynamic_update_buffer =
-        br::BufferDesc::new_for_type::<UniformData>(br::BufferUsage::TRANSFER_SRC)
-            .create(engine.device())
-            .expect("Failed to create dynamic update buffer");
-    let dynamic_update_buffer_requirements = dynamic_update_buffer.requirements();
-    let mut dynamic_update_memory = br::DeviceMemoryRequest::allocate(
-        dynamic_update_buffer_requirements.size as _,
-        engine
-            .find_matching_host_visible_memory_index(
-                dynamic_update_buffer_requirements.memoryTypeBits,
-            )
-            .expect("no suitable memory for dynamic uploading"),
-    )
-    .execute(engine.device())
-    .expect("Failed to allocate dynamic upload memory");
-    dynamic_update_buffer
-        .bind(&dynamic_update_memory, 0)
-        .expect("Failed to bind dynamic update buffer with memory");
-
-    let mut update_command_pool = engine
-        .command_pool_builder_for_graphics_works()
-        .create(engine.device())
-        .expect("Failed to create update command pool");
-    let [mut update_commands] = update_command_pool
-        .alloc_array::<1>(true)
-        .expect("Failed to allocate update command buffer");
+    let tinted_material = |tint| MaterialDesc {
+        descriptor_set: Some(texture_descriptor),
+        parameters: Some(MaterialParameters::new(&TintParameters { tint })),
+        ..MaterialDesc::new(textured_state.clone())
+    };
+    let textured_material = scene_renderer.add_material(
+        &mut descriptors,
+        &shaders,
+        tinted_material(Vec4::new(1.0, 1.0, 1.0, 1.0)),
+    )?;
+    // same state as the textured material: drawn with the same pipeline
+    let pulsing_material = scene_renderer.add_material(
+        &mut descriptors,
+        &shaders,
+        tinted_material(Vec4::new(1.0, 1.0, 1.0, 1.0)),
+    )?;
+
+    let camera = Camera::look_at(
+        Vec3::new(0.0, 0.0, 2.5),
+        Vec3::ZERO,
+        Projection::Perspective {
+            fov_y: 60.0f32.to_radians(),
+            near: 0.1,
+            far: 100.0,
+        },
+    );
+
+    let mut scene = Scene::new();
+    let spinner = scene.add_node("spinner", None).expect("no root node");
+    let quad = scene
+        .add_node("quad", Some(spinner))
+        .expect("no spinner node");
+    scene.node_mut(quad).expect("no quad node").mesh = Some(MeshInstance {
+        mesh: quad_mesh,
+        material: pulsing_material,
+    });
+    // every material of the model is drawn with the checker texture for now
+    model
+        .instantiate(&mut scene, Some(spinner), &model_meshes, |_| {
+            textured_material
+        })
+        .expect("no spinner node");
+
+    let mut render_graph = engine.create_render_graph();
 
     let mut rot = 0.0f32;
     let mut t = std::time::Instant::now();
-    let mut presentation_suspending = false;
-    loop {
-        futures_util::select! {
-            e = event_bus.recv().fuse() => match e.unwrap() {
-                EngineEvents::Shutdown => break,
-            },
-            r = frame_request_bus.recv().fuse() => {
-                r.unwrap();
-
-                if last_render_occured && !last_render_fence.status().expect("Failed to get status")
-                {
-                    // previous rendering does not completed.
-                    // println!("frameskip");
-                    continue;
-                }
+    let mut last_frame_timestamp = None;
+    let mut last_presented_back_buffer = None;
+    let result = async {
+        loop {
+            futures_util::select! {
+                e = event_bus.recv().fuse() => match e {
+                    // platform side has gone: treat as shutdown
+                    Ok(EngineEvents::Shutdown) | Err(_) => break,
+                    Ok(EngineEvents::Resized { width, height, .. }) => {
+                        engine.rebuild_swapchain(br::vk::VkExtent2D { width, height })?;
+                        last_presented_back_buffer = None;
+                    }
+                    Ok(EngineEvents::CaptureLastFrame(reply)) => {
+                        let result = match last_presented_back_buffer {
+                            Some(x) => engine.capture_back_buffer(x),
+                            None => Err(EngineError::NoPresentedFrame),
+                        };
+                        let _ = reply.try_send(result);
+                    }
+                },
+                r = frame_request_bus.recv().fuse() => {
+                    let Ok(frame_request) = r else {
+                        // every frame pacing source has gone
+                        break;
+                    };
 
-                if presentation_suspending {
-                    continue;
-                }
+                    if !engine.is_presentable() {
+                        continue;
+                    }
+
+                    let reloaded = shaders.poll_changes();
+                    if !reloaded.is_empty() {
+                        // in-flight frames may still use the old pipelines
+                        engine.q.wait()?;
+                        scene_renderer.reload_shaders(
+                            engine.device(),
+                            &shaders,
+                            &reloaded,
+                            &engine.main_pipeline_target(),
+                        )?;
+                    }
+                    // pipelines of new materials are created here
+                    scene_renderer
+                        .create_pipelines(engine.device(), &engine.main_pipeline_target())?;
+
+                    // every requested frame must be rendered in headless mode for deterministic results
+                    let Some(frame) = frames.begin(engine.is_headless())? else {
+                        // every frame in flight is still being rendered
+                        continue;
+                    };
 
-                last_render_fence
-                    .reset()
-                    .expect("Failed to reset last render fence");
-                last_render_occured = false;
-
-                let dt = t.elapsed().as_secs_f64();
-                println!(
-                    "(th {:?}) frame: {dt} (approx {} fps)",
-                    std::thread::current().id(),
-                    1.0 / dt,
-                );
-
-                t = std::time::Instant::now();
-
-                rot += 90.0 * dt as f32;
-                unsafe {
-                    let ptr = dynamic_update_memory
-                        .map(0..core::mem::size_of::<UniformData>())
-                        .expect("Failed to map memory of dynamic update buffer");
-                    ptr.clone_at(
-                        0,
-                        &UniformData {
-                            object_matrix: [
-                                rot.to_radians().cos(),
-                                -rot.to_radians().sin(),
-                                0.0,
-                                0.0,
-                                rot.to_radians().sin(),
-                                rot.to_radians().cos(),
-                                0.0,
-                                0.0,
-                                0.0,
-                                0.0,
-                                1.0,
-                                0.0,
-                                0.0,
-                                0.0,
-                                0.0,
-                                1.0,
-                            ],
-                        },
+                    let back_buffer_size = engine.back_buffer_size();
+                    let full_scissor_rect = back_buffer_size.into_rect(br::vk::VkOffset2D::ZERO);
+                    let full_viewport = full_scissor_rect.make_viewport(0.0..1.0);
+
+                    let back_buffer_index = match engine
+                        .acquire_next_back_buffer(frame.render_ready_mut())
+                    {
+                        Ok(x) => x,
+                        Err(br::vk::VK_ERROR_OUT_OF_DATE_KHR) => {
+                            eprintln!("out of date swapchain: rebuilding");
+                            engine.refresh_swapchain()?;
+                            last_presented_back_buffer = None;
+                            continue;
+                        }
+                        Err(e) => return Err(e.into()),
+                    };
+
+                    // prefer presentation timestamps from the platform if available
+                    let dt = match (last_frame_timestamp, frame_request.timestamp) {
+                        (Some(last), Some(current)) if current > last => (current - last).as_secs_f64(),
+                        // no reference point yet
+                        (None, Some(_)) => 0.0,
+                        _ => t.elapsed().as_secs_f64(),
+                    };
+                    println!(
+                        "(th {:?}) frame: {dt} (approx {} fps)",
+                        std::thread::current().id(),
+                        1.0 / dt,
                     );
-                    dynamic_update_memory.unmap();
-                }
-                update_command_pool
-                    .reset(true)
-                    .expect("Failed to reset update command pool");
-                unsafe {
-                    update_commands
-                        .begin(engine.device())
-                        .expect("Failed to begin recording update commands")
-                }
-                .copy_buffer(
-                    &dynamic_update_buffer,
-                    &uniform_buffer,
-                    &[br::BufferCopy::copy_data::<UniformData>(0, 0)],
-                )
-                .pipeline_barrier(
-                    br::PipelineStageFlags::TRANSFER,
-                    br::PipelineStageFlags::VERTEX_SHADER,
-                    false,
-                    &[br::vk::VkMemoryBarrier {
-                        sType: br::vk::VkMemoryBarrier::TYPE,
-                        pNext: core::ptr::null(),
-                        srcAccessMask: br::AccessFlags::TRANSFER.write,
-                        dstAccessMask: br::AccessFlags::UNIFORM_READ,
-                    }],
-                    &[],
-                    &[],
-                )
-                .end()
-                .expect("Failed to finish update command recording");
-
-                let back_buffer_index = engine
-                    .swapchain
-                    .acquire_next(
-                        None,
-                        br::CompletionHandlerMut::Queue(render_ready.as_transparent_mut_ref()),
-                    )
-                    .expect("Failed to acquire back buffer");
-                engine
-                    .submit_graphics_work(
+
+                    t = std::time::Instant::now();
+                    last_frame_timestamp = frame_request.timestamp;
+
+                    rot += 90.0 * dt as f32;
+                    scene.node_mut(spinner).expect("no spinner node").transform.rotation =
+                        Quat::rotation_z(-rot.to_radians());
+                    scene.update_world_transforms();
+                    let pulse = 0.75 + 0.25 * (rot * 2.0).to_radians().cos();
+                    scene_renderer
+                        .material_mut(pulsing_material)
+                        .parameters_mut()
+                        .expect("no parameters")
+                        .update(&TintParameters {
+                            tint: Vec4::new(1.0, pulse, pulse, 1.0),
+                        });
+
+                    let view_uniform = frame
+                        .dynamic_buffer_mut()
+                        .push(&camera.view_uniform(back_buffer_size, engine.output_encoding()))
+                        .ok_or(EngineError::DynamicBufferExhausted)?;
+                    let draw_list = scene_renderer.prepare(&scene, frame.dynamic_buffer_mut())?;
+
+                    // recycled when the slot is reused
+                    let view_descriptor = descriptors.allocate_transient(frame, &view_signature)?;
+                    let object_descriptor =
+                        descriptors.allocate_transient(frame, &object_signature)?;
+                    let dynamic_buffer = frame.dynamic_buffer_mut().buffer();
+                    engine.device().update_descriptor_sets(
                         &[
-                            br::SubmissionBatch3::new_wait_semaphore_array(
-                                &[],
-                                &[],
-                                &[update_commands.as_transparent_ref()],
-                                &[updated.as_transparent_ref()],
+                            view_descriptor.binding_at(0).write(
+                                br::DescriptorContents::uniform_buffer_dynamic(
+                                    dynamic_buffer,
+                                    0..core::mem::size_of::<ViewUniform>() as u64,
+                                ),
                             ),
-                            br::SubmissionBatch3::new_wait_semaphore_array(
-                                &[
-                                    render_ready.as_transparent_ref(),
-                                    updated.as_transparent_ref(),
-                                ],
-                                &[
-                                    br::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-                                    br::PipelineStageFlags::VERTEX_SHADER,
-                                ],
-                                &[render_cb[back_buffer_index as usize].as_transparent_ref()],
-                                &[present_ready.as_transparent_ref()],
+                            object_descriptor.binding_at(0).write(
+                                br::DescriptorContents::uniform_buffer_dynamic(
+                                    dynamic_buffer,
+                                    0..core::mem::size_of::<ObjectUniform>() as u64,
+                                ),
                             ),
                         ],
-                        Some(last_render_fence.as_transparent_mut_ref()),
-                    )
-                    .expect("Failed to submit work");
-                match engine.queue_present(back_buffer_index, &[present_ready.as_transparent_ref()])
-                {
-                    Ok(_) => (),
-                    Err(br::vk::VK_ERROR_OUT_OF_DATE_KHR) => {
-                        eprintln!("out of date presentation: ignoring");
-                        presentation_suspending = true;
+                        &[],
+                    );
+
+                    let mut graph = RenderGraphDesc::new();
+                    let back_buffer = engine.import_back_buffer(&mut graph, back_buffer_index);
+                    let depth_buffer = engine.import_depth_buffer(&mut graph);
+                    let vertex_buffers = draw_list
+                        .vertex_buffers()
+                        .map(|b| graph.import_buffer(b))
+                        .collect::<Vec<_>>();
+                    let index_buffers = draw_list
+                        .index_buffers()
+                        .map(|b| graph.import_buffer(b))
+                        .collect::<Vec<_>>();
+                    let mut main_pass = graph
+                        .add_pass()
+                        .image(back_buffer, ImageAccess::ExternalColorAttachment);
+                    for &b in &vertex_buffers {
+                        main_pass = main_pass.buffer(b, BufferAccess::Vertex);
+                    }
+                    for &b in &index_buffers {
+                        main_pass = main_pass.buffer(b, BufferAccess::Index);
                     }
-                    Err(e) => Err(e).expect("Failed to present"),
+                    if let Some(depth_buffer) = depth_buffer {
+                        main_pass =
+                            main_pass.image(depth_buffer, ImageAccess::ExternalDepthAttachment);
+                    }
+                    let main_pass = main_pass.id();
+                    let compiled = render_graph.compile(&graph)?;
+
+                    let mut clear_values = vec![br::ClearValue::color_f32(
+                        engine.encode_output_color([0.0, 0.0, 0.0, 1.0]),
+                    )];
+                    if engine.depth_format().is_some() {
+                        clear_values.push(br::ClearValue::depth_stencil(1.0, 0));
+                    }
+                    // command buffers of the slot are reset in FrameRing::begin
+                    let mut rec =
+                        unsafe { render_commands[frame.index() as usize].begin_once(engine.device())? };
+                    for pass in compiled.passes() {
+                        if let Some(b) = &pass.barrier {
+                            rec = rec.pipeline_barrier(
+                                b.src_stage,
+                                b.dst_stage,
+                                false,
+                                &b.memory_barriers,
+                                &[],
+                                &b.image_barriers,
+                            );
+                        }
+
+                        if pass.id != main_pass {
+                            continue;
+                        }
+
+                        rec = rec.begin_render_pass(
+                            engine.main_render_pass(),
+                            &engine.main_framebuffers()[back_buffer_index as usize],
+                            full_scissor_rect,
+                            &clear_values,
+                            true,
+                        );
+                        // dynamic states shared by every pipeline
+                        rec = rec
+                            .set_viewport(0, &[full_viewport])
+                            .set_scissor(0, &[full_scissor_rect]);
+                        let mut prev_batch = None;
+                        for batch in draw_list.batches() {
+                            if batch.switches_pipeline(prev_batch) {
+                                rec = rec
+                                    .bind_graphics_pipeline(batch.pipeline)
+                                    .bind_graphics_descriptor_sets(
+                                        batch.layout,
+                                        VIEW_SET,
+                                        &[view_descriptor],
+                                        &[view_uniform.offset],
+                                    );
+                            }
+                            if let Some(d) = batch.material_descriptor {
+                                rec = rec.bind_graphics_descriptor_sets(
+                                    batch.layout,
+                                    MATERIAL_SET,
+                                    &[d],
+                                    batch.parameter_offset.as_slice(),
+                                );
+                            }
+                            for d in &batch.draws {
+                                rec = rec
+                                    .bind_graphics_descriptor_sets(
+                                        batch.layout,
+                                        OBJECT_SET,
+                                        &[object_descriptor],
+                                        &[d.object_offset],
+                                    )
+                                    .bind_vertex_buffers(
+                                        0,
+                                        &[br::BufferObjectRef::new(d.mesh.vertex_buffer.buffer())],
+                                        &[0],
+                                    );
+                                rec = match &d.mesh.index_buffer {
+                                    Some(ib) => rec
+                                        .bind_index_buffer(ib.buffer.buffer(), 0, ib.index_type)
+                                        .draw_indexed(ib.count, 1, 0, 0, 0),
+                                    None => rec.draw(d.mesh.vertex_count, 1, 0, 0),
+                                };
+                            }
+                            prev_batch = Some(batch);
+                        }
+                        rec = rec.end_render_pass();
+                    }
+                    if let Some(b) = compiled.final_barrier() {
+                        rec = rec.pipeline_barrier(
+                            b.src_stage,
+                            b.dst_stage,
+                            false,
+                            &b.memory_barriers,
+                            &[],
+                            &b.image_barriers,
+                        );
+                    }
+                    rec.end()?;
+
+                    // uploads requested by other tasks must be visible to this frame
+                    engine.flush_uploads()?;
+                    let submission = frame.submission();
+                    engine.submit_graphics_work(
+                        &[br::SubmissionBatch3::new_wait_semaphore_array(
+                            &[submission.render_ready.as_transparent_ref()],
+                            &[br::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT],
+                            &[render_commands[submission.index as usize].as_transparent_ref()],
+                            &[submission.present_ready.as_transparent_ref()],
+                        )],
+                        Some(submission.fence),
+                    )?;
+                    match engine.queue_present(back_buffer_index, frame.present_ready()) {
+                        Ok(_) => (),
+                        Err(br::vk::VK_ERROR_OUT_OF_DATE_KHR) => {
+                            eprintln!("out of date presentation: rebuilding swapchain");
+                            engine.refresh_swapchain()?;
+                            // rebuild waits for the queue: rendering has been completed
+                            last_presented_back_buffer = None;
+                            continue;
+                        }
+                        Err(e) => return Err(e.into()),
+                    }
+                    last_presented_back_buffer = Some(back_buffer_index);
                 }
-                last_render_occured = true;
             }
         }
+
+        Ok::<_, EngineError>(())
     }
+    .await;
 
-    if last_render_occured {
-        last_render_fence
-            .wait()
-            .expect("Failed to wait last render completion");
+    // in-flight works must be completed before releasing resources(also on errors)
+    let idle_result = engine.q.wait().map_err(EngineError::from);
+    // failing to persist the cache only slows down the next launch
+    if let Err(e) = engine.save_pipeline_cache() {
+        eprintln!("failed to save pipeline cache: {e}");
     }
+    let stats = frames.stats();
+    println!(
+        "shutdown: {} frames rendered, {} dropped ({:.1}%), {} stalled",
+        stats.begun,
+        stats.dropped,
+        stats.drop_ratio() * 100.0,
+        stats.stalled
+    );
 
-    println!("shutdown");
+    result.and(idle_result)
 }
@@ -0,0 +1,135 @@
+//! Golden image regression tests, driven by the headless backend(`--golden`).
+//!
+//! Each test renders the game loop for a fixed number of frames with a fixed frame interval,
+//! then compares the last frame with `tests/golden/<name>.png` in the package directory.
+//! Run with `--golden --bless` to (re)generate the golden images from the current output.
+
+use std::{path::Path, time::Duration};
+
+use crate::render::{capture::CapturedImage, headless::HeadlessOptions};
+
+// Note: 作業ディレクトリに依存しないようにパッケージのディレクトリから解決する
+pub const GOLDEN_IMAGE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden");
+/// Actual outputs of failed tests are written here
+pub const FAILURE_OUTPUT_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/target/golden");
+
+pub struct GoldenTest {
+    pub name: &'static str,
+    pub frames: u32,
+    pub frame_interval: Duration,
+    pub extent: (u32, u32),
+    /// Maximum per-channel difference treated as same
+    pub tolerance: u8,
+    /// Ratio of mismatched pixels allowed(absorbs rasterization differences between drivers on edges)
+    pub max_mismatch_ratio: f64,
+}
+impl GoldenTest {
+    fn headless_options(&self) -> HeadlessOptions {
+        HeadlessOptions {
+            frames: self.frames,
+            extent: self.extent,
+            frame_interval: self.frame_interval,
+            capture_path: None,
+        }
+    }
+}
+
+pub const GOLDEN_TESTS: &[GoldenTest] = &[
+    // The game scene: the checker-textured quad and the sample glTF pyramid(depth tested,
+    // premultiplied blending, no culling) spinning at 90 degrees/s.
+    // 25 steps of 20ms = 0.5s after the first frame: rotated by exactly 45 degrees
+    // Note: シーンを変更したら--golden --blessで再生成すること
+    GoldenTest {
+        name: "spinning_scene",
+        frames: 26,
+        frame_interval: Duration::from_millis(20),
+        extent: (256, 256),
+        tolerance: 2,
+        max_mismatch_ratio: 0.002,
+    },
+];
+
+/// Returns `Some(bless)` if `--golden` is specified.
+pub fn mode_from_args(args: impl IntoIterator<Item = String>) -> Option<bool> {
+    let mut golden = false;
+    let mut bless = false;
+    for a in args {
+        match &a[..] {
+            "--golden" => golden = true,
+            "--bless" => bless = true,
+            _ => (),
+        }
+    }
+
+    golden.then_some(bless)
+}
+
+pub async fn main(bless: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let mut failures = Vec::new();
+
+    for t in GOLDEN_TESTS {
+        let actual = crate::main_headless::run(&t.headless_options(), true)
+            .await?
+            .expect("no capture");
+        let golden_path = Path::new(GOLDEN_IMAGE_DIR).join(format!("{}.png", t.name));
+
+        if bless {
+            std::fs::create_dir_all(GOLDEN_IMAGE_DIR)?;
+            actual.write_png(&golden_path)?;
+            println!("[golden] {}: blessed -> {}", t.name, golden_path.display());
+            continue;
+        }
+
+        let expected = match CapturedImage::read_png(&golden_path) {
+            Ok(x) => x,
+            Err(e) => {
+                eprintln!(
+                    "[golden] {}: cannot read {}: {e} (run with --bless to create)",
+                    t.name,
+                    golden_path.display()
+                );
+                write_failure_output(t, &actual)?;
+                failures.push(t.name);
+                continue;
+            }
+        };
+
+        match actual.compare(&expected, t.tolerance) {
+            Some(diff) if diff.mismatch_ratio() <= t.max_mismatch_ratio => {
+                println!("[golden] {}: ok {diff:?}", t.name);
+            }
+            diff => {
+                match diff {
+                    Some(diff) => eprintln!("[golden] {}: mismatch {diff:?}", t.name),
+                    None => eprintln!(
+                        "[golden] {}: size mismatch: {}x{} (expected {}x{})",
+                        t.name, actual.width, actual.height, expected.width, expected.height
+                    ),
+                }
+
+                write_failure_output(t, &actual)?;
+                failures.push(t.name);
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        return Err(format!("golden tests failed: {failures:?}").into());
+    }
+
+    Ok(())
+}
+
+/// Writes the actual output of a failed test for inspection.
+fn write_failure_output(t: &GoldenTest, actual: &CapturedImage) -> std::io::Result<()> {
+    std::fs::create_dir_all(FAILURE_OUTPUT_DIR)?;
+    let actual_path = Path::new(FAILURE_OUTPUT_DIR).join(format!("{}.actual.png", t.name));
+    actual.write_png(&actual_path)?;
+    eprintln!(
+        "[golden] {}: actual output -> {}",
+        t.name,
+        actual_path.display()
+    );
+
+    Ok(())
+}
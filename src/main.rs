@@ -1,3 +1,4 @@
+mod main_headless;
 #[cfg(target_os = "linux")]
 mod main_linux;
 #[cfg(target_os = "macos")]
@@ -5,10 +6,24 @@ mod main_mac;
 #[cfg(target_os = "windows")]
 mod main_windows;
 
+mod asset;
+mod error;
 mod game;
+mod golden;
+mod math;
+mod render;
+mod scene;
+mod shader;
 
 #[async_std::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(bless) = golden::mode_from_args(std::env::args().skip(1)) {
+        return golden::main(bless).await;
+    }
+    if let Some(options) = render::headless::HeadlessOptions::from_args(std::env::args().skip(1)) {
+        return main_headless::main(options).await;
+    }
+
     #[cfg(target_os = "macos")]
     {
         main_mac::main().await
@@ -0,0 +1,65 @@
+use crate::render::{
+    bootstrap::{self, BootstrapOptions},
+    capture::CapturedImage,
+    headless::{self, HeadlessConfig, HeadlessOptions},
+};
+
+/// Runs the game loop for a fixed number of frames without any window server.
+pub async fn main(headless_options: HeadlessOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let capture_path = headless_options.capture_path.clone();
+    let captured = run(&headless_options, capture_path.is_some()).await?;
+
+    if let (Some(path), Some(image)) = (capture_path, captured) {
+        image.write_png(&path)?;
+        println!("captured: {}", path.display());
+    }
+
+    Ok(())
+}
+
+/// Runs the game loop headlessly. Returns the last frame if `capture` is true.
+pub async fn run(
+    headless_options: &HeadlessOptions,
+    capture: bool,
+) -> Result<Option<CapturedImage>, Box<dyn std::error::Error>> {
+    let (events_sender, events_receiver) = async_std::channel::unbounded();
+    let (frame_request_sender, frame_request_receiver) = crate::game::frame_request_bus();
+
+    let options = BootstrapOptions::headless();
+    let instance = bootstrap::create_instance(&options)?;
+    let mut device_context =
+        bootstrap::DeviceContext::new_headless(&instance, &options, HeadlessConfig::default())?;
+    let engine = device_context.engine(headless_options.extent())?;
+
+    println!(
+        "headless run: {} frames({:?} per frame)",
+        headless_options.frames, headless_options.frame_interval
+    );
+    let frame_interval = headless_options.frame_interval;
+    let frames = headless_options.frames;
+    let driver = async_std::task::spawn(async move {
+        let completed =
+            headless::run_fixed_frame_clock(&frame_request_sender, frame_interval, frames).await;
+        let captured = if completed && capture {
+            headless::capture_last_frame(&events_sender).await
+        } else {
+            None
+        };
+        let _ = events_sender
+            .send(crate::game::EngineEvents::Shutdown)
+            .await;
+
+        captured
+    });
+
+    let result = crate::game::game_main(engine, events_receiver, frame_request_receiver).await;
+    let captured = driver.await;
+    result?;
+
+    match captured {
+        Some(Ok(x)) => Ok(Some(x)),
+        Some(Err(e)) => Err(format!("Failed to capture the last frame: {e}").into()),
+        None if capture => Err("game loop exited before capturing".into()),
+        None => Ok(None),
+    }
+}
@@ -1,25 +1,36 @@
 use std::{os::fd::AsRawFd, sync::Arc};
 
-use bedrock::{self as br, Instance, PhysicalDevice};
+use bedrock as br;
 use epoll::{Epoll, EpollData, EPOLLET, EPOLLIN};
 use eventfd::EventFD;
 use wayland_client::{
     wl_array, OwnedWlCallback, OwnedWlCompositor, OwnedXDGWMBase, WlCallback, WlCallbackListener,
-    WlCompositor, WlDisplayConnection, WlRegistryListener, WlSurface, XDGWMBase, XDGWMBaseListener,
+    WlCompositor, WlDisplayConnection, WlRegistryListener, WlSurface, XDGToplevelState, XDGWMBase,
+    XDGWMBaseListener,
 };
 
-use crate::game::EngineEvents;
+use crate::{
+    error::EngineError,
+    game::{EngineEvents, FrameRequestSender, WindowState},
+    render::{
+        bootstrap::{self, BootstrapOptions},
+        depth::DepthMode,
+        presentation::PresentationConfig,
+        surface_format::OutputColorSpace,
+    },
+};
 
 pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
     if let Some(dp) = WlDisplayConnection::new(None) {
         return wayland_main(dp).await;
     }
 
-    panic!("no window server available!");
+    Err(EngineError::NoWindowServer.into())
 }
 
 pub async fn wayland_main(mut dp: WlDisplayConnection) -> Result<(), Box<dyn std::error::Error>> {
     let (events_sender, events_receiver) = async_std::channel::unbounded();
+    let (frame_request_sender, frame_request_receiver) = crate::game::frame_request_bus();
 
     let mut registry = dp.get_registry();
     struct RegistryListener {
@@ -39,14 +50,16 @@ pub async fn wayland_main(mut dp: WlDisplayConnection) -> Result<(), Box<dyn std
             match ifname {
                 "wl_compositor" => {
                     if self.compositor.is_some() {
-                        panic!("one or more wl_compositor found");
+                        println!("[registry] extra wl_compositor ignored: name={name}");
+                        return;
                     }
 
                     self.compositor = Some(sender.bind::<WlCompositor>(name, version));
                 }
                 "xdg_wm_base" => {
                     if self.wm_base.is_some() {
-                        panic!("one or more xdg_wm_base found");
+                        println!("[registry] extra xdg_wm_base ignored: name={name}");
+                        return;
                     }
 
                     self.wm_base = Some(sender.bind::<XDGWMBase>(name, version));
@@ -68,8 +81,12 @@ pub async fn wayland_main(mut dp: WlDisplayConnection) -> Result<(), Box<dyn std
     registry.add_listener(&mut registry_listener).unwrap();
     dp.roundtrip().unwrap();
 
-    let mut compositor = registry_listener.compositor.unwrap();
-    let mut wm_base = registry_listener.wm_base.unwrap();
+    let mut compositor = registry_listener
+        .compositor
+        .ok_or(EngineError::MissingWindowServerInterface("wl_compositor"))?;
+    let mut wm_base = registry_listener
+        .wm_base
+        .ok_or(EngineError::MissingWindowServerInterface("xdg_wm_base"))?;
     struct WMBaseListener;
     impl XDGWMBaseListener for WMBaseListener {
         fn ping(&mut self, sender: &mut XDGWMBase, serial: std::ffi::c_uint) {
@@ -102,13 +119,26 @@ pub async fn wayland_main(mut dp: WlDisplayConnection) -> Result<(), Box<dyn std
             height: std::ffi::c_int,
             states: &mut wl_array,
         ) {
-            println!(
-                "[xdg toplevel configure] {width}x{height} states={:?}",
-                unsafe { states.as_slice_of::<core::ffi::c_uint>() }
-            );
+            let states = unsafe { states.as_slice_of::<core::ffi::c_uint>() }
+                .iter()
+                .filter_map(|&s| XDGToplevelState::from_raw(s))
+                .map(window_state_from_xdg)
+                .collect::<Vec<_>>();
+            println!("[xdg toplevel configure] {width}x{height} states={states:?}");
+
+            if width <= 0 || height <= 0 {
+                // client may decide its size
+                return;
+            }
 
             self.configure_width = width as _;
             self.configure_height = height as _;
+            // Note: この時点ではまだgame_mainが起動していないこともあるが、その場合もイベントはキューされる
+            let _ = self.events_sender.try_send(EngineEvents::Resized {
+                width: width as _,
+                height: height as _,
+                states,
+            });
         }
 
         fn close(&mut self, _sender: &mut wayland_client::XDGToplevel) {
@@ -153,20 +183,18 @@ pub async fn wayland_main(mut dp: WlDisplayConnection) -> Result<(), Box<dyn std
     struct SurfaceFrameEventListener<'s> {
         surface_ref: &'s mut WlSurface,
         callback_instance: Option<OwnedWlCallback>,
-        events_sender: async_std::channel::Sender<EngineEvents>,
+        frame_request_sender: FrameRequestSender,
     }
     impl WlCallbackListener for SurfaceFrameEventListener<'_> {
-        fn done(&mut self, _sender: &mut WlCallback, _callback_data: u32) {
+        fn done(&mut self, _sender: &mut WlCallback, callback_data: u32) {
             let mut new_callback = self.surface_ref.frame();
             new_callback.add_listener(self).unwrap();
             self.callback_instance = Some(new_callback);
 
-            let cont = async_std::task::block_on(async {
-                self.events_sender
-                    .send(EngineEvents::NextFrame)
-                    .await
-                    .is_ok()
-            });
+            // callback_data: current time in milliseconds
+            let cont = self
+                .frame_request_sender
+                .request(Some(std::time::Duration::from_millis(callback_data as _)));
 
             if !cont {
                 // Shutdown済なので登録解除
@@ -178,7 +206,7 @@ pub async fn wayland_main(mut dp: WlDisplayConnection) -> Result<(), Box<dyn std
     let mut surface_frame_event_listener = SurfaceFrameEventListener {
         surface_ref: &mut surface,
         callback_instance: None,
-        events_sender: events_sender.clone(),
+        frame_request_sender: frame_request_sender.clone(),
     };
     surface_frame_callback
         .add_listener(&mut surface_frame_event_listener)
@@ -197,156 +225,29 @@ pub async fn wayland_main(mut dp: WlDisplayConnection) -> Result<(), Box<dyn std
         core::sync::atomic::AtomicPtr::new(surface.as_raw_ptr_mut() as *mut core::ffi::c_void);
     let terminate_event_fd = Arc::new(EventFD::new(0, 0));
     let terminate_event_fd_game = terminate_event_fd.clone();
-    let _ = async_std::task::spawn(async move {
-        for x in br::enumerate_extension_properties(None).expect("Failed to enumerate extensions") {
-            let ext_name = x.extensionName.as_cstr().unwrap().to_str().unwrap();
-            println!("vkext: {ext_name}");
+    let game_task = async_std::task::spawn(async move {
+        let result = async {
+            let mut options = BootstrapOptions::new(c"VK_KHR_wayland_surface");
+            options.presentation = PresentationConfig::from_args(std::env::args().skip(1));
+            options.output_color_space = OutputColorSpace::from_args(std::env::args().skip(1));
+            options.depth = DepthMode::from_args(std::env::args().skip(1));
+            let instance = bootstrap::create_instance(&options)?;
+            let mut device_context =
+                bootstrap::DeviceContext::new(&instance, &options, |adapter| {
+                    adapter.new_surface_wayland(dp_ptr.into_inner(), s_ptr.into_inner())
+                })?;
+            let engine = device_context.engine(init_size)?;
+
+            // emit first frame
+            frame_request_sender.request(None);
+
+            crate::game::game_main(engine, events_receiver, frame_request_receiver).await
         }
-
-        for x in br::enumerate_layer_properties().expect("Failed to enumerate layers") {
-            let layer_name_cstr = x.layerName.as_cstr().unwrap();
-
-            println!(
-                "vk layer: {} {} {}",
-                layer_name_cstr.to_str().unwrap(),
-                x.specVersion,
-                x.implementationVersion
-            );
-
-            for e in br::enumerate_extension_properties_cstr(Some(layer_name_cstr))
-                .expect("Failed to enumerate layer extensions")
-            {
-                println!(
-                    "* vkext: {}",
-                    e.extensionName.as_cstr().unwrap().to_str().unwrap()
-                );
-            }
-        }
-
-        let instance = {
-            let app =
-                br::ApplicationInfo::new(c"peridot2-test", (0, 1, 0), c"Peridot 2", (0, 1, 0));
-            let mut builder = br::InstanceBuilder::new(&app);
-            builder
-                .add_extensions([
-                    c"VK_EXT_debug_utils",
-                    c"VK_KHR_wayland_surface",
-                    c"VK_KHR_surface",
-                ])
-                .add_layer(c"VK_LAYER_KHRONOS_validation");
-
-            builder.create().expect("Failed to create instance")
-        };
-
-        let _debug_utils_messenger = {
-            let builder = br::DebugUtilsMessengerCreateInfo::new(debug_utils_message);
-
-            builder
-                .create(&instance)
-                .expect("Failed to create debug messenger")
-        };
-
-        let adapter = instance
-            .iter_physical_devices()
-            .expect("Failed to enumerate adapters")
-            .next()
-            .expect("no vulkan devices");
-        let memory_properties = adapter.memory_properties();
-
-        let surface = (&adapter)
-            .new_surface_wayland(dp_ptr.into_inner(), s_ptr.into_inner())
-            .expect("Failed to create vk surface");
-
-        let queue_info = adapter.queue_family_properties();
-        let graphics_queue_family_index = queue_info
-            .find_matching_index(br::QueueFlags::GRAPHICS)
-            .expect("no graphics queue family");
-        println!(
-            "graphics queue count: {}",
-            queue_info.queue_count(graphics_queue_family_index)
-        );
-        let device = {
-            let queue_family_builder =
-                br::DeviceQueueCreateInfo::new(graphics_queue_family_index, &[0.0]);
-            let mut builder = br::DeviceBuilder::new(&adapter);
-            builder
-                .add_extension(c"VK_KHR_swapchain")
-                .add_queue(queue_family_builder);
-
-            builder.create().expect("Failed to create device")
-        };
-
-        let q = br::Device::queue(&device, graphics_queue_family_index, 0);
-
-        let surface_caps = adapter
-            .surface_capabilities(&surface)
-            .expect("Failed to get surface caps");
-        let surface_fmt = adapter
-            .surface_formats(&surface)
-            .expect("Failed to get surface formats");
-        let surface_pm = adapter
-            .surface_present_modes(&surface)
-            .expect("Failed to get surface presentation modes");
-        println!("** Surface Info **");
-        println!("*** Formats: {:?}", surface_fmt);
-        println!("*** Caps: {:?}", surface_caps);
-        println!("*** PresentModes: {:?}", surface_pm);
-
-        let sc_format = surface_fmt
-            .iter()
-            .find(|f| {
-                f.format == br::vk::VK_FORMAT_R8G8B8A8_UNORM
-                    || f.format == br::vk::VK_FORMAT_B8G8R8A8_UNORM
-            })
-            .or_else(|| {
-                surface_fmt.iter().find(|f| {
-                    f.format == br::vk::VK_FORMAT_R8G8B8A8_SRGB
-                        || f.format == br::vk::VK_FORMAT_B8G8R8A8_SRGB
-                })
-            })
-            .expect("No suitable format supported");
-        let back_buffer_count = 2.clamp(surface_caps.minImageCount, surface_caps.maxImageCount);
-        let present_mode = surface_pm[0];
-        let extent = br::vk::VkExtent2D {
-            width: if surface_caps.currentExtent.width == 0xffff_ffff {
-                init_size.width
-            } else {
-                surface_caps.currentExtent.width
-            },
-            height: if surface_caps.currentExtent.height == 0xffff_ffff {
-                init_size.height
-            } else {
-                surface_caps.currentExtent.height
-            },
-        };
-        let swapchain = br::SwapchainBuilder::new(
-            surface,
-            back_buffer_count,
-            sc_format.clone(),
-            extent,
-            br::ImageUsageFlags::COLOR_ATTACHMENT,
-        )
-        .present_mode(present_mode)
-        .pre_transform(br::SurfaceTransform::Identity)
-        .composite_alpha(br::CompositeAlpha::Opaque)
-        .create(&device)
-        .expect("Failed to create swapchain");
-
-        // emit first frame
-        events_sender.send(EngineEvents::NextFrame).await.unwrap();
-
-        crate::game::game_main(
-            crate::game::Engine {
-                graphics_queue_family_index,
-                q,
-                swapchain: Arc::new(swapchain),
-                memory_properties,
-            },
-            events_receiver,
-        )
         .await;
 
+        // terminate the event loop also on errors
         terminate_event_fd_game.add(1).unwrap();
+        result
     });
 
     let mut ep = Epoll::new(2);
@@ -377,25 +278,20 @@ pub async fn wayland_main(mut dp: WlDisplayConnection) -> Result<(), Box<dyn std
         }
     }
 
+    game_task.await?;
     Ok(())
 }
 
-extern "system" fn debug_utils_message(
-    severity: br::vk::VkDebugUtilsMessageSeverityFlagBitsEXT,
-    types: br::vk::VkDebugUtilsMessageTypeFlagsEXT,
-    data: *const br::vk::VkDebugUtilsMessengerCallbackDataEXT,
-    _user_data: *mut core::ffi::c_void,
-) -> br::vk::VkBool32 {
-    let data_ref = unsafe { data.as_ref().expect("null data") };
-    eprintln!("[{severity:08x}, {types:08x}] {}", unsafe {
-        std::ffi::CStr::from_ptr(data_ref.pMessage)
-            .to_str()
-            .expect("invalid message str")
-    });
-
-    if (severity & br::vk::VK_DEBUG_UTILS_MESSAGE_SEVERITY_ERROR_BIT_EXT) != 0 {
-        br::vk::VK_TRUE
-    } else {
-        br::vk::VK_FALSE
+fn window_state_from_xdg(state: XDGToplevelState) -> WindowState {
+    match state {
+        XDGToplevelState::Maximized => WindowState::Maximized,
+        XDGToplevelState::Fullscreen => WindowState::Fullscreen,
+        XDGToplevelState::Resizing => WindowState::Resizing,
+        XDGToplevelState::Activated => WindowState::Activated,
+        XDGToplevelState::TiledLeft => WindowState::TiledLeft,
+        XDGToplevelState::TiledRight => WindowState::TiledRight,
+        XDGToplevelState::TiledTop => WindowState::TiledTop,
+        XDGToplevelState::TiledBottom => WindowState::TiledBottom,
+        XDGToplevelState::Suspeneded => WindowState::Suspended,
     }
 }
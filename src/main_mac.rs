@@ -21,9 +21,48 @@ use futures_util::future::FutureExt;
 use objc::{msg_send, sel, sel_impl};
 use objc_ext::ObjcObject;
 
+use crate::{
+    error::EngineError,
+    game::{EngineEvents, FrameRequestSender},
+    render::bootstrap::{self, BootstrapOptions},
+};
+
 #[link(name = "vulkan", kind = "framework")]
 extern "C" {}
 
+/// Failures of AppKit/CoreVideo calls(returning either `Option` or `Result`) as [`EngineError`]
+trait WindowSystemResult<T> {
+    fn or_window_system_error(self, what: &'static str) -> Result<T, EngineError>;
+}
+impl<T> WindowSystemResult<T> for Option<T> {
+    fn or_window_system_error(self, what: &'static str) -> Result<T, EngineError> {
+        self.ok_or(EngineError::WindowSystem(what))
+    }
+}
+impl<T, E> WindowSystemResult<T> for Result<T, E> {
+    fn or_window_system_error(self, what: &'static str) -> Result<T, EngineError> {
+        self.map_err(|_| EngineError::WindowSystem(what))
+    }
+}
+
+/// Handshake between `applicationShouldTerminate:` and the engine task
+struct ShutdownState {
+    /// `applicationShouldTerminate:` returned NSTerminateLater and waits for the reply
+    terminate_pending: bool,
+    /// game_main has returned(nobody receives the shutdown event)
+    engine_finished: bool,
+}
+static SHUTDOWN_STATE: Mutex<ShutdownState> = Mutex::new(ShutdownState {
+    terminate_pending: false,
+    engine_finished: false,
+});
+
+fn shutdown_state() -> std::sync::MutexGuard<'static, ShutdownState> {
+    SHUTDOWN_STATE
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
 objc_ext::DefineObjcObjectWrapper!(PeridotAppDelegate : NSObject);
 impl PeridotAppDelegate {
     fn cls() -> &'static objc::runtime::Class {
@@ -62,8 +101,17 @@ impl PeridotAppDelegate {
             "(th {:?}) should terminate app {sender:p}",
             std::thread::current().id()
         );
+        {
+            let mut state = shutdown_state();
+            if state.engine_finished {
+                // nobody will reply
+                return 1; // NSTerminateNow
+            }
+            state.terminate_pending = true;
+        }
+
         sender.stop(this.as_id());
-        let event = NSEvent::new_other_event(
+        match NSEvent::new_other_event(
             NSEventType::ApplicationDefined,
             appkit::CGPoint { x: 0.0, y: 0.0 },
             NSEventModifierFlags::empty(),
@@ -74,14 +122,21 @@ impl PeridotAppDelegate {
             0,
             0,
         )
-        .expect("Failed to create dummy event");
-        sender.post_event(&event, true);
+        .or_window_system_error("create a dummy event")
+        {
+            Ok(event) => sender.post_event(&event, true),
+            Err(e) => eprintln!("{e}"),
+        }
 
-        this.display_timer()
+        if let Err(e) = this
+            .display_timer()
             .stop()
-            .expect("Failed to stop display timer");
-        async_std::task::block_on(this.event_bus().send(EngineEvents::Shutdown))
-            .expect("Failed to send shutdown");
+            .or_window_system_error("stop the display link")
+        {
+            eprintln!("{e}");
+        }
+        // Note: エンジンが先に終了していたら受信側は閉じている(その場合も返信はエンジン側で行われる)
+        let _ = async_std::task::block_on(this.event_bus().send(EngineEvents::Shutdown));
 
         2 // NSTerminateLater
     }
@@ -125,9 +180,10 @@ impl PeridotAppDelegate {
 
 pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // initialize macos window system
-    let app = NSApplication::shared_mut().expect("Failed to initialize shared NSApplication");
+    let app = NSApplication::shared_mut().or_window_system_error("initialize NSApplication")?;
 
-    let mut appdelegate = PeridotAppDelegate::new().expect("Failed to create appdelegate");
+    let mut appdelegate =
+        PeridotAppDelegate::new().or_window_system_error("create the app delegate")?;
     app.set_delegate(appdelegate.as_id());
 
     let mut w = NSWindow::new(
@@ -143,24 +199,24 @@ pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
             | NSWindowStyleMask::MINIATURIZABLE
             | NSWindowStyleMask::FULLSIZE_CONTENT_VIEW,
     )
-    .expect("Failed to create window");
+    .or_window_system_error("create the window")?;
     w.set_title("Peridot 2");
 
-    let mut app_submenu = NSMenu::new().expect("Failed to create app submenu");
+    let mut app_submenu = NSMenu::new().or_window_system_error("create the app submenu")?;
     app_submenu
         .add_new_item(
             "Quit",
             Some(sel!(terminate:)),
-            Some(&NSString::from_str("q").expect("Failed to convert str")),
+            Some(&NSString::from_str("q").or_window_system_error("create a key equivalent")?),
         )
-        .expect("Failed to add quit action");
-    let mut menu = NSMenu::new().expect("Failed to create menu");
+        .or_window_system_error("add the quit action")?;
+    let mut menu = NSMenu::new().or_window_system_error("create the main menu")?;
     menu.add_new_item("Peridot 2", None, None)
-        .expect("Failed to create app menu")
+        .or_window_system_error("create the app menu")?
         .set_submenu(&app_submenu);
     app.set_main_menu(&menu);
 
-    let layer = CAMetalLayer::new().expect("Failed to create CAMetalLayer");
+    let layer = CAMetalLayer::new().or_window_system_error("create CAMetalLayer")?;
     w.content_view_mut().set_wants_layer(true);
     w.content_view_mut().set_layer(&layer);
 
@@ -169,173 +225,53 @@ pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
     w.make_main_window();
 
     let (events_sender, events_receiver) = async_std::channel::unbounded();
+    let (frame_request_sender, frame_request_receiver) = crate::game::frame_request_bus();
 
-    let mut timer =
-        CVDisplayLink::new_for_active_displays().expect("Failed to initialize sync timer");
+    let mut timer = CVDisplayLink::new_for_active_displays()
+        .or_window_system_error("create the display link")?;
     timer
         .set_output_callback(
             Some(cv_display_link_callback),
-            &events_sender as *const _ as _,
+            &frame_request_sender as *const _ as _,
         )
-        .expect("Failed to set callback");
-    timer.start().expect("Failed to start timer");
+        .or_window_system_error("set the display link callback")?;
+    timer
+        .start()
+        .or_window_system_error("start the display link")?;
 
     let _ = async_std::task::spawn(async move {
-        {
-            let mut portability_enumeration_available = false;
-            for x in
-                br::enumerate_extension_properties(None).expect("Failed to enumerate extensions")
-            {
-                let ext_name = x.extensionName.as_cstr().unwrap().to_str().unwrap();
-                println!("vkext: {ext_name}");
-
-                if ext_name == "VK_KHR_portability_enumeration" {
-                    portability_enumeration_available = true;
-                }
-            }
-
-            for x in br::enumerate_layer_properties().expect("Failed to enumerate layers") {
-                let layer_name_cstr = x.layerName.as_cstr().unwrap();
-
-                println!(
-                    "vk layer: {} {} {}",
-                    layer_name_cstr.to_str().unwrap(),
-                    x.specVersion,
-                    x.implementationVersion
-                );
+        let result = async {
+            let options = BootstrapOptions::new(c"VK_MVK_macos_surface");
+            let instance = bootstrap::create_instance(&options)?;
+            let mut device_context =
+                bootstrap::DeviceContext::new(&instance, &options, |adapter| {
+                    adapter.new_surface_macos(layer.id() as _)
+                })?;
+            // Note: CAMetalLayerの場合はcurrentExtentが決まっているのでこのサイズは使われない想定
+            let engine = device_context.engine(br::vk::VkExtent2D {
+                width: 640,
+                height: 480,
+            })?;
+
+            crate::game::game_main(engine, events_receiver, frame_request_receiver).await
+            // vulkan objects are terminated here(before replying ShouldTerminate)
+        }
+        .await;
+        if let Err(e) = result {
+            eprintln!("engine error: {e}");
+        }
 
-                for e in br::enumerate_extension_properties_cstr(Some(layer_name_cstr))
-                    .expect("Failed to enumerate layer extensions")
-                {
-                    println!(
-                        "* vkext: {}",
-                        e.extensionName.as_cstr().unwrap().to_str().unwrap()
-                    );
-                }
+        let terminate_pending = {
+            let mut state = shutdown_state();
+            state.engine_finished = true;
+            state.terminate_pending
+        };
+        if terminate_pending {
+            match NSApplication::shared().or_window_system_error("get NSApplication") {
+                Ok(app) => app.reply_to_application_should_terminate(true),
+                Err(e) => eprintln!("{e}"),
             }
-
-            let instance = {
-                let mut builder =
-                    br::InstanceBuilder::new("peridot2-test", (0, 1, 0), "Peridot 2", (0, 1, 0));
-                builder
-                    .add_extensions([
-                        "VK_EXT_debug_utils",
-                        "VK_MVK_macos_surface",
-                        "VK_KHR_surface",
-                    ])
-                    .add_layer("VK_LAYER_KHRONOS_validation");
-                if portability_enumeration_available {
-                    // Note: どうやらMoltenVKではこれが必要らしい https://stackoverflow.com/a/73408303
-                    builder
-                        .enumerate_portability()
-                        .add_extension("VK_KHR_get_physical_device_properties2");
-                }
-
-                builder.create().expect("Failed to create instance")
-            };
-
-            let _debug_utils_messenger = {
-                let builder = br::DebugUtilsMessengerCreateInfo::new(debug_utils_message);
-
-                builder
-                    .create(&instance)
-                    .expect("Failed to create debug messenger")
-            };
-
-            let adapter = instance
-                .iter_physical_devices()
-                .expect("Failed to enumerate adapters")
-                .next()
-                .expect("no vulkan devices");
-            let memory_properties = adapter.memory_properties();
-
-            let surface = (&adapter)
-                .new_surface_macos(layer.id() as _)
-                .expect("Failed to create vk surface");
-
-            let queue_info = adapter.queue_family_properties();
-            let graphics_queue_family_index = queue_info
-                .find_matching_index(br::QueueFlags::GRAPHICS)
-                .expect("no graphics queue family");
-            println!(
-                "graphics queue count: {}",
-                queue_info.queue_count(graphics_queue_family_index)
-            );
-            let device = {
-                let queue_family_builder =
-                    br::DeviceQueueCreateInfo::new(graphics_queue_family_index).add(0.0);
-                let mut builder = br::DeviceBuilder::new(&adapter);
-                builder
-                    .add_extension("VK_KHR_swapchain")
-                    .add_queue(queue_family_builder);
-                if portability_enumeration_available {
-                    builder.add_extension("VK_KHR_portability_subset");
-                }
-
-                builder.create().expect("Failed to create device")
-            };
-
-            let q = br::Device::queue(&device, graphics_queue_family_index, 0);
-
-            let surface_caps = adapter
-                .surface_capabilities(&surface)
-                .expect("Failed to get surface caps");
-            let surface_fmt = adapter
-                .surface_formats(&surface)
-                .expect("Failed to get surface formats");
-            let surface_pm = adapter
-                .surface_present_modes(&surface)
-                .expect("Failed to get surface presentation modes");
-            println!("** Surface Info **");
-            println!("*** Formats: {:?}", surface_fmt);
-            println!("*** Caps: {:?}", surface_caps);
-            println!("*** PresentModes: {:?}", surface_pm);
-
-            let sc_format = surface_fmt
-                .iter()
-                .find(|f| {
-                    f.format == br::vk::VK_FORMAT_R8G8B8A8_UNORM
-                        || f.format == br::vk::VK_FORMAT_B8G8R8A8_UNORM
-                })
-                .or_else(|| {
-                    surface_fmt.iter().find(|f| {
-                        f.format == br::vk::VK_FORMAT_R8G8B8A8_SRGB
-                            || f.format == br::vk::VK_FORMAT_B8G8R8A8_SRGB
-                    })
-                })
-                .expect("No suitable format supported");
-            let back_buffer_count = 2.clamp(surface_caps.minImageCount, surface_caps.maxImageCount);
-            let present_mode = surface_pm[0];
-            // TODO: 0xffff_ffffの場合はNSViewのサイズから取得する
-            let extent = surface_caps.currentExtent;
-            let swapchain = br::SwapchainBuilder::new(
-                surface,
-                back_buffer_count,
-                sc_format.clone(),
-                extent,
-                br::ImageUsageFlags::COLOR_ATTACHMENT,
-            )
-            .present_mode(present_mode)
-            .pre_transform(br::SurfaceTransform::Identity)
-            .composite_alpha(br::CompositeAlpha::Opaque)
-            .create(&device)
-            .expect("Failed to create swapchain");
-
-            crate::game::game_main(
-                crate::game::Engine {
-                    graphics_queue_family_index,
-                    q,
-                    swapchain: Arc::new(swapchain),
-                    memory_properties,
-                },
-                events_receiver,
-            )
-            .await;
-            // vulkan objects are terminated here(before replying ShouldTerminate)
         }
-        NSApplication::shared()
-            .expect("no shared app?")
-            .reply_to_application_should_terminate(true);
     });
     appdelegate.set_event_bus(&events_sender);
     appdelegate.set_display_timer(&mut timer);
@@ -349,7 +285,7 @@ pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
 extern "system" fn cv_display_link_callback(
     _display_link: CVDisplayLinkRef,
     _in_now: *const CVTimeStamp,
-    _in_output_time: *const CVTimeStamp,
+    in_output_time: *const CVTimeStamp,
     _flags_in: CVOptionFlags,
     _flags_out: *mut CVOptionFlags,
     context: *mut std::ffi::c_void,
@@ -363,29 +299,11 @@ extern "system" fn cv_display_link_callback(
     //     w.wake();
     // }
 
-    let event_bus = unsafe { &*(context as *const async_std::channel::Sender<EngineEvents>) };
-    async_std::task::block_on(event_bus.send(EngineEvents::NextFrame))
-        .expect("Failed to send next frame");
+    let frame_request_sender = unsafe { &*(context as *const FrameRequestSender) };
+    let timestamp = unsafe { in_output_time.as_ref() }
+        .filter(|t| t.videoTimeScale > 0)
+        .map(|t| std::time::Duration::from_secs_f64(t.videoTime as f64 / t.videoTimeScale as f64));
+    frame_request_sender.request(timestamp);
 
     0
 }
-
-extern "system" fn debug_utils_message(
-    severity: br::vk::VkDebugUtilsMessageSeverityFlagBitsEXT,
-    types: br::vk::VkDebugUtilsMessageTypeFlagsEXT,
-    data: *const br::vk::VkDebugUtilsMessengerCallbackDataEXT,
-    _user_data: *mut core::ffi::c_void,
-) -> br::vk::VkBool32 {
-    let data_ref = unsafe { data.as_ref().expect("null data") };
-    eprintln!("[{severity:08x}, {types:08x}] {}", unsafe {
-        std::ffi::CStr::from_ptr(data_ref.pMessage)
-            .to_str()
-            .expect("invalid message str")
-    });
-
-    if (severity & br::vk::VK_DEBUG_UTILS_MESSAGE_SEVERITY_ERROR_BIT_EXT) != 0 {
-        br::vk::VK_TRUE
-    } else {
-        br::vk::VK_FALSE
-    }
-}
@@ -1,6 +1,6 @@
-use std::sync::Arc;
+use std::sync::OnceLock;
 
-use bedrock::{self as br, Instance, PhysicalDevice};
+use bedrock as br;
 use windows::{
     core::PCSTR,
     Win32::{
@@ -10,22 +10,30 @@ use windows::{
         UI::WindowsAndMessaging::{
             AdjustWindowRectEx, CreateWindowExA, DefWindowProcA, DispatchMessageA, PeekMessageA,
             PostQuitMessage, RegisterClassExA, TranslateMessage, CW_USEDEFAULT, HCURSOR, HICON,
-            MSG, PM_REMOVE, WM_DESTROY, WM_QUIT, WNDCLASSEXA, WNDCLASS_STYLES, WS_CAPTION,
-            WS_EX_APPWINDOW, WS_MINIMIZEBOX, WS_OVERLAPPED, WS_SYSMENU, WS_VISIBLE,
+            MSG, PM_REMOVE, SIZE_MAXIMIZED, WM_DESTROY, WM_QUIT, WM_SIZE, WNDCLASSEXA,
+            WNDCLASS_STYLES, WS_CAPTION, WS_EX_APPWINDOW, WS_MAXIMIZEBOX, WS_MINIMIZEBOX,
+            WS_OVERLAPPED, WS_SYSMENU, WS_THICKFRAME, WS_VISIBLE,
         },
     },
 };
 
-use crate::game::EngineEvents;
+use crate::{
+    game::{EngineEvents, WindowState},
+    render::bootstrap::{self, BootstrapOptions},
+};
 
 #[repr(transparent)]
 pub struct ThreadSafeWindowHandle(pub HWND);
 unsafe impl Sync for ThreadSafeWindowHandle {}
 unsafe impl Send for ThreadSafeWindowHandle {}
 
+/// wndprocから参照するためのイベントバス
+static EVENT_BUS: OnceLock<async_std::channel::Sender<EngineEvents>> = OnceLock::new();
+
 pub async fn main() -> Result<(), Box<dyn core::error::Error>> {
     let (events_sender, events_receiver) = async_std::channel::unbounded();
-    let (frame_request_sender, frame_request_receiver) = async_std::channel::bounded(1);
+    let (frame_request_sender, frame_request_receiver) = crate::game::frame_request_bus();
+    let _ = EVENT_BUS.set(events_sender.clone());
 
     let hinstance = HINSTANCE(unsafe { GetModuleHandleA(None).unwrap().0 });
     let window_class = unsafe {
@@ -54,7 +62,13 @@ pub async fn main() -> Result<(), Box<dyn core::error::Error>> {
         right: 1280,
         bottom: 720,
     };
-    let style = WS_OVERLAPPED | WS_MINIMIZEBOX | WS_CAPTION | WS_SYSMENU | WS_VISIBLE;
+    let style = WS_OVERLAPPED
+        | WS_MINIMIZEBOX
+        | WS_MAXIMIZEBOX
+        | WS_THICKFRAME
+        | WS_CAPTION
+        | WS_SYSMENU
+        | WS_VISIBLE;
     let ex_style = WS_EX_APPWINDOW;
     unsafe {
         AdjustWindowRectEx(&mut wc_rect, style, false, ex_style).unwrap();
@@ -86,153 +100,15 @@ pub async fn main() -> Result<(), Box<dyn core::error::Error>> {
     let th = async_std::task::spawn(async move {
         let hinstance = HINSTANCE(unsafe { GetModuleHandleA(None).unwrap().0 });
 
-        for x in br::enumerate_extension_properties(None).expect("Failed to enumerate extensions") {
-            let ext_name = x.extensionName.as_cstr().unwrap().to_str().unwrap();
-            println!("vkext: {ext_name}");
-        }
-
-        for x in br::enumerate_layer_properties().expect("Failed to enumerate layers") {
-            let layer_name_cstr = x.layerName.as_cstr().unwrap();
-
-            println!(
-                "vk layer: {} {} {}",
-                layer_name_cstr.to_str().unwrap(),
-                x.specVersion,
-                x.implementationVersion
-            );
+        let options = BootstrapOptions::new(c"VK_KHR_win32_surface");
+        let instance = bootstrap::create_instance(&options)?;
+        let mut device_context =
+            bootstrap::DeviceContext::new(&instance, &options, |adapter| unsafe {
+                adapter.new_surface_win32(core::mem::transmute(hinstance), core::mem::transmute(hw))
+            })?;
+        let engine = device_context.engine(init_size)?;
 
-            for e in br::enumerate_extension_properties_cstr(Some(layer_name_cstr))
-                .expect("Failed to enumerate layer extensions")
-            {
-                println!(
-                    "* vkext: {}",
-                    e.extensionName.as_cstr().unwrap().to_str().unwrap()
-                );
-            }
-        }
-
-        let instance = {
-            let app =
-                br::ApplicationInfo::new(c"peridot2-test", (0, 1, 0), c"Peridot 2", (0, 1, 0));
-            let mut builder = br::InstanceBuilder::new(&app);
-            builder
-                .add_extensions([
-                    c"VK_EXT_debug_utils",
-                    c"VK_KHR_win32_surface",
-                    c"VK_KHR_surface",
-                ])
-                .add_layer(c"VK_LAYER_KHRONOS_validation");
-
-            builder.create().expect("Failed to create instance")
-        };
-
-        let _debug_utils_messenger = {
-            let builder = br::DebugUtilsMessengerCreateInfo::new(debug_utils_message);
-
-            builder
-                .create(&instance)
-                .expect("Failed to create debug messenger")
-        };
-
-        let adapter = instance
-            .iter_physical_devices()
-            .expect("Failed to enumerate adapters")
-            .next()
-            .expect("no vulkan devices");
-        let memory_properties = adapter.memory_properties();
-
-        let surface = unsafe {
-            (&adapter)
-                .new_surface_win32(core::mem::transmute(hinstance), core::mem::transmute(hw))
-                .expect("Failed to create vk surface")
-        };
-
-        let queue_info = adapter.queue_family_properties();
-        let graphics_queue_family_index = queue_info
-            .find_matching_index(br::QueueFlags::GRAPHICS)
-            .expect("no graphics queue family");
-        println!(
-            "graphics queue count: {}",
-            queue_info.queue_count(graphics_queue_family_index)
-        );
-        let device = {
-            let queue_family_builder =
-                br::DeviceQueueCreateInfo::new(graphics_queue_family_index, &[0.0]);
-            let mut builder = br::DeviceBuilder::new(&adapter);
-            builder
-                .add_extension(c"VK_KHR_swapchain")
-                .add_queue(queue_family_builder);
-
-            builder.create().expect("Failed to create device")
-        };
-
-        let q = br::Device::queue(&device, graphics_queue_family_index, 0);
-
-        let surface_caps = adapter
-            .surface_capabilities(&surface)
-            .expect("Failed to get surface caps");
-        let surface_fmt = adapter
-            .surface_formats(&surface)
-            .expect("Failed to get surface formats");
-        let surface_pm = adapter
-            .surface_present_modes(&surface)
-            .expect("Failed to get surface presentation modes");
-        println!("** Surface Info **");
-        println!("*** Formats: {:?}", surface_fmt);
-        println!("*** Caps: {:?}", surface_caps);
-        println!("*** PresentModes: {:?}", surface_pm);
-
-        let sc_format = surface_fmt
-            .iter()
-            .find(|f| {
-                f.format == br::vk::VK_FORMAT_R8G8B8A8_UNORM
-                    || f.format == br::vk::VK_FORMAT_B8G8R8A8_UNORM
-            })
-            .or_else(|| {
-                surface_fmt.iter().find(|f| {
-                    f.format == br::vk::VK_FORMAT_R8G8B8A8_SRGB
-                        || f.format == br::vk::VK_FORMAT_B8G8R8A8_SRGB
-                })
-            })
-            .expect("No suitable format supported");
-        let back_buffer_count = 2.clamp(surface_caps.minImageCount, surface_caps.maxImageCount);
-        let present_mode = surface_pm[0];
-        let extent = br::vk::VkExtent2D {
-            width: if surface_caps.currentExtent.width == 0xffff_ffff {
-                init_size.width
-            } else {
-                surface_caps.currentExtent.width
-            },
-            height: if surface_caps.currentExtent.height == 0xffff_ffff {
-                init_size.height
-            } else {
-                surface_caps.currentExtent.height
-            },
-        };
-        let swapchain = br::SwapchainBuilder::new(
-            surface,
-            back_buffer_count,
-            sc_format.clone(),
-            extent,
-            br::ImageUsageFlags::COLOR_ATTACHMENT,
-        )
-        .present_mode(present_mode)
-        .pre_transform(br::SurfaceTransform::Identity)
-        .composite_alpha(br::CompositeAlpha::Opaque)
-        .create(&device)
-        .expect("Failed to create swapchain");
-
-        crate::game::game_main(
-            crate::game::Engine {
-                graphics_queue_family_index,
-                q,
-                swapchain: Arc::new(swapchain),
-                memory_properties,
-            },
-            events_receiver,
-            frame_request_receiver,
-        )
-        .await;
+        crate::game::game_main(engine, events_receiver, frame_request_receiver).await
     });
 
     let mut msg = core::mem::MaybeUninit::<MSG>::uninit();
@@ -249,19 +125,15 @@ pub async fn main() -> Result<(), Box<dyn core::error::Error>> {
             }
         }
 
-        match frame_request_sender.try_send(()) {
-            Ok(_) => (),
-            Err(async_std::channel::TrySendError::Full(_)) => (),
-            Err(async_std::channel::TrySendError::Closed(_)) => {
-                // event bus gone
-                break;
-            }
+        if !frame_request_sender.request(None) {
+            // event bus gone
+            break;
         }
     }
 
-    if events_sender.send(EngineEvents::Shutdown).await.is_ok() {
-        th.await;
-    }
+    // the game loop may have already gone(e.g. on errors)
+    let _ = events_sender.send(EngineEvents::Shutdown).await;
+    th.await?;
 
     Ok(())
 }
@@ -273,26 +145,22 @@ extern "system" fn wndproc(hwnd: HWND, msg: u32, wp: WPARAM, lp: LPARAM) -> LRES
         }
         return LRESULT(0);
     }
+    if msg == WM_SIZE {
+        let (width, height) = (lp.0 as u32 & 0xffff, (lp.0 as u32 >> 16) & 0xffff);
+        let states = if wp.0 as u32 == SIZE_MAXIMIZED {
+            vec![WindowState::Maximized]
+        } else {
+            Vec::new()
+        };
+        if let Some(bus) = EVENT_BUS.get() {
+            let _ = bus.try_send(EngineEvents::Resized {
+                width,
+                height,
+                states,
+            });
+        }
+        return LRESULT(0);
+    }
 
     unsafe { DefWindowProcA(hwnd, msg, wp, lp) }
 }
-
-extern "system" fn debug_utils_message(
-    severity: br::vk::VkDebugUtilsMessageSeverityFlagBitsEXT,
-    types: br::vk::VkDebugUtilsMessageTypeFlagsEXT,
-    data: *const br::vk::VkDebugUtilsMessengerCallbackDataEXT,
-    _user_data: *mut core::ffi::c_void,
-) -> br::vk::VkBool32 {
-    let data_ref = unsafe { data.as_ref().expect("null data") };
-    eprintln!("[{severity:08x}, {types:08x}] {}", unsafe {
-        std::ffi::CStr::from_ptr(data_ref.pMessage)
-            .to_str()
-            .expect("invalid message str")
-    });
-
-    if (severity & br::vk::VK_DEBUG_UTILS_MESSAGE_SEVERITY_ERROR_BIT_EXT) != 0 {
-        br::vk::VK_TRUE
-    } else {
-        br::vk::VK_FALSE
-    }
-}
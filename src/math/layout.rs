@@ -0,0 +1,184 @@
+//! std140/std430 layout checks for structs shared with shaders.
+//!
+//! Structs declared with [`std140_struct!`] or [`std430_struct!`] are `#[repr(C)]`, and fail to
+//! compile if any field offset differs from the GLSL layout rules. Paddings have to be written
+//! explicitly(e.g. `vec3` followed by a scalar is fine, but arrays of scalars are not std140-compatible).
+
+use super::{Mat4, Vec2, Vec3, Vec4};
+
+/// Types usable as members of layout-checked structs.
+pub trait UniformField: Copy {
+    /// Base alignment in std140
+    const STD140_ALIGN: usize;
+    /// Base alignment in std430
+    const STD430_ALIGN: usize;
+    /// false if the Rust layout of the type itself does not match std140(e.g. array strides)
+    const STD140_VALID: bool = true;
+    /// false if the Rust layout of the type itself does not match std430
+    const STD430_VALID: bool = true;
+}
+
+/// Field of a layout-checked struct, compared with shader blocks by reflection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FieldLayout {
+    pub name: &'static str,
+    pub offset: usize,
+    pub size: usize,
+}
+
+/// Structs declared with [`std140_struct!`] or [`std430_struct!`].
+pub trait UniformStruct: UniformField {
+    /// In declaration order
+    const FIELDS: &'static [FieldLayout];
+}
+
+pub const fn align_up(offset: usize, alignment: usize) -> usize {
+    offset.div_ceil(alignment) * alignment
+}
+
+pub const fn max(a: usize, b: usize) -> usize {
+    if a > b {
+        a
+    } else {
+        b
+    }
+}
+
+macro_rules! uniform_field {
+    ($t: ty, $align: expr) => {
+        impl UniformField for $t {
+            const STD140_ALIGN: usize = $align;
+            const STD430_ALIGN: usize = $align;
+        }
+    };
+}
+uniform_field!(f32, 4);
+uniform_field!(i32, 4);
+uniform_field!(u32, 4);
+uniform_field!(Vec2, 8);
+// Note: vec3はvec4と同じアラインメント(サイズは12なので後ろにスカラーを詰められる)
+uniform_field!(Vec3, 16);
+uniform_field!(Vec4, 16);
+uniform_field!(Mat4, 16);
+
+/// Array strides are the element size rounded up to the base alignment(16 bytes in std140).
+impl<T: UniformField, const N: usize> UniformField for [T; N] {
+    const STD140_ALIGN: usize = align_up(T::STD140_ALIGN, 16);
+    const STD430_ALIGN: usize = T::STD430_ALIGN;
+    const STD140_VALID: bool = T::STD140_VALID
+        && core::mem::size_of::<T>() == align_up(core::mem::size_of::<T>(), Self::STD140_ALIGN);
+    const STD430_VALID: bool = T::STD430_VALID
+        && core::mem::size_of::<T>() == align_up(core::mem::size_of::<T>(), T::STD430_ALIGN);
+}
+
+/// Implements [`UniformField`] for a `#[repr(C)]` struct by checking every field offset.
+///
+/// Struct sizes must be multiples of their base alignment(16 bytes in std140), so that members
+/// following them start at the right offset.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __uniform_struct_impl {
+    ($name: ident { $($field: ident : $t: ty),* }) => {
+        impl $crate::math::UniformField for $name {
+            const STD140_ALIGN: usize = {
+                let mut a = 16;
+                $(a = $crate::math::layout::max(a, <$t as $crate::math::UniformField>::STD140_ALIGN);)*
+                a
+            };
+            const STD430_ALIGN: usize = {
+                let mut a = 4;
+                $(a = $crate::math::layout::max(a, <$t as $crate::math::UniformField>::STD430_ALIGN);)*
+                a
+            };
+            const STD140_VALID: bool = {
+                let mut offset = 0;
+                let mut valid = core::mem::size_of::<$name>() % Self::STD140_ALIGN == 0;
+                $(
+                    offset = $crate::math::layout::align_up(
+                        offset,
+                        <$t as $crate::math::UniformField>::STD140_ALIGN,
+                    );
+                    valid = valid
+                        && <$t as $crate::math::UniformField>::STD140_VALID
+                        && core::mem::offset_of!($name, $field) == offset;
+                    offset += core::mem::size_of::<$t>();
+                )*
+                let _ = offset;
+                valid
+            };
+            const STD430_VALID: bool = {
+                let mut offset = 0;
+                let mut valid = core::mem::size_of::<$name>() % Self::STD430_ALIGN == 0;
+                $(
+                    offset = $crate::math::layout::align_up(
+                        offset,
+                        <$t as $crate::math::UniformField>::STD430_ALIGN,
+                    );
+                    valid = valid
+                        && <$t as $crate::math::UniformField>::STD430_VALID
+                        && core::mem::offset_of!($name, $field) == offset;
+                    offset += core::mem::size_of::<$t>();
+                )*
+                let _ = offset;
+                valid
+            };
+        }
+        impl $crate::math::layout::UniformStruct for $name {
+            const FIELDS: &'static [$crate::math::layout::FieldLayout] = &[
+                $($crate::math::layout::FieldLayout {
+                    name: stringify!($field),
+                    offset: core::mem::offset_of!($name, $field),
+                    size: core::mem::size_of::<$t>(),
+                }),*
+            ];
+        }
+    };
+}
+
+/// Declares a `#[repr(C)]` struct whose layout is checked against std140 at compile time
+/// (for uniform buffers).
+#[macro_export]
+macro_rules! std140_struct {
+    (
+        $(#[$meta: meta])*
+        $vis: vis struct $name: ident {
+            $($(#[$fmeta: meta])* $fvis: vis $field: ident : $t: ty),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[repr(C)]
+        #[derive(Clone, Copy)]
+        $vis struct $name {
+            $($(#[$fmeta])* $fvis $field: $t),*
+        }
+        $crate::__uniform_struct_impl!($name { $($field: $t),* });
+        const _: () = assert!(
+            <$name as $crate::math::UniformField>::STD140_VALID,
+            concat!("layout of ", stringify!($name), " does not match std140"),
+        );
+    };
+}
+
+/// Declares a `#[repr(C)]` struct whose layout is checked against std430 at compile time
+/// (for storage buffers and push constants).
+#[macro_export]
+macro_rules! std430_struct {
+    (
+        $(#[$meta: meta])*
+        $vis: vis struct $name: ident {
+            $($(#[$fmeta: meta])* $fvis: vis $field: ident : $t: ty),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[repr(C)]
+        #[derive(Clone, Copy)]
+        $vis struct $name {
+            $($(#[$fmeta])* $fvis $field: $t),*
+        }
+        $crate::__uniform_struct_impl!($name { $($field: $t),* });
+        const _: () = assert!(
+            <$name as $crate::math::UniformField>::STD430_VALID,
+            concat!("layout of ", stringify!($name), " does not match std430"),
+        );
+    };
+}
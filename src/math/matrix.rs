@@ -0,0 +1,411 @@
+//! 4x4 matrix.
+
+use core::ops::{Mul, MulAssign};
+
+use super::{Quat, Vec3, Vec4};
+
+/// Column-major 4x4 matrix. Same memory layout as GLSL `mat4`(4 columns of `vec4`).
+///
+/// Transforms operate on column vectors(`m * v`), so `a * b` applies `b` first.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat4 {
+    pub cols: [Vec4; 4],
+}
+impl Default for Mat4 {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+impl Mat4 {
+    pub const IDENTITY: Self = Self::from_cols(
+        Vec4::new(1.0, 0.0, 0.0, 0.0),
+        Vec4::new(0.0, 1.0, 0.0, 0.0),
+        Vec4::new(0.0, 0.0, 1.0, 0.0),
+        Vec4::new(0.0, 0.0, 0.0, 1.0),
+    );
+
+    pub const fn from_cols(c0: Vec4, c1: Vec4, c2: Vec4, c3: Vec4) -> Self {
+        Self {
+            cols: [c0, c1, c2, c3],
+        }
+    }
+
+    /// Elements in column-major order
+    pub const fn from_cols_array(a: [f32; 16]) -> Self {
+        Self::from_cols(
+            Vec4::new(a[0], a[1], a[2], a[3]),
+            Vec4::new(a[4], a[5], a[6], a[7]),
+            Vec4::new(a[8], a[9], a[10], a[11]),
+            Vec4::new(a[12], a[13], a[14], a[15]),
+        )
+    }
+
+    /// Elements in column-major order
+    pub const fn to_cols_array(&self) -> [f32; 16] {
+        let [c0, c1, c2, c3] = self.cols;
+
+        [
+            c0.x, c0.y, c0.z, c0.w, c1.x, c1.y, c1.z, c1.w, c2.x, c2.y, c2.z, c2.w, c3.x, c3.y,
+            c3.z, c3.w,
+        ]
+    }
+
+    pub fn row(&self, n: usize) -> Vec4 {
+        let component = |c: &Vec4| c.to_array()[n];
+
+        Vec4::new(
+            component(&self.cols[0]),
+            component(&self.cols[1]),
+            component(&self.cols[2]),
+            component(&self.cols[3]),
+        )
+    }
+
+    pub fn transpose(&self) -> Self {
+        Self::from_cols(self.row(0), self.row(1), self.row(2), self.row(3))
+    }
+
+    pub const fn translation(t: Vec3) -> Self {
+        let mut m = Self::IDENTITY;
+        m.cols[3] = t.extend(1.0);
+
+        m
+    }
+
+    pub const fn scale(s: Vec3) -> Self {
+        Self::from_cols(
+            Vec4::new(s.x, 0.0, 0.0, 0.0),
+            Vec4::new(0.0, s.y, 0.0, 0.0),
+            Vec4::new(0.0, 0.0, s.z, 0.0),
+            Vec4::new(0.0, 0.0, 0.0, 1.0),
+        )
+    }
+
+    pub fn rotation(q: Quat) -> Self {
+        let Quat { x, y, z, w } = q.normalize();
+        let (x2, y2, z2) = (x + x, y + y, z + z);
+        let (xx, yy, zz) = (x * x2, y * y2, z * z2);
+        let (xy, xz, yz) = (x * y2, x * z2, y * z2);
+        let (wx, wy, wz) = (w * x2, w * y2, w * z2);
+
+        Self::from_cols(
+            Vec4::new(1.0 - (yy + zz), xy + wz, xz - wy, 0.0),
+            Vec4::new(xy - wz, 1.0 - (xx + zz), yz + wx, 0.0),
+            Vec4::new(xz + wy, yz - wx, 1.0 - (xx + yy), 0.0),
+            Vec4::new(0.0, 0.0, 0.0, 1.0),
+        )
+    }
+
+    /// `translation * rotation * scale`(scale is applied first)
+    pub fn from_trs(translation: Vec3, rotation: Quat, scale: Vec3) -> Self {
+        let mut m = Self::rotation(rotation);
+        m.cols[0] *= scale.x;
+        m.cols[1] *= scale.y;
+        m.cols[2] *= scale.z;
+        m.cols[3] = translation.extend(1.0);
+
+        m
+    }
+
+    /// Perspective projection into Vulkan clip space(Y down, depth 0..1) from right-handed view space.
+    pub fn perspective(fov_y_radians: f32, aspect: f32, near: f32, far: f32) -> Self {
+        let f = 1.0 / (fov_y_radians * 0.5).tan();
+
+        Self::from_cols(
+            Vec4::new(f / aspect, 0.0, 0.0, 0.0),
+            Vec4::new(0.0, -f, 0.0, 0.0),
+            Vec4::new(0.0, 0.0, far / (near - far), -1.0),
+            Vec4::new(0.0, 0.0, near * far / (near - far), 0.0),
+        )
+    }
+
+    /// Orthographic projection into Vulkan clip space(Y down, depth 0..1) from right-handed view space.
+    pub fn orthographic(left: f32, right: f32, bottom: f32, top: f32, near: f32, far: f32) -> Self {
+        let (w, h) = (right - left, top - bottom);
+
+        Self::from_cols(
+            Vec4::new(2.0 / w, 0.0, 0.0, 0.0),
+            Vec4::new(0.0, -2.0 / h, 0.0, 0.0),
+            Vec4::new(0.0, 0.0, 1.0 / (near - far), 0.0),
+            Vec4::new(
+                -(right + left) / w,
+                (top + bottom) / h,
+                near / (near - far),
+                1.0,
+            ),
+        )
+    }
+
+    /// Right-handed view matrix(the camera looks towards -Z)
+    pub fn look_at(eye: Vec3, target: Vec3, up: Vec3) -> Self {
+        let f = (target - eye).normalize();
+        let s = f.cross(up).normalize();
+        let u = s.cross(f);
+
+        Self::from_cols(
+            Vec4::new(s.x, u.x, -f.x, 0.0),
+            Vec4::new(s.y, u.y, -f.y, 0.0),
+            Vec4::new(s.z, u.z, -f.z, 0.0),
+            Vec4::new(-s.dot(eye), -u.dot(eye), f.dot(eye), 1.0),
+        )
+    }
+
+    /// General inverse. None if the matrix is singular.
+    pub fn inverse(&self) -> Option<Self> {
+        let m = self.to_cols_array();
+        let mut inv = [0.0f32; 16];
+
+        inv[0] = m[5] * m[10] * m[15] - m[5] * m[11] * m[14] - m[9] * m[6] * m[15]
+            + m[9] * m[7] * m[14]
+            + m[13] * m[6] * m[11]
+            - m[13] * m[7] * m[10];
+        inv[4] = -m[4] * m[10] * m[15] + m[4] * m[11] * m[14] + m[8] * m[6] * m[15]
+            - m[8] * m[7] * m[14]
+            - m[12] * m[6] * m[11]
+            + m[12] * m[7] * m[10];
+        inv[8] = m[4] * m[9] * m[15] - m[4] * m[11] * m[13] - m[8] * m[5] * m[15]
+            + m[8] * m[7] * m[13]
+            + m[12] * m[5] * m[11]
+            - m[12] * m[7] * m[9];
+        inv[12] = -m[4] * m[9] * m[14] + m[4] * m[10] * m[13] + m[8] * m[5] * m[14]
+            - m[8] * m[6] * m[13]
+            - m[12] * m[5] * m[10]
+            + m[12] * m[6] * m[9];
+        inv[1] = -m[1] * m[10] * m[15] + m[1] * m[11] * m[14] + m[9] * m[2] * m[15]
+            - m[9] * m[3] * m[14]
+            - m[13] * m[2] * m[11]
+            + m[13] * m[3] * m[10];
+        inv[5] = m[0] * m[10] * m[15] - m[0] * m[11] * m[14] - m[8] * m[2] * m[15]
+            + m[8] * m[3] * m[14]
+            + m[12] * m[2] * m[11]
+            - m[12] * m[3] * m[10];
+        inv[9] = -m[0] * m[9] * m[15] + m[0] * m[11] * m[13] + m[8] * m[1] * m[15]
+            - m[8] * m[3] * m[13]
+            - m[12] * m[1] * m[11]
+            + m[12] * m[3] * m[9];
+        inv[13] = m[0] * m[9] * m[14] - m[0] * m[10] * m[13] - m[8] * m[1] * m[14]
+            + m[8] * m[2] * m[13]
+            + m[12] * m[1] * m[10]
+            - m[12] * m[2] * m[9];
+        inv[2] = m[1] * m[6] * m[15] - m[1] * m[7] * m[14] - m[5] * m[2] * m[15]
+            + m[5] * m[3] * m[14]
+            + m[13] * m[2] * m[7]
+            - m[13] * m[3] * m[6];
+        inv[6] = -m[0] * m[6] * m[15] + m[0] * m[7] * m[14] + m[4] * m[2] * m[15]
+            - m[4] * m[3] * m[14]
+            - m[12] * m[2] * m[7]
+            + m[12] * m[3] * m[6];
+        inv[10] = m[0] * m[5] * m[15] - m[0] * m[7] * m[13] - m[4] * m[1] * m[15]
+            + m[4] * m[3] * m[13]
+            + m[12] * m[1] * m[7]
+            - m[12] * m[3] * m[5];
+        inv[14] = -m[0] * m[5] * m[14] + m[0] * m[6] * m[13] + m[4] * m[1] * m[14]
+            - m[4] * m[2] * m[13]
+            - m[12] * m[1] * m[6]
+            + m[12] * m[2] * m[5];
+        inv[3] = -m[1] * m[6] * m[11] + m[1] * m[7] * m[10] + m[5] * m[2] * m[11]
+            - m[5] * m[3] * m[10]
+            - m[9] * m[2] * m[7]
+            + m[9] * m[3] * m[6];
+        inv[7] = m[0] * m[6] * m[11] - m[0] * m[7] * m[10] - m[4] * m[2] * m[11]
+            + m[4] * m[3] * m[10]
+            + m[8] * m[2] * m[7]
+            - m[8] * m[3] * m[6];
+        inv[11] = -m[0] * m[5] * m[11] + m[0] * m[7] * m[9] + m[4] * m[1] * m[11]
+            - m[4] * m[3] * m[9]
+            - m[8] * m[1] * m[7]
+            + m[8] * m[3] * m[5];
+        inv[15] = m[0] * m[5] * m[10] - m[0] * m[6] * m[9] - m[4] * m[1] * m[10]
+            + m[4] * m[2] * m[9]
+            + m[8] * m[1] * m[6]
+            - m[8] * m[2] * m[5];
+
+        let det = m[0] * inv[0] + m[1] * inv[4] + m[2] * inv[8] + m[3] * inv[12];
+        if det == 0.0 {
+            return None;
+        }
+
+        Some(Self::from_cols_array(inv.map(|x| x / det)))
+    }
+}
+impl Mul for Mat4 {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        Self {
+            cols: rhs.cols.map(|c| self * c),
+        }
+    }
+}
+impl MulAssign for Mat4 {
+    fn mul_assign(&mut self, rhs: Self) {
+        *self = *self * rhs;
+    }
+}
+impl Mul<Vec4> for Mat4 {
+    type Output = Vec4;
+
+    fn mul(self, rhs: Vec4) -> Vec4 {
+        self.cols[0] * rhs.x + self.cols[1] * rhs.y + self.cols[2] * rhs.z + self.cols[3] * rhs.w
+    }
+}
+
+// GLSL mat4: 4 columns of vec4 without paddings(both in std140 and std430)
+const _: () = assert!(core::mem::size_of::<Mat4>() == 64);
+const _: () = assert!(core::mem::offset_of!(Mat4, cols) == 0);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-4;
+
+    fn assert_near(actual: Mat4, expected: Mat4) {
+        let (a, e) = (actual.to_cols_array(), expected.to_cols_array());
+        assert!(
+            a.iter().zip(&e).all(|(a, e)| (a - e).abs() < EPSILON),
+            "{actual:?} != {expected:?}"
+        );
+    }
+
+    fn assert_near_vec(actual: Vec4, expected: Vec4) {
+        assert!(
+            (actual - expected).length() < EPSILON,
+            "{actual:?} != {expected:?}"
+        );
+    }
+
+    /// Clip space position divided by w
+    fn project(m: Mat4, p: Vec3) -> Vec4 {
+        let c = m * p.extend(1.0);
+
+        c / c.w
+    }
+
+    #[test]
+    fn perspective_maps_depth_to_zero_one() {
+        let m = Mat4::perspective(90f32.to_radians(), 2.0, 0.1, 100.0);
+
+        assert_near_vec(
+            project(m, Vec3::new(0.0, 0.0, -0.1)),
+            Vec4::new(0.0, 0.0, 0.0, 1.0),
+        );
+        assert_near_vec(
+            project(m, Vec3::new(0.0, 0.0, -100.0)),
+            Vec4::new(0.0, 0.0, 1.0, 1.0),
+        );
+        // the top-right corner of the near plane(aspect 2: twice as wide as tall)
+        let p = project(m, Vec3::new(0.2, 0.1, -0.1));
+        assert!((p.x - 1.0).abs() < EPSILON && (p.y + 1.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn orthographic_maps_depth_to_zero_one() {
+        let m = Mat4::orthographic(-2.0, 2.0, -1.0, 1.0, 0.5, 10.0);
+
+        assert_near_vec(
+            project(m, Vec3::new(-2.0, 1.0, -0.5)),
+            Vec4::new(-1.0, -1.0, 0.0, 1.0),
+        );
+        assert_near_vec(
+            project(m, Vec3::new(2.0, -1.0, -10.0)),
+            Vec4::new(1.0, 1.0, 1.0, 1.0),
+        );
+    }
+
+    #[test]
+    fn look_at_faces_negative_z() {
+        let m = Mat4::look_at(Vec3::new(3.0, 0.0, 0.0), Vec3::ZERO, Vec3::Y);
+
+        assert_near_vec(
+            m * Vec4::new(3.0, 0.0, 0.0, 1.0),
+            Vec4::new(0.0, 0.0, 0.0, 1.0),
+        );
+        assert_near_vec(
+            m * Vec4::new(0.0, 0.0, 0.0, 1.0),
+            Vec4::new(0.0, 0.0, -3.0, 1.0),
+        );
+        // up stays up, and +Z of the world is on the left when looking towards -X
+        assert_near_vec(
+            m * Vec4::new(0.0, 1.0, 0.0, 0.0),
+            Vec4::new(0.0, 1.0, 0.0, 0.0),
+        );
+        assert_near_vec(
+            m * Vec4::new(0.0, 0.0, 1.0, 0.0),
+            Vec4::new(-1.0, 0.0, 0.0, 0.0),
+        );
+    }
+
+    #[test]
+    fn inverse_undoes_transforms() {
+        let m = Mat4::perspective(1.0, 1.5, 0.1, 50.0)
+            * Mat4::look_at(Vec3::new(1.0, 2.0, 3.0), Vec3::ZERO, Vec3::Y)
+            * Mat4::from_trs(
+                Vec3::new(4.0, -5.0, 6.0),
+                Quat::from_axis_angle(Vec3::new(1.0, 1.0, 0.0), 0.7),
+                Vec3::new(2.0, 0.5, 3.0),
+            );
+
+        let inv = m.inverse().unwrap();
+        assert_near(inv * m, Mat4::IDENTITY);
+        assert_near(m * inv, Mat4::IDENTITY);
+    }
+
+    #[test]
+    fn singular_matrices_have_no_inverse() {
+        assert_eq!(Mat4::scale(Vec3::new(1.0, 0.0, 1.0)).inverse(), None);
+    }
+
+    #[test]
+    fn rotation_matches_axis_angle() {
+        let m = Mat4::rotation(Quat::rotation_z(90f32.to_radians()));
+        assert_near_vec(
+            m * Vec4::new(1.0, 0.0, 0.0, 0.0),
+            Vec4::new(0.0, 1.0, 0.0, 0.0),
+        );
+        assert_near_vec(
+            m * Vec4::new(0.0, 1.0, 0.0, 0.0),
+            Vec4::new(-1.0, 0.0, 0.0, 0.0),
+        );
+
+        // 120 degrees around (1, 1, 1) cycles the axes
+        let m = Mat4::rotation(Quat::from_axis_angle(Vec3::ONE, 120f32.to_radians()));
+        assert_near_vec(
+            m * Vec4::new(1.0, 0.0, 0.0, 0.0),
+            Vec4::new(0.0, 1.0, 0.0, 0.0),
+        );
+        assert_near_vec(
+            m * Vec4::new(0.0, 1.0, 0.0, 0.0),
+            Vec4::new(0.0, 0.0, 1.0, 0.0),
+        );
+    }
+
+    #[test]
+    fn quaternion_products_match_matrix_products() {
+        let a = Quat::from_axis_angle(Vec3::new(0.0, 1.0, 2.0), 0.4);
+        let b = Quat::from_axis_angle(Vec3::new(-1.0, 0.5, 0.0), 1.3);
+
+        assert_near(Mat4::rotation(a * b), Mat4::rotation(a) * Mat4::rotation(b));
+    }
+
+    #[test]
+    fn from_trs_scales_first() {
+        let m = Mat4::from_trs(
+            Vec3::new(1.0, 2.0, 3.0),
+            Quat::rotation_z(90f32.to_radians()),
+            Vec3::new(2.0, 1.0, 1.0),
+        );
+
+        assert_near_vec(
+            m * Vec4::new(1.0, 0.0, 0.0, 1.0),
+            Vec4::new(1.0, 4.0, 3.0, 1.0),
+        );
+        assert_near(
+            m,
+            Mat4::translation(Vec3::new(1.0, 2.0, 3.0))
+                * Mat4::rotation(Quat::rotation_z(90f32.to_radians()))
+                * Mat4::scale(Vec3::new(2.0, 1.0, 1.0)),
+        );
+    }
+}
@@ -0,0 +1,12 @@
+//! Vector, matrix and quaternion types for transforms, laid out to be shared with shaders.
+
+pub mod layout;
+mod matrix;
+mod quat;
+mod vector;
+
+pub use self::layout::{UniformField, UniformStruct};
+pub use self::matrix::Mat4;
+pub use self::quat::Quat;
+pub use self::vector::{Vec2, Vec3, Vec4};
+pub use crate::{std140_struct, std430_struct};
@@ -0,0 +1,65 @@
+//! Rotation quaternion.
+
+use core::ops::Mul;
+
+use super::Vec3;
+
+/// Unit quaternion(`w + xi + yj + zk`) representing a rotation.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quat {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub w: f32,
+}
+impl Default for Quat {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+impl Quat {
+    pub const IDENTITY: Self = Self::from_xyzw(0.0, 0.0, 0.0, 1.0);
+
+    pub const fn from_xyzw(x: f32, y: f32, z: f32, w: f32) -> Self {
+        Self { x, y, z, w }
+    }
+
+    /// Counter-clockwise rotation around the axis(looking from the tip of the axis)
+    pub fn from_axis_angle(axis: Vec3, radians: f32) -> Self {
+        let (s, c) = (radians * 0.5).sin_cos();
+        let axis = axis.normalize() * s;
+
+        Self::from_xyzw(axis.x, axis.y, axis.z, c)
+    }
+
+    pub fn rotation_z(radians: f32) -> Self {
+        Self::from_axis_angle(Vec3::Z, radians)
+    }
+
+    pub fn dot(self, other: Self) -> f32 {
+        self.x * other.x + self.y * other.y + self.z * other.z + self.w * other.w
+    }
+
+    pub fn normalize(self) -> Self {
+        let l = self.dot(self).sqrt();
+        if l == 0.0 {
+            return Self::IDENTITY;
+        }
+
+        Self::from_xyzw(self.x / l, self.y / l, self.z / l, self.w / l)
+    }
+}
+/// `a * b` rotates by `b` first
+impl Mul for Quat {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        Self::from_xyzw(
+            self.w * rhs.x + self.x * rhs.w + self.y * rhs.z - self.z * rhs.y,
+            self.w * rhs.y - self.x * rhs.z + self.y * rhs.w + self.z * rhs.x,
+            self.w * rhs.z + self.x * rhs.y - self.y * rhs.x + self.z * rhs.w,
+            self.w * rhs.w - self.x * rhs.x - self.y * rhs.y - self.z * rhs.z,
+        )
+    }
+}
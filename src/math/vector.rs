@@ -0,0 +1,163 @@
+//! Vector types. Layouts are the same as the corresponding GLSL types(without paddings).
+
+use core::ops::{Add, AddAssign, Div, Mul, MulAssign, Neg, Sub, SubAssign};
+
+macro_rules! vector_ops {
+    ($t: ident { $($c: ident),+ }) => {
+        impl $t {
+            pub const ZERO: Self = Self { $($c: 0.0),+ };
+
+            pub const fn new($($c: f32),+) -> Self {
+                Self { $($c),+ }
+            }
+
+            pub const fn splat(v: f32) -> Self {
+                Self { $($c: v),+ }
+            }
+
+            pub fn dot(self, other: Self) -> f32 {
+                0.0 $(+ self.$c * other.$c)+
+            }
+
+            pub fn length_squared(self) -> f32 {
+                self.dot(self)
+            }
+
+            pub fn length(self) -> f32 {
+                self.length_squared().sqrt()
+            }
+
+            /// Zero vectors are returned as is
+            pub fn normalize(self) -> Self {
+                let l = self.length();
+                if l == 0.0 {
+                    return self;
+                }
+
+                self / l
+            }
+        }
+        impl Add for $t {
+            type Output = Self;
+
+            fn add(self, rhs: Self) -> Self {
+                Self { $($c: self.$c + rhs.$c),+ }
+            }
+        }
+        impl AddAssign for $t {
+            fn add_assign(&mut self, rhs: Self) {
+                *self = *self + rhs;
+            }
+        }
+        impl Sub for $t {
+            type Output = Self;
+
+            fn sub(self, rhs: Self) -> Self {
+                Self { $($c: self.$c - rhs.$c),+ }
+            }
+        }
+        impl SubAssign for $t {
+            fn sub_assign(&mut self, rhs: Self) {
+                *self = *self - rhs;
+            }
+        }
+        impl Mul<f32> for $t {
+            type Output = Self;
+
+            fn mul(self, rhs: f32) -> Self {
+                Self { $($c: self.$c * rhs),+ }
+            }
+        }
+        impl MulAssign<f32> for $t {
+            fn mul_assign(&mut self, rhs: f32) {
+                *self = *self * rhs;
+            }
+        }
+        /// Component-wise product
+        impl Mul for $t {
+            type Output = Self;
+
+            fn mul(self, rhs: Self) -> Self {
+                Self { $($c: self.$c * rhs.$c),+ }
+            }
+        }
+        impl Div<f32> for $t {
+            type Output = Self;
+
+            fn div(self, rhs: f32) -> Self {
+                Self { $($c: self.$c / rhs),+ }
+            }
+        }
+        impl Neg for $t {
+            type Output = Self;
+
+            fn neg(self) -> Self {
+                Self { $($c: -self.$c),+ }
+            }
+        }
+    };
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Vec2 {
+    pub x: f32,
+    pub y: f32,
+}
+vector_ops!(Vec2 { x, y });
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Vec3 {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+vector_ops!(Vec3 { x, y, z });
+impl Vec3 {
+    pub const X: Self = Self::new(1.0, 0.0, 0.0);
+    pub const Y: Self = Self::new(0.0, 1.0, 0.0);
+    pub const Z: Self = Self::new(0.0, 0.0, 1.0);
+    pub const ONE: Self = Self::splat(1.0);
+
+    pub fn cross(self, other: Self) -> Self {
+        Self::new(
+            self.y * other.z - self.z * other.y,
+            self.z * other.x - self.x * other.z,
+            self.x * other.y - self.y * other.x,
+        )
+    }
+
+    pub const fn extend(self, w: f32) -> Vec4 {
+        Vec4::new(self.x, self.y, self.z, w)
+    }
+
+    pub const fn to_array(self) -> [f32; 3] {
+        [self.x, self.y, self.z]
+    }
+}
+impl From<[f32; 3]> for Vec3 {
+    fn from([x, y, z]: [f32; 3]) -> Self {
+        Self::new(x, y, z)
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Vec4 {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub w: f32,
+}
+vector_ops!(Vec4 { x, y, z, w });
+impl Vec4 {
+    pub const fn to_array(self) -> [f32; 4] {
+        [self.x, self.y, self.z, self.w]
+    }
+}
+impl From<[f32; 4]> for Vec4 {
+    fn from([x, y, z, w]: [f32; 4]) -> Self {
+        Self::new(x, y, z, w)
+    }
+}
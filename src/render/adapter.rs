@@ -0,0 +1,282 @@
+//! Physical device(adapter) selection policy.
+
+use std::ffi::CStr;
+
+use bedrock::{self as br, Instance, PhysicalDevice};
+
+use crate::error::EngineError;
+
+use super::bootstrap::{Adapter, InstanceObject, Surface};
+
+/// Environment variable to force an adapter(index or a part of device name).
+pub const ADAPTER_OVERRIDE_ENV_NAME: &str = "PERIDOT_ADAPTER";
+/// Command line option to force an adapter(takes precedence over the environment variable).
+pub const ADAPTER_OVERRIDE_ARG_NAME: &str = "--adapter";
+
+/// User-specified adapter.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AdapterOverride {
+    /// Index in the enumeration order of `vkEnumeratePhysicalDevices`
+    Index(usize),
+    /// Case-insensitive substring of the device name
+    Name(String),
+}
+impl AdapterOverride {
+    pub fn parse(s: &str) -> Self {
+        match s.parse() {
+            Ok(x) => Self::Index(x),
+            Err(_) => Self::Name(s.to_owned()),
+        }
+    }
+
+    /// Reads the override from `--adapter <index|name>` or `PERIDOT_ADAPTER`.
+    pub fn from_environment() -> Option<Self> {
+        let mut args = std::env::args().skip(1);
+        while let Some(a) = args.next() {
+            if a == ADAPTER_OVERRIDE_ARG_NAME {
+                return args.next().map(|x| Self::parse(&x));
+            }
+            if let Some(v) = a
+                .strip_prefix(ADAPTER_OVERRIDE_ARG_NAME)
+                .and_then(|x| x.strip_prefix('='))
+            {
+                return Some(Self::parse(v));
+            }
+        }
+
+        std::env::var(ADAPTER_OVERRIDE_ENV_NAME)
+            .ok()
+            .filter(|x| !x.is_empty())
+            .map(|x| Self::parse(&x))
+    }
+
+    fn matches(&self, index: usize, name: &str) -> bool {
+        match self {
+            Self::Index(x) => *x == index,
+            Self::Name(x) => name.to_lowercase().contains(&x.to_lowercase()),
+        }
+    }
+}
+
+/// Ordering key of acceptable adapters. Larger is better.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AdapterScore {
+    pub device_type_rank: u32,
+    pub device_local_heap_size: u64,
+}
+
+/// discrete > integrated > virtual > others > cpu(software rasterizers like lavapipe)
+pub fn device_type_rank(device_type: br::vk::VkPhysicalDeviceType) -> u32 {
+    match device_type {
+        br::vk::VK_PHYSICAL_DEVICE_TYPE_DISCRETE_GPU => 4,
+        br::vk::VK_PHYSICAL_DEVICE_TYPE_INTEGRATED_GPU => 3,
+        br::vk::VK_PHYSICAL_DEVICE_TYPE_VIRTUAL_GPU => 2,
+        br::vk::VK_PHYSICAL_DEVICE_TYPE_CPU => 0,
+        _ => 1,
+    }
+}
+
+fn device_type_name(device_type: br::vk::VkPhysicalDeviceType) -> &'static str {
+    match device_type {
+        br::vk::VK_PHYSICAL_DEVICE_TYPE_DISCRETE_GPU => "discrete",
+        br::vk::VK_PHYSICAL_DEVICE_TYPE_INTEGRATED_GPU => "integrated",
+        br::vk::VK_PHYSICAL_DEVICE_TYPE_VIRTUAL_GPU => "virtual",
+        br::vk::VK_PHYSICAL_DEVICE_TYPE_CPU => "cpu",
+        _ => "other",
+    }
+}
+
+pub struct SelectedAdapter<'i> {
+    pub adapter: Adapter<'i>,
+    pub name: String,
+    pub graphics_queue_family_index: u32,
+    /// Device extensions supported by the adapter
+    pub extensions: Vec<String>,
+}
+impl SelectedAdapter<'_> {
+    pub fn has_extension(&self, name: &CStr) -> bool {
+        self.extensions
+            .iter()
+            .any(|x| x.as_bytes() == name.to_bytes())
+    }
+}
+
+struct Candidate<'i> {
+    index: usize,
+    selected: SelectedAdapter<'i>,
+    score: AdapterScore,
+}
+
+/// Evaluates every adapter and picks the best one(or the overridden one).
+///
+/// Adapters are rejected if they lack a graphics queue family that can present to `surface`,
+/// or any of `required_extensions`.
+/// [`EngineError::NoGraphicsQueue`] is reported only if every adapter has been rejected by the queue family.
+pub fn select_adapter<'i>(
+    instance: &'i InstanceObject,
+    surface: Option<&Surface<'i>>,
+    required_extensions: &[&CStr],
+    adapter_override: Option<&AdapterOverride>,
+) -> Result<SelectedAdapter<'i>, EngineError> {
+    let mut candidates = Vec::new();
+    let mut override_matched = false;
+    let mut adapter_found = false;
+    let mut rejected_by_extensions = false;
+    let mut rejected_by_queue = false;
+    for (index, adapter) in instance.iter_physical_devices()?.enumerate() {
+        let props = adapter.properties();
+        let name = props
+            .deviceName
+            .as_cstr()
+            .and_then(|x| x.to_str().ok())
+            .unwrap_or("<unknown>")
+            .to_owned();
+        let type_name = device_type_name(props.deviceType);
+
+        if let Some(o) = adapter_override {
+            if !o.matches(index, &name) {
+                println!("[adapter #{index}] {name} ({type_name}): skipped by override {o:?}");
+                continue;
+            }
+            override_matched = true;
+        }
+        adapter_found = true;
+
+        let extensions = adapter
+            .enumerate_extension_properties(None)?
+            .iter()
+            .filter_map(|x| x.extensionName.as_cstr())
+            .filter_map(|x| x.to_str().ok())
+            .map(ToOwned::to_owned)
+            .collect::<Vec<_>>();
+        let missing_extensions = required_extensions
+            .iter()
+            .filter(|r| !extensions.iter().any(|x| x.as_bytes() == r.to_bytes()))
+            .collect::<Vec<_>>();
+        if !missing_extensions.is_empty() {
+            println!(
+                "[adapter #{index}] {name} ({type_name}): rejected: missing extensions {missing_extensions:?}"
+            );
+            rejected_by_extensions = true;
+            continue;
+        }
+
+        // the first graphics queue family is not always the one supporting presentation
+        let graphics_queue_families = adapter
+            .queue_family_properties()
+            .0
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| (p.queueFlags & br::vk::VK_QUEUE_GRAPHICS_BIT) != 0)
+            .map(|(qf, _)| qf as u32)
+            .collect::<Vec<_>>();
+        let mut graphics_queue_family_index = None;
+        for qf in graphics_queue_families {
+            if surface.map_or(Ok(true), |s| adapter.surface_support(qf, s))? {
+                graphics_queue_family_index = Some(qf);
+                break;
+            }
+        }
+        let Some(graphics_queue_family_index) = graphics_queue_family_index else {
+            println!(
+                "[adapter #{index}] {name} ({type_name}): rejected: no graphics queue family supporting presentation"
+            );
+            rejected_by_queue = true;
+            continue;
+        };
+
+        let memory_properties = adapter.memory_properties();
+        let device_local_heap_size = memory_properties
+            .heaps()
+            .filter(|h| (h.flags & br::vk::VK_MEMORY_HEAP_DEVICE_LOCAL_BIT) != 0)
+            .map(|h| h.size)
+            .max()
+            .unwrap_or(0);
+        let score = AdapterScore {
+            device_type_rank: device_type_rank(props.deviceType),
+            device_local_heap_size,
+        };
+        println!(
+            "[adapter #{index}] {name} ({type_name}): accepted: score={score:?} graphics queue family={graphics_queue_family_index}"
+        );
+
+        candidates.push(Candidate {
+            index,
+            selected: SelectedAdapter {
+                adapter,
+                name,
+                graphics_queue_family_index,
+                extensions,
+            },
+            score,
+        });
+    }
+
+    if let Some(o) = adapter_override {
+        if !override_matched {
+            return Err(EngineError::AdapterOverrideNotMatched(format!("{o:?}")));
+        }
+    }
+    if !adapter_found {
+        return Err(EngineError::NoVulkanDevice);
+    }
+
+    let Some(best) = candidates
+        .into_iter()
+        // prefer earlier adapter if scores are same
+        .max_by(|a, b| a.score.cmp(&b.score).then(b.index.cmp(&a.index)))
+    else {
+        return Err(if rejected_by_queue && !rejected_by_extensions {
+            EngineError::NoGraphicsQueue
+        } else {
+            EngineError::NoSuitableAdapter
+        });
+    };
+    println!("selected adapter: #{} {}", best.index, best.selected.name);
+
+    Ok(best.selected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn override_is_parsed_as_index_or_name() {
+        assert_eq!(AdapterOverride::parse("1"), AdapterOverride::Index(1));
+        assert_eq!(
+            AdapterOverride::parse("GeForce"),
+            AdapterOverride::Name("GeForce".to_owned())
+        );
+        assert_eq!(
+            AdapterOverride::parse("-1"),
+            AdapterOverride::Name("-1".to_owned())
+        );
+    }
+
+    #[test]
+    fn name_override_is_case_insensitive() {
+        let o = AdapterOverride::parse("radeon");
+        assert!(o.matches(0, "AMD Radeon RX 7900"));
+        assert!(!o.matches(0, "llvmpipe"));
+    }
+
+    #[test]
+    fn index_override_matches_only_the_index() {
+        let o = AdapterOverride::parse("1");
+        assert!(o.matches(1, "any"));
+        assert!(!o.matches(0, "1"));
+    }
+
+    #[test]
+    fn discrete_gpus_rank_highest_and_cpus_lowest() {
+        let discrete = device_type_rank(br::vk::VK_PHYSICAL_DEVICE_TYPE_DISCRETE_GPU);
+        let integrated = device_type_rank(br::vk::VK_PHYSICAL_DEVICE_TYPE_INTEGRATED_GPU);
+        let cpu = device_type_rank(br::vk::VK_PHYSICAL_DEVICE_TYPE_CPU);
+        assert!(discrete > integrated && integrated > cpu);
+        assert!(
+            device_type_rank(br::vk::VK_PHYSICAL_DEVICE_TYPE_OTHER) > cpu,
+            "software rasterizers are the last resort"
+        );
+    }
+}
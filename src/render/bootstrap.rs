@@ -0,0 +1,439 @@
+//! Vulkan instance/device setup shared by every platform entry point.
+//!
+//! Objects are created in two stages([`create_instance`] then [`DeviceContext::new`]) since
+//! the device and the engine borrow the instance.
+
+use std::ffi::CStr;
+
+use bedrock::{self as br, Instance, PhysicalDevice};
+
+use crate::{
+    error::EngineError,
+    game::{Engine, SwapchainConfig},
+};
+
+use super::{
+    adapter::{select_adapter, AdapterOverride},
+    depth::DepthMode,
+    headless::HeadlessConfig,
+    presentation::PresentationConfig,
+    surface_format::{FormatNegotiation, OutputColorSpace},
+};
+
+pub type InstanceObject = br::InstanceObject;
+pub type Adapter<'i> = br::PhysicalDeviceObject<&'i InstanceObject>;
+pub type Device<'i> = br::DeviceObject<&'i InstanceObject>;
+pub type Surface<'i> = br::SurfaceObject<&'i InstanceObject>;
+
+const VALIDATION_LAYER_NAME: &CStr = c"VK_LAYER_KHRONOS_validation";
+const PORTABILITY_ENUMERATION_EXTENSION_NAME: &CStr = c"VK_KHR_portability_enumeration";
+const SWAPCHAIN_COLORSPACE_EXTENSION_NAME: &CStr = c"VK_EXT_swapchain_colorspace";
+
+pub struct BootstrapOptions {
+    pub app_name: &'static CStr,
+    pub app_version: (u32, u32, u32),
+    /// Platform-specific surface extension(e.g. `VK_KHR_wayland_surface`). None for headless
+    pub surface_extension: Option<&'static CStr>,
+    /// Enables `VK_LAYER_KHRONOS_validation` and debug messenger if available
+    pub validation: bool,
+    /// Prints available extensions, layers and surface properties
+    pub verbose: bool,
+    /// Forces an adapter instead of automatic selection
+    pub adapter_override: Option<AdapterOverride>,
+    pub presentation: PresentationConfig,
+    /// HDR outputs fall back to sRGB if `VK_EXT_swapchain_colorspace` or suitable formats are not available
+    pub output_color_space: OutputColorSpace,
+    /// Depth attachment of the main render pass
+    pub depth: DepthMode,
+}
+impl BootstrapOptions {
+    /// Default options. Adapter override is read from the command line or the environment.
+    pub fn new(surface_extension: &'static CStr) -> Self {
+        Self::with_surface_extension(Some(surface_extension))
+    }
+
+    /// Options for rendering without any window server(no surface/swapchain extensions).
+    pub fn headless() -> Self {
+        Self::with_surface_extension(None)
+    }
+
+    fn with_surface_extension(surface_extension: Option<&'static CStr>) -> Self {
+        Self {
+            app_name: c"peridot2-test",
+            app_version: (0, 1, 0),
+            surface_extension,
+            validation: true,
+            verbose: true,
+            adapter_override: AdapterOverride::from_environment(),
+            presentation: PresentationConfig::default(),
+            output_color_space: OutputColorSpace::Srgb,
+            depth: DepthMode::Depth,
+        }
+    }
+}
+
+/// Extensions/layers reported by the loader.
+struct InstanceCapabilities {
+    extensions: Vec<String>,
+    layers: Vec<String>,
+}
+impl InstanceCapabilities {
+    fn enumerate(verbose: bool) -> br::Result<Self> {
+        let mut extensions = Vec::new();
+        for x in br::enumerate_extension_properties(None)? {
+            let ext_name = x.extensionName.as_cstr().unwrap().to_str().unwrap();
+            if verbose {
+                println!("vkext: {ext_name}");
+            }
+
+            extensions.push(ext_name.to_owned());
+        }
+
+        let mut layers = Vec::new();
+        for x in br::enumerate_layer_properties()? {
+            let layer_name_cstr = x.layerName.as_cstr().unwrap();
+
+            if verbose {
+                println!(
+                    "vk layer: {} {} {}",
+                    layer_name_cstr.to_str().unwrap(),
+                    x.specVersion,
+                    x.implementationVersion
+                );
+
+                for e in br::enumerate_extension_properties_cstr(Some(layer_name_cstr))? {
+                    println!(
+                        "* vkext: {}",
+                        e.extensionName.as_cstr().unwrap().to_str().unwrap()
+                    );
+                }
+            }
+
+            layers.push(layer_name_cstr.to_str().unwrap().to_owned());
+        }
+
+        Ok(Self { extensions, layers })
+    }
+
+    fn has_extension(&self, name: &CStr) -> bool {
+        self.extensions
+            .iter()
+            .any(|x| x.as_bytes() == name.to_bytes())
+    }
+
+    fn has_layer(&self, name: &CStr) -> bool {
+        self.layers.iter().any(|x| x.as_bytes() == name.to_bytes())
+    }
+}
+
+/// Result of instance creation.
+pub struct BootstrapInstance {
+    pub instance: InstanceObject,
+    /// true if `VK_KHR_portability_enumeration` is enabled(MoltenVK)
+    pub portability_enumeration: bool,
+    pub debug_utils: bool,
+    /// true if `VK_EXT_swapchain_colorspace` is enabled
+    pub swapchain_colorspace: bool,
+}
+
+pub fn create_instance(options: &BootstrapOptions) -> Result<BootstrapInstance, EngineError> {
+    let caps = InstanceCapabilities::enumerate(options.verbose)?;
+    let portability_enumeration = caps.has_extension(PORTABILITY_ENUMERATION_EXTENSION_NAME);
+    let debug_utils = options.validation && caps.has_extension(c"VK_EXT_debug_utils");
+    let swapchain_colorspace = options
+        .output_color_space
+        .requires_swapchain_colorspace_extension()
+        && caps.has_extension(SWAPCHAIN_COLORSPACE_EXTENSION_NAME);
+
+    let app = br::ApplicationInfo::new(
+        options.app_name,
+        options.app_version,
+        c"Peridot 2",
+        (0, 1, 0),
+    );
+    let mut builder = br::InstanceBuilder::new(&app);
+    if let Some(surface_extension) = options.surface_extension {
+        builder.add_extensions([surface_extension, c"VK_KHR_surface"]);
+    }
+    if debug_utils {
+        builder.add_extension(c"VK_EXT_debug_utils");
+    }
+    if swapchain_colorspace {
+        builder.add_extension(SWAPCHAIN_COLORSPACE_EXTENSION_NAME);
+    }
+    if options.validation {
+        if caps.has_layer(VALIDATION_LAYER_NAME) {
+            builder.add_layer(VALIDATION_LAYER_NAME);
+        } else {
+            eprintln!("validation layer is not available");
+        }
+    }
+    if portability_enumeration {
+        // Note: どうやらMoltenVKではこれが必要らしい https://stackoverflow.com/a/73408303
+        builder
+            .enumerate_portability()
+            .add_extension(c"VK_KHR_get_physical_device_properties2");
+    }
+
+    Ok(BootstrapInstance {
+        instance: builder.create()?,
+        portability_enumeration,
+        debug_utils,
+        swapchain_colorspace,
+    })
+}
+
+enum PresentationTargetConfig<'i> {
+    Surface(Surface<'i>, SwapchainConfig),
+    Headless(HeadlessConfig),
+}
+
+/// Device and presentation resources needed to construct an [`Engine`].
+pub struct DeviceContext<'i> {
+    pub adapter: Adapter<'i>,
+    pub device: Device<'i>,
+    pub graphics_queue_family_index: u32,
+    pub memory_properties: br::MemoryProperties,
+    /// Negotiated format of the depth buffer. None if disabled
+    pub depth_format: Option<br::vk::VkFormat>,
+    target: Option<PresentationTargetConfig<'i>>,
+    _debug_utils_messenger: Option<br::DebugUtilsMessengerObject<&'i InstanceObject>>,
+}
+impl<'i> DeviceContext<'i> {
+    pub fn new(
+        instance: &'i BootstrapInstance,
+        options: &BootstrapOptions,
+        create_surface: impl FnOnce(&Adapter<'i>) -> br::Result<Surface<'i>>,
+    ) -> Result<Self, EngineError> {
+        let debug_utils_messenger = create_debug_utils_messenger(instance)?;
+
+        // surfaces are not bound to a specific adapter: create with the first one to evaluate every adapter
+        let surface = {
+            let first_adapter = instance
+                .instance
+                .iter_physical_devices()?
+                .next()
+                .ok_or(EngineError::NoVulkanDevice)?;
+
+            create_surface(&first_adapter)?
+        };
+
+        let mut this = Self::create_device(
+            instance,
+            options,
+            Some(&surface),
+            &[c"VK_KHR_swapchain"],
+            debug_utils_messenger,
+        )?;
+        let adapter = &this.adapter;
+
+        let surface_caps = adapter.surface_capabilities(&surface)?;
+        let surface_fmt = adapter.surface_formats(&surface)?;
+        let surface_pm = adapter.surface_present_modes(&surface)?;
+        if options.verbose {
+            println!("** Surface Info **");
+            println!("*** Formats: {:?}", surface_fmt);
+            println!("*** Caps: {:?}", surface_caps);
+            println!("*** PresentModes: {:?}", surface_pm);
+        }
+
+        let output_color_space = if options
+            .output_color_space
+            .requires_swapchain_colorspace_extension()
+            && !instance.swapchain_colorspace
+        {
+            eprintln!(
+                "{:?} output requires VK_EXT_swapchain_colorspace: falling back to sRGB",
+                options.output_color_space
+            );
+            OutputColorSpace::Srgb
+        } else {
+            options.output_color_space
+        };
+        let format = FormatNegotiation::for_output(output_color_space)
+            .negotiate(&surface_fmt)
+            .ok_or(EngineError::NoSuitableFormat)?;
+        println!(
+            "surface format: {:?} {:?}",
+            format.format, format.colorSpace
+        );
+
+        let present_mode = options.presentation.choose_present_mode(&surface_pm);
+        let swapchain_config = SwapchainConfig {
+            format,
+            present_mode,
+            back_buffer_count: options
+                .presentation
+                .back_buffer_count(present_mode, &surface_caps),
+            frames_in_flight: options.presentation.frames_in_flight,
+        };
+        println!(
+            "presentation: {:?} -> {present_mode:?} x{} ({} frames in flight)",
+            options.presentation.mode,
+            swapchain_config.back_buffer_count,
+            swapchain_config.frames_in_flight
+        );
+
+        this.target = Some(PresentationTargetConfig::Surface(surface, swapchain_config));
+        Ok(this)
+    }
+
+    /// Creates a device without any surface. Every adapter with a graphics queue is acceptable
+    /// (including software rasterizers like lavapipe).
+    pub fn new_headless(
+        instance: &'i BootstrapInstance,
+        options: &BootstrapOptions,
+        config: HeadlessConfig,
+    ) -> Result<Self, EngineError> {
+        let debug_utils_messenger = create_debug_utils_messenger(instance)?;
+        let mut this = Self::create_device(instance, options, None, &[], debug_utils_messenger)?;
+        println!(
+            "headless: {:?} x{}",
+            config.format, config.back_buffer_count
+        );
+
+        this.target = Some(PresentationTargetConfig::Headless(config));
+        Ok(this)
+    }
+
+    fn create_device(
+        instance: &'i BootstrapInstance,
+        options: &BootstrapOptions,
+        surface: Option<&Surface<'i>>,
+        required_extensions: &[&'static CStr],
+        debug_utils_messenger: Option<br::DebugUtilsMessengerObject<&'i InstanceObject>>,
+    ) -> Result<Self, EngineError> {
+        let selected = select_adapter(
+            &instance.instance,
+            surface,
+            required_extensions,
+            options.adapter_override.as_ref(),
+        )?;
+        let adapter = selected.adapter.clone();
+        let graphics_queue_family_index = selected.graphics_queue_family_index;
+        let memory_properties = adapter.memory_properties();
+        if options.verbose {
+            println!(
+                "graphics queue count: {}",
+                adapter
+                    .queue_family_properties()
+                    .queue_count(graphics_queue_family_index)
+            );
+        }
+        let device = {
+            let queue_family_builder =
+                br::DeviceQueueCreateInfo::new(graphics_queue_family_index, &[0.0]);
+            let mut builder = br::DeviceBuilder::new(&adapter);
+            builder.add_queue(queue_family_builder);
+            for &x in required_extensions {
+                builder.add_extension(x);
+            }
+            // must be enabled if the adapter supports it
+            if selected.has_extension(c"VK_KHR_portability_subset") {
+                builder.add_extension(c"VK_KHR_portability_subset");
+            }
+
+            builder.create()?
+        };
+
+        let depth_format = options.depth.negotiate(|f| {
+            (adapter.format_properties(f).optimalTilingFeatures
+                & br::vk::VK_FORMAT_FEATURE_DEPTH_STENCIL_ATTACHMENT_BIT)
+                != 0
+        });
+        match depth_format {
+            Some(f) => println!("depth format: {f:?}"),
+            None if options.depth != DepthMode::Disabled => {
+                eprintln!("no supported depth formats: depth buffer disabled")
+            }
+            None => (),
+        }
+
+        Ok(Self {
+            adapter,
+            device,
+            graphics_queue_family_index,
+            memory_properties,
+            depth_format,
+            target: None,
+            _debug_utils_messenger: debug_utils_messenger,
+        })
+    }
+
+    /// Creates the engine. Can be called only once per context(the surface is moved into the engine).
+    ///
+    /// `requested_extent` is used if the surface does not decide its size by itself
+    /// (always used for headless contexts).
+    pub fn engine(
+        &mut self,
+        requested_extent: br::vk::VkExtent2D,
+    ) -> Result<Engine<'_, Device<'i>>, EngineError> {
+        let Self {
+            adapter,
+            device,
+            graphics_queue_family_index,
+            memory_properties,
+            depth_format,
+            target,
+            ..
+        } = self;
+        let device = &*device;
+        let q = br::Device::queue(device, *graphics_queue_family_index, 0);
+
+        match target.take().expect("engine has already been created") {
+            PresentationTargetConfig::Surface(surface, swapchain_config) => Engine::new(
+                device,
+                adapter.clone(),
+                *graphics_queue_family_index,
+                q,
+                surface,
+                swapchain_config,
+                *depth_format,
+                requested_extent,
+                memory_properties.clone(),
+            ),
+            PresentationTargetConfig::Headless(config) => Engine::new_headless(
+                device,
+                adapter.clone(),
+                *graphics_queue_family_index,
+                q,
+                config,
+                *depth_format,
+                requested_extent,
+                memory_properties.clone(),
+            ),
+        }
+    }
+}
+
+fn create_debug_utils_messenger(
+    instance: &BootstrapInstance,
+) -> br::Result<Option<br::DebugUtilsMessengerObject<&InstanceObject>>> {
+    if !instance.debug_utils {
+        return Ok(None);
+    }
+
+    br::DebugUtilsMessengerCreateInfo::new(debug_utils_message)
+        .create(&instance.instance)
+        .map(Some)
+}
+
+extern "system" fn debug_utils_message(
+    severity: br::vk::VkDebugUtilsMessageSeverityFlagBitsEXT,
+    types: br::vk::VkDebugUtilsMessageTypeFlagsEXT,
+    data: *const br::vk::VkDebugUtilsMessengerCallbackDataEXT,
+    _user_data: *mut core::ffi::c_void,
+) -> br::vk::VkBool32 {
+    let data_ref = unsafe { data.as_ref().expect("null data") };
+    eprintln!("[{severity:08x}, {types:08x}] {}", unsafe {
+        std::ffi::CStr::from_ptr(data_ref.pMessage)
+            .to_str()
+            .expect("invalid message str")
+    });
+
+    if (severity & br::vk::VK_DEBUG_UTILS_MESSAGE_SEVERITY_ERROR_BIT_EXT) != 0 {
+        br::vk::VK_TRUE
+    } else {
+        br::vk::VK_FALSE
+    }
+}
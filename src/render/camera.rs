@@ -0,0 +1,100 @@
+//! Camera producing view/projection matrices for the per-view uniform.
+//!
+//! Projections map to Vulkan clip space(Y down, depth in 0..1), with right-handed view space
+//! (looking towards -Z).
+
+use bedrock as br;
+
+use crate::math::{std140_struct, Mat4, Vec2, Vec3, Vec4};
+
+use super::surface_format::{OutputEncoding, HDR_SDR_WHITE_NITS};
+
+std140_struct! {
+    /// Contents of the per-view uniform buffer.
+    #[derive(Debug)]
+    pub struct ViewUniform {
+        pub view: Mat4,
+        pub projection: Mat4,
+        pub view_projection: Mat4,
+        /// xyz: world space position of the camera, w: 1
+        pub camera_position: Vec4,
+        /// [`OutputEncoding`] of the render target
+        pub output_encoding: u32,
+        /// Luminance of 1.0 for [`OutputEncoding::Hdr10`]
+        pub sdr_white_nits: f32,
+        _padding: Vec2,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    Perspective {
+        /// Vertical field of view in radians
+        fov_y: f32,
+        near: f32,
+        far: f32,
+    },
+    Orthographic {
+        /// Visible height in view space units(width is decided by the aspect ratio)
+        height: f32,
+        near: f32,
+        far: f32,
+    },
+}
+impl Projection {
+    pub fn matrix(&self, aspect: f32) -> Mat4 {
+        match *self {
+            Self::Perspective { fov_y, near, far } => Mat4::perspective(fov_y, aspect, near, far),
+            Self::Orthographic { height, near, far } => {
+                let (hw, hh) = (height * aspect * 0.5, height * 0.5);
+
+                Mat4::orthographic(-hw, hw, -hh, hh, near, far)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Camera {
+    pub position: Vec3,
+    /// The point the camera looks at
+    pub target: Vec3,
+    pub up: Vec3,
+    pub projection: Projection,
+}
+impl Camera {
+    pub fn look_at(position: Vec3, target: Vec3, projection: Projection) -> Self {
+        Self {
+            position,
+            target,
+            up: Vec3::Y,
+            projection,
+        }
+    }
+
+    pub fn view_matrix(&self) -> Mat4 {
+        Mat4::look_at(self.position, self.target, self.up)
+    }
+
+    /// `extent` is the size of the render target(used for the aspect ratio).
+    /// Shaders encode their outputs with `output_encoding`.
+    pub fn view_uniform(
+        &self,
+        extent: br::vk::VkExtent2D,
+        output_encoding: OutputEncoding,
+    ) -> ViewUniform {
+        let aspect = extent.width as f32 / extent.height.max(1) as f32;
+        let view = self.view_matrix();
+        let projection = self.projection.matrix(aspect);
+
+        ViewUniform {
+            view,
+            projection,
+            view_projection: projection * view,
+            camera_position: self.position.extend(1.0),
+            output_encoding: output_encoding as _,
+            sdr_white_nits: HDR_SDR_WHITE_NITS,
+            _padding: Vec2::ZERO,
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Back buffer readback results, PNG encoding and image comparison.
+
+use std::path::Path;
+
+use bedrock as br;
+
+use crate::asset::texture::decode_png_rgba8;
+
+/// A captured image, always converted into 8bit RGBA(rows are tightly packed, top to bottom).
+///
+/// Values are stored as is: sRGB formats produce sRGB-encoded bytes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CapturedImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+impl CapturedImage {
+    /// Bytes per texel of formats which can be captured. None if the format is not supported.
+    pub fn texel_size(format: br::vk::VkFormat) -> Option<usize> {
+        match format {
+            br::vk::VK_FORMAT_R8G8B8A8_SRGB
+            | br::vk::VK_FORMAT_R8G8B8A8_UNORM
+            | br::vk::VK_FORMAT_B8G8R8A8_SRGB
+            | br::vk::VK_FORMAT_B8G8R8A8_UNORM
+            | br::vk::VK_FORMAT_A8B8G8R8_SRGB_PACK32
+            | br::vk::VK_FORMAT_A8B8G8R8_UNORM_PACK32 => Some(4),
+            _ => None,
+        }
+    }
+
+    /// Converts tightly packed texels read back from an image of the format.
+    pub fn from_texels(
+        format: br::vk::VkFormat,
+        width: u32,
+        height: u32,
+        mut texels: Vec<u8>,
+    ) -> Option<Self> {
+        match format {
+            // A8B8G8R8_PACK32 has the same memory layout as R8G8B8A8 on little endian
+            br::vk::VK_FORMAT_R8G8B8A8_SRGB
+            | br::vk::VK_FORMAT_R8G8B8A8_UNORM
+            | br::vk::VK_FORMAT_A8B8G8R8_SRGB_PACK32
+            | br::vk::VK_FORMAT_A8B8G8R8_UNORM_PACK32 => (),
+            br::vk::VK_FORMAT_B8G8R8A8_SRGB | br::vk::VK_FORMAT_B8G8R8A8_UNORM => {
+                for px in texels.chunks_exact_mut(4) {
+                    px.swap(0, 2);
+                }
+            }
+            _ => return None,
+        }
+
+        Some(Self {
+            width,
+            height,
+            pixels: texels,
+        })
+    }
+
+    pub fn write_png(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        let mut encoder = png::Encoder::new(file, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_srgb(png::SrgbRenderingIntent::Perceptual);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.pixels)?;
+        writer.finish()?;
+
+        Ok(())
+    }
+
+    /// Reads a PNG file. Grayscale/RGB/palette images are expanded into RGBA.
+    pub fn read_png(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let (width, height, pixels) =
+            decode_png_rgba8(std::io::BufReader::new(std::fs::File::open(path)?))?;
+
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    /// Compares with `expected`. A pixel mismatches if any channel differs by more than `tolerance`.
+    ///
+    /// None if sizes are different.
+    pub fn compare(&self, expected: &Self, tolerance: u8) -> Option<ImageDiff> {
+        if self.width != expected.width || self.height != expected.height {
+            return None;
+        }
+
+        let mut diff = ImageDiff {
+            total_pixels: self.width as usize * self.height as usize,
+            mismatched_pixels: 0,
+            max_difference: 0,
+        };
+        for (a, b) in self
+            .pixels
+            .chunks_exact(4)
+            .zip(expected.pixels.chunks_exact(4))
+        {
+            let d = a
+                .iter()
+                .zip(b)
+                .map(|(a, b)| a.abs_diff(*b))
+                .max()
+                .unwrap_or(0);
+            diff.max_difference = diff.max_difference.max(d);
+            if d > tolerance {
+                diff.mismatched_pixels += 1;
+            }
+        }
+
+        Some(diff)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImageDiff {
+    pub total_pixels: usize,
+    pub mismatched_pixels: usize,
+    /// Largest per-channel difference over the whole image
+    pub max_difference: u8,
+}
+impl ImageDiff {
+    pub fn mismatch_ratio(&self) -> f64 {
+        if self.total_pixels == 0 {
+            return 0.0;
+        }
+
+        self.mismatched_pixels as f64 / self.total_pixels as f64
+    }
+}
@@ -0,0 +1,151 @@
+//! Depth buffer format negotiation and the depth image shared by the main framebuffers.
+
+use std::sync::Arc;
+
+use bedrock::{self as br, ImageSubresourceSlice};
+
+use crate::error::EngineError;
+
+use super::{
+    graph::{ImageHandle, ImportedImage, RenderGraphDesc},
+    headless::{OffscreenImage, OffscreenImageView},
+    memory::{MemoryAllocation, MemoryAllocator, MemoryLocation},
+};
+
+/// Whether the main render pass has a depth(/stencil) attachment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DepthMode {
+    Disabled,
+    /// Depth only formats are preferred. `D16_UNORM` is always supported by spec.
+    Depth,
+    /// Formats with a stencil aspect. Falls back to depth only formats if not supported.
+    DepthStencil,
+}
+impl DepthMode {
+    /// Parses `--no-depth` or `--depth-stencil` command line flags(depth only if none specified).
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Self {
+        let mut mode = Self::Depth;
+        for a in args {
+            match &a[..] {
+                "--no-depth" => mode = Self::Disabled,
+                "--depth-stencil" => mode = Self::DepthStencil,
+                _ => (),
+            }
+        }
+
+        mode
+    }
+
+    fn candidates(self) -> &'static [br::vk::VkFormat] {
+        match self {
+            Self::Disabled => &[],
+            Self::Depth => DEPTH_FORMATS,
+            Self::DepthStencil => DEPTH_STENCIL_FORMATS,
+        }
+    }
+
+    /// Picks the first format usable as a depth/stencil attachment with optimal tiling.
+    pub fn negotiate(
+        self,
+        is_supported: impl Fn(br::vk::VkFormat) -> bool,
+    ) -> Option<br::vk::VkFormat> {
+        let found = self.candidates().iter().copied().find(|&f| is_supported(f));
+        if found.is_none() && self == Self::DepthStencil {
+            eprintln!("no depth/stencil formats supported: falling back to depth only formats");
+            return Self::Depth.negotiate(is_supported);
+        }
+
+        found
+    }
+}
+
+const DEPTH_FORMATS: &[br::vk::VkFormat] = &[
+    br::vk::VK_FORMAT_D32_SFLOAT,
+    br::vk::VK_FORMAT_X8_D24_UNORM_PACK32,
+    br::vk::VK_FORMAT_D16_UNORM,
+];
+// Note: D24S8かD32S8のどちらかは必ずサポートされている
+const DEPTH_STENCIL_FORMATS: &[br::vk::VkFormat] = &[
+    br::vk::VK_FORMAT_D24_UNORM_S8_UINT,
+    br::vk::VK_FORMAT_D32_SFLOAT_S8_UINT,
+    br::vk::VK_FORMAT_D16_UNORM_S8_UINT,
+];
+
+pub fn has_stencil(format: br::vk::VkFormat) -> bool {
+    DEPTH_STENCIL_FORMATS.contains(&format)
+}
+
+fn aspect_mask(format: br::vk::VkFormat) -> br::vk::VkImageAspectFlags {
+    if has_stencil(format) {
+        br::vk::VK_IMAGE_ASPECT_DEPTH_BIT | br::vk::VK_IMAGE_ASPECT_STENCIL_BIT
+    } else {
+        br::vk::VK_IMAGE_ASPECT_DEPTH_BIT
+    }
+}
+
+/// Depth image sized to the back buffers. Recreated with the swapchain.
+pub struct DepthBuffer<'d, Device: br::Device + ?Sized + 'd> {
+    format: br::vk::VkFormat,
+    extent: br::vk::VkExtent2D,
+    // Note: メモリより先にイメージを破棄する
+    image: Arc<OffscreenImage<'d, Device>>,
+    _allocation: MemoryAllocation<'d, Device>,
+}
+impl<'d, Device: br::Device + ?Sized + 'd> DepthBuffer<'d, Device> {
+    pub fn new(
+        allocator: &MemoryAllocator<'d, Device>,
+        format: br::vk::VkFormat,
+        extent: br::vk::VkExtent2D,
+    ) -> Result<Self, EngineError> {
+        let (image, allocation) = allocator
+            .create_image(
+                br::ImageDesc::new(
+                    extent,
+                    format,
+                    br::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+                    br::ImageLayout::Undefined,
+                ),
+                MemoryLocation::DeviceLocal,
+            )?
+            .into_parts();
+
+        Ok(Self {
+            format,
+            extent,
+            image: Arc::new(image),
+            _allocation: allocation,
+        })
+    }
+
+    pub fn format(&self) -> br::vk::VkFormat {
+        self.format
+    }
+
+    /// Creates a view for the framebuffer attachment.
+    pub fn create_view(&self) -> br::Result<OffscreenImageView<'d, Device>> {
+        self.image
+            .clone()
+            .subresource_range(br::AspectMask(aspect_mask(self.format)), 0..1, 0..1)
+            .view_builder()
+            .create()
+    }
+
+    /// Imports into the render graph of the frame. The contents of previous frames are discarded.
+    pub fn import(&self, graph: &mut RenderGraphDesc) -> ImageHandle {
+        graph.import_image(ImportedImage {
+            image: br::VkHandle::native_ptr(&*self.image),
+            format: self.format,
+            extent: self.extent,
+            aspect: aspect_mask(self.format),
+            initial_layout: br::ImageLayout::Undefined,
+            // the image is shared by frames in flight: depth tests and writes of the previous frame
+            // must be completed before overwriting
+            initial_stages: br::PipelineStageFlags(
+                br::PipelineStageFlags::EARLY_FRAGMENT_TESTS.0
+                    | br::PipelineStageFlags::LATE_FRAGMENT_TESTS.0,
+            ),
+            initial_access: br::AccessFlags::DEPTH_STENCIL_ATTACHMENT.write,
+            final_layout: None,
+        })
+    }
+}
@@ -0,0 +1,282 @@
+//! Descriptor set allocation.
+//!
+//! Set layouts are shared by their bindings([`SetLayoutSignature`]), and sets are allocated from
+//! chains of pools which grow on exhaustion, so nobody has to size pools by hand.
+//! Sets living for a frame are allocated from the pools of the frame slot, which are reset when
+//! the slot is reused([`FrameRing::begin`](super::frame::FrameRing::begin)).
+
+use std::{collections::HashMap, sync::Arc};
+
+use bedrock::{self as br, DescriptorPoolMut};
+
+use crate::{
+    error::EngineError,
+    shader::reflect::{stage_flags, DescriptorKind, ShaderStage},
+};
+
+use super::frame::FrameContext;
+
+/// Max sets of the first pool in a chain. Doubled for every new pool up to [`MAX_SETS_PER_POOL`].
+const INITIAL_SETS_PER_POOL: u32 = 16;
+const MAX_SETS_PER_POOL: u32 = 1024;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct LayoutBinding {
+    pub binding: u32,
+    pub kind: DescriptorKind,
+    /// Buffers are bound with dynamic offsets
+    pub dynamic: bool,
+    pub count: u32,
+    /// Stages referring the binding
+    pub stages: Vec<ShaderStage>,
+}
+impl LayoutBinding {
+    pub fn descriptor_type(&self) -> br::DescriptorType {
+        self.kind.descriptor_type(self.dynamic)
+    }
+
+    pub fn layout_binding(&self) -> br::DescriptorSetLayoutBinding {
+        self.descriptor_type()
+            .make_binding(self.binding, self.count)
+            .for_shader_stage(stage_flags(&self.stages))
+    }
+}
+
+/// Bindings of a descriptor set layout. Layouts of the same signature are shared.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SetLayoutSignature {
+    bindings: Vec<LayoutBinding>,
+}
+impl SetLayoutSignature {
+    /// Bindings and stages are sorted so that the same layouts have the same signatures.
+    pub fn new(mut bindings: Vec<LayoutBinding>) -> Self {
+        for b in &mut bindings {
+            b.stages.sort();
+            b.stages.dedup();
+        }
+        bindings.sort_by_key(|b| b.binding);
+
+        Self { bindings }
+    }
+
+    pub fn bindings(&self) -> &[LayoutBinding] {
+        &self.bindings
+    }
+
+    /// Descriptors required for a set of this layout, per descriptor type(summed over bindings)
+    fn descriptor_counts(&self) -> HashMap<(DescriptorKind, bool), u32> {
+        let mut counts = HashMap::new();
+        for b in &self.bindings {
+            *counts.entry((b.kind, b.dynamic)).or_insert(0) += b.count;
+        }
+
+        counts
+    }
+}
+
+/// Descriptor pools sets are allocated from. A new(larger) pool is created when every pool is
+/// exhausted.
+pub struct DescriptorPoolChain<'d, Device: br::Device + ?Sized + 'd> {
+    device: &'d Device,
+    pools: Vec<br::DescriptorPoolObject<&'d Device>>,
+    /// Index of the pool sets are allocated from
+    current: usize,
+    /// Max sets of the next pool
+    next_max_sets: u32,
+    /// The largest number of descriptors of each type required by a set seen so far
+    demand: HashMap<(DescriptorKind, bool), u32>,
+}
+impl<'d, Device: br::Device + ?Sized + 'd> DescriptorPoolChain<'d, Device> {
+    /// No pools are created until the first allocation.
+    pub fn new(device: &'d Device) -> Self {
+        Self {
+            device,
+            pools: Vec::new(),
+            current: 0,
+            next_max_sets: INITIAL_SETS_PER_POOL,
+            demand: HashMap::new(),
+        }
+    }
+
+    /// `signature` must be the one `layout` was created from(used for sizing pools).
+    pub fn allocate(
+        &mut self,
+        layout: &br::DescriptorSetLayoutObject<&'d Device>,
+        signature: &SetLayoutSignature,
+    ) -> Result<br::DescriptorSet, EngineError> {
+        // Note: 新しいプールには必ず確保できるように、セット単位の合計で見積もる
+        for (key, count) in signature.descriptor_counts() {
+            let demand = self.demand.entry(key).or_insert(0);
+            *demand = (*demand).max(count);
+        }
+
+        // the current pool first, then the others(exhausted pools may still have room for sets
+        // of other layouts)
+        for n in (self.current..self.pools.len()).chain(0..self.current) {
+            match self.pools[n].alloc_array(&[br::DescriptorSetLayoutObjectRef::new(layout)]) {
+                Ok([set]) => {
+                    self.current = n;
+                    return Ok(set);
+                }
+                Err(br::vk::VK_ERROR_OUT_OF_POOL_MEMORY | br::vk::VK_ERROR_FRAGMENTED_POOL) => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        // every pool is exhausted(or lacks some of the descriptor types)
+        let mut pool = self.create_pool()?;
+        let [set] = pool.alloc_array(&[br::DescriptorSetLayoutObjectRef::new(layout)])?;
+        self.pools.push(pool);
+        self.current = self.pools.len() - 1;
+
+        Ok(set)
+    }
+
+    fn create_pool(&mut self) -> br::Result<br::DescriptorPoolObject<&'d Device>> {
+        let max_sets = self.next_max_sets;
+        let mut sizes = self
+            .demand
+            .iter()
+            .map(|(&(kind, dynamic), &count)| {
+                kind.descriptor_type(dynamic).make_size(count * max_sets)
+            })
+            .collect::<Vec<_>>();
+        if sizes.is_empty() {
+            // Note: プールサイズは1つ以上必要(空のセットしか確保しない場合)
+            sizes.push(br::DescriptorType::UniformBuffer.make_size(1));
+        }
+        let pool = br::DescriptorPoolBuilder::new(max_sets, &sizes).create(self.device)?;
+
+        self.next_max_sets = (max_sets * 2).min(MAX_SETS_PER_POOL);
+        Ok(pool)
+    }
+
+    /// Frees every set allocated from the chain. The sets must not be in use by the GPU.
+    pub fn reset(&mut self) -> br::Result<()> {
+        for p in &mut self.pools {
+            p.reset()?;
+        }
+        self.current = 0;
+
+        Ok(())
+    }
+}
+
+/// Shared set layouts and pools for sets living as long as the allocator.
+pub struct DescriptorAllocator<'d, Device: br::Device + ?Sized + 'd> {
+    device: &'d Device,
+    layouts: HashMap<SetLayoutSignature, Arc<br::DescriptorSetLayoutObject<&'d Device>>>,
+    pools: DescriptorPoolChain<'d, Device>,
+}
+impl<'d, Device: br::Device + ?Sized + 'd> DescriptorAllocator<'d, Device> {
+    pub fn new(device: &'d Device) -> Self {
+        Self {
+            device,
+            layouts: HashMap::new(),
+            pools: DescriptorPoolChain::new(device),
+        }
+    }
+
+    pub fn device(&self) -> &'d Device {
+        self.device
+    }
+
+    /// Layout of the signature, created at the first request.
+    pub fn layout(
+        &mut self,
+        signature: &SetLayoutSignature,
+    ) -> br::Result<Arc<br::DescriptorSetLayoutObject<&'d Device>>> {
+        if let Some(l) = self.layouts.get(signature) {
+            return Ok(l.clone());
+        }
+
+        let bindings = signature
+            .bindings()
+            .iter()
+            .map(LayoutBinding::layout_binding)
+            .collect::<Vec<_>>();
+        let layout = Arc::new(br::DescriptorSetLayoutBuilder::new(&bindings).create(self.device)?);
+        self.layouts.insert(signature.clone(), layout.clone());
+
+        Ok(layout)
+    }
+
+    /// Allocates a set living as long as the allocator.
+    pub fn allocate(
+        &mut self,
+        signature: &SetLayoutSignature,
+    ) -> Result<br::DescriptorSet, EngineError> {
+        let layout = self.layout(signature)?;
+
+        self.pools.allocate(&layout, signature)
+    }
+
+    /// Allocates a set from the pools of the frame slot. The set is freed when the slot is reused,
+    /// so it must be written and bound in the frame.
+    pub fn allocate_transient(
+        &mut self,
+        frame: &mut FrameContext<'d, Device>,
+        signature: &SetLayoutSignature,
+    ) -> Result<br::DescriptorSet, EngineError> {
+        let layout = self.layout(signature)?;
+
+        frame.descriptor_pools_mut().allocate(&layout, signature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binding(binding: u32, kind: DescriptorKind, count: u32) -> LayoutBinding {
+        LayoutBinding {
+            binding,
+            kind,
+            dynamic: false,
+            count,
+            stages: vec![
+                ShaderStage::Fragment,
+                ShaderStage::Vertex,
+                ShaderStage::Fragment,
+            ],
+        }
+    }
+
+    #[test]
+    fn signatures_are_normalized() {
+        let a = SetLayoutSignature::new(vec![
+            binding(1, DescriptorKind::CombinedImageSampler, 1),
+            binding(0, DescriptorKind::UniformBuffer, 1),
+        ]);
+        let b = SetLayoutSignature::new(vec![
+            binding(0, DescriptorKind::UniformBuffer, 1),
+            binding(1, DescriptorKind::CombinedImageSampler, 1),
+        ]);
+
+        assert_eq!(a, b);
+        assert_eq!(
+            a.bindings()[0].stages,
+            [ShaderStage::Vertex, ShaderStage::Fragment]
+        );
+    }
+
+    #[test]
+    fn descriptor_counts_are_summed_per_type() {
+        // base color, metallic-roughness, normal, occlusion and emissive textures
+        let mut bindings = (0..5)
+            .map(|n| binding(n, DescriptorKind::CombinedImageSampler, 1))
+            .collect::<Vec<_>>();
+        bindings.push(binding(5, DescriptorKind::CombinedImageSampler, 4));
+        bindings.push(binding(6, DescriptorKind::UniformBuffer, 1));
+        bindings.push(LayoutBinding {
+            dynamic: true,
+            ..binding(7, DescriptorKind::UniformBuffer, 2)
+        });
+
+        let counts = SetLayoutSignature::new(bindings).descriptor_counts();
+        assert_eq!(counts.len(), 3);
+        assert_eq!(counts[&(DescriptorKind::CombinedImageSampler, false)], 9);
+        assert_eq!(counts[&(DescriptorKind::UniformBuffer, false)], 1);
+        assert_eq!(counts[&(DescriptorKind::UniformBuffer, true)], 2);
+    }
+}
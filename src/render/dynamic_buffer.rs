@@ -0,0 +1,101 @@
+//! Persistently mapped buffer for per-draw uniform/storage data bound with dynamic offsets.
+
+use std::sync::Arc;
+
+use bedrock as br;
+
+use crate::error::EngineError;
+
+use super::memory::{AllocatedBuffer, MemoryAllocator, MemoryLocation};
+
+/// A part of the dynamic buffer, given as a dynamic offset of `*_DYNAMIC` descriptors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DynamicSlice {
+    /// Offset from the beginning of the buffer(not the segment)
+    pub offset: u32,
+    pub size: u64,
+}
+
+/// Linear allocator over a segment of the shared dynamic buffer.
+///
+/// Each frame in flight owns one segment, so the contents can be written directly while the GPU
+/// reads other segments.
+pub struct DynamicBufferSegment<'d, Device: br::Device + ?Sized + 'd> {
+    buffer: Arc<AllocatedBuffer<'d, Device>>,
+    base: u64,
+    size: u64,
+    alignment: u64,
+    cursor: u64,
+}
+impl<'d, Device: br::Device + ?Sized + 'd> DynamicBufferSegment<'d, Device> {
+    /// Creates `count` segments sharing one buffer.
+    ///
+    /// `alignment` should satisfy both `minUniformBufferOffsetAlignment` and
+    /// `minStorageBufferOffsetAlignment`. The memory is host visible, device local if possible(ReBAR/UMA).
+    pub fn create_shared(
+        allocator: &MemoryAllocator<'d, Device>,
+        count: u32,
+        segment_size: u64,
+        alignment: u64,
+    ) -> Result<Vec<Self>, EngineError> {
+        let alignment = alignment.max(1);
+        let stride = segment_size.div_ceil(alignment) * alignment;
+        let buffer = Arc::new(allocator.create_buffer(
+            br::BufferDesc::new(
+                (stride * count as u64).max(1) as _,
+                br::BufferUsage::UNIFORM_BUFFER.storage_buffer(),
+            ),
+            MemoryLocation::DeviceLocalHostVisible,
+        )?);
+
+        Ok((0..count as u64)
+            .map(|n| Self {
+                buffer: buffer.clone(),
+                base: stride * n,
+                size: segment_size,
+                alignment,
+                cursor: 0,
+            })
+            .collect())
+    }
+
+    /// The shared buffer. Descriptors should refer it with a range as large as the largest slice.
+    pub fn buffer(&self) -> &br::BufferObject<&'d Device> {
+        self.buffer.buffer()
+    }
+
+    /// Discards every slice. The GPU must not be reading the segment.
+    pub fn reset(&mut self) {
+        self.cursor = 0;
+    }
+
+    /// Copies bytes into a new aligned slice. None if the segment is exhausted.
+    pub fn push_bytes(&mut self, bytes: &[u8]) -> Option<DynamicSlice> {
+        let start = self.cursor.div_ceil(self.alignment) * self.alignment;
+        let end = start + bytes.len() as u64;
+        if end > self.size {
+            return None;
+        }
+        self.cursor = end;
+
+        let offset = self.base + start;
+        // segment is not used by the GPU(reset after its frame has been completed)
+        unsafe {
+            self.buffer.write_mapped_bytes(offset as _, bytes);
+        }
+
+        Some(DynamicSlice {
+            offset: offset as _,
+            size: bytes.len() as _,
+        })
+    }
+
+    /// Writes a plain value into a new aligned slice. None if the segment is exhausted.
+    pub fn push<T: Copy>(&mut self, value: &T) -> Option<DynamicSlice> {
+        let bytes = unsafe {
+            core::slice::from_raw_parts(value as *const T as *const u8, core::mem::size_of::<T>())
+        };
+
+        self.push_bytes(bytes)
+    }
+}
@@ -0,0 +1,202 @@
+//! Frames-in-flight ring.
+//!
+//! Each frame slot owns its own fence, semaphores, command pool, descriptor pools and a segment of
+//! the dynamic buffer, so the CPU can record the next frame while the GPU still renders previous
+//! ones.
+
+use bedrock::{self as br, CommandPoolMut, Fence, FenceMut, Status};
+
+use crate::error::EngineError;
+
+use super::{
+    descriptor::DescriptorPoolChain, dynamic_buffer::DynamicBufferSegment, memory::MemoryAllocator,
+};
+
+pub const DEFAULT_FRAMES_IN_FLIGHT: u32 = 2;
+
+/// Resources of a frame slot.
+pub struct FrameContext<'d, Device: br::Device + ?Sized + 'd> {
+    index: u32,
+    fence: br::FenceObject<&'d Device>,
+    /// true while the fence is pending a submission
+    submitted: bool,
+    render_ready: br::SemaphoreObject<&'d Device>,
+    present_ready: br::SemaphoreObject<&'d Device>,
+    command_pool: br::CommandPoolObject<&'d Device>,
+    descriptor_pools: DescriptorPoolChain<'d, Device>,
+    dynamic_buffer: DynamicBufferSegment<'d, Device>,
+}
+impl<'d, Device: br::Device + ?Sized + 'd> FrameContext<'d, Device> {
+    /// Index of the slot(0..depth)
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// Signaled when the acquired back buffer becomes available
+    pub fn render_ready_mut(&mut self) -> &mut br::SemaphoreObject<&'d Device> {
+        &mut self.render_ready
+    }
+
+    pub fn render_ready(&self) -> &br::SemaphoreObject<&'d Device> {
+        &self.render_ready
+    }
+
+    /// Should be signaled by the last submission of the frame and waited by presentation
+    pub fn present_ready(&self) -> &br::SemaphoreObject<&'d Device> {
+        &self.present_ready
+    }
+
+    /// Reset at the beginning of the frame. Command buffers allocated from this can be reused
+    /// in every frame using this slot.
+    pub fn command_pool_mut(&mut self) -> &mut br::CommandPoolObject<&'d Device> {
+        &mut self.command_pool
+    }
+
+    /// Resources for the last submission of the frame. The slot is reused after the fence is signaled.
+    pub fn submission(&mut self) -> FrameSubmission<'_, 'd, Device> {
+        self.submitted = true;
+
+        FrameSubmission {
+            index: self.index,
+            render_ready: &self.render_ready,
+            present_ready: &self.present_ready,
+            fence: self.fence.as_transparent_mut_ref(),
+        }
+    }
+
+    /// Transient descriptor sets of this frame. Reset at the beginning of the frame
+    ///
+    /// Sets are usually allocated through `DescriptorAllocator::allocate_transient`.
+    pub fn descriptor_pools_mut(&mut self) -> &mut DescriptorPoolChain<'d, Device> {
+        &mut self.descriptor_pools
+    }
+
+    /// Per-draw uniform/storage data of this frame. Reset at the beginning of the frame
+    pub fn dynamic_buffer_mut(&mut self) -> &mut DynamicBufferSegment<'d, Device> {
+        &mut self.dynamic_buffer
+    }
+}
+
+pub struct FrameSubmission<'f, 'd, Device: br::Device + ?Sized + 'd> {
+    pub index: u32,
+    /// Should be waited at `COLOR_ATTACHMENT_OUTPUT` stage before writing to the back buffer
+    pub render_ready: &'f br::SemaphoreObject<&'d Device>,
+    pub present_ready: &'f br::SemaphoreObject<&'d Device>,
+    pub fence: br::FenceMutRef<'f>,
+}
+
+/// Frame pacing statistics.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    /// Frames started
+    pub begun: u64,
+    /// Frame requests dropped because every slot was still in use by the GPU
+    pub dropped: u64,
+    /// Frames which had to wait for the GPU before starting
+    pub stalled: u64,
+}
+impl FrameStats {
+    /// Ratio of dropped frames to all frame requests
+    pub fn drop_ratio(&self) -> f64 {
+        let requested = self.begun + self.dropped;
+        if requested == 0 {
+            return 0.0;
+        }
+
+        self.dropped as f64 / requested as f64
+    }
+}
+
+pub struct FrameRing<'d, Device: br::Device + ?Sized + 'd> {
+    frames: Vec<FrameContext<'d, Device>>,
+    next: usize,
+    stats: FrameStats,
+}
+impl<'d, Device: br::Device + ?Sized + 'd> FrameRing<'d, Device> {
+    /// Creates `depth` slots, each of them owns `dynamic_buffer_size` bytes of the dynamic buffer.
+    ///
+    /// `dynamic_offset_alignment` is passed to [`DynamicBufferSegment::create_shared`].
+    pub fn new(
+        device: &'d Device,
+        queue_family_index: u32,
+        allocator: &MemoryAllocator<'d, Device>,
+        depth: u32,
+        dynamic_buffer_size: u64,
+        dynamic_offset_alignment: u64,
+    ) -> Result<Self, EngineError> {
+        let depth = depth.max(1);
+        let dynamic_buffers = DynamicBufferSegment::create_shared(
+            allocator,
+            depth,
+            dynamic_buffer_size,
+            dynamic_offset_alignment,
+        )?;
+
+        let frames = (0..depth)
+            .zip(dynamic_buffers)
+            .map(|(index, dynamic_buffer)| {
+                Ok(FrameContext {
+                    index,
+                    fence: br::FenceBuilder::new().create(device)?,
+                    submitted: false,
+                    render_ready: br::SemaphoreBuilder::new().create(device)?,
+                    present_ready: br::SemaphoreBuilder::new().create(device)?,
+                    command_pool: br::CommandPoolBuilder::new(queue_family_index).create(device)?,
+                    descriptor_pools: DescriptorPoolChain::new(device),
+                    dynamic_buffer,
+                })
+            })
+            .collect::<Result<Vec<_>, EngineError>>()?;
+
+        Ok(Self {
+            frames,
+            next: 0,
+            stats: FrameStats::default(),
+        })
+    }
+
+    pub fn depth(&self) -> u32 {
+        self.frames.len() as _
+    }
+
+    pub fn frames_mut(&mut self) -> impl Iterator<Item = &mut FrameContext<'d, Device>> {
+        self.frames.iter_mut()
+    }
+
+    /// Starts the next frame slot.
+    ///
+    /// If the GPU is still using the slot, waits for it when `wait` is true, otherwise returns None
+    /// and counts the request as dropped.
+    pub fn begin(
+        &mut self,
+        wait: bool,
+    ) -> Result<Option<&mut FrameContext<'d, Device>>, EngineError> {
+        let depth = self.frames.len();
+        let frame = &mut self.frames[self.next];
+        if frame.submitted {
+            if !frame.fence.status()? {
+                if !wait {
+                    self.stats.dropped += 1;
+                    return Ok(None);
+                }
+
+                frame.fence.wait()?;
+                self.stats.stalled += 1;
+            }
+
+            frame.fence.reset()?;
+            frame.submitted = false;
+        }
+        frame.command_pool.reset(true)?;
+        frame.descriptor_pools.reset()?;
+        frame.dynamic_buffer.reset();
+
+        self.next = (self.next + 1) % depth;
+        self.stats.begun += 1;
+        Ok(Some(frame))
+    }
+
+    pub fn stats(&self) -> &FrameStats {
+        &self.stats
+    }
+}
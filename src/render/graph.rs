@@ -0,0 +1,1281 @@
+//! Render graph.
+//!
+//! Passes declare the images and buffers they read or write, and the graph derives image layouts,
+//! pipeline barriers, transient attachments and the execution order from the declarations.
+//! Recording commands of each pass is left to the caller.
+
+use std::{collections::HashMap, sync::Arc};
+
+use bedrock::{self as br, ImageSubresourceSlice};
+
+use crate::error::EngineError;
+
+use super::memory::{MemoryAllocation, MemoryAllocator, MemoryLocation};
+
+pub type TransientImage<'d, Device> = br::ImageObject<&'d Device>;
+pub type TransientImageView<'d, Device> = br::ImageViewObject<Arc<TransientImage<'d, Device>>>;
+pub type GraphFramebuffer<'d, Device> =
+    br::FramebufferObject<&'d Device, TransientImageView<'d, Device>>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ImageHandle(usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BufferHandle(usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PassId(usize);
+
+/// How the contents of an attachment are treated at the beginning of a graph-managed render pass.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AttachmentLoad {
+    Clear,
+    Load,
+    DontCare,
+}
+impl AttachmentLoad {
+    fn op(self) -> br::LoadOp {
+        match self {
+            Self::Clear => br::LoadOp::Clear,
+            Self::Load => br::LoadOp::Load,
+            Self::DontCare => br::LoadOp::DontCare,
+        }
+    }
+}
+
+/// Usage of an image in a pass.
+#[derive(Clone, Copy, Debug)]
+pub enum ImageAccess {
+    /// Color attachment of the render pass created by the graph(transient images only)
+    ColorAttachment(AttachmentLoad),
+    /// Depth/stencil attachment of the render pass created by the graph(transient images only)
+    DepthAttachment(AttachmentLoad),
+    /// Color attachment of a render pass created outside of the graph(e.g. the main render pass).
+    /// The render pass must take and leave the image in `COLOR_ATTACHMENT_OPTIMAL`.
+    ExternalColorAttachment,
+    /// Depth/stencil attachment of a render pass created outside of the graph.
+    /// The render pass must take and leave the image in `DEPTH_STENCIL_ATTACHMENT_OPTIMAL`.
+    ExternalDepthAttachment,
+    /// Read through a sampler in the stages
+    Sampled(br::PipelineStageFlags),
+    StorageRead(br::PipelineStageFlags),
+    StorageWrite(br::PipelineStageFlags),
+    TransferSrc,
+    TransferDst,
+}
+impl ImageAccess {
+    fn requirement(self) -> Requirement<br::ImageLayout> {
+        match self {
+            Self::ColorAttachment(load) => Requirement {
+                layout: br::ImageLayout::ColorAttachmentOpt,
+                stages: br::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT.0,
+                access: if load == AttachmentLoad::Load {
+                    br::AccessFlags::COLOR_ATTACHMENT.read | br::AccessFlags::COLOR_ATTACHMENT.write
+                } else {
+                    br::AccessFlags::COLOR_ATTACHMENT.write
+                },
+                writes: true,
+            },
+            Self::ExternalColorAttachment => Requirement {
+                layout: br::ImageLayout::ColorAttachmentOpt,
+                stages: br::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT.0,
+                access: br::AccessFlags::COLOR_ATTACHMENT.read
+                    | br::AccessFlags::COLOR_ATTACHMENT.write,
+                writes: true,
+            },
+            Self::DepthAttachment(_) | Self::ExternalDepthAttachment => Requirement {
+                layout: br::ImageLayout::DepthStencilAttachmentOpt,
+                stages: br::PipelineStageFlags::EARLY_FRAGMENT_TESTS.0
+                    | br::PipelineStageFlags::LATE_FRAGMENT_TESTS.0,
+                access: br::AccessFlags::DEPTH_STENCIL_ATTACHMENT.read
+                    | br::AccessFlags::DEPTH_STENCIL_ATTACHMENT.write,
+                writes: true,
+            },
+            Self::Sampled(stages) => Requirement {
+                layout: br::ImageLayout::ShaderReadOnlyOpt,
+                stages: stages.0,
+                access: br::AccessFlags::SHADER.read,
+                writes: false,
+            },
+            Self::StorageRead(stages) => Requirement {
+                layout: br::ImageLayout::General,
+                stages: stages.0,
+                access: br::AccessFlags::SHADER.read,
+                writes: false,
+            },
+            Self::StorageWrite(stages) => Requirement {
+                layout: br::ImageLayout::General,
+                stages: stages.0,
+                access: br::AccessFlags::SHADER.read | br::AccessFlags::SHADER.write,
+                writes: true,
+            },
+            Self::TransferSrc => Requirement {
+                layout: br::ImageLayout::TransferSrcOpt,
+                stages: br::PipelineStageFlags::TRANSFER.0,
+                access: br::AccessFlags::TRANSFER.read,
+                writes: false,
+            },
+            Self::TransferDst => Requirement {
+                layout: br::ImageLayout::TransferDestOpt,
+                stages: br::PipelineStageFlags::TRANSFER.0,
+                access: br::AccessFlags::TRANSFER.write,
+                writes: true,
+            },
+        }
+    }
+
+    /// true if the previous contents are observed
+    fn reads(self) -> bool {
+        !matches!(
+            self,
+            Self::ColorAttachment(AttachmentLoad::Clear | AttachmentLoad::DontCare)
+                | Self::DepthAttachment(AttachmentLoad::Clear | AttachmentLoad::DontCare)
+                | Self::TransferDst
+        )
+    }
+
+    fn usage(self) -> br::vk::VkImageUsageFlags {
+        match self {
+            Self::ColorAttachment(_) | Self::ExternalColorAttachment => {
+                br::vk::VK_IMAGE_USAGE_COLOR_ATTACHMENT_BIT
+            }
+            Self::DepthAttachment(_) | Self::ExternalDepthAttachment => {
+                br::vk::VK_IMAGE_USAGE_DEPTH_STENCIL_ATTACHMENT_BIT
+            }
+            Self::Sampled(_) => br::vk::VK_IMAGE_USAGE_SAMPLED_BIT,
+            Self::StorageRead(_) | Self::StorageWrite(_) => br::vk::VK_IMAGE_USAGE_STORAGE_BIT,
+            Self::TransferSrc => br::vk::VK_IMAGE_USAGE_TRANSFER_SRC_BIT,
+            Self::TransferDst => br::vk::VK_IMAGE_USAGE_TRANSFER_DST_BIT,
+        }
+    }
+
+    /// (is_depth, load) if the image becomes an attachment of the graph-managed render pass
+    fn graph_attachment(self) -> Option<(bool, AttachmentLoad)> {
+        match self {
+            Self::ColorAttachment(load) => Some((false, load)),
+            Self::DepthAttachment(load) => Some((true, load)),
+            _ => None,
+        }
+    }
+}
+
+/// Usage of a buffer in a pass.
+#[derive(Clone, Copy, Debug)]
+pub enum BufferAccess {
+    Vertex,
+    Index,
+    Indirect,
+    Uniform(br::PipelineStageFlags),
+    StorageRead(br::PipelineStageFlags),
+    StorageWrite(br::PipelineStageFlags),
+    TransferSrc,
+    TransferDst,
+}
+impl BufferAccess {
+    fn requirement(self) -> Requirement<()> {
+        let (stages, access, writes) = match self {
+            Self::Vertex => (
+                br::PipelineStageFlags::VERTEX_INPUT.0,
+                br::AccessFlags::VERTEX_ATTRIBUTE_READ,
+                false,
+            ),
+            Self::Index => (
+                br::PipelineStageFlags::VERTEX_INPUT.0,
+                br::AccessFlags::INDEX_READ,
+                false,
+            ),
+            Self::Indirect => (
+                br::PipelineStageFlags::DRAW_INDIRECT.0,
+                br::AccessFlags::INDIRECT_COMMAND_READ,
+                false,
+            ),
+            Self::Uniform(stages) => (stages.0, br::AccessFlags::UNIFORM_READ, false),
+            Self::StorageRead(stages) => (stages.0, br::AccessFlags::SHADER.read, false),
+            Self::StorageWrite(stages) => (
+                stages.0,
+                br::AccessFlags::SHADER.read | br::AccessFlags::SHADER.write,
+                true,
+            ),
+            Self::TransferSrc => (
+                br::PipelineStageFlags::TRANSFER.0,
+                br::AccessFlags::TRANSFER.read,
+                false,
+            ),
+            Self::TransferDst => (
+                br::PipelineStageFlags::TRANSFER.0,
+                br::AccessFlags::TRANSFER.write,
+                true,
+            ),
+        };
+
+        Requirement {
+            layout: (),
+            stages,
+            access,
+            writes,
+        }
+    }
+}
+
+/// State required by an access
+struct Requirement<L> {
+    layout: L,
+    stages: br::vk::VkPipelineStageFlags,
+    access: br::vk::VkAccessFlags,
+    writes: bool,
+}
+
+/// An image owned outside of the graph(e.g. back buffers).
+#[derive(Clone, Debug)]
+pub struct ImportedImage {
+    pub image: br::vk::VkImage,
+    pub format: br::vk::VkFormat,
+    pub extent: br::vk::VkExtent2D,
+    pub aspect: br::vk::VkImageAspectFlags,
+    /// Layout at the beginning of the frame
+    pub initial_layout: br::ImageLayout,
+    /// Stages to be waited before the first access(e.g. the wait stage of the acquisition semaphore)
+    pub initial_stages: br::PipelineStageFlags,
+    /// Writes in `initial_stages` to be made available before the first access(e.g. depth writes
+    /// of the previous frame sharing the image)
+    pub initial_access: br::vk::VkAccessFlags,
+    /// The image is transitioned into this layout after the last access. Passes writing images
+    /// without final layouts are culled unless their results are used by other passes.
+    pub final_layout: Option<br::ImageLayout>,
+}
+
+/// An image created(and recycled across frames) by the graph. The contents are undefined at the
+/// beginning of the frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TransientImageDesc {
+    pub format: br::vk::VkFormat,
+    pub extent: br::vk::VkExtent2D,
+}
+
+enum ImageResource {
+    Imported(ImportedImage),
+    Transient(TransientImageDesc),
+}
+impl ImageResource {
+    fn format(&self) -> br::vk::VkFormat {
+        match self {
+            Self::Imported(x) => x.format,
+            Self::Transient(x) => x.format,
+        }
+    }
+}
+
+struct PassDesc {
+    images: Vec<(ImageHandle, ImageAccess)>,
+    buffers: Vec<(BufferHandle, BufferAccess)>,
+    side_effects: bool,
+}
+
+/// Declarations of the passes in a frame.
+///
+/// Passes are executed in declaration order: an access observes the results of the passes
+/// declared before it.
+#[derive(Default)]
+pub struct RenderGraphDesc {
+    images: Vec<ImageResource>,
+    buffers: Vec<br::vk::VkBuffer>,
+    passes: Vec<PassDesc>,
+}
+impl RenderGraphDesc {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn import_image(&mut self, image: ImportedImage) -> ImageHandle {
+        self.images.push(ImageResource::Imported(image));
+
+        ImageHandle(self.images.len() - 1)
+    }
+
+    pub fn create_image(&mut self, desc: TransientImageDesc) -> ImageHandle {
+        self.images.push(ImageResource::Transient(desc));
+
+        ImageHandle(self.images.len() - 1)
+    }
+
+    /// Buffers are always treated as outputs: passes writing them are never culled.
+    /// Importing the same buffer again returns the same handle.
+    pub fn import_buffer(&mut self, buffer: br::vk::VkBuffer) -> BufferHandle {
+        if let Some(n) = self.buffers.iter().position(|&b| b == buffer) {
+            return BufferHandle(n);
+        }
+        self.buffers.push(buffer);
+
+        BufferHandle(self.buffers.len() - 1)
+    }
+
+    pub fn add_pass(&mut self) -> PassBuilder<'_> {
+        self.passes.push(PassDesc {
+            images: Vec::new(),
+            buffers: Vec::new(),
+            side_effects: false,
+        });
+
+        PassBuilder {
+            index: self.passes.len() - 1,
+            graph: self,
+        }
+    }
+}
+
+pub struct PassBuilder<'g> {
+    graph: &'g mut RenderGraphDesc,
+    index: usize,
+}
+impl PassBuilder<'_> {
+    pub fn image(self, image: ImageHandle, access: ImageAccess) -> Self {
+        self.graph.passes[self.index].images.push((image, access));
+        self
+    }
+
+    pub fn buffer(self, buffer: BufferHandle, access: BufferAccess) -> Self {
+        self.graph.passes[self.index].buffers.push((buffer, access));
+        self
+    }
+
+    /// Keeps the pass even if none of its results are used(e.g. readbacks via host-visible memory)
+    pub fn side_effects(self) -> Self {
+        self.graph.passes[self.index].side_effects = true;
+        self
+    }
+
+    pub fn id(self) -> PassId {
+        PassId(self.index)
+    }
+}
+
+/// A merged `vkCmdPipelineBarrier` call.
+pub struct Barrier {
+    pub src_stage: br::PipelineStageFlags,
+    pub dst_stage: br::PipelineStageFlags,
+    pub memory_barriers: Vec<br::vk::VkMemoryBarrier>,
+    pub image_barriers: Vec<br::vk::VkImageMemoryBarrier>,
+}
+
+#[derive(Default)]
+struct BarrierBuilder {
+    src_stages: br::vk::VkPipelineStageFlags,
+    dst_stages: br::vk::VkPipelineStageFlags,
+    memory_src_access: br::vk::VkAccessFlags,
+    memory_dst_access: br::vk::VkAccessFlags,
+    has_memory_barrier: bool,
+    image_barriers: Vec<br::vk::VkImageMemoryBarrier>,
+}
+impl BarrierBuilder {
+    fn build(self) -> Option<Barrier> {
+        if !self.has_memory_barrier && self.image_barriers.is_empty() {
+            return None;
+        }
+
+        let memory_barriers = if self.has_memory_barrier {
+            vec![br::vk::VkMemoryBarrier {
+                sType: br::vk::VkMemoryBarrier::TYPE,
+                pNext: core::ptr::null(),
+                srcAccessMask: self.memory_src_access,
+                dstAccessMask: self.memory_dst_access,
+            }]
+        } else {
+            Vec::new()
+        };
+
+        Some(Barrier {
+            src_stage: br::PipelineStageFlags(if self.src_stages == 0 {
+                br::PipelineStageFlags::TOP_OF_PIPE.0
+            } else {
+                self.src_stages
+            }),
+            dst_stage: br::PipelineStageFlags(if self.dst_stages == 0 {
+                br::PipelineStageFlags::BOTTOM_OF_PIPE.0
+            } else {
+                self.dst_stages
+            }),
+            memory_barriers,
+            image_barriers: self.image_barriers,
+        })
+    }
+}
+
+/// Synchronization state of a resource while walking the passes
+struct ResourceTrack<L> {
+    layout: L,
+    /// Stages of the last write(or the last layout transition)
+    write_stages: br::vk::VkPipelineStageFlags,
+    write_access: br::vk::VkAccessFlags,
+    /// Stages which have been synchronized with the last write
+    read_stages: br::vk::VkPipelineStageFlags,
+}
+impl<L: Copy + PartialEq> ResourceTrack<L> {
+    /// Updates the state for the access. Returns (src stages, src access) if a barrier is needed.
+    fn access(
+        &mut self,
+        req: &Requirement<L>,
+    ) -> Option<(br::vk::VkPipelineStageFlags, br::vk::VkAccessFlags)> {
+        let barrier = if self.layout != req.layout || req.writes {
+            // layout transitions and writes must wait for every previous access
+            let src = self.write_stages | self.read_stages;
+            (src != 0 || self.layout != req.layout).then_some((src, self.write_access))
+        } else {
+            // reads need the last write to be visible in its stages
+            (self.write_stages != 0 && (req.stages & !self.read_stages) != 0)
+                .then_some((self.write_stages, self.write_access))
+        };
+
+        if req.writes {
+            self.write_stages = req.stages;
+            self.write_access = req.access;
+            self.read_stages = 0;
+        } else if self.layout != req.layout {
+            // later readers in other stages have to wait for the transition
+            self.write_stages = req.stages;
+            self.write_access = 0;
+            self.read_stages = req.stages;
+        } else {
+            self.read_stages |= req.stages;
+        }
+        self.layout = req.layout;
+
+        barrier
+    }
+}
+
+fn format_aspect(format: br::vk::VkFormat) -> br::vk::VkImageAspectFlags {
+    match format {
+        br::vk::VK_FORMAT_D16_UNORM
+        | br::vk::VK_FORMAT_X8_D24_UNORM_PACK32
+        | br::vk::VK_FORMAT_D32_SFLOAT => br::vk::VK_IMAGE_ASPECT_DEPTH_BIT,
+        br::vk::VK_FORMAT_D16_UNORM_S8_UINT
+        | br::vk::VK_FORMAT_D24_UNORM_S8_UINT
+        | br::vk::VK_FORMAT_D32_SFLOAT_S8_UINT => {
+            br::vk::VK_IMAGE_ASPECT_DEPTH_BIT | br::vk::VK_IMAGE_ASPECT_STENCIL_BIT
+        }
+        br::vk::VK_FORMAT_S8_UINT => br::vk::VK_IMAGE_ASPECT_STENCIL_BIT,
+        _ => br::vk::VK_IMAGE_ASPECT_COLOR_BIT,
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct AttachmentKey {
+    format: br::vk::VkFormat,
+    depth: bool,
+    load: AttachmentLoad,
+}
+
+/// A transient image and its memory
+struct TransientResources<'d, Device: br::Device + ?Sized + 'd> {
+    // Note: メモリより先にイメージを破棄する
+    image: Arc<TransientImage<'d, Device>>,
+    _allocation: MemoryAllocation<'d, Device>,
+}
+
+struct TransientSlot<R> {
+    id: u64,
+    desc: TransientImageDesc,
+    usage: br::vk::VkImageUsageFlags,
+    last_used_frame: u64,
+    /// Accesses written by the last frame using the image(to be made available before reuse)
+    last_write_access: br::vk::VkAccessFlags,
+    resources: R,
+}
+
+/// Transient images recycled across frames.
+struct TransientPool<R> {
+    /// Slots not used for this number of frames are released
+    retain_frames: u64,
+    frame: u64,
+    next_id: u64,
+    slots: Vec<TransientSlot<R>>,
+}
+impl<R> TransientPool<R> {
+    fn new(retain_frames: u64) -> Self {
+        Self {
+            retain_frames,
+            frame: 0,
+            next_id: 0,
+            slots: Vec::new(),
+        }
+    }
+
+    /// Starts a new frame and releases slots unused for a while. Returns ids of the released slots.
+    fn begin_frame(&mut self) -> Vec<u64> {
+        self.frame += 1;
+
+        let (frame, retain_frames) = (self.frame, self.retain_frames);
+        let expired = |s: &TransientSlot<R>| frame - s.last_used_frame > retain_frames;
+        let released = self
+            .slots
+            .iter()
+            .filter(|s| expired(s))
+            .map(|s| s.id)
+            .collect();
+        self.slots.retain(|s| !expired(s));
+
+        released
+    }
+
+    /// Index of a slot not used in this frame yet. A new slot is made with `create` if there is
+    /// none of the same description and usage.
+    fn acquire<E>(
+        &mut self,
+        desc: &TransientImageDesc,
+        usage: br::vk::VkImageUsageFlags,
+        create: impl FnOnce() -> Result<R, E>,
+    ) -> Result<usize, E> {
+        let reusable = self
+            .slots
+            .iter()
+            .position(|s| s.desc == *desc && s.usage == usage && s.last_used_frame != self.frame);
+        let n = match reusable {
+            Some(n) => n,
+            None => {
+                self.slots.push(TransientSlot {
+                    id: self.next_id,
+                    desc: *desc,
+                    usage,
+                    last_used_frame: 0,
+                    last_write_access: 0,
+                    resources: create()?,
+                });
+                self.next_id += 1;
+
+                self.slots.len() - 1
+            }
+        };
+        self.slots[n].last_used_frame = self.frame;
+
+        Ok(n)
+    }
+
+    fn find(&self, id: u64) -> &TransientSlot<R> {
+        self.slots
+            .iter()
+            .find(|s| s.id == id)
+            .expect("no transient slot")
+    }
+}
+
+/// Caches of render passes, framebuffers and transient images used by compiled graphs.
+pub struct RenderGraph<'d, Device: br::Device + ?Sized + 'd> {
+    device: &'d Device,
+    allocator: MemoryAllocator<'d, Device>,
+    // Note: framebuffersがtransientsのイメージを参照しているので先に破棄されるようにする
+    framebuffers: HashMap<(Vec<AttachmentKey>, Vec<u64>), GraphFramebuffer<'d, Device>>,
+    render_passes: HashMap<Vec<AttachmentKey>, br::RenderPassObject<&'d Device>>,
+    transients: TransientPool<TransientResources<'d, Device>>,
+}
+impl<'d, Device: br::Device + ?Sized + 'd> RenderGraph<'d, Device> {
+    /// `frames_in_flight` decides how long unused transient images are kept(the GPU may still
+    /// use them in previous frames).
+    pub fn new(
+        device: &'d Device,
+        allocator: MemoryAllocator<'d, Device>,
+        frames_in_flight: u32,
+    ) -> Self {
+        Self {
+            device,
+            allocator,
+            framebuffers: HashMap::new(),
+            render_passes: HashMap::new(),
+            transients: TransientPool::new(frames_in_flight.max(1) as _),
+        }
+    }
+
+    /// Compiles the declarations of a frame. Must be called once per frame.
+    pub fn compile(
+        &mut self,
+        desc: &RenderGraphDesc,
+    ) -> Result<CompiledGraph<'_, 'd, Device>, EngineError> {
+        let order = cull_passes(desc);
+
+        let released = self.transients.begin_frame();
+        if !released.is_empty() {
+            // framebuffers referencing the released images
+            self.framebuffers
+                .retain(|(_, ids), _| !ids.iter().any(|id| released.contains(id)));
+        }
+        let transient_slots = self.assign_transients(desc, &order)?;
+
+        let mut image_tracks = desc
+            .images
+            .iter()
+            .zip(&transient_slots)
+            .map(|(r, slot)| match r {
+                ImageResource::Imported(x) => ResourceTrack {
+                    layout: x.initial_layout,
+                    write_stages: x.initial_stages.0,
+                    write_access: x.initial_access,
+                    read_stages: 0,
+                },
+                // Note: 前のフレームがまだ使っている可能性があるので全部待つ(書き込みも可視化する)
+                ImageResource::Transient(_) => ResourceTrack {
+                    layout: br::ImageLayout::Undefined,
+                    write_stages: br::PipelineStageFlags::ALL_COMMANDS.0,
+                    write_access: slot.map_or(0, |s| self.transients.slots[s].last_write_access),
+                    read_stages: 0,
+                },
+            })
+            .collect::<Vec<_>>();
+        let mut written_access = vec![0; desc.images.len()];
+        // writes in previous frames are made visible by the submission/upload barriers:
+        // only reads of previous frames must be waited
+        let mut buffer_tracks = desc
+            .buffers
+            .iter()
+            .map(|_| ResourceTrack {
+                layout: (),
+                write_stages: 0,
+                write_access: 0,
+                read_stages: br::PipelineStageFlags::ALL_COMMANDS.0,
+            })
+            .collect::<Vec<_>>();
+
+        let mut pass_barriers = Vec::with_capacity(order.len());
+        let mut pass_attachments = Vec::with_capacity(order.len());
+        for &p in &order {
+            let pass = &desc.passes[p];
+            let mut barrier = BarrierBuilder::default();
+
+            for &(h, access) in &pass.images {
+                let req = access.requirement();
+                if req.writes {
+                    written_access[h.0] |= req.access;
+                }
+                let track = &mut image_tracks[h.0];
+                let old_layout = track.layout;
+                if let Some((src_stages, src_access)) = track.access(&req) {
+                    barrier.src_stages |= src_stages;
+                    barrier.dst_stages |= req.stages;
+                    barrier.image_barriers.push(self.image_barrier(
+                        desc,
+                        &transient_slots,
+                        h,
+                        (src_access, req.access),
+                        (old_layout, req.layout),
+                    ));
+                }
+            }
+            for &(h, access) in &pass.buffers {
+                let req = access.requirement();
+                if let Some((src_stages, src_access)) = buffer_tracks[h.0].access(&req) {
+                    barrier.src_stages |= src_stages;
+                    barrier.dst_stages |= req.stages;
+                    barrier.memory_src_access |= src_access;
+                    barrier.memory_dst_access |= req.access;
+                    barrier.has_memory_barrier = true;
+                }
+            }
+            pass_barriers.push(barrier.build());
+
+            let attachments = pass
+                .images
+                .iter()
+                .filter_map(|&(h, access)| access.graph_attachment().map(|a| (h, a)))
+                .collect::<Vec<_>>();
+            if attachments.is_empty() {
+                pass_attachments.push(None);
+                continue;
+            }
+            let mut key = Vec::with_capacity(attachments.len());
+            let mut slot_ids = Vec::with_capacity(attachments.len());
+            for (h, (depth, load)) in attachments {
+                let Some(slot) = transient_slots[h.0] else {
+                    return Err(EngineError::Unsupported(
+                        "graph-managed attachments of imported images",
+                    ));
+                };
+                key.push(AttachmentKey {
+                    format: desc.images[h.0].format(),
+                    depth,
+                    load,
+                });
+                slot_ids.push(self.transients.slots[slot].id);
+            }
+            self.prepare_framebuffer(&key, &slot_ids)?;
+            pass_attachments.push(Some((key, slot_ids)));
+        }
+
+        for (slot, access) in transient_slots.iter().zip(written_access) {
+            if let Some(s) = slot {
+                self.transients.slots[*s].last_write_access = access;
+            }
+        }
+
+        let mut final_barrier = BarrierBuilder::default();
+        for (n, r) in desc.images.iter().enumerate() {
+            let ImageResource::Imported(ImportedImage {
+                final_layout: Some(final_layout),
+                ..
+            }) = r
+            else {
+                continue;
+            };
+            let track = &image_tracks[n];
+            if track.layout == *final_layout {
+                continue;
+            }
+
+            final_barrier.src_stages |= track.write_stages | track.read_stages;
+            final_barrier.image_barriers.push(self.image_barrier(
+                desc,
+                &transient_slots,
+                ImageHandle(n),
+                (track.write_access, 0),
+                (track.layout, *final_layout),
+            ));
+        }
+
+        let this = &*self;
+        let passes = order
+            .into_iter()
+            .zip(pass_barriers)
+            .zip(pass_attachments)
+            .map(|((p, barrier), attachments)| CompiledPass {
+                id: PassId(p),
+                barrier,
+                render_pass: attachments.map(|(key, slot_ids)| {
+                    let extent = this.transients.find(slot_ids[0]).desc.extent;
+                    let framebuffer = &this.framebuffers[&(key.clone(), slot_ids)];
+
+                    GraphRenderPass {
+                        render_pass: &this.render_passes[&key],
+                        framebuffer,
+                        render_area: br::vk::VkRect2D {
+                            offset: br::vk::VkOffset2D::ZERO,
+                            extent,
+                        },
+                    }
+                }),
+            })
+            .collect();
+
+        Ok(CompiledGraph {
+            passes,
+            final_barrier: final_barrier.build(),
+        })
+    }
+
+    fn image_barrier(
+        &self,
+        desc: &RenderGraphDesc,
+        transient_slots: &[Option<usize>],
+        handle: ImageHandle,
+        (src_access, dst_access): (br::vk::VkAccessFlags, br::vk::VkAccessFlags),
+        (old_layout, new_layout): (br::ImageLayout, br::ImageLayout),
+    ) -> br::vk::VkImageMemoryBarrier {
+        let (image, aspect) = match &desc.images[handle.0] {
+            ImageResource::Imported(x) => (x.image, x.aspect),
+            ImageResource::Transient(x) => {
+                let slot = transient_slots[handle.0].expect("transient image not assigned");
+                (
+                    br::VkHandle::native_ptr(&*self.transients.slots[slot].resources.image),
+                    format_aspect(x.format),
+                )
+            }
+        };
+
+        br::vk::VkImageMemoryBarrier {
+            sType: br::vk::VkImageMemoryBarrier::TYPE,
+            pNext: core::ptr::null(),
+            srcAccessMask: src_access,
+            dstAccessMask: dst_access,
+            oldLayout: old_layout as _,
+            newLayout: new_layout as _,
+            srcQueueFamilyIndex: br::vk::VK_QUEUE_FAMILY_IGNORED,
+            dstQueueFamilyIndex: br::vk::VK_QUEUE_FAMILY_IGNORED,
+            image,
+            subresourceRange: br::vk::VkImageSubresourceRange {
+                aspectMask: aspect,
+                baseMipLevel: 0,
+                levelCount: br::vk::VK_REMAINING_MIP_LEVELS,
+                baseArrayLayer: 0,
+                layerCount: br::vk::VK_REMAINING_ARRAY_LAYERS,
+            },
+        }
+    }
+
+    /// Picks(or creates) transient images for the frame. Returns slot indices for each image.
+    fn assign_transients(
+        &mut self,
+        desc: &RenderGraphDesc,
+        order: &[usize],
+    ) -> Result<Vec<Option<usize>>, EngineError> {
+        let mut usages = vec![0; desc.images.len()];
+        for &p in order {
+            for &(h, access) in &desc.passes[p].images {
+                usages[h.0] |= access.usage();
+            }
+        }
+
+        let mut slots = vec![None; desc.images.len()];
+        for (n, r) in desc.images.iter().enumerate() {
+            let ImageResource::Transient(image_desc) = r else {
+                continue;
+            };
+            if usages[n] == 0 {
+                // not used by any alive passes
+                continue;
+            }
+
+            let allocator = &self.allocator;
+            let slot = self.transients.acquire(image_desc, usages[n], || {
+                let (image, allocation) = allocator
+                    .create_image(
+                        br::ImageDesc::new(
+                            image_desc.extent,
+                            image_desc.format,
+                            br::ImageUsageFlags(usages[n]),
+                            br::ImageLayout::Undefined,
+                        ),
+                        MemoryLocation::DeviceLocal,
+                    )?
+                    .into_parts();
+
+                Ok::<_, EngineError>(TransientResources {
+                    image: Arc::new(image),
+                    _allocation: allocation,
+                })
+            })?;
+            slots[n] = Some(slot);
+        }
+
+        Ok(slots)
+    }
+
+    fn prepare_framebuffer(
+        &mut self,
+        key: &[AttachmentKey],
+        slot_ids: &[u64],
+    ) -> Result<(), EngineError> {
+        if !self.render_passes.contains_key(key) {
+            let render_pass = create_render_pass(self.device, key)?;
+            self.render_passes.insert(key.to_vec(), render_pass);
+        }
+
+        let framebuffer_key = (key.to_vec(), slot_ids.to_vec());
+        if self.framebuffers.contains_key(&framebuffer_key) {
+            return Ok(());
+        }
+
+        let mut views = slot_ids
+            .iter()
+            .map(|id| {
+                let slot = self.transients.find(*id);
+
+                slot.resources
+                    .image
+                    .clone()
+                    .subresource_range(br::AspectMask(format_aspect(slot.desc.format)), 0..1, 0..1)
+                    .view_builder()
+                    .create()
+            })
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter();
+        let render_pass = &self.render_passes[key];
+        let mut builder = br::FramebufferBuilder::new_with_attachment(
+            render_pass,
+            views.next().expect("no attachments"),
+        );
+        for v in views {
+            builder = builder.with_attachment(v);
+        }
+        self.framebuffers.insert(framebuffer_key, builder.create()?);
+
+        Ok(())
+    }
+}
+
+/// Creates a single-subpass render pass. Attachments stay in the attachment-optimal layout:
+/// transitions are done by the barriers of the graph.
+fn create_render_pass<'d, Device: br::Device + ?Sized + 'd>(
+    device: &'d Device,
+    key: &[AttachmentKey],
+) -> br::Result<br::RenderPassObject<&'d Device>> {
+    let attachments = key
+        .iter()
+        .map(|a| {
+            if a.depth {
+                br::AttachmentDescription::new(
+                    a.format,
+                    br::ImageLayout::DepthStencilAttachmentOpt,
+                    br::ImageLayout::DepthStencilAttachmentOpt,
+                )
+                .depth_memory_op(a.load.op(), br::StoreOp::Store)
+            } else {
+                br::AttachmentDescription::new(
+                    a.format,
+                    br::ImageLayout::ColorAttachmentOpt,
+                    br::ImageLayout::ColorAttachmentOpt,
+                )
+                .color_memory_op(a.load.op(), br::StoreOp::Store)
+            }
+        })
+        .collect::<Vec<_>>();
+    let color_refs = key
+        .iter()
+        .enumerate()
+        .filter(|(_, a)| !a.depth)
+        .map(|(n, _)| br::AttachmentReference::new(n as _, br::ImageLayout::ColorAttachmentOpt))
+        .collect::<Vec<_>>();
+    let depth_ref = key
+        .iter()
+        .position(|a| a.depth)
+        .map(|n| br::AttachmentReference::new(n as _, br::ImageLayout::DepthStencilAttachmentOpt));
+
+    let mut subpass = br::SubpassDescription::new().color_attachments(&color_refs, &[]);
+    if let Some(depth_ref) = depth_ref.as_ref() {
+        subpass = subpass.depth_stencil(depth_ref);
+    }
+
+    br::RenderPassBuilder::new(&attachments, &[subpass], &[]).create(device)
+}
+
+/// Returns indices of passes to be executed, in declaration order.
+///
+/// A pass is alive if it has side effects, writes buffers, or writes images which are outputs of
+/// the graph(imported images with final layouts) or read by later alive passes.
+fn cull_passes(desc: &RenderGraphDesc) -> Vec<usize> {
+    let mut needed = desc
+        .images
+        .iter()
+        .map(|r| matches!(r, ImageResource::Imported(x) if x.final_layout.is_some()))
+        .collect::<Vec<_>>();
+    let mut alive = vec![false; desc.passes.len()];
+    for (n, pass) in desc.passes.iter().enumerate().rev() {
+        alive[n] = pass.side_effects
+            || pass.buffers.iter().any(|(_, a)| a.requirement().writes)
+            || pass
+                .images
+                .iter()
+                .any(|&(h, a)| a.requirement().writes && needed[h.0]);
+        if !alive[n] {
+            continue;
+        }
+
+        // contents overwritten by this pass are not needed from earlier passes
+        for &(h, a) in &pass.images {
+            if a.requirement().writes && !a.reads() {
+                needed[h.0] = false;
+            }
+        }
+        for &(h, a) in &pass.images {
+            if a.reads() {
+                needed[h.0] = true;
+            }
+        }
+    }
+
+    (0..desc.passes.len()).filter(|&n| alive[n]).collect()
+}
+
+/// Render pass and framebuffer created by the graph for a pass.
+pub struct GraphRenderPass<'g, 'd, Device: br::Device + ?Sized + 'd> {
+    pub render_pass: &'g br::RenderPassObject<&'d Device>,
+    pub framebuffer: &'g GraphFramebuffer<'d, Device>,
+    pub render_area: br::vk::VkRect2D,
+}
+
+pub struct CompiledPass<'g, 'd, Device: br::Device + ?Sized + 'd> {
+    pub id: PassId,
+    /// Should be recorded before the commands of the pass
+    pub barrier: Option<Barrier>,
+    /// Present if the pass has graph-managed attachments. Clear values are in declaration order.
+    pub render_pass: Option<GraphRenderPass<'g, 'd, Device>>,
+}
+
+/// Execution plan of a frame.
+pub struct CompiledGraph<'g, 'd, Device: br::Device + ?Sized + 'd> {
+    passes: Vec<CompiledPass<'g, 'd, Device>>,
+    final_barrier: Option<Barrier>,
+}
+impl<'g, 'd, Device: br::Device + ?Sized + 'd> CompiledGraph<'g, 'd, Device> {
+    /// Alive passes in execution order
+    pub fn passes(&self) -> &[CompiledPass<'g, 'd, Device>] {
+        &self.passes
+    }
+
+    /// Transitions of imported images into their final layouts. Should be recorded after every pass.
+    pub fn final_barrier(&self) -> Option<&Barrier> {
+        self.final_barrier.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXTENT: br::vk::VkExtent2D = br::vk::VkExtent2D {
+        width: 640,
+        height: 480,
+    };
+    const COLOR_OUTPUT: br::vk::VkPipelineStageFlags =
+        br::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT.0;
+    const FRAGMENT: br::vk::VkPipelineStageFlags = br::PipelineStageFlags::FRAGMENT_SHADER.0;
+    const VERTEX: br::vk::VkPipelineStageFlags = br::PipelineStageFlags::VERTEX_SHADER.0;
+    const TRANSFER: br::vk::VkPipelineStageFlags = br::PipelineStageFlags::TRANSFER.0;
+
+    fn untouched<L>(layout: L) -> ResourceTrack<L> {
+        ResourceTrack {
+            layout,
+            write_stages: 0,
+            write_access: 0,
+            read_stages: 0,
+        }
+    }
+
+    fn color_desc() -> TransientImageDesc {
+        TransientImageDesc {
+            format: br::vk::VK_FORMAT_R8G8B8A8_UNORM,
+            extent: EXTENT,
+        }
+    }
+
+    /// An imported image transitioned into `TRANSFER_SRC_OPTIMAL` at the end(an output of the graph)
+    fn import_output(graph: &mut RenderGraphDesc) -> ImageHandle {
+        graph.import_image(ImportedImage {
+            // Note: カリングではハンドルを見ないのでヌルで良い
+            image: unsafe { core::mem::zeroed() },
+            format: br::vk::VK_FORMAT_B8G8R8A8_UNORM,
+            extent: EXTENT,
+            aspect: br::vk::VK_IMAGE_ASPECT_COLOR_BIT,
+            initial_layout: br::ImageLayout::Undefined,
+            initial_stages: br::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            initial_access: 0,
+            final_layout: Some(br::ImageLayout::TransferSrcOpt),
+        })
+    }
+
+    #[test]
+    fn reads_wait_for_writes() {
+        let mut track = untouched(br::ImageLayout::Undefined);
+        let write = ImageAccess::ColorAttachment(AttachmentLoad::Clear).requirement();
+        assert_eq!(track.access(&write), Some((0, 0)));
+
+        // the first read transitions the layout
+        let sampled = ImageAccess::Sampled(br::PipelineStageFlags::FRAGMENT_SHADER).requirement();
+        assert_eq!(
+            track.access(&sampled),
+            Some((COLOR_OUTPUT, br::AccessFlags::COLOR_ATTACHMENT.write))
+        );
+        assert!(track.layout == br::ImageLayout::ShaderReadOnlyOpt);
+        // already synchronized in the stage
+        assert_eq!(track.access(&sampled), None);
+        // reads in other stages have to wait for the transition
+        let vertex_sampled =
+            ImageAccess::Sampled(br::PipelineStageFlags::VERTEX_SHADER).requirement();
+        assert_eq!(track.access(&vertex_sampled), Some((FRAGMENT, 0)));
+        assert_eq!(track.access(&vertex_sampled), None);
+    }
+
+    #[test]
+    fn reads_without_layout_changes_wait_for_writes_once() {
+        let mut track = untouched(());
+        let write = BufferAccess::TransferDst.requirement();
+        assert_eq!(track.access(&write), None);
+
+        let read = BufferAccess::Uniform(br::PipelineStageFlags::VERTEX_SHADER).requirement();
+        assert_eq!(
+            track.access(&read),
+            Some((TRANSFER, br::AccessFlags::TRANSFER.write))
+        );
+        assert_eq!(track.access(&read), None);
+        assert_eq!(track.read_stages, VERTEX);
+    }
+
+    #[test]
+    fn writes_wait_for_reads_and_writes() {
+        let mut track = untouched(());
+        let read = BufferAccess::StorageRead(br::PipelineStageFlags::FRAGMENT_SHADER).requirement();
+        let write = BufferAccess::TransferDst.requirement();
+
+        // reads of untouched resources need nothing
+        assert_eq!(track.access(&read), None);
+        // write after read: an execution dependency is enough
+        assert_eq!(track.access(&write), Some((FRAGMENT, 0)));
+        // write after write: the previous writes must be made available
+        assert_eq!(
+            track.access(&write),
+            Some((TRANSFER, br::AccessFlags::TRANSFER.write))
+        );
+        assert_eq!(track.read_stages, 0);
+    }
+
+    #[test]
+    fn layout_transitions_wait_for_every_access() {
+        let mut track = untouched(br::ImageLayout::Undefined);
+        let copy = ImageAccess::TransferDst.requirement();
+        assert_eq!(track.access(&copy), Some((0, 0)));
+
+        let sampled = ImageAccess::Sampled(br::PipelineStageFlags::FRAGMENT_SHADER).requirement();
+        assert_eq!(
+            track.access(&sampled),
+            Some((TRANSFER, br::AccessFlags::TRANSFER.write))
+        );
+        // back to the transfer layout: waits for the reads(and the transition)
+        let copy_src = ImageAccess::TransferSrc.requirement();
+        assert_eq!(track.access(&copy_src), Some((FRAGMENT, 0)));
+        assert!(track.layout == br::ImageLayout::TransferSrcOpt);
+    }
+
+    #[test]
+    fn passes_with_unused_outputs_are_culled() {
+        let mut graph = RenderGraphDesc::new();
+        let output = import_output(&mut graph);
+        let unused = graph.create_image(color_desc());
+        let intermediate = graph.create_image(color_desc());
+
+        let unused_pass = graph
+            .add_pass()
+            .image(unused, ImageAccess::ColorAttachment(AttachmentLoad::Clear))
+            .id();
+        let producer = graph
+            .add_pass()
+            .image(
+                intermediate,
+                ImageAccess::ColorAttachment(AttachmentLoad::Clear),
+            )
+            .id();
+        let consumer = graph
+            .add_pass()
+            .image(
+                intermediate,
+                ImageAccess::Sampled(br::PipelineStageFlags::FRAGMENT_SHADER),
+            )
+            .image(output, ImageAccess::ExternalColorAttachment)
+            .id();
+
+        let alive = cull_passes(&graph);
+        assert!(!alive.contains(&unused_pass.0));
+        assert_eq!(alive, [producer.0, consumer.0]);
+    }
+
+    #[test]
+    fn overwritten_results_are_culled() {
+        let mut graph = RenderGraphDesc::new();
+        let output = import_output(&mut graph);
+        let image = graph.create_image(color_desc());
+
+        graph
+            .add_pass()
+            .image(image, ImageAccess::ColorAttachment(AttachmentLoad::Clear));
+        // clears the image again before anyone reads it
+        let writer = graph
+            .add_pass()
+            .image(image, ImageAccess::ColorAttachment(AttachmentLoad::Clear))
+            .id();
+        let blender = graph
+            .add_pass()
+            .image(image, ImageAccess::ColorAttachment(AttachmentLoad::Load))
+            .id();
+        let copier = graph
+            .add_pass()
+            .image(image, ImageAccess::TransferSrc)
+            .image(output, ImageAccess::TransferDst)
+            .id();
+
+        assert_eq!(cull_passes(&graph), [writer.0, blender.0, copier.0]);
+    }
+
+    #[test]
+    fn passes_with_side_effects_or_buffer_writes_are_kept() {
+        let mut graph = RenderGraphDesc::new();
+        let image = graph.create_image(color_desc());
+        let buffer = graph.import_buffer(unsafe { core::mem::zeroed() });
+
+        let readback = graph
+            .add_pass()
+            .image(image, ImageAccess::ColorAttachment(AttachmentLoad::Clear))
+            .side_effects()
+            .id();
+        let update = graph
+            .add_pass()
+            .buffer(buffer, BufferAccess::TransferDst)
+            .id();
+        graph.add_pass().buffer(buffer, BufferAccess::Vertex);
+
+        assert_eq!(cull_passes(&graph), [readback.0, update.0]);
+    }
+
+    fn acquire(
+        pool: &mut TransientPool<()>,
+        desc: &TransientImageDesc,
+        created: &mut u32,
+    ) -> usize {
+        pool.acquire(desc, br::vk::VK_IMAGE_USAGE_COLOR_ATTACHMENT_BIT, || {
+            *created += 1;
+            Ok::<_, ()>(())
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn transient_images_are_reused_across_frames() {
+        let mut pool = TransientPool::new(2);
+        let mut created = 0;
+
+        pool.begin_frame();
+        let a = acquire(&mut pool, &color_desc(), &mut created);
+        // not shared in the same frame
+        let b = acquire(&mut pool, &color_desc(), &mut created);
+        assert_ne!(a, b);
+        assert_eq!(created, 2);
+
+        pool.begin_frame();
+        let reused = [
+            acquire(&mut pool, &color_desc(), &mut created),
+            acquire(&mut pool, &color_desc(), &mut created),
+        ];
+        assert!(reused.contains(&a) && reused.contains(&b));
+        assert_eq!(created, 2);
+
+        // other descriptions or usages are not shared
+        let depth = TransientImageDesc {
+            format: br::vk::VK_FORMAT_D32_SFLOAT,
+            extent: EXTENT,
+        };
+        acquire(&mut pool, &depth, &mut created);
+        pool.acquire(&color_desc(), br::vk::VK_IMAGE_USAGE_SAMPLED_BIT, || {
+            Ok::<_, ()>(())
+        })
+        .unwrap();
+        assert_eq!(created, 3);
+        assert_eq!(pool.slots.len(), 4);
+    }
+
+    #[test]
+    fn unused_transient_images_are_released() {
+        let mut pool = TransientPool::new(2);
+        let mut created = 0;
+
+        pool.begin_frame();
+        let n = acquire(&mut pool, &color_desc(), &mut created);
+        let id = pool.slots[n].id;
+
+        // kept while previous frames may still use the image
+        assert!(pool.begin_frame().is_empty());
+        assert!(pool.begin_frame().is_empty());
+        assert_eq!(pool.begin_frame(), [id]);
+        assert!(pool.slots.is_empty());
+
+        acquire(&mut pool, &color_desc(), &mut created);
+        assert_eq!(created, 2);
+        assert_ne!(pool.slots[0].id, id);
+    }
+
+    #[test]
+    fn failed_creations_leave_no_slots() {
+        let mut pool = TransientPool::<()>::new(2);
+
+        pool.begin_frame();
+        let r = pool.acquire(
+            &color_desc(),
+            br::vk::VK_IMAGE_USAGE_COLOR_ATTACHMENT_BIT,
+            || Err("out of memory"),
+        );
+        assert_eq!(r, Err("out of memory"));
+        assert!(pool.slots.is_empty());
+    }
+}
@@ -0,0 +1,225 @@
+//! Offscreen presentation target and deterministic frame clock for running without any window server.
+
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use bedrock::{self as br, ImageSubresourceSlice};
+
+use crate::{
+    error::EngineError,
+    game::{EngineEvents, FrameRequest, FrameRequestSender},
+};
+
+use super::{
+    capture::CapturedImage,
+    frame::DEFAULT_FRAMES_IN_FLIGHT,
+    memory::{MemoryAllocation, MemoryAllocator, MemoryLocation},
+};
+
+pub type OffscreenImage<'d, Device> = br::ImageObject<&'d Device>;
+pub type OffscreenImageView<'d, Device> = br::ImageViewObject<Arc<OffscreenImage<'d, Device>>>;
+
+/// Parameters used for (re)creating the offscreen back buffers.
+#[derive(Clone, Debug)]
+pub struct HeadlessConfig {
+    pub format: br::vk::VkFormat,
+    pub back_buffer_count: u32,
+    pub frames_in_flight: u32,
+}
+impl Default for HeadlessConfig {
+    fn default() -> Self {
+        Self {
+            // same as the most preferred sRGB swapchain format
+            format: br::vk::VK_FORMAT_R8G8B8A8_SRGB,
+            back_buffer_count: 2,
+            frames_in_flight: DEFAULT_FRAMES_IN_FLIGHT,
+        }
+    }
+}
+
+/// A ring of offscreen color images used in place of swapchain images.
+///
+/// Images are left in `TRANSFER_SRC_OPTIMAL` layout at the end of each frame, so that
+/// they can be read back directly.
+pub struct HeadlessTarget<'d, Device: br::Device + ?Sized + 'd> {
+    config: HeadlessConfig,
+    // Note: imagesより後に破棄されるようにフィールドの順番を維持すること
+    images: Vec<Arc<OffscreenImage<'d, Device>>>,
+    allocations: Vec<MemoryAllocation<'d, Device>>,
+    next_index: u32,
+}
+impl<'d, Device: br::Device + ?Sized + 'd> HeadlessTarget<'d, Device> {
+    pub fn new(config: HeadlessConfig) -> Self {
+        Self {
+            config,
+            images: Vec::new(),
+            allocations: Vec::new(),
+            next_index: 0,
+        }
+    }
+
+    pub fn config(&self) -> &HeadlessConfig {
+        &self.config
+    }
+
+    /// Releases every image. Views(and framebuffers) referencing them must be released before.
+    pub fn release(&mut self) {
+        self.images.clear();
+        self.allocations.clear();
+        self.next_index = 0;
+    }
+
+    /// Recreates the offscreen images with the extent, in device local memory.
+    pub fn rebuild(
+        &mut self,
+        allocator: &MemoryAllocator<'d, Device>,
+        extent: br::vk::VkExtent2D,
+    ) -> Result<(), EngineError> {
+        self.release();
+
+        for _ in 0..self.config.back_buffer_count {
+            let (image, allocation) = allocator
+                .create_image(
+                    br::ImageDesc::new(
+                        extent,
+                        self.config.format,
+                        br::ImageUsageFlags::COLOR_ATTACHMENT | br::ImageUsageFlags::TRANSFER_SRC,
+                        br::ImageLayout::Undefined,
+                    ),
+                    MemoryLocation::DeviceLocal,
+                )?
+                .into_parts();
+            self.images.push(Arc::new(image));
+            self.allocations.push(allocation);
+        }
+
+        Ok(())
+    }
+
+    pub fn images(&self) -> &[Arc<OffscreenImage<'d, Device>>] {
+        &self.images
+    }
+
+    /// Creates color attachment views for every image.
+    pub fn create_views(&self) -> br::Result<Vec<OffscreenImageView<'d, Device>>> {
+        self.images
+            .iter()
+            .map(|x| {
+                x.clone()
+                    .subresource_range(br::AspectMask::COLOR, 0..1, 0..1)
+                    .view_builder()
+                    .create()
+            })
+            .collect()
+    }
+
+    /// Picks the next image in round-robin order.
+    pub fn acquire_next(&mut self) -> u32 {
+        let index = self.next_index;
+        self.next_index = (self.next_index + 1) % self.images.len() as u32;
+
+        index
+    }
+}
+
+/// Headless run configuration, parsed from the command line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeadlessOptions {
+    /// Number of frames to be rendered before shutdown
+    pub frames: u32,
+    pub extent: (u32, u32),
+    /// Simulated time between frames
+    pub frame_interval: Duration,
+    /// Writes the last frame into a PNG file if specified
+    pub capture_path: Option<PathBuf>,
+}
+impl Default for HeadlessOptions {
+    fn default() -> Self {
+        Self {
+            frames: 60,
+            extent: (640, 480),
+            frame_interval: Duration::from_micros(16_667),
+            capture_path: None,
+        }
+    }
+}
+impl HeadlessOptions {
+    /// Returns Some if `--headless` is specified.
+    /// Also parses `--frames <count>`, `--size <w>x<h>` and `--capture <png path>`.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Option<Self> {
+        let mut headless = false;
+        let mut options = Self::default();
+        let mut args = args.into_iter();
+        while let Some(a) = args.next() {
+            match &a[..] {
+                "--headless" => headless = true,
+                "--frames" => match args.next().map(|x| x.parse()) {
+                    Some(Ok(n)) => options.frames = n,
+                    _ => eprintln!("--frames requires a number: ignored"),
+                },
+                "--size" => match args.next().as_deref().and_then(parse_extent) {
+                    Some(x) => options.extent = x,
+                    _ => eprintln!("--size requires <width>x<height>: ignored"),
+                },
+                "--capture" => match args.next() {
+                    Some(x) => options.capture_path = Some(x.into()),
+                    None => eprintln!("--capture requires a path: ignored"),
+                },
+                _ => (),
+            }
+        }
+
+        headless.then_some(options)
+    }
+
+    pub fn extent(&self) -> br::vk::VkExtent2D {
+        br::vk::VkExtent2D {
+            width: self.extent.0,
+            height: self.extent.1,
+        }
+    }
+}
+
+fn parse_extent(s: &str) -> Option<(u32, u32)> {
+    let (w, h) = s.split_once('x')?;
+    let (w, h) = (w.parse().ok()?, h.parse().ok()?);
+
+    (w > 0 && h > 0).then_some((w, h))
+}
+
+/// Deterministic frame source: requests `frames` frames with timestamps advancing by exactly
+/// `interval`. Returns false if the game loop has gone before all requests are consumed.
+///
+/// Unlike platform sources, each request waits for the previous one to be consumed(no coalescing).
+/// Note that the first frame only establishes the reference point: simulated time of the last frame
+/// is `interval * (frames - 1)`.
+pub async fn run_fixed_frame_clock(
+    frame_requests: &FrameRequestSender,
+    interval: Duration,
+    frames: u32,
+) -> bool {
+    for n in 0..frames {
+        let request = FrameRequest {
+            timestamp: Some(interval * n),
+        };
+        if !frame_requests.send(request).await {
+            return false;
+        }
+    }
+
+    // the last request must be taken before following events(the frame is processed before them)
+    frame_requests.wait_consumed().await;
+    true
+}
+
+/// Asks the game loop for the contents of the last presented frame.
+pub async fn capture_last_frame(
+    events: &async_std::channel::Sender<EngineEvents>,
+) -> Option<Result<CapturedImage, EngineError>> {
+    let (reply_sender, reply_receiver) = async_std::channel::bounded(1);
+    events
+        .send(EngineEvents::CaptureLastFrame(reply_sender))
+        .await
+        .ok()?;
+
+    reply_receiver.recv().await.ok()
+}
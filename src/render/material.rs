@@ -0,0 +1,261 @@
+//! Materials: a shader pair and fixed-function states of the pipeline, with parameters bound at
+//! [`MATERIAL_SET`].
+//!
+//! Materials sharing the same [`PipelineState`] share one pipeline, which is created lazily by
+//! [`SceneRenderer::create_pipelines`].
+//!
+//! [`MATERIAL_SET`]: super::scene_renderer::MATERIAL_SET
+//! [`SceneRenderer::create_pipelines`]: super::scene_renderer::SceneRenderer::create_pipelines
+
+use std::any::TypeId;
+
+use bedrock as br;
+
+use crate::math::{layout::FieldLayout, UniformStruct};
+
+/// Names of the shaders in the [`ShaderLibrary`](crate::shader::ShaderLibrary).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ShaderPair {
+    pub vertex: String,
+    pub fragment: String,
+}
+impl ShaderPair {
+    pub fn new(vertex: impl Into<String>, fragment: impl Into<String>) -> Self {
+        Self {
+            vertex: vertex.into(),
+            fragment: fragment.into(),
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.vertex == name || self.fragment == name
+    }
+}
+
+/// Color blending. Colors written by the shaders are straight(non-premultiplied) except for
+/// [`Self::Premultiplied`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+    Opaque,
+    Alpha,
+    Premultiplied,
+    Additive,
+}
+impl BlendMode {
+    pub fn attachment_state(self) -> br::AttachmentColorBlendState {
+        match self {
+            Self::Opaque => br::AttachmentColorBlendState::noblend(),
+            Self::Alpha => br::AttachmentColorBlendState::noblend()
+                .enable()
+                .color_blend(
+                    br::BlendFactor::SourceAlpha,
+                    br::BlendOp::Add,
+                    br::BlendFactor::OneMinusSourceAlpha,
+                )
+                .alpha_blend(
+                    br::BlendFactor::One,
+                    br::BlendOp::Add,
+                    br::BlendFactor::OneMinusSourceAlpha,
+                ),
+            Self::Premultiplied => br::AttachmentColorBlendState::premultiplied(),
+            Self::Additive => br::AttachmentColorBlendState::noblend()
+                .enable()
+                .color_blend(
+                    br::BlendFactor::SourceAlpha,
+                    br::BlendOp::Add,
+                    br::BlendFactor::One,
+                )
+                .alpha_blend(
+                    br::BlendFactor::Zero,
+                    br::BlendOp::Add,
+                    br::BlendFactor::One,
+                ),
+        }
+    }
+}
+
+/// Faces to be culled. Front faces are counter-clockwise(same as glTF).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CullMode {
+    None,
+    Back,
+    Front,
+}
+impl CullMode {
+    pub fn flags(self) -> br::vk::VkCullModeFlags {
+        match self {
+            Self::None => br::vk::VK_CULL_MODE_NONE,
+            Self::Back => br::vk::VK_CULL_MODE_BACK_BIT,
+            Self::Front => br::vk::VK_CULL_MODE_FRONT_BIT,
+        }
+    }
+}
+
+/// Ignored if the render pass has no depth attachment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DepthMode {
+    Disabled,
+    /// Tested but not written(e.g. blended surfaces)
+    Test,
+    TestAndWrite,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Topology {
+    TriangleList,
+    TriangleStrip,
+    LineList,
+    PointList,
+}
+impl Topology {
+    pub fn primitive_topology(self) -> br::vk::VkPrimitiveTopology {
+        match self {
+            Self::TriangleList => br::vk::VK_PRIMITIVE_TOPOLOGY_TRIANGLE_LIST,
+            Self::TriangleStrip => br::vk::VK_PRIMITIVE_TOPOLOGY_TRIANGLE_STRIP,
+            Self::LineList => br::vk::VK_PRIMITIVE_TOPOLOGY_LINE_LIST,
+            Self::PointList => br::vk::VK_PRIMITIVE_TOPOLOGY_POINT_LIST,
+        }
+    }
+}
+
+/// Everything baked into a pipeline object. Viewports and scissors are dynamic states.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineState {
+    pub shaders: ShaderPair,
+    pub blend: BlendMode,
+    pub cull: CullMode,
+    pub depth: DepthMode,
+    pub topology: Topology,
+}
+impl PipelineState {
+    /// Opaque triangle lists with back face culling and depth writes
+    pub fn new(shaders: ShaderPair) -> Self {
+        Self {
+            shaders,
+            blend: BlendMode::Opaque,
+            cull: CullMode::Back,
+            depth: DepthMode::TestAndWrite,
+            topology: Topology::TriangleList,
+        }
+    }
+}
+
+/// Contents of a material parameter block(a layout-checked struct).
+///
+/// Written into the frame's dynamic buffer every frame, so they can be updated at any time.
+#[derive(Clone, Debug)]
+pub struct MaterialParameters {
+    type_id: TypeId,
+    type_name: &'static str,
+    fields: &'static [FieldLayout],
+    bytes: Vec<u8>,
+}
+impl MaterialParameters {
+    pub fn new<T: UniformStruct + 'static>(value: &T) -> Self {
+        Self {
+            type_id: TypeId::of::<T>(),
+            type_name: std::any::type_name::<T>(),
+            fields: T::FIELDS,
+            bytes: Self::bytes_of(value),
+        }
+    }
+
+    /// Replaces the contents. The type must be the same as the one given at creation.
+    pub fn update<T: UniformStruct + 'static>(&mut self, value: &T) {
+        assert_eq!(
+            self.type_id,
+            TypeId::of::<T>(),
+            "material parameter type mismatch"
+        );
+
+        self.bytes = Self::bytes_of(value);
+    }
+
+    fn bytes_of<T: UniformStruct>(value: &T) -> Vec<u8> {
+        unsafe {
+            core::slice::from_raw_parts(value as *const T as *const u8, core::mem::size_of::<T>())
+        }
+        .to_vec()
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
+    pub fn fields(&self) -> &'static [FieldLayout] {
+        self.fields
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+pub struct MaterialDesc {
+    pub state: PipelineState,
+    /// Bound at [`MATERIAL_SET`] if present.
+    ///
+    /// Must be allocated with the set layout of the shader pair([`ShaderLayout::set_signature`]).
+    /// The parameter block(if any) has to refer the dynamic buffer of the frame ring.
+    ///
+    /// [`MATERIAL_SET`]: super::scene_renderer::MATERIAL_SET
+    /// [`ShaderLayout::set_signature`]: super::scene_renderer::ShaderLayout::set_signature
+    pub descriptor_set: Option<br::DescriptorSet>,
+    /// Required if the shaders declare a uniform block in the material set
+    pub parameters: Option<MaterialParameters>,
+}
+impl MaterialDesc {
+    pub fn new(state: PipelineState) -> Self {
+        Self {
+            state,
+            descriptor_set: None,
+            parameters: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::{std140_struct, Vec4};
+
+    std140_struct! {
+        struct Tint {
+            color: Vec4,
+        }
+    }
+    std140_struct! {
+        struct Offset {
+            offset: Vec4,
+        }
+    }
+
+    #[test]
+    fn parameters_are_updated_in_place() {
+        let mut parameters = MaterialParameters::new(&Tint {
+            color: Vec4::new(1.0, 1.0, 1.0, 1.0),
+        });
+        assert_eq!(parameters.fields(), Tint::FIELDS);
+        assert!(parameters.type_name().ends_with("Tint"));
+
+        parameters.update(&Tint {
+            color: Vec4::new(0.5, 0.25, 0.0, 1.0),
+        });
+        let expected = [0.5f32, 0.25, 0.0, 1.0]
+            .iter()
+            .flat_map(|c| c.to_ne_bytes())
+            .collect::<Vec<_>>();
+        assert_eq!(parameters.bytes(), expected);
+    }
+
+    #[test]
+    #[should_panic(expected = "material parameter type mismatch")]
+    fn parameters_of_other_type_are_rejected() {
+        let mut parameters = MaterialParameters::new(&Tint {
+            color: Vec4::new(1.0, 1.0, 1.0, 1.0),
+        });
+        parameters.update(&Offset {
+            offset: Vec4::new(0.0, 0.0, 0.0, 0.0),
+        });
+    }
+}
@@ -0,0 +1,661 @@
+//! Block-based device memory suballocator.
+//!
+//! Device memory is allocated in large blocks per memory type, and buffers/images are placed into
+//! the blocks with their alignment and `bufferImageGranularity` respected.
+//! Allocations are returned to the free list of the block when dropped.
+
+use std::{
+    collections::BTreeMap,
+    ops::Range,
+    ptr::NonNull,
+    sync::{Arc, Mutex},
+};
+
+use bedrock::{self as br, DeviceMemory, MemoryBound};
+
+use crate::error::EngineError;
+
+/// Size of a memory block. Requests larger than half of this get a dedicated block.
+pub const DEFAULT_BLOCK_SIZE: u64 = 64 << 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryLocation {
+    /// For resources only accessed by the device
+    DeviceLocal,
+    /// Persistently mapped host visible and coherent memory(for uploading or reading back)
+    HostVisible,
+    /// Persistently mapped memory which is also device local if available(ReBAR/UMA),
+    /// otherwise same as `HostVisible`. For data written by the host every frame
+    DeviceLocalHostVisible,
+}
+
+/// Resources of different kinds must not share a `bufferImageGranularity` page.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ResourceKind {
+    /// buffers and linear tiling images
+    Linear,
+    /// optimal tiling images
+    Optimal,
+}
+
+struct Suballocation {
+    end: u64,
+    kind: ResourceKind,
+}
+
+struct MappedPtr(NonNull<u8>);
+// Note: マップ先はブロックのメモリが生きている間有効で、アクセスの同期は利用側の責任
+unsafe impl Send for MappedPtr {}
+unsafe impl Sync for MappedPtr {}
+
+/// Placement of suballocations in a range of `[0, size)`(first-fit).
+struct FreeList {
+    /// sorted by offset, adjacent ranges are always merged
+    free_ranges: Vec<Range<u64>>,
+    allocations: BTreeMap<u64, Suballocation>,
+}
+impl FreeList {
+    fn new(size: u64) -> Self {
+        Self {
+            free_ranges: vec![0..size],
+            allocations: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the offset of the new suballocation. Resources of different kinds are placed in
+    /// different `granularity` pages.
+    fn try_allocate(
+        &mut self,
+        size: u64,
+        alignment: u64,
+        kind: ResourceKind,
+        granularity: u64,
+    ) -> Option<u64> {
+        let (n, offset, end) = self.free_ranges.iter().enumerate().find_map(|(n, r)| {
+            let mut offset = align_up(r.start, alignment);
+            if let Some((_, prev)) = self.allocations.range(..offset).next_back() {
+                if prev.kind != kind && same_page(prev.end - 1, offset, granularity) {
+                    offset = align_up(offset, granularity);
+                }
+            }
+            let end = offset + size;
+            if end > r.end {
+                return None;
+            }
+            if let Some((&next_start, next)) = self.allocations.range(end..).next() {
+                if next.kind != kind && same_page(end - 1, next_start, granularity) {
+                    return None;
+                }
+            }
+
+            Some((n, offset, end))
+        })?;
+
+        let r = self.free_ranges[n].clone();
+        let mut remainders = Vec::with_capacity(2);
+        if r.start < offset {
+            remainders.push(r.start..offset);
+        }
+        if end < r.end {
+            remainders.push(end..r.end);
+        }
+        self.free_ranges.splice(n..n + 1, remainders);
+        self.allocations.insert(offset, Suballocation { end, kind });
+
+        Some(offset)
+    }
+
+    fn free(&mut self, offset: u64) {
+        let a = self
+            .allocations
+            .remove(&offset)
+            .expect("freeing unknown suballocation");
+
+        let n = self.free_ranges.partition_point(|r| r.start < offset);
+        let merge_prev = n > 0 && self.free_ranges[n - 1].end == offset;
+        let merge_next = n < self.free_ranges.len() && self.free_ranges[n].start == a.end;
+        match (merge_prev, merge_next) {
+            (true, true) => {
+                self.free_ranges[n - 1].end = self.free_ranges[n].end;
+                self.free_ranges.remove(n);
+            }
+            (true, false) => self.free_ranges[n - 1].end = a.end,
+            (false, true) => self.free_ranges[n].start = offset,
+            (false, false) => self.free_ranges.insert(n, offset..a.end),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.allocations.is_empty()
+    }
+
+    fn allocation_count(&self) -> usize {
+        self.allocations.len()
+    }
+
+    fn allocated_bytes(&self) -> u64 {
+        self.allocations.iter().map(|(o, a)| a.end - o).sum()
+    }
+}
+
+struct MemoryBlock<'d, Device: br::Device + ?Sized + 'd> {
+    memory: br::DeviceMemoryObject<&'d Device>,
+    type_index: u32,
+    size: u64,
+    dedicated: bool,
+    mapped: Option<MappedPtr>,
+    space: FreeList,
+}
+
+fn align_up(x: u64, alignment: u64) -> u64 {
+    let alignment = alignment.max(1);
+
+    x.div_ceil(alignment) * alignment
+}
+
+fn same_page(a: u64, b: u64, page_size: u64) -> bool {
+    a / page_size.max(1) == b / page_size.max(1)
+}
+
+struct AllocatorState<'d, Device: br::Device + ?Sized + 'd> {
+    device: &'d Device,
+    memory_properties: br::MemoryProperties,
+    /// heap index of each memory type
+    type_heap_indices: Vec<u32>,
+    buffer_image_granularity: u64,
+    block_size: u64,
+    // Note: MemoryAllocationがindexで参照するので、解放したブロックはNoneにして位置を維持する
+    blocks: Vec<Option<MemoryBlock<'d, Device>>>,
+}
+impl<'d, Device: br::Device + ?Sized + 'd> AllocatorState<'d, Device> {
+    /// Host visible memory is always coherent: mapped ranges are never flushed or invalidated.
+    fn find_type_index(&self, type_bits: u32, location: MemoryLocation) -> Option<u32> {
+        const HOST_COHERENT: br::vk::VkMemoryPropertyFlags =
+            br::vk::VK_MEMORY_PROPERTY_HOST_VISIBLE_BIT
+                | br::vk::VK_MEMORY_PROPERTY_HOST_COHERENT_BIT;
+
+        match location {
+            MemoryLocation::DeviceLocal => {
+                self.memory_properties.find_device_local_index(type_bits)
+            }
+            MemoryLocation::HostVisible => self.find_type_index_with(type_bits, HOST_COHERENT),
+            MemoryLocation::DeviceLocalHostVisible => self
+                .find_type_index_with(
+                    type_bits,
+                    br::vk::VK_MEMORY_PROPERTY_DEVICE_LOCAL_BIT | HOST_COHERENT,
+                )
+                .or_else(|| self.find_type_index_with(type_bits, HOST_COHERENT)),
+        }
+    }
+
+    fn find_type_index_with(
+        &self,
+        type_bits: u32,
+        flags: br::vk::VkMemoryPropertyFlags,
+    ) -> Option<u32> {
+        self.memory_properties
+            .types()
+            .enumerate()
+            .find(|&(n, t)| (type_bits & (1 << n)) != 0 && (t.propertyFlags & flags) == flags)
+            .map(|(n, _)| n as u32)
+    }
+
+    fn allocate(
+        &mut self,
+        requirements: &br::vk::VkMemoryRequirements,
+        location: MemoryLocation,
+        kind: ResourceKind,
+    ) -> Result<(usize, u64), EngineError> {
+        let type_index = self
+            .find_type_index(requirements.memoryTypeBits, location)
+            .ok_or(EngineError::NoSuitableMemoryType(match location {
+                MemoryLocation::DeviceLocal => "device local resources",
+                MemoryLocation::HostVisible | MemoryLocation::DeviceLocalHostVisible => {
+                    "host visible resources"
+                }
+            }))?;
+        let dedicated = requirements.size > self.block_size / 2;
+
+        let mapped = location != MemoryLocation::DeviceLocal;
+        if !dedicated {
+            let granularity = self.buffer_image_granularity;
+            for (n, b) in self.blocks.iter_mut().enumerate() {
+                // Note: UMA環境ではDeviceLocalとHostVisibleが同じメモリタイプになり得るので、マップ状態も一致させる
+                let Some(b) = b.as_mut().filter(|b| {
+                    b.type_index == type_index && !b.dedicated && b.mapped.is_some() == mapped
+                }) else {
+                    continue;
+                };
+
+                if let Some(offset) = b.space.try_allocate(
+                    requirements.size,
+                    requirements.alignment,
+                    kind,
+                    granularity,
+                ) {
+                    return Ok((n, offset));
+                }
+            }
+        }
+
+        let block_size = if dedicated {
+            requirements.size
+        } else {
+            self.block_size
+        };
+        let mut memory =
+            br::DeviceMemoryRequest::allocate(block_size as _, type_index).execute(self.device)?;
+        let mapped = if mapped {
+            // kept mapped until the block is freed
+            let ptr = memory.map(0..block_size as usize)?;
+            let ptr = unsafe { ptr.slice_mut::<u8>(0, block_size as usize).as_mut_ptr() };
+
+            NonNull::new(ptr).map(MappedPtr)
+        } else {
+            None
+        };
+        let mut block = MemoryBlock {
+            memory,
+            type_index,
+            size: block_size,
+            dedicated,
+            mapped,
+            space: FreeList::new(block_size),
+        };
+        let offset = block
+            .space
+            .try_allocate(
+                requirements.size,
+                requirements.alignment,
+                kind,
+                self.buffer_image_granularity,
+            )
+            .expect("fresh block cannot hold the allocation");
+
+        let n = match self.blocks.iter().position(Option::is_none) {
+            Some(n) => {
+                self.blocks[n] = Some(block);
+                n
+            }
+            None => {
+                self.blocks.push(Some(block));
+                self.blocks.len() - 1
+            }
+        };
+
+        Ok((n, offset))
+    }
+
+    fn free(&mut self, block_index: usize, offset: u64) {
+        let block = self.blocks[block_index]
+            .as_mut()
+            .expect("freeing from released block");
+        block.space.free(offset);
+
+        if block.space.is_empty() {
+            let (type_index, mapped) = (block.type_index, block.mapped.is_some());
+            let keep = !block.dedicated
+                && !self.blocks.iter().enumerate().any(|(n, b)| {
+                    n != block_index
+                        && b.as_ref().is_some_and(|b| {
+                            b.type_index == type_index
+                                && b.mapped.is_some() == mapped
+                                && !b.dedicated
+                                && b.space.is_empty()
+                        })
+                });
+
+            // keep one empty block per memory type to avoid reallocation thrashing
+            if !keep {
+                self.blocks[block_index] = None;
+            }
+        }
+    }
+}
+
+/// Memory usage of a heap, as seen from this allocator.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeapStats {
+    pub heap_index: u32,
+    pub heap_size: u64,
+    pub device_local: bool,
+    pub block_count: usize,
+    /// Total size of device memory objects allocated from the heap
+    pub block_bytes: u64,
+    pub allocation_count: usize,
+    /// Total size of live suballocations(excluding alignment paddings)
+    pub allocated_bytes: u64,
+}
+impl HeapStats {
+    /// Ratio of the heap consumed by the blocks
+    pub fn budget_usage(&self) -> f64 {
+        if self.heap_size == 0 {
+            return 0.0;
+        }
+
+        self.block_bytes as f64 / self.heap_size as f64
+    }
+}
+
+/// Suballocator shared by the engine and the resources allocated from it.
+pub struct MemoryAllocator<'d, Device: br::Device + ?Sized + 'd> {
+    device: &'d Device,
+    state: Arc<Mutex<AllocatorState<'d, Device>>>,
+}
+impl<'d, Device: br::Device + ?Sized + 'd> Clone for MemoryAllocator<'d, Device> {
+    fn clone(&self) -> Self {
+        Self {
+            device: self.device,
+            state: self.state.clone(),
+        }
+    }
+}
+impl<'d, Device: br::Device + ?Sized + 'd> MemoryAllocator<'d, Device> {
+    pub fn new(
+        device: &'d Device,
+        memory_properties: br::MemoryProperties,
+        buffer_image_granularity: u64,
+    ) -> Self {
+        let type_heap_indices = memory_properties.types().map(|t| t.heapIndex).collect();
+
+        Self {
+            device,
+            state: Arc::new(Mutex::new(AllocatorState {
+                device,
+                memory_properties,
+                type_heap_indices,
+                buffer_image_granularity,
+                block_size: DEFAULT_BLOCK_SIZE,
+                blocks: Vec::new(),
+            })),
+        }
+    }
+
+    fn allocate(
+        &self,
+        requirements: &br::vk::VkMemoryRequirements,
+        location: MemoryLocation,
+        kind: ResourceKind,
+    ) -> Result<MemoryAllocation<'d, Device>, EngineError> {
+        let (block_index, offset) =
+            self.state
+                .lock()
+                .expect("Poisoned")
+                .allocate(requirements, location, kind)?;
+
+        Ok(MemoryAllocation {
+            state: self.state.clone(),
+            block_index,
+            offset,
+            size: requirements.size,
+        })
+    }
+
+    pub fn create_buffer(
+        &self,
+        desc: br::BufferDesc,
+        location: MemoryLocation,
+    ) -> Result<AllocatedBuffer<'d, Device>, EngineError> {
+        let mut buffer = desc.create(self.device)?;
+        let allocation = self.allocate(&buffer.requirements(), location, ResourceKind::Linear)?;
+        {
+            let state = self.state.lock().expect("Poisoned");
+            buffer.bind(&allocation.block(&state).memory, allocation.offset as _)?;
+        }
+
+        Ok(AllocatedBuffer { buffer, allocation })
+    }
+
+    /// Creates an optimal tiling image.
+    pub fn create_image(
+        &self,
+        desc: br::ImageDesc,
+        location: MemoryLocation,
+    ) -> Result<AllocatedImage<'d, Device>, EngineError> {
+        let mut image = desc.create(self.device)?;
+        let allocation = self.allocate(&image.requirements(), location, ResourceKind::Optimal)?;
+        {
+            let state = self.state.lock().expect("Poisoned");
+            image.bind(&allocation.block(&state).memory, allocation.offset as _)?;
+        }
+
+        Ok(AllocatedImage { image, allocation })
+    }
+
+    /// Usage statistics of every heap.
+    pub fn heap_stats(&self) -> Vec<HeapStats> {
+        let state = self.state.lock().expect("Poisoned");
+        let mut stats = state
+            .memory_properties
+            .heaps()
+            .enumerate()
+            .map(|(n, h)| HeapStats {
+                heap_index: n as _,
+                heap_size: h.size,
+                device_local: (h.flags & br::vk::VK_MEMORY_HEAP_DEVICE_LOCAL_BIT) != 0,
+                block_count: 0,
+                block_bytes: 0,
+                allocation_count: 0,
+                allocated_bytes: 0,
+            })
+            .collect::<Vec<_>>();
+        for b in state.blocks.iter().flatten() {
+            let s = &mut stats[state.type_heap_indices[b.type_index as usize] as usize];
+            s.block_count += 1;
+            s.block_bytes += b.size;
+            s.allocation_count += b.space.allocation_count();
+            s.allocated_bytes += b.space.allocated_bytes();
+        }
+
+        stats
+    }
+}
+
+/// A range of device memory. Returned to the allocator when dropped.
+pub struct MemoryAllocation<'d, Device: br::Device + ?Sized + 'd> {
+    state: Arc<Mutex<AllocatorState<'d, Device>>>,
+    block_index: usize,
+    offset: u64,
+    size: u64,
+}
+impl<'d, Device: br::Device + ?Sized + 'd> MemoryAllocation<'d, Device> {
+    fn block<'s>(&self, state: &'s AllocatorState<'d, Device>) -> &'s MemoryBlock<'d, Device> {
+        state.blocks[self.block_index]
+            .as_ref()
+            .expect("block has been released")
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Host address of the allocation. None if not host visible.
+    pub fn mapped_ptr(&self) -> Option<NonNull<u8>> {
+        let state = self.state.lock().expect("Poisoned");
+        let base = self.block(&state).mapped.as_ref()?.0;
+
+        Some(unsafe { base.add(self.offset as usize) })
+    }
+}
+impl<'d, Device: br::Device + ?Sized + 'd> Drop for MemoryAllocation<'d, Device> {
+    fn drop(&mut self) {
+        self.state
+            .lock()
+            .expect("Poisoned")
+            .free(self.block_index, self.offset);
+    }
+}
+
+pub struct AllocatedBuffer<'d, Device: br::Device + ?Sized + 'd> {
+    // Note: メモリより先にバッファを破棄する
+    buffer: br::BufferObject<&'d Device>,
+    allocation: MemoryAllocation<'d, Device>,
+}
+impl<'d, Device: br::Device + ?Sized + 'd> AllocatedBuffer<'d, Device> {
+    pub fn buffer(&self) -> &br::BufferObject<&'d Device> {
+        &self.buffer
+    }
+
+    pub fn allocation(&self) -> &MemoryAllocation<'d, Device> {
+        &self.allocation
+    }
+
+    /// Copies bytes into the persistently mapped memory.
+    ///
+    /// # Safety
+    /// The range must not be accessed by the device at the same time.
+    pub unsafe fn write_mapped_bytes(&self, offset: usize, bytes: &[u8]) {
+        let ptr = self
+            .allocation
+            .mapped_ptr()
+            .expect("buffer is not host visible");
+
+        core::ptr::copy_nonoverlapping(bytes.as_ptr(), ptr.as_ptr().add(offset), bytes.len());
+    }
+
+    /// Reads bytes from the persistently mapped memory.
+    ///
+    /// # Safety
+    /// The range must not be written by the device at the same time.
+    pub unsafe fn read_mapped_bytes(&self, range: Range<usize>) -> Vec<u8> {
+        let ptr = self
+            .allocation
+            .mapped_ptr()
+            .expect("buffer is not host visible");
+
+        core::slice::from_raw_parts(ptr.as_ptr().add(range.start), range.len()).to_vec()
+    }
+}
+
+pub struct AllocatedImage<'d, Device: br::Device + ?Sized + 'd> {
+    // Note: メモリより先にイメージを破棄する
+    image: br::ImageObject<&'d Device>,
+    allocation: MemoryAllocation<'d, Device>,
+}
+impl<'d, Device: br::Device + ?Sized + 'd> AllocatedImage<'d, Device> {
+    pub fn image(&self) -> &br::ImageObject<&'d Device> {
+        &self.image
+    }
+
+    pub fn allocation(&self) -> &MemoryAllocation<'d, Device> {
+        &self.allocation
+    }
+
+    /// Splits into the image and its memory(e.g. for creating views which take the image by value).
+    /// The image must be dropped before the allocation.
+    pub fn into_parts(self) -> (br::ImageObject<&'d Device>, MemoryAllocation<'d, Device>) {
+        (self.image, self.allocation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINEAR: ResourceKind = ResourceKind::Linear;
+    const OPTIMAL: ResourceKind = ResourceKind::Optimal;
+
+    #[test]
+    fn first_fit_respects_alignment() {
+        let mut list = FreeList::new(1024);
+
+        assert_eq!(list.try_allocate(10, 1, LINEAR, 1), Some(0));
+        assert_eq!(list.try_allocate(16, 64, LINEAR, 1), Some(64));
+        // the gap before the aligned allocation is used first
+        assert_eq!(list.try_allocate(20, 4, LINEAR, 1), Some(12));
+        assert_eq!(list.free_ranges, [10..12, 32..64, 80..1024]);
+        assert_eq!(list.allocation_count(), 3);
+        assert_eq!(list.allocated_bytes(), 46);
+    }
+
+    #[test]
+    fn exhausted_list_fails() {
+        let mut list = FreeList::new(256);
+
+        assert_eq!(list.try_allocate(256, 1, LINEAR, 1), Some(0));
+        assert_eq!(list.try_allocate(1, 1, LINEAR, 1), None);
+        assert!(list.free_ranges.is_empty());
+
+        let mut list = FreeList::new(256);
+        assert_eq!(list.try_allocate(100, 1, LINEAR, 1), Some(0));
+        assert_eq!(list.try_allocate(100, 128, LINEAR, 1), Some(128));
+        // no room after aligning
+        assert_eq!(list.try_allocate(100, 128, LINEAR, 1), None);
+    }
+
+    #[test]
+    fn different_kinds_do_not_share_granularity_pages() {
+        let mut list = FreeList::new(4096);
+
+        assert_eq!(list.try_allocate(100, 16, LINEAR, 1024), Some(0));
+        // pushed to the next page after a linear resource
+        assert_eq!(list.try_allocate(100, 16, OPTIMAL, 1024), Some(1024));
+        // same kind can be packed right after
+        assert_eq!(list.try_allocate(100, 16, LINEAR, 1024), Some(112));
+        // the gap after the linear resources shares their page
+        assert_eq!(list.try_allocate(100, 16, OPTIMAL, 1024), Some(1136));
+    }
+
+    #[test]
+    fn allocations_ending_in_pages_of_different_kinds_are_rejected() {
+        let mut list = FreeList::new(4096);
+        assert_eq!(list.try_allocate(1500, 1, LINEAR, 1024), Some(0));
+        assert_eq!(list.try_allocate(100, 1, LINEAR, 1024), Some(1500));
+        list.free(0);
+
+        // [0, 1500) is free, but an optimal image there would end in the page of the buffer at 1500
+        assert_eq!(list.try_allocate(1200, 16, OPTIMAL, 1024), Some(2048));
+        assert_eq!(list.try_allocate(1200, 16, LINEAR, 1024), Some(0));
+    }
+
+    #[test]
+    fn freed_ranges_are_merged() {
+        let mut list = FreeList::new(400);
+        for n in 0..4 {
+            assert_eq!(list.try_allocate(100, 1, LINEAR, 1), Some(n * 100));
+        }
+
+        // no neighbors
+        list.free(100);
+        assert_eq!(list.free_ranges, [100..200]);
+        // merged with the previous range
+        list.free(200);
+        assert_eq!(list.free_ranges, [100..300]);
+        // merged with the next range
+        list.free(0);
+        assert_eq!(list.free_ranges, [0..300]);
+        // merged with both
+        assert_eq!(list.try_allocate(100, 1, LINEAR, 1), Some(0));
+        assert_eq!(list.try_allocate(100, 1, LINEAR, 1), Some(100));
+        list.free(0);
+        list.free(300);
+        assert_eq!(list.free_ranges, [0..100, 200..400]);
+        list.free(100);
+        assert_eq!(list.free_ranges, [0..400]);
+        assert!(list.is_empty());
+        assert_eq!(list.allocated_bytes(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "freeing unknown suballocation")]
+    fn freeing_unknown_offset_panics() {
+        FreeList::new(100).free(0);
+    }
+
+    #[test]
+    fn alignment_helpers() {
+        assert_eq!(align_up(0, 16), 0);
+        assert_eq!(align_up(1, 16), 16);
+        assert_eq!(align_up(16, 16), 16);
+        // zero alignment is treated as 1
+        assert_eq!(align_up(5, 0), 5);
+
+        assert!(same_page(0, 1023, 1024));
+        assert!(!same_page(1023, 1024, 1024));
+        assert!(!same_page(0, 1, 0));
+    }
+}
@@ -0,0 +1,209 @@
+//! Engine vertex layout and CPU side mesh data uploaded through the staging upload queue.
+
+use std::sync::Arc;
+
+use bedrock as br;
+
+use crate::{error::EngineError, math::Vec3};
+
+use super::{
+    memory::{MemoryAllocator, MemoryLocation},
+    scene_renderer::{IndexBuffer, Mesh},
+    upload::UploadQueue,
+};
+
+/// Vertex layout of meshes loaded from assets.
+///
+/// Locations: 0 = position, 1 = normal, 2 = uv, 3 = tangent(w: bitangent sign)
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MeshVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+    pub tangent: [f32; 4],
+}
+impl MeshVertex {
+    pub fn binding() -> br::VertexInputBindingDescription {
+        br::VertexInputBindingDescription::per_vertex_typed::<Self>(0)
+    }
+
+    pub fn attributes() -> [br::vk::VkVertexInputAttributeDescription; 4] {
+        [
+            br::vk::VkVertexInputAttributeDescription {
+                location: 0,
+                binding: 0,
+                format: br::vk::VK_FORMAT_R32G32B32_SFLOAT,
+                offset: core::mem::offset_of!(Self, position) as _,
+            },
+            br::vk::VkVertexInputAttributeDescription {
+                location: 1,
+                binding: 0,
+                format: br::vk::VK_FORMAT_R32G32B32_SFLOAT,
+                offset: core::mem::offset_of!(Self, normal) as _,
+            },
+            br::vk::VkVertexInputAttributeDescription {
+                location: 2,
+                binding: 0,
+                format: br::vk::VK_FORMAT_R32G32_SFLOAT,
+                offset: core::mem::offset_of!(Self, uv) as _,
+            },
+            br::vk::VkVertexInputAttributeDescription {
+                location: 3,
+                binding: 0,
+                format: br::vk::VK_FORMAT_R32G32B32A32_SFLOAT,
+                offset: core::mem::offset_of!(Self, tangent) as _,
+            },
+        ]
+    }
+}
+
+/// Triangle list on the CPU side.
+#[derive(Clone, Debug, Default)]
+pub struct MeshData {
+    pub vertices: Vec<MeshVertex>,
+    /// Non-indexed if None
+    pub indices: Option<Vec<u32>>,
+}
+impl MeshData {
+    fn triangles(&self) -> Vec<[usize; 3]> {
+        match &self.indices {
+            Some(indices) => indices
+                .chunks_exact(3)
+                .map(|t| [t[0] as usize, t[1] as usize, t[2] as usize])
+                .collect(),
+            None => (0..self.vertices.len() / 3)
+                .map(|t| [t * 3, t * 3 + 1, t * 3 + 2])
+                .collect(),
+        }
+    }
+
+    /// Replaces normals with area-weighted averages of the face normals.
+    pub fn generate_normals(&mut self) {
+        let mut normals = vec![Vec3::ZERO; self.vertices.len()];
+        for [a, b, c] in self.triangles() {
+            let [pa, pb, pc] = [a, b, c].map(|i| Vec3::from(self.vertices[i].position));
+            // length of the cross product is proportional to the area
+            let n = (pb - pa).cross(pc - pa);
+            for i in [a, b, c] {
+                normals[i] += n;
+            }
+        }
+
+        for (v, n) in self.vertices.iter_mut().zip(normals) {
+            v.normal = n.normalize().to_array();
+        }
+    }
+
+    /// Replaces tangents with ones derived from the UV gradients of the faces.
+    ///
+    /// Normals must be present. Degenerated UVs produce an arbitrary tangent perpendicular to the
+    /// normal.
+    pub fn generate_tangents(&mut self) {
+        let mut tangents = vec![Vec3::ZERO; self.vertices.len()];
+        let mut bitangents = vec![Vec3::ZERO; self.vertices.len()];
+        for [a, b, c] in self.triangles() {
+            let [va, vb, vc] = [a, b, c].map(|i| self.vertices[i]);
+            let (e1, e2) = (
+                Vec3::from(vb.position) - Vec3::from(va.position),
+                Vec3::from(vc.position) - Vec3::from(va.position),
+            );
+            let (du1, dv1) = (vb.uv[0] - va.uv[0], vb.uv[1] - va.uv[1]);
+            let (du2, dv2) = (vc.uv[0] - va.uv[0], vc.uv[1] - va.uv[1]);
+            let det = du1 * dv2 - du2 * dv1;
+            if det.abs() <= f32::EPSILON {
+                continue;
+            }
+
+            let tangent = (e1 * dv2 - e2 * dv1) / det;
+            let bitangent = (e2 * du1 - e1 * du2) / det;
+            for i in [a, b, c] {
+                tangents[i] += tangent;
+                bitangents[i] += bitangent;
+            }
+        }
+
+        for ((v, t), b) in self.vertices.iter_mut().zip(tangents).zip(bitangents) {
+            let n = Vec3::from(v.normal);
+            // Gram-Schmidt orthogonalization
+            let mut t = (t - n * n.dot(t)).normalize();
+            if t == Vec3::ZERO {
+                let axis = if n.x.abs() < 0.9 { Vec3::X } else { Vec3::Y };
+                t = (axis - n * n.dot(axis)).normalize();
+            }
+            let sign = if n.cross(t).dot(b) < 0.0 { -1.0 } else { 1.0 };
+
+            v.tangent = t.extend(sign).to_array();
+        }
+    }
+
+    /// Creates device local buffers and requests uploads of the contents.
+    ///
+    /// Indices are packed into 16 bits if possible. The mesh can be drawn in submissions after the
+    /// next [`Engine::flush_uploads`].
+    ///
+    /// [`Engine::flush_uploads`]: crate::game::Engine::flush_uploads
+    pub fn upload<'d, Device: br::Device + ?Sized + 'd>(
+        &self,
+        allocator: &MemoryAllocator<'d, Device>,
+        upload_queue: &UploadQueue<'d, Device>,
+    ) -> Result<Mesh<'d, Device>, EngineError> {
+        if self.vertices.is_empty() {
+            return Err(EngineError::Unsupported("empty meshes"));
+        }
+
+        let vertex_bytes = unsafe {
+            core::slice::from_raw_parts(
+                self.vertices.as_ptr() as *const u8,
+                core::mem::size_of_val(&self.vertices[..]),
+            )
+        };
+        let vertex_buffer = Arc::new(allocator.create_buffer(
+            br::BufferDesc::new(
+                vertex_bytes.len(),
+                br::BufferUsage::VERTEX_BUFFER.transfer_dest(),
+            ),
+            MemoryLocation::DeviceLocal,
+        )?);
+        // Note: 完了は後続のsubmitから見えるので待たない
+        upload_queue.upload_buffer(vertex_buffer.clone(), 0, vertex_bytes);
+
+        let index_buffer = match &self.indices {
+            Some(indices) if !indices.is_empty() => {
+                let (index_type, bytes) = if self.vertices.len() <= u16::MAX as usize + 1 {
+                    let bytes = indices
+                        .iter()
+                        .flat_map(|&i| (i as u16).to_ne_bytes())
+                        .collect::<Vec<_>>();
+
+                    (br::IndexType::U16, bytes)
+                } else {
+                    let bytes = indices
+                        .iter()
+                        .flat_map(|i| i.to_ne_bytes())
+                        .collect::<Vec<_>>();
+
+                    (br::IndexType::U32, bytes)
+                };
+                let buffer = Arc::new(allocator.create_buffer(
+                    br::BufferDesc::new(bytes.len(), br::BufferUsage::INDEX_BUFFER.transfer_dest()),
+                    MemoryLocation::DeviceLocal,
+                )?);
+                upload_queue.upload_buffer(buffer.clone(), 0, bytes);
+
+                Some(IndexBuffer {
+                    buffer,
+                    index_type,
+                    count: indices.len() as _,
+                })
+            }
+            _ => None,
+        };
+
+        Ok(Mesh {
+            vertex_buffer,
+            vertex_count: self.vertices.len() as _,
+            index_buffer,
+        })
+    }
+}
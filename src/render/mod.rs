@@ -0,0 +1,21 @@
+//! Platform-independent rendering infrastructure.
+
+pub mod adapter;
+pub mod bootstrap;
+pub mod camera;
+pub mod capture;
+pub mod depth;
+pub mod descriptor;
+pub mod dynamic_buffer;
+pub mod frame;
+pub mod graph;
+pub mod headless;
+pub mod material;
+pub mod memory;
+pub mod mesh;
+pub mod pipeline_cache;
+pub mod presentation;
+pub mod scene_renderer;
+pub mod surface_format;
+pub mod texture;
+pub mod upload;
@@ -0,0 +1,336 @@
+//! Pipeline cache persisted in the per-user cache directory.
+//!
+//! The blob written by the driver is stored after a header identifying the device and the driver
+//! version. Caches created on other devices or by other drivers are discarded on load instead of
+//! being passed to the driver.
+
+use std::path::PathBuf;
+
+use bedrock::{self as br, PipelineCache};
+
+use crate::error::EngineError;
+
+/// Environment variable to override the cache directory.
+pub const CACHE_DIR_ENV_NAME: &str = "PERIDOT_CACHE_DIR";
+
+const FILE_MAGIC: [u8; 4] = *b"PDPC";
+const UUID_SIZE: usize = 16;
+/// magic, vendor ID, device ID, driver version and pipeline cache UUID
+const FILE_HEADER_SIZE: usize = 4 + 4 * 3 + UUID_SIZE;
+/// Size of `VkPipelineCacheHeaderVersionOne`
+const DRIVER_HEADER_SIZE: usize = 4 * 4 + UUID_SIZE;
+
+/// Device and driver the cache contents are valid for(from `VkPhysicalDeviceProperties`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PipelineCacheIdentity {
+    pub vendor_id: u32,
+    pub device_id: u32,
+    pub driver_version: u32,
+    pub uuid: [u8; UUID_SIZE],
+}
+impl PipelineCacheIdentity {
+    fn file_name(&self) -> String {
+        format!("pipeline-{:08x}-{:08x}.bin", self.vendor_id, self.device_id)
+    }
+
+    fn write_header(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&FILE_MAGIC);
+        out.extend_from_slice(&self.vendor_id.to_le_bytes());
+        out.extend_from_slice(&self.device_id.to_le_bytes());
+        out.extend_from_slice(&self.driver_version.to_le_bytes());
+        out.extend_from_slice(&self.uuid);
+    }
+
+    /// Returns the driver blob in a cache file, or the reason why it cannot be used.
+    fn validate<'b>(&self, bytes: &'b [u8]) -> Result<&'b [u8], &'static str> {
+        let u32_at = |b: &[u8], offset: usize| {
+            u32::from_le_bytes(b[offset..offset + 4].try_into().expect("size mismatch"))
+        };
+
+        let (Some(header), Some(blob)) =
+            (bytes.get(..FILE_HEADER_SIZE), bytes.get(FILE_HEADER_SIZE..))
+        else {
+            return Err("truncated file");
+        };
+        if header[..4] != FILE_MAGIC {
+            return Err("unknown file format");
+        }
+        if (u32_at(header, 4), u32_at(header, 8)) != (self.vendor_id, self.device_id) {
+            return Err("created on another device");
+        }
+        if u32_at(header, 12) != self.driver_version {
+            return Err("created by another driver version");
+        }
+        if header[16..] != self.uuid {
+            return Err("pipeline cache UUID mismatch");
+        }
+
+        // the driver header is little endian regardless of the host
+        if blob.len() < DRIVER_HEADER_SIZE {
+            return Err("truncated driver blob");
+        }
+        if (u32_at(blob, 0) as usize) < DRIVER_HEADER_SIZE
+            || u32_at(blob, 4) != br::vk::VK_PIPELINE_CACHE_HEADER_VERSION_ONE as u32
+            || (u32_at(blob, 8), u32_at(blob, 12)) != (self.vendor_id, self.device_id)
+            || blob[16..DRIVER_HEADER_SIZE] != self.uuid
+        {
+            return Err("corrupted driver blob");
+        }
+
+        Ok(blob)
+    }
+}
+
+/// A `VkPipelineCache` loaded from and written back to the cache directory.
+pub struct PersistentPipelineCache<'d, Device: br::Device + ?Sized + 'd> {
+    cache: br::PipelineCacheObject<&'d Device>,
+    identity: PipelineCacheIdentity,
+    /// None if no cache directories are available(the cache is not persisted)
+    path: Option<PathBuf>,
+}
+impl<'d, Device: br::Device + ?Sized + 'd> PersistentPipelineCache<'d, Device> {
+    /// Missing or invalid cache files are replaced with an empty cache(with a logged reason).
+    pub fn load(device: &'d Device, identity: PipelineCacheIdentity) -> br::Result<Self> {
+        let path = cache_dir().map(|d| d.join(identity.file_name()));
+
+        let initial_data = match path.as_deref().map(|p| (p, std::fs::read(p))) {
+            Some((p, Ok(bytes))) => match identity.validate(&bytes) {
+                Ok(blob) => {
+                    println!("pipeline cache loaded: {} bytes", blob.len());
+                    blob.to_vec()
+                }
+                Err(reason) => {
+                    println!("pipeline cache {} discarded: {reason}", p.display());
+                    Vec::new()
+                }
+            },
+            Some((_, Err(e))) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Some((p, Err(e))) => {
+                eprintln!("failed to read pipeline cache {}: {e}", p.display());
+                Vec::new()
+            }
+            None => Vec::new(),
+        };
+        let cache = br::PipelineCacheBuilder::new(&initial_data).create(device)?;
+
+        Ok(Self {
+            cache,
+            identity,
+            path,
+        })
+    }
+
+    pub fn object(&self) -> &br::PipelineCacheObject<&'d Device> {
+        &self.cache
+    }
+
+    /// Writes the current contents back.
+    ///
+    /// The file is replaced by renaming a temporary file, so an interrupted write never leaves a
+    /// broken cache behind.
+    pub fn save(&self) -> Result<(), EngineError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let blob = self.cache.data()?;
+        let mut bytes = Vec::with_capacity(FILE_HEADER_SIZE + blob.len());
+        self.identity.write_header(&mut bytes);
+        bytes.extend_from_slice(&blob);
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| EngineError::io(dir, e))?;
+        }
+        let temp_path = path.with_extension("tmp");
+        std::fs::write(&temp_path, &bytes).map_err(|e| EngineError::io(&temp_path, e))?;
+        std::fs::rename(&temp_path, path).map_err(|e| EngineError::io(path, e))
+    }
+}
+
+/// Per-user cache directory of the engine.
+///
+/// `PERIDOT_CACHE_DIR` if set. Otherwise `%LOCALAPPDATA%` on Windows, `~/Library/Caches` on macOS
+/// and `$XDG_CACHE_HOME`(or `~/.cache`) on others.
+pub fn cache_dir() -> Option<PathBuf> {
+    let env_path = |name: &str| {
+        std::env::var_os(name)
+            .filter(|x| !x.is_empty())
+            .map(PathBuf::from)
+    };
+
+    if let Some(d) = env_path(CACHE_DIR_ENV_NAME) {
+        return Some(d);
+    }
+
+    let base = if cfg!(target_os = "windows") {
+        env_path("LOCALAPPDATA")?
+    } else if cfg!(target_os = "macos") {
+        env_path("HOME")?.join("Library").join("Caches")
+    } else {
+        // relative paths in XDG_CACHE_HOME are invalid and must be ignored
+        match env_path("XDG_CACHE_HOME").filter(|p| p.is_absolute()) {
+            Some(p) => p,
+            None => env_path("HOME")?.join(".cache"),
+        }
+    };
+
+    Some(base.join("peridot2"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDENTITY: PipelineCacheIdentity = PipelineCacheIdentity {
+        vendor_id: 0x10de,
+        device_id: 0x2204,
+        driver_version: 0x0220_0000,
+        uuid: [7; UUID_SIZE],
+    };
+
+    /// `VkPipelineCacheHeaderVersionOne` followed by opaque driver data
+    fn driver_blob(identity: &PipelineCacheIdentity) -> Vec<u8> {
+        let mut blob = Vec::new();
+        blob.extend_from_slice(&(DRIVER_HEADER_SIZE as u32).to_le_bytes());
+        blob.extend_from_slice(
+            &(br::vk::VK_PIPELINE_CACHE_HEADER_VERSION_ONE as u32).to_le_bytes(),
+        );
+        blob.extend_from_slice(&identity.vendor_id.to_le_bytes());
+        blob.extend_from_slice(&identity.device_id.to_le_bytes());
+        blob.extend_from_slice(&identity.uuid);
+        blob.extend_from_slice(b"driver data");
+
+        blob
+    }
+
+    fn cache_file(identity: &PipelineCacheIdentity) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        identity.write_header(&mut bytes);
+        bytes.extend_from_slice(&driver_blob(identity));
+
+        bytes
+    }
+
+    #[test]
+    fn written_header_is_accepted() {
+        let bytes = cache_file(&IDENTITY);
+
+        assert_eq!(bytes.len(), FILE_HEADER_SIZE + DRIVER_HEADER_SIZE + 11);
+        assert_eq!(IDENTITY.validate(&bytes), Ok(&driver_blob(&IDENTITY)[..]));
+    }
+
+    #[test]
+    fn truncated_files_are_rejected() {
+        let bytes = cache_file(&IDENTITY);
+
+        assert_eq!(IDENTITY.validate(&[]), Err("truncated file"));
+        assert_eq!(
+            IDENTITY.validate(&bytes[..FILE_HEADER_SIZE - 1]),
+            Err("truncated file")
+        );
+        assert_eq!(
+            IDENTITY.validate(&bytes[..FILE_HEADER_SIZE + DRIVER_HEADER_SIZE - 1]),
+            Err("truncated driver blob")
+        );
+    }
+
+    #[test]
+    fn unknown_magic_is_rejected() {
+        let mut bytes = cache_file(&IDENTITY);
+        bytes[..4].copy_from_slice(b"PDPX");
+
+        assert_eq!(IDENTITY.validate(&bytes), Err("unknown file format"));
+    }
+
+    #[test]
+    fn caches_of_other_devices_are_rejected() {
+        let other_vendor = PipelineCacheIdentity {
+            vendor_id: 0x1002,
+            ..IDENTITY
+        };
+        let other_device = PipelineCacheIdentity {
+            device_id: 0x2206,
+            ..IDENTITY
+        };
+
+        for other in [other_vendor, other_device] {
+            assert_eq!(
+                IDENTITY.validate(&cache_file(&other)),
+                Err("created on another device")
+            );
+        }
+    }
+
+    #[test]
+    fn caches_of_other_driver_versions_are_rejected() {
+        let other = PipelineCacheIdentity {
+            driver_version: 0x0220_0001,
+            ..IDENTITY
+        };
+
+        assert_eq!(
+            IDENTITY.validate(&cache_file(&other)),
+            Err("created by another driver version")
+        );
+    }
+
+    #[test]
+    fn uuid_mismatch_is_rejected() {
+        let other = PipelineCacheIdentity {
+            uuid: [8; UUID_SIZE],
+            ..IDENTITY
+        };
+
+        assert_eq!(
+            IDENTITY.validate(&cache_file(&other)),
+            Err("pipeline cache UUID mismatch")
+        );
+    }
+
+    #[test]
+    fn corrupted_driver_headers_are_rejected() {
+        let corrupt = |offset: usize, value: u8| {
+            let mut bytes = cache_file(&IDENTITY);
+            bytes[FILE_HEADER_SIZE + offset] = value;
+
+            IDENTITY.validate(&bytes).err()
+        };
+
+        // header size smaller than VkPipelineCacheHeaderVersionOne
+        assert_eq!(corrupt(0, 4), Some("corrupted driver blob"));
+        // header version, vendor ID, device ID and UUID
+        for offset in [4, 8, 12, 16] {
+            assert_eq!(corrupt(offset, 0xff), Some("corrupted driver blob"));
+        }
+    }
+
+    #[test]
+    fn cache_dir_follows_the_environment() {
+        // Note: 環境変数を触るテストはここだけにする(テストは並列に実行される)
+        let saved = ["XDG_CACHE_HOME", "HOME"].map(|n| (n, std::env::var_os(n)));
+        std::env::set_var(CACHE_DIR_ENV_NAME, "/tmp/peridot-cache");
+        assert_eq!(cache_dir(), Some(PathBuf::from("/tmp/peridot-cache")));
+
+        // empty values are ignored
+        std::env::set_var(CACHE_DIR_ENV_NAME, "");
+        if cfg!(not(any(target_os = "windows", target_os = "macos"))) {
+            std::env::set_var("XDG_CACHE_HOME", "/tmp/xdg-cache");
+            assert_eq!(cache_dir(), Some(PathBuf::from("/tmp/xdg-cache/peridot2")));
+
+            // relative paths fall back to ~/.cache
+            std::env::set_var("XDG_CACHE_HOME", "relative");
+            std::env::set_var("HOME", "/tmp/home");
+            assert_eq!(
+                cache_dir(),
+                Some(PathBuf::from("/tmp/home/.cache/peridot2"))
+            );
+        }
+        std::env::remove_var(CACHE_DIR_ENV_NAME);
+        for (name, value) in saved {
+            match value {
+                Some(v) => std::env::set_var(name, v),
+                None => std::env::remove_var(name),
+            }
+        }
+    }
+}
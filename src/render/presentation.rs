@@ -0,0 +1,212 @@
+//! Presentation(present mode, swapchain image count and frames in flight) configuration.
+
+use bedrock as br;
+
+use super::frame::DEFAULT_FRAMES_IN_FLIGHT;
+
+/// How the present mode is chosen. Each preference falls back to the next supported mode,
+/// finally to `FIFO`(which is always supported by spec).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PresentModePreference {
+    /// `FIFO`
+    VSync,
+    /// `IMMEDIATE` -> `FIFO_RELAXED` -> `MAILBOX` -> `FIFO`
+    NoVSync,
+    /// `MAILBOX` -> `FIFO`
+    MailboxPreferred,
+    /// `MAILBOX` -> `IMMEDIATE` -> `FIFO_RELAXED` -> `FIFO`, with minimum buffering
+    LowLatency,
+}
+impl PresentModePreference {
+    fn candidates(self) -> &'static [br::PresentMode] {
+        match self {
+            Self::VSync => &[br::PresentMode::FIFO],
+            Self::NoVSync => &[
+                br::PresentMode::Immediate,
+                br::PresentMode::FIFORelaxed,
+                br::PresentMode::Mailbox,
+                br::PresentMode::FIFO,
+            ],
+            Self::MailboxPreferred => &[br::PresentMode::Mailbox, br::PresentMode::FIFO],
+            Self::LowLatency => &[
+                br::PresentMode::Mailbox,
+                br::PresentMode::Immediate,
+                br::PresentMode::FIFORelaxed,
+                br::PresentMode::FIFO,
+            ],
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PresentationConfig {
+    pub mode: PresentModePreference,
+    /// Desired swapchain image count. If not specified, decided from the present mode
+    /// (3 for `MAILBOX`, otherwise 2). Always clamped to the surface capabilities.
+    pub desired_back_buffer_count: Option<u32>,
+    /// Number of frames the CPU can record ahead of the GPU
+    pub frames_in_flight: u32,
+}
+impl Default for PresentationConfig {
+    fn default() -> Self {
+        Self {
+            mode: PresentModePreference::VSync,
+            desired_back_buffer_count: None,
+            frames_in_flight: DEFAULT_FRAMES_IN_FLIGHT,
+        }
+    }
+}
+impl PresentationConfig {
+    /// Parses command line flags: `--vsync`, `--no-vsync`, `--mailbox`, `--low-latency` and
+    /// `--back-buffers <count>` and `--frames-in-flight <count>`. Unknown arguments are ignored.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Self {
+        let mut config = Self::default();
+        let mut args = args.into_iter();
+        while let Some(a) = args.next() {
+            match &a[..] {
+                "--vsync" => config.mode = PresentModePreference::VSync,
+                "--no-vsync" => config.mode = PresentModePreference::NoVSync,
+                "--mailbox" => config.mode = PresentModePreference::MailboxPreferred,
+                "--low-latency" => config.mode = PresentModePreference::LowLatency,
+                "--back-buffers" => match args.next().map(|x| x.parse()) {
+                    Some(Ok(n)) => config.desired_back_buffer_count = Some(n),
+                    _ => eprintln!("--back-buffers requires a number: ignored"),
+                },
+                "--frames-in-flight" => match args.next().map(|x| x.parse()) {
+                    Some(Ok(n)) if n > 0 => config.frames_in_flight = n,
+                    _ => eprintln!("--frames-in-flight requires a positive number: ignored"),
+                },
+                _ => (),
+            }
+        }
+
+        config
+    }
+
+    /// Picks the first supported mode along the fallback chain of the preference.
+    pub fn choose_present_mode(&self, supported: &[br::PresentMode]) -> br::PresentMode {
+        self.mode
+            .candidates()
+            .iter()
+            .find(|m| supported.contains(m))
+            .copied()
+            .unwrap_or(br::PresentMode::FIFO)
+    }
+
+    pub fn back_buffer_count(
+        &self,
+        present_mode: br::PresentMode,
+        caps: &br::vk::VkSurfaceCapabilitiesKHR,
+    ) -> u32 {
+        let desired = self.desired_back_buffer_count.unwrap_or(
+            if present_mode == br::PresentMode::Mailbox
+                && self.mode != PresentModePreference::LowLatency
+            {
+                3
+            } else {
+                2
+            },
+        );
+
+        clamp_image_count(desired, caps)
+    }
+}
+
+/// Clamps image count into `[minImageCount, maxImageCount]`. `maxImageCount == 0` means no upper limit.
+pub fn clamp_image_count(desired: u32, caps: &br::vk::VkSurfaceCapabilitiesKHR) -> u32 {
+    let count = desired.max(caps.minImageCount);
+    if caps.maxImageCount == 0 {
+        count
+    } else {
+        count.min(caps.maxImageCount)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn caps(min_image_count: u32, max_image_count: u32) -> br::vk::VkSurfaceCapabilitiesKHR {
+        // Note: テストに関係しないフィールドはゼロで良い
+        let mut caps: br::vk::VkSurfaceCapabilitiesKHR = unsafe { core::mem::zeroed() };
+        caps.minImageCount = min_image_count;
+        caps.maxImageCount = max_image_count;
+
+        caps
+    }
+
+    fn config(args: &[&str]) -> PresentationConfig {
+        PresentationConfig::from_args(args.iter().map(|x| x.to_string()))
+    }
+
+    #[test]
+    fn image_count_is_clamped() {
+        assert_eq!(clamp_image_count(2, &caps(2, 8)), 2);
+        assert_eq!(clamp_image_count(1, &caps(2, 8)), 2);
+        assert_eq!(clamp_image_count(10, &caps(2, 8)), 8);
+    }
+
+    #[test]
+    fn zero_max_image_count_means_no_limit() {
+        assert_eq!(clamp_image_count(10, &caps(2, 0)), 10);
+        assert_eq!(clamp_image_count(1, &caps(3, 0)), 3);
+    }
+
+    #[test]
+    fn back_buffer_count_depends_on_present_mode() {
+        let c = PresentationConfig::default();
+        assert_eq!(c.back_buffer_count(br::PresentMode::FIFO, &caps(2, 0)), 2);
+        assert_eq!(
+            c.back_buffer_count(br::PresentMode::Mailbox, &caps(2, 0)),
+            3
+        );
+
+        let low_latency = config(&["--low-latency"]);
+        assert_eq!(
+            low_latency.back_buffer_count(br::PresentMode::Mailbox, &caps(2, 0)),
+            2
+        );
+    }
+
+    #[test]
+    fn args_are_parsed() {
+        assert_eq!(config(&[]), PresentationConfig::default());
+        assert_eq!(
+            config(&[
+                "--no-vsync",
+                "--back-buffers",
+                "4",
+                "--frames-in-flight",
+                "3"
+            ]),
+            PresentationConfig {
+                mode: PresentModePreference::NoVSync,
+                desired_back_buffer_count: Some(4),
+                frames_in_flight: 3,
+            }
+        );
+        // the last one wins
+        assert_eq!(
+            config(&["--mailbox", "--vsync"]).mode,
+            PresentModePreference::VSync
+        );
+    }
+
+    #[test]
+    fn invalid_counts_are_ignored() {
+        assert_eq!(
+            config(&["--back-buffers", "many", "--frames-in-flight", "0"]),
+            PresentationConfig::default()
+        );
+        assert_eq!(config(&["--back-buffers"]), PresentationConfig::default());
+    }
+
+    #[test]
+    fn present_mode_falls_back() {
+        let supported = [br::PresentMode::FIFO, br::PresentMode::FIFORelaxed];
+        assert!(
+            config(&["--no-vsync"]).choose_present_mode(&supported) == br::PresentMode::FIFORelaxed
+        );
+        assert!(config(&["--mailbox"]).choose_present_mode(&supported) == br::PresentMode::FIFO);
+    }
+}
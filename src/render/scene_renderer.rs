@@ -0,0 +1,611 @@
+//! Draw list generation from the scene graph.
+//!
+//! The renderer owns meshes, materials and pipelines referenced by scene nodes. Every frame it
+//! collects visible meshes, writes per-object uniforms(and material parameters) into the frame's
+//! dynamic buffer and groups draws by pipeline and material, so the recording side binds each of
+//! them once per batch.
+//!
+//! Pipelines are shared by materials with the same [`PipelineState`] and created lazily. Layouts
+//! are derived from the shaders by reflection, and buffers in every descriptor set are bound with
+//! dynamic offsets into the frame's dynamic buffer.
+
+use std::{collections::HashMap, sync::Arc};
+
+use bedrock::{
+    self as br, GraphicsPipelineBuilder, PipelineShaderStageProvider, RenderPass, ShaderModule,
+    VkHandle,
+};
+
+use crate::{
+    error::EngineError,
+    math::{std140_struct, Mat4},
+    scene::{MaterialId, MeshId, Scene, VisibleMesh},
+    shader::{
+        reflect::{DescriptorKind, PipelineInterface},
+        ShaderLibrary,
+    },
+};
+
+use super::{
+    camera::ViewUniform,
+    descriptor::{DescriptorAllocator, SetLayoutSignature},
+    dynamic_buffer::DynamicBufferSegment,
+    material::{DepthMode, MaterialDesc, MaterialParameters, PipelineState, ShaderPair},
+    memory::AllocatedBuffer,
+    mesh::MeshVertex,
+};
+
+/// Descriptor set index of the per-view uniform
+pub const VIEW_SET: u32 = 0;
+/// Descriptor set index of the per-object uniform
+pub const OBJECT_SET: u32 = 1;
+/// Descriptor set index of material resources
+pub const MATERIAL_SET: u32 = 2;
+
+std140_struct! {
+    /// Contents of the per-object uniform buffer.
+    pub struct ObjectUniform {
+        pub object_matrix: Mat4,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PipelineId(pub u32);
+
+pub struct IndexBuffer<'d, Device: br::Device + ?Sized + 'd> {
+    pub buffer: Arc<AllocatedBuffer<'d, Device>>,
+    pub index_type: br::IndexType,
+    pub count: u32,
+}
+
+pub struct Mesh<'d, Device: br::Device + ?Sized + 'd> {
+    /// Bound at binding 0
+    pub vertex_buffer: Arc<AllocatedBuffer<'d, Device>>,
+    pub vertex_count: u32,
+    /// Indexed draw if present
+    pub index_buffer: Option<IndexBuffer<'d, Device>>,
+}
+
+pub struct Material {
+    pipeline: PipelineId,
+    descriptor_set: Option<br::DescriptorSet>,
+    parameters: Option<MaterialParameters>,
+}
+impl Material {
+    /// Changes are reflected from the next [`SceneRenderer::prepare`].
+    pub fn parameters_mut(&mut self) -> Option<&mut MaterialParameters> {
+        self.parameters.as_mut()
+    }
+}
+
+/// Reflects the interface of the shader pair and checks it against the engine side layouts.
+///
+/// Shaders must declare [`ViewUniform`] at [`VIEW_SET`] and [`ObjectUniform`] at [`OBJECT_SET`]
+/// (both at binding 0), and take [`MeshVertex`] as vertex inputs.
+fn reflect_shader_pair(
+    shaders: &ShaderLibrary,
+    pair: &ShaderPair,
+) -> Result<PipelineInterface, EngineError> {
+    let interface = PipelineInterface::new(&[
+        shaders.reflect(&pair.vertex)?,
+        shaders.reflect(&pair.fragment)?,
+    ])?;
+    interface.check_vertex_attributes(&MeshVertex::attributes())?;
+    interface.check_uniform_block::<ViewUniform>(VIEW_SET, 0)?;
+    interface.check_uniform_block::<ObjectUniform>(OBJECT_SET, 0)?;
+
+    Ok(interface)
+}
+
+/// SPIR-V of a shader pair the pipelines are created from.
+struct PairSpirv {
+    vertex: Vec<u8>,
+    fragment: Vec<u8>,
+}
+impl PairSpirv {
+    fn load(shaders: &ShaderLibrary, pair: &ShaderPair) -> Result<Self, EngineError> {
+        let spirv = |name: &str| {
+            shaders
+                .spirv(name)
+                .map(<[u8]>::to_vec)
+                .ok_or(EngineError::Unsupported("pipelines with unloaded shaders"))
+        };
+
+        Ok(Self {
+            vertex: spirv(&pair.vertex)?,
+            fragment: spirv(&pair.fragment)?,
+        })
+    }
+}
+
+/// Descriptor set layouts and the pipeline layout derived from a shader pair.
+pub struct ShaderLayout<'d, Device: br::Device + ?Sized + 'd> {
+    interface: PipelineInterface,
+    // Note: セットレイアウトより先にパイプラインレイアウトを破棄する
+    pipeline_layout: Arc<br::PipelineLayoutObject<&'d Device>>,
+    /// Indexed by the set number(empty layouts for unused sets)
+    set_signatures: Vec<SetLayoutSignature>,
+    /// Shared with other shader pairs through the [`DescriptorAllocator`]
+    _set_layouts: Vec<Arc<br::DescriptorSetLayoutObject<&'d Device>>>,
+    /// Kept until a reload with the same interface succeeds, so pipelines are never created from
+    /// SPIR-V disagreeing with the layouts
+    spirv: PairSpirv,
+}
+impl<'d, Device: br::Device + ?Sized + 'd> ShaderLayout<'d, Device> {
+    fn new(
+        descriptors: &mut DescriptorAllocator<'d, Device>,
+        shaders: &ShaderLibrary,
+        pair: &ShaderPair,
+    ) -> Result<Self, EngineError> {
+        let interface = reflect_shader_pair(shaders, pair)?;
+        let spirv = PairSpirv::load(shaders, pair)?;
+
+        let set_signatures = (0..interface.set_count().max(MATERIAL_SET + 1))
+            .map(|set| interface.set_layout_signature(set, true))
+            .collect::<Vec<_>>();
+        let set_layouts = set_signatures
+            .iter()
+            .map(|s| descriptors.layout(s))
+            .collect::<Result<Vec<_>, _>>()?;
+        let pipeline_layout = br::PipelineLayoutBuilder::new(
+            &set_layouts
+                .iter()
+                .map(|l| br::DescriptorSetLayoutObjectRef::new(&**l))
+                .collect::<Vec<_>>(),
+            &interface.push_constant_ranges(),
+        )
+        .create(descriptors.device())?;
+
+        Ok(Self {
+            interface,
+            pipeline_layout: Arc::new(pipeline_layout),
+            set_signatures,
+            _set_layouts: set_layouts,
+            spirv,
+        })
+    }
+
+    pub fn interface(&self) -> &PipelineInterface {
+        &self.interface
+    }
+
+    /// Signature to allocate descriptor sets bound at the set number
+    pub fn set_signature(&self, set: u32) -> &SetLayoutSignature {
+        &self.set_signatures[set as usize]
+    }
+}
+
+/// Subpass the pipelines render in.
+pub struct PipelineTarget<'r, 'd, Device: br::Device + ?Sized + 'd> {
+    pub render_pass: &'r br::RenderPassObject<&'d Device>,
+    pub subpass: u32,
+    /// true if the subpass has a depth attachment
+    pub depth: bool,
+    pub cache: Option<&'r br::PipelineCacheObject<&'d Device>>,
+}
+
+/// Distinct pipeline states, identified by [`PipelineId`]s in the order of registration.
+#[derive(Default)]
+struct PipelineStates {
+    ids: HashMap<PipelineState, PipelineId>,
+    states: Vec<PipelineState>,
+}
+impl PipelineStates {
+    /// Id of the state, registering it if not seen yet
+    fn intern(&mut self, state: &PipelineState) -> PipelineId {
+        if let Some(&id) = self.ids.get(state) {
+            return id;
+        }
+
+        let id = PipelineId(self.states.len() as _);
+        self.states.push(state.clone());
+        self.ids.insert(state.clone(), id);
+
+        id
+    }
+
+    fn get(&self, id: PipelineId) -> &PipelineState {
+        &self.states[id.0 as usize]
+    }
+
+    fn len(&self) -> usize {
+        self.states.len()
+    }
+}
+
+pub struct SceneRenderer<'d, Device: br::Device + ?Sized + 'd> {
+    meshes: Vec<Mesh<'d, Device>>,
+    materials: Vec<Material>,
+    pipeline_states: PipelineStates,
+    /// Indexed by [`PipelineId`]. None until created by [`SceneRenderer::create_pipelines`]
+    pipelines: Vec<Option<br::PipelineObject<&'d Device>>>,
+    shader_layouts: HashMap<ShaderPair, ShaderLayout<'d, Device>>,
+    visible_meshes: Vec<VisibleMesh>,
+}
+impl<'d, Device: br::Device + ?Sized + 'd> Default for SceneRenderer<'d, Device> {
+    fn default() -> Self {
+        Self {
+            meshes: Vec::new(),
+            materials: Vec::new(),
+            pipeline_states: PipelineStates::default(),
+            pipelines: Vec::new(),
+            shader_layouts: HashMap::new(),
+            visible_meshes: Vec::new(),
+        }
+    }
+}
+impl<'d, Device: br::Device + ?Sized + 'd> SceneRenderer<'d, Device> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_mesh(&mut self, mesh: Mesh<'d, Device>) -> MeshId {
+        self.meshes.push(mesh);
+
+        MeshId((self.meshes.len() - 1) as _)
+    }
+
+    /// Layouts of the shader pair, created at the first request. The shaders must be loaded.
+    pub fn shader_layout(
+        &mut self,
+        descriptors: &mut DescriptorAllocator<'d, Device>,
+        shaders: &ShaderLibrary,
+        pair: &ShaderPair,
+    ) -> Result<&ShaderLayout<'d, Device>, EngineError> {
+        if !self.shader_layouts.contains_key(pair) {
+            let layout = ShaderLayout::new(descriptors, shaders, pair)?;
+            self.shader_layouts.insert(pair.clone(), layout);
+        }
+
+        Ok(&self.shader_layouts[pair])
+    }
+
+    /// Registers a material. The pipeline is shared with other materials of the same state.
+    ///
+    /// Fails if the material set resources or the parameters disagree with the shaders.
+    pub fn add_material(
+        &mut self,
+        descriptors: &mut DescriptorAllocator<'d, Device>,
+        shaders: &ShaderLibrary,
+        desc: MaterialDesc,
+    ) -> Result<MaterialId, EngineError> {
+        let interface = self
+            .shader_layout(descriptors, shaders, &desc.state.shaders)?
+            .interface();
+        let mismatch = |reason: &str| {
+            EngineError::ShaderInterfaceMismatch(format!(
+                "{}, {}: {reason}",
+                desc.state.shaders.vertex, desc.state.shaders.fragment
+            ))
+        };
+
+        if interface.bindings(MATERIAL_SET).next().is_some() && desc.descriptor_set.is_none() {
+            return Err(mismatch("no descriptor set for the material resources"));
+        }
+        let blocks = interface
+            .bindings(MATERIAL_SET)
+            .filter(|b| b.kind == DescriptorKind::UniformBuffer)
+            .collect::<Vec<_>>();
+        match (&blocks[..], &desc.parameters) {
+            ([], None) => (),
+            ([b], Some(p)) => interface.check_uniform_block_fields(
+                MATERIAL_SET,
+                b.binding,
+                p.fields(),
+                p.type_name(),
+            )?,
+            ([], Some(_)) => return Err(mismatch("no parameter block for the parameters")),
+            ([_], None) => return Err(mismatch("no parameters for the parameter block")),
+            _ => return Err(mismatch("multiple parameter blocks are not supported")),
+        }
+
+        let pipeline = self.pipeline_states.intern(&desc.state);
+        self.pipelines
+            .resize_with(self.pipeline_states.len(), || None);
+        self.materials.push(Material {
+            pipeline,
+            descriptor_set: desc.descriptor_set,
+            parameters: desc.parameters,
+        });
+
+        Ok(MaterialId((self.materials.len() - 1) as _))
+    }
+
+    pub fn material_mut(&mut self, id: MaterialId) -> &mut Material {
+        &mut self.materials[id.0 as usize]
+    }
+
+    /// Creates pipelines which have not been created yet. Does nothing if every pipeline exists.
+    pub fn create_pipelines(
+        &mut self,
+        device: &'d Device,
+        target: &PipelineTarget<'_, 'd, Device>,
+    ) -> Result<(), EngineError> {
+        for (n, p) in self.pipelines.iter_mut().enumerate() {
+            if p.is_some() {
+                continue;
+            }
+
+            let state = self.pipeline_states.get(PipelineId(n as _));
+            let layout = &self.shader_layouts[&state.shaders];
+            *p = Some(create_pipeline(
+                device,
+                state,
+                &layout.pipeline_layout,
+                &layout.spirv,
+                target,
+            )?);
+        }
+
+        Ok(())
+    }
+
+    /// Recreates pipelines using the reloaded shaders. The GPU must not be using them.
+    ///
+    /// Pipelines of a shader pair are replaced only if all of them are created successfully.
+    /// Layouts are not rebuilt: if the interface of a shader pair has changed(or disagrees with the
+    /// engine side layouts), the previous SPIR-V and pipelines are kept with a logged diagnostic.
+    /// Fails only if the device has been lost.
+    pub fn reload_shaders(
+        &mut self,
+        device: &'d Device,
+        shaders: &ShaderLibrary,
+        reloaded: &[String],
+        target: &PipelineTarget<'_, 'd, Device>,
+    ) -> Result<(), EngineError> {
+        for (pair, layout) in &mut self.shader_layouts {
+            if !reloaded.iter().any(|n| pair.contains(n)) {
+                continue;
+            }
+
+            let result = reflect_shader_pair(shaders, pair).and_then(|interface| {
+                if interface != layout.interface {
+                    return Err(EngineError::ShaderInterfaceMismatch(
+                        "interface has changed since the layouts were created".to_owned(),
+                    ));
+                }
+
+                let spirv = PairSpirv::load(shaders, pair)?;
+                let recreated = (0..self.pipelines.len())
+                    .filter(|&n| self.pipelines[n].is_some())
+                    .map(|n| (n, self.pipeline_states.get(PipelineId(n as _))))
+                    .filter(|(_, state)| state.shaders == *pair)
+                    .map(|(n, state)| {
+                        let pipeline = create_pipeline(
+                            device,
+                            state,
+                            &layout.pipeline_layout,
+                            &spirv,
+                            target,
+                        )?;
+
+                        Ok((n, pipeline))
+                    })
+                    .collect::<Result<Vec<_>, EngineError>>()?;
+
+                Ok((spirv, recreated))
+            });
+            match result {
+                Ok((spirv, recreated)) => {
+                    layout.spirv = spirv;
+                    for (n, pipeline) in recreated {
+                        self.pipelines[n] = Some(pipeline);
+                    }
+                }
+                Err(e) if e.is_device_lost() => return Err(e),
+                Err(e) => eprintln!(
+                    "{e}: keeping previous pipelines of {}, {}",
+                    pair.vertex, pair.fragment
+                ),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Builds the draw list of the frame.
+    ///
+    /// World transforms of the scene must be up to date. Meshes whose pipeline has not been created
+    /// yet are skipped.
+    pub fn prepare<'r>(
+        &'r mut self,
+        scene: &Scene,
+        dynamic_buffer: &mut DynamicBufferSegment<'d, Device>,
+    ) -> Result<DrawList<'r, 'd, Device>, EngineError> {
+        self.visible_meshes.clear();
+        scene.collect_visible_meshes(&mut self.visible_meshes);
+
+        let (meshes, materials, pipelines, pipeline_states, shader_layouts) = (
+            &self.meshes,
+            &self.materials,
+            &self.pipelines,
+            &self.pipeline_states,
+            &self.shader_layouts,
+        );
+        self.visible_meshes.retain(|v| {
+            pipelines[materials[v.instance.material.0 as usize].pipeline.0 as usize].is_some()
+        });
+        self.visible_meshes.sort_by_key(|v| {
+            let material = &materials[v.instance.material.0 as usize];
+
+            (material.pipeline, v.instance.material, v.instance.mesh)
+        });
+
+        let mut batches: Vec<DrawBatch<'r, 'd, Device>> = Vec::new();
+        for v in &self.visible_meshes {
+            let object = dynamic_buffer
+                .push(&ObjectUniform {
+                    object_matrix: v.world_transform,
+                })
+                .ok_or(EngineError::DynamicBufferExhausted)?;
+            let draw = DrawCall {
+                object_offset: object.offset,
+                mesh: &meshes[v.instance.mesh.0 as usize],
+            };
+
+            match batches.last_mut() {
+                Some(b) if b.material == v.instance.material => b.draws.push(draw),
+                _ => {
+                    let material = &materials[v.instance.material.0 as usize];
+                    let shaders = &pipeline_states.get(material.pipeline).shaders;
+                    let parameter_offset = match &material.parameters {
+                        Some(p) => Some(
+                            dynamic_buffer
+                                .push_bytes(p.bytes())
+                                .ok_or(EngineError::DynamicBufferExhausted)?
+                                .offset,
+                        ),
+                        None => None,
+                    };
+
+                    batches.push(DrawBatch {
+                        pipeline_id: material.pipeline,
+                        material: v.instance.material,
+                        pipeline: pipelines[material.pipeline.0 as usize]
+                            .as_ref()
+                            .expect("no pipeline"),
+                        layout: &shader_layouts[shaders].pipeline_layout,
+                        material_descriptor: material.descriptor_set,
+                        parameter_offset,
+                        draws: vec![draw],
+                    });
+                }
+            }
+        }
+
+        Ok(DrawList { batches })
+    }
+}
+
+fn create_pipeline<'d, Device: br::Device + ?Sized + 'd>(
+    device: &'d Device,
+    state: &PipelineState,
+    pipeline_layout: &br::PipelineLayoutObject<&'d Device>,
+    spirv: &PairSpirv,
+    target: &PipelineTarget<'_, 'd, Device>,
+) -> Result<br::PipelineObject<&'d Device>, EngineError> {
+    // Note: シェーダモジュールはパイプライン作成後に破棄してよい
+    let vert_shader = device.new_shader_module_ref(&spirv.vertex[..])?;
+    let frag_shader = device.new_shader_module_ref(&spirv.fragment[..])?;
+    let vbind = [MeshVertex::binding()];
+    let vattr = MeshVertex::attributes();
+
+    let mut builder = br::NonDerivedGraphicsPipelineBuilder::new(
+        pipeline_layout,
+        target.render_pass.subpass(target.subpass),
+        br::VertexProcessingStages::new(
+            br::VertexShaderStage::new(vert_shader.with_entry_point(c"main"))
+                .with_fragment_shader_stage(frag_shader.with_entry_point(c"main")),
+            &vbind,
+            &vattr,
+            state.topology.primitive_topology(),
+        ),
+    );
+    // viewports and scissors are set at recording: resizes do not rebuild pipelines
+    builder
+        .viewport_scissors(
+            br::DynamicArrayState::Dynamic(1),
+            br::DynamicArrayState::Dynamic(1),
+        )
+        .add_attachment_blend(state.blend.attachment_state())
+        .multisample_state(Some(br::MultisampleState::new()))
+        .cull_mode(state.cull.flags())
+        .front_face(br::vk::VK_FRONT_FACE_COUNTER_CLOCKWISE);
+    if target.depth {
+        match state.depth {
+            DepthMode::Disabled => (),
+            DepthMode::Test => {
+                builder.depth_test_settings(Some(br::CompareOp::LessOrEqual), false);
+            }
+            DepthMode::TestAndWrite => {
+                builder.depth_test_settings(Some(br::CompareOp::Less), true);
+            }
+        }
+    }
+
+    Ok(builder.create(device, target.cache)?)
+}
+
+/// Draws in a batch share the pipeline and the material.
+pub struct DrawBatch<'r, 'd, Device: br::Device + ?Sized + 'd> {
+    pipeline_id: PipelineId,
+    material: MaterialId,
+    pub pipeline: &'r br::PipelineObject<&'d Device>,
+    pub layout: &'r br::PipelineLayoutObject<&'d Device>,
+    pub material_descriptor: Option<br::DescriptorSet>,
+    /// Dynamic offset of the material parameter block if the material has parameters
+    pub parameter_offset: Option<u32>,
+    pub draws: Vec<DrawCall<'r, 'd, Device>>,
+}
+impl<'r, 'd, Device: br::Device + ?Sized + 'd> DrawBatch<'r, 'd, Device> {
+    /// true if the pipeline must be bound(differs from the previous batch)
+    pub fn switches_pipeline(&self, prev: Option<&Self>) -> bool {
+        prev.map_or(true, |p| p.pipeline_id != self.pipeline_id)
+    }
+}
+
+pub struct DrawCall<'r, 'd, Device: br::Device + ?Sized + 'd> {
+    /// Dynamic offset of the per-object uniform([`ObjectUniform`])
+    pub object_offset: u32,
+    pub mesh: &'r Mesh<'d, Device>,
+}
+
+/// Draws of a frame, sorted by pipeline and material.
+pub struct DrawList<'r, 'd, Device: br::Device + ?Sized + 'd> {
+    batches: Vec<DrawBatch<'r, 'd, Device>>,
+}
+impl<'r, 'd, Device: br::Device + ?Sized + 'd> DrawList<'r, 'd, Device> {
+    pub fn batches(&self) -> &[DrawBatch<'r, 'd, Device>] {
+        &self.batches
+    }
+
+    /// Vertex buffers read by the draws(for declaring to the render graph)
+    pub fn vertex_buffers(&self) -> impl Iterator<Item = br::vk::VkBuffer> + '_ {
+        self.meshes().map(|m| m.vertex_buffer.buffer().native_ptr())
+    }
+
+    /// Index buffers read by the draws(for declaring to the render graph)
+    pub fn index_buffers(&self) -> impl Iterator<Item = br::vk::VkBuffer> + '_ {
+        self.meshes()
+            .filter_map(|m| m.index_buffer.as_ref())
+            .map(|ib| ib.buffer.buffer().native_ptr())
+    }
+
+    fn meshes(&self) -> impl Iterator<Item = &Mesh<'d, Device>> + '_ {
+        self.batches
+            .iter()
+            .flat_map(|b| b.draws.iter().map(|d| d.mesh))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::material::{BlendMode, CullMode};
+
+    #[test]
+    fn materials_with_same_state_share_pipeline() {
+        let base = PipelineState::new(ShaderPair::new("test.vert", "test.frag"));
+        let blended = PipelineState {
+            blend: BlendMode::Alpha,
+            ..base.clone()
+        };
+        let other_shaders = PipelineState::new(ShaderPair::new("test.vert", "other.frag"));
+
+        let mut states = PipelineStates::default();
+        let first = states.intern(&base);
+        assert_eq!(states.intern(&blended), PipelineId(1));
+        assert_eq!(states.intern(&base.clone()), first);
+        assert_eq!(
+            states.intern(&PipelineState {
+                cull: CullMode::Back,
+                ..base.clone()
+            }),
+            first
+        );
+        assert_eq!(states.intern(&other_shaders), PipelineId(2));
+        assert_eq!(states.len(), 3);
+        assert_eq!(*states.get(first), base);
+        assert_eq!(*states.get(PipelineId(1)), blended);
+    }
+}
@@ -0,0 +1,410 @@
+//! Swapchain format/color space negotiation.
+
+use bedrock as br;
+
+/// Output color space requested by the application.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputColorSpace {
+    /// sRGB nonlinear(SDR)
+    Srgb,
+    /// HDR10(BT.2020 primaries + ST.2084 PQ). requires `VK_EXT_swapchain_colorspace`
+    Hdr10,
+    /// scRGB(linear extended sRGB, fp16). requires `VK_EXT_swapchain_colorspace`
+    ScRgb,
+}
+impl OutputColorSpace {
+    pub const fn requires_swapchain_colorspace_extension(self) -> bool {
+        !matches!(self, Self::Srgb)
+    }
+
+    /// Parses `--hdr10` or `--scrgb` command line flags(sRGB if none specified).
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Self {
+        let mut output = Self::Srgb;
+        for a in args {
+            match &a[..] {
+                "--hdr10" => output = Self::Hdr10,
+                "--scrgb" => output = Self::ScRgb,
+                _ => (),
+            }
+        }
+
+        output
+    }
+
+    /// Color space of the back buffer contents. Unknown color spaces are treated as sRGB.
+    pub fn from_vk(color_space: br::vk::VkColorSpaceKHR) -> Self {
+        match color_space {
+            br::vk::VK_COLOR_SPACE_HDR10_ST2084_EXT => Self::Hdr10,
+            br::vk::VK_COLOR_SPACE_EXTENDED_SRGB_LINEAR_EXT => Self::ScRgb,
+            _ => Self::Srgb,
+        }
+    }
+}
+
+/// Luminance of SDR white(1.0) on HDR10 outputs
+pub const HDR_SDR_WHITE_NITS: f32 = 200.0;
+
+/// How linear sRGB colors are written to the back buffer. Passed to shaders as `uint`
+/// (`ViewUniform.outputEncoding`).
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputEncoding {
+    /// As is(encoded by the hardware for sRGB formats, or scRGB)
+    Linear = 0,
+    /// sRGB transfer function(UNORM formats in sRGB nonlinear color space)
+    Srgb = 1,
+    /// BT.2020 primaries and ST.2084(PQ) transfer function
+    Hdr10 = 2,
+}
+impl OutputEncoding {
+    pub fn new(color_space: OutputColorSpace, format: br::vk::VkFormat) -> Self {
+        match color_space {
+            OutputColorSpace::Srgb if is_srgb_format(format) => Self::Linear,
+            OutputColorSpace::Srgb => Self::Srgb,
+            OutputColorSpace::ScRgb => Self::Linear,
+            OutputColorSpace::Hdr10 => Self::Hdr10,
+        }
+    }
+
+    /// Alpha is kept as is. Must match `encodeOutput` in shaders.
+    pub fn encode(self, linear: [f32; 4]) -> [f32; 4] {
+        let [r, g, b, a] = linear;
+
+        match self {
+            Self::Linear => linear,
+            Self::Srgb => [encode_srgb(r), encode_srgb(g), encode_srgb(b), a],
+            Self::Hdr10 => {
+                let [r, g, b] = bt709_to_bt2020([r, g, b]);
+
+                [
+                    encode_pq(r, HDR_SDR_WHITE_NITS),
+                    encode_pq(g, HDR_SDR_WHITE_NITS),
+                    encode_pq(b, HDR_SDR_WHITE_NITS),
+                    a,
+                ]
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FormatPreference {
+    pub format: br::vk::VkFormat,
+    pub color_space: br::vk::VkColorSpaceKHR,
+}
+impl FormatPreference {
+    pub const fn srgb_nonlinear(format: br::vk::VkFormat) -> Self {
+        Self {
+            format,
+            color_space: br::vk::VK_COLOR_SPACE_SRGB_NONLINEAR_KHR,
+        }
+    }
+}
+
+const SRGB_PREFERENCES: &[FormatPreference] = &[
+    FormatPreference::srgb_nonlinear(br::vk::VK_FORMAT_B8G8R8A8_SRGB),
+    FormatPreference::srgb_nonlinear(br::vk::VK_FORMAT_R8G8B8A8_SRGB),
+    FormatPreference::srgb_nonlinear(br::vk::VK_FORMAT_A8B8G8R8_SRGB_PACK32),
+    // UNORM formats need manual encoding in shaders
+    FormatPreference::srgb_nonlinear(br::vk::VK_FORMAT_B8G8R8A8_UNORM),
+    FormatPreference::srgb_nonlinear(br::vk::VK_FORMAT_R8G8B8A8_UNORM),
+];
+const HDR10_PREFERENCES: &[FormatPreference] = &[
+    FormatPreference {
+        format: br::vk::VK_FORMAT_A2B10G10R10_UNORM_PACK32,
+        color_space: br::vk::VK_COLOR_SPACE_HDR10_ST2084_EXT,
+    },
+    FormatPreference {
+        format: br::vk::VK_FORMAT_A2R10G10B10_UNORM_PACK32,
+        color_space: br::vk::VK_COLOR_SPACE_HDR10_ST2084_EXT,
+    },
+    FormatPreference {
+        format: br::vk::VK_FORMAT_R16G16B16A16_SFLOAT,
+        color_space: br::vk::VK_COLOR_SPACE_HDR10_ST2084_EXT,
+    },
+];
+const SCRGB_PREFERENCES: &[FormatPreference] = &[FormatPreference {
+    format: br::vk::VK_FORMAT_R16G16B16A16_SFLOAT,
+    color_space: br::vk::VK_COLOR_SPACE_EXTENDED_SRGB_LINEAR_EXT,
+}];
+
+/// Ranked list of (format, color space) pairs. Earlier is preferred.
+#[derive(Clone, Debug)]
+pub struct FormatNegotiation {
+    pub preferences: Vec<FormatPreference>,
+}
+impl Default for FormatNegotiation {
+    fn default() -> Self {
+        Self::for_output(OutputColorSpace::Srgb)
+    }
+}
+impl FormatNegotiation {
+    /// Preferences for the output color space. HDR outputs fall back to sRGB.
+    pub fn for_output(output: OutputColorSpace) -> Self {
+        let preferences = match output {
+            OutputColorSpace::Srgb => SRGB_PREFERENCES.to_vec(),
+            OutputColorSpace::Hdr10 => [HDR10_PREFERENCES, SRGB_PREFERENCES].concat(),
+            OutputColorSpace::ScRgb => [SCRGB_PREFERENCES, SRGB_PREFERENCES].concat(),
+        };
+
+        Self { preferences }
+    }
+
+    /// Picks the most preferred format supported by the surface.
+    ///
+    /// If nothing in the list is supported, any sRGB nonlinear format is used, then the first one.
+    pub fn negotiate(
+        &self,
+        supported: &[br::vk::VkSurfaceFormatKHR],
+    ) -> Option<br::vk::VkSurfaceFormatKHR> {
+        if let [single] = supported {
+            if single.format == br::vk::VK_FORMAT_UNDEFINED {
+                // surface has no preferred format
+                return self
+                    .preferences
+                    .first()
+                    .map(|p| br::vk::VkSurfaceFormatKHR {
+                        format: p.format,
+                        colorSpace: p.color_space,
+                    });
+            }
+        }
+
+        self.preferences
+            .iter()
+            .find_map(|p| {
+                supported
+                    .iter()
+                    .find(|f| f.format == p.format && f.colorSpace == p.color_space)
+            })
+            .or_else(|| {
+                supported
+                    .iter()
+                    .find(|f| f.colorSpace == br::vk::VK_COLOR_SPACE_SRGB_NONLINEAR_KHR)
+            })
+            .or_else(|| supported.first())
+            .cloned()
+    }
+}
+
+/// true if the hardware performs linear -> sRGB encoding on writes to the format
+pub fn is_srgb_format(format: br::vk::VkFormat) -> bool {
+    matches!(
+        format,
+        br::vk::VK_FORMAT_B8G8R8A8_SRGB
+            | br::vk::VK_FORMAT_R8G8B8A8_SRGB
+            | br::vk::VK_FORMAT_A8B8G8R8_SRGB_PACK32
+            | br::vk::VK_FORMAT_B8G8R8_SRGB
+            | br::vk::VK_FORMAT_R8G8B8_SRGB
+    )
+}
+
+/// linear -> sRGB nonlinear transfer function
+pub fn encode_srgb(linear: f32) -> f32 {
+    if linear <= 0.0031308 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// linear -> ST.2084(PQ) encoding, mapping 1.0 to `sdr_white_nits`.
+pub fn encode_pq(linear: f32, sdr_white_nits: f32) -> f32 {
+    const M1: f32 = 2610.0 / 16384.0;
+    const M2: f32 = 2523.0 / 4096.0 * 128.0;
+    const C1: f32 = 3424.0 / 4096.0;
+    const C2: f32 = 2413.0 / 4096.0 * 32.0;
+    const C3: f32 = 2392.0 / 4096.0 * 32.0;
+
+    let y = (linear * sdr_white_nits / 10000.0).max(0.0).powf(M1);
+    ((C1 + C2 * y) / (1.0 + C3 * y)).powf(M2)
+}
+
+/// BT.709 primaries -> BT.2020 primaries(both linear)
+pub fn bt709_to_bt2020(rgb: [f32; 3]) -> [f32; 3] {
+    [
+        0.6274 * rgb[0] + 0.3293 * rgb[1] + 0.0433 * rgb[2],
+        0.0691 * rgb[0] + 0.9195 * rgb[1] + 0.0114 * rgb[2],
+        0.0164 * rgb[0] + 0.0880 * rgb[1] + 0.8956 * rgb[2],
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn surface_format(
+        format: br::vk::VkFormat,
+        color_space: br::vk::VkColorSpaceKHR,
+    ) -> br::vk::VkSurfaceFormatKHR {
+        br::vk::VkSurfaceFormatKHR {
+            format,
+            colorSpace: color_space,
+        }
+    }
+
+    fn negotiate(
+        output: OutputColorSpace,
+        supported: &[br::vk::VkSurfaceFormatKHR],
+    ) -> Option<(br::vk::VkFormat, br::vk::VkColorSpaceKHR)> {
+        FormatNegotiation::for_output(output)
+            .negotiate(supported)
+            .map(|f| (f.format, f.colorSpace))
+    }
+
+    #[test]
+    fn srgb_format_is_preferred_over_unorm() {
+        let supported = [
+            surface_format(
+                br::vk::VK_FORMAT_B8G8R8A8_UNORM,
+                br::vk::VK_COLOR_SPACE_SRGB_NONLINEAR_KHR,
+            ),
+            surface_format(
+                br::vk::VK_FORMAT_R8G8B8A8_SRGB,
+                br::vk::VK_COLOR_SPACE_SRGB_NONLINEAR_KHR,
+            ),
+        ];
+
+        assert_eq!(
+            negotiate(OutputColorSpace::Srgb, &supported),
+            Some((
+                br::vk::VK_FORMAT_R8G8B8A8_SRGB,
+                br::vk::VK_COLOR_SPACE_SRGB_NONLINEAR_KHR
+            ))
+        );
+    }
+
+    #[test]
+    fn undefined_format_means_any() {
+        let supported = [surface_format(
+            br::vk::VK_FORMAT_UNDEFINED,
+            br::vk::VK_COLOR_SPACE_SRGB_NONLINEAR_KHR,
+        )];
+
+        assert_eq!(
+            negotiate(OutputColorSpace::Srgb, &supported),
+            Some((
+                br::vk::VK_FORMAT_B8G8R8A8_SRGB,
+                br::vk::VK_COLOR_SPACE_SRGB_NONLINEAR_KHR
+            ))
+        );
+    }
+
+    #[test]
+    fn hdr_output_falls_back_to_srgb() {
+        let supported = [surface_format(
+            br::vk::VK_FORMAT_B8G8R8A8_SRGB,
+            br::vk::VK_COLOR_SPACE_SRGB_NONLINEAR_KHR,
+        )];
+
+        assert_eq!(
+            negotiate(OutputColorSpace::Hdr10, &supported),
+            Some((
+                br::vk::VK_FORMAT_B8G8R8A8_SRGB,
+                br::vk::VK_COLOR_SPACE_SRGB_NONLINEAR_KHR
+            ))
+        );
+    }
+
+    #[test]
+    fn hdr_output_picks_hdr_format() {
+        let supported = [
+            surface_format(
+                br::vk::VK_FORMAT_B8G8R8A8_SRGB,
+                br::vk::VK_COLOR_SPACE_SRGB_NONLINEAR_KHR,
+            ),
+            surface_format(
+                br::vk::VK_FORMAT_A2B10G10R10_UNORM_PACK32,
+                br::vk::VK_COLOR_SPACE_HDR10_ST2084_EXT,
+            ),
+        ];
+
+        assert_eq!(
+            negotiate(OutputColorSpace::Hdr10, &supported),
+            Some((
+                br::vk::VK_FORMAT_A2B10G10R10_UNORM_PACK32,
+                br::vk::VK_COLOR_SPACE_HDR10_ST2084_EXT
+            ))
+        );
+    }
+
+    #[test]
+    fn unknown_formats_fall_back_to_srgb_nonlinear_then_first() {
+        let srgb_nonlinear = [
+            surface_format(
+                br::vk::VK_FORMAT_R16G16B16A16_SFLOAT,
+                br::vk::VK_COLOR_SPACE_EXTENDED_SRGB_LINEAR_EXT,
+            ),
+            surface_format(
+                br::vk::VK_FORMAT_R5G6B5_UNORM_PACK16,
+                br::vk::VK_COLOR_SPACE_SRGB_NONLINEAR_KHR,
+            ),
+        ];
+        assert_eq!(
+            negotiate(OutputColorSpace::Srgb, &srgb_nonlinear),
+            Some((
+                br::vk::VK_FORMAT_R5G6B5_UNORM_PACK16,
+                br::vk::VK_COLOR_SPACE_SRGB_NONLINEAR_KHR
+            ))
+        );
+
+        let others = [surface_format(
+            br::vk::VK_FORMAT_R16G16B16A16_SFLOAT,
+            br::vk::VK_COLOR_SPACE_EXTENDED_SRGB_LINEAR_EXT,
+        )];
+        assert_eq!(
+            negotiate(OutputColorSpace::Srgb, &others),
+            Some((
+                br::vk::VK_FORMAT_R16G16B16A16_SFLOAT,
+                br::vk::VK_COLOR_SPACE_EXTENDED_SRGB_LINEAR_EXT
+            ))
+        );
+    }
+
+    #[test]
+    fn no_formats() {
+        assert_eq!(negotiate(OutputColorSpace::Srgb, &[]), None);
+    }
+
+    #[test]
+    fn unorm_formats_are_encoded_in_shaders() {
+        assert_eq!(
+            OutputEncoding::new(OutputColorSpace::Srgb, br::vk::VK_FORMAT_B8G8R8A8_SRGB),
+            OutputEncoding::Linear
+        );
+        assert_eq!(
+            OutputEncoding::new(OutputColorSpace::Srgb, br::vk::VK_FORMAT_B8G8R8A8_UNORM),
+            OutputEncoding::Srgb
+        );
+        assert_eq!(
+            OutputEncoding::new(
+                OutputColorSpace::ScRgb,
+                br::vk::VK_FORMAT_R16G16B16A16_SFLOAT
+            ),
+            OutputEncoding::Linear
+        );
+        assert_eq!(
+            OutputEncoding::new(
+                OutputColorSpace::Hdr10,
+                br::vk::VK_FORMAT_A2B10G10R10_UNORM_PACK32
+            ),
+            OutputEncoding::Hdr10
+        );
+    }
+
+    #[test]
+    fn output_encodings() {
+        let color = [0.5, 0.0, 1.0, 0.25];
+        assert_eq!(OutputEncoding::Linear.encode(color), color);
+
+        let [r, g, b, a] = OutputEncoding::Srgb.encode(color);
+        assert!((r - 0.7354).abs() < 1e-3);
+        assert_eq!([g, b, a], [0.0, 1.0, 0.25]);
+
+        // SDR white is HDR_SDR_WHITE_NITS(200 nits: about 0.58 in PQ)
+        let [r, g, b, a] = OutputEncoding::Hdr10.encode([1.0, 1.0, 1.0, 1.0]);
+        assert!((r - g).abs() < 1e-3 && (g - b).abs() < 1e-3);
+        assert!((r - 0.5791).abs() < 1e-3);
+        assert_eq!(a, 1.0);
+    }
+}
@@ -0,0 +1,208 @@
+//! Sampled images and samplers.
+
+use std::sync::Arc;
+
+use bedrock::{self as br, ImageSubresourceSlice};
+
+use crate::{asset::texture::TextureData, error::EngineError};
+
+use super::{
+    memory::{MemoryAllocation, MemoryAllocator, MemoryLocation},
+    upload::{ImageUploadRegion, UploadQueue},
+};
+
+pub type TextureView<'d, Device> = br::ImageViewObject<Arc<br::ImageObject<&'d Device>>>;
+
+/// A 2D image sampled by shaders, left in `SHADER_READ_ONLY_OPTIMAL` after the upload.
+pub struct Texture<'d, Device: br::Device + ?Sized + 'd> {
+    format: br::vk::VkFormat,
+    extent: br::vk::VkExtent2D,
+    mip_levels: u32,
+    // Note: メモリより先にイメージ(ビュー)を破棄する
+    view: TextureView<'d, Device>,
+    _allocation: MemoryAllocation<'d, Device>,
+}
+impl<'d, Device: br::Device + ?Sized + 'd> Texture<'d, Device> {
+    /// Creates the image and requests uploads of the texels.
+    ///
+    /// If `data` requests mip generation, the chain is generated by blits when `linear_blit` is
+    /// true(the format supports linear filtered blits). Otherwise only the stored levels are used.
+    /// The texture can be sampled in submissions after the next [`Engine::flush_uploads`].
+    ///
+    /// [`Engine::flush_uploads`]: crate::game::Engine::flush_uploads
+    pub fn new(
+        allocator: &MemoryAllocator<'d, Device>,
+        upload_queue: &UploadQueue<'d, Device>,
+        data: TextureData,
+        linear_blit: bool,
+    ) -> Result<Self, EngineError> {
+        let generated_mip_levels = if data.generate_mips && linear_blit {
+            data.full_mip_levels() - data.levels.len() as u32
+        } else {
+            0
+        };
+        let mip_levels = data.levels.len() as u32 + generated_mip_levels;
+
+        let mut usage = br::ImageUsageFlags::SAMPLED.transfer_dest();
+        if generated_mip_levels > 0 {
+            usage = usage.transfer_src();
+        }
+        let (image, allocation) = allocator
+            .create_image(
+                br::ImageDesc::new(data.extent, data.format, usage, br::ImageLayout::Undefined)
+                    .mip_levels(mip_levels),
+                MemoryLocation::DeviceLocal,
+            )?
+            .into_parts();
+        let image = Arc::new(image);
+
+        let last_level = data.levels.len() - 1;
+        // texels are moved into the upload requests
+        for (level, texels) in data.levels.into_iter().enumerate() {
+            let region = ImageUploadRegion {
+                mip_level: level as _,
+                ..ImageUploadRegion::whole_2d(br::vk::VkExtent2D {
+                    width: (data.extent.width >> level).max(1),
+                    height: (data.extent.height >> level).max(1),
+                })
+            };
+            let generated = if level == last_level {
+                generated_mip_levels
+            } else {
+                0
+            };
+
+            upload_queue.upload_image_with_mips(
+                image.clone(),
+                region,
+                generated,
+                br::ImageLayout::Undefined,
+                br::ImageLayout::ShaderReadOnlyOpt,
+                texels,
+            );
+        }
+
+        let view = image
+            .subresource_range(br::AspectMask::COLOR, 0..mip_levels, 0..1)
+            .view_builder()
+            .create()?;
+
+        Ok(Self {
+            format: data.format,
+            extent: data.extent,
+            mip_levels,
+            view,
+            _allocation: allocation,
+        })
+    }
+
+    pub fn format(&self) -> br::vk::VkFormat {
+        self.format
+    }
+
+    pub fn extent(&self) -> br::vk::VkExtent2D {
+        self.extent
+    }
+
+    pub fn mip_levels(&self) -> u32 {
+        self.mip_levels
+    }
+
+    /// View of every mip level
+    pub fn view(&self) -> &TextureView<'d, Device> {
+        &self.view
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Filter {
+    Nearest,
+    Linear,
+}
+impl Filter {
+    fn filter_mode(self) -> br::FilterMode {
+        match self {
+            Self::Nearest => br::FilterMode::Nearest,
+            Self::Linear => br::FilterMode::Linear,
+        }
+    }
+
+    fn mipmap_mode(self) -> br::MipmapFilterMode {
+        match self {
+            Self::Nearest => br::MipmapFilterMode::Nearest,
+            Self::Linear => br::MipmapFilterMode::Linear,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressMode {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+    /// Opaque black outside of the image
+    ClampToBorder,
+}
+impl AddressMode {
+    fn addressing_mode(self) -> br::AddressingMode {
+        match self {
+            Self::Repeat => br::AddressingMode::Repeat,
+            Self::MirroredRepeat => br::AddressingMode::MirroredRepeat,
+            Self::ClampToEdge => br::AddressingMode::ClampToEdge,
+            Self::ClampToBorder => br::AddressingMode::ClampToBorder,
+        }
+    }
+}
+
+/// Sampler configuration. Trilinear filtering with repeat addressing by default.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SamplerDesc {
+    pub mag_filter: Filter,
+    pub min_filter: Filter,
+    /// Filter between mip levels
+    pub mipmap_filter: Filter,
+    /// For U, V and W coordinates
+    pub address_mode: [AddressMode; 3],
+    /// Upper bound of the sampled mip level(covers every level by default)
+    pub max_lod: f32,
+}
+impl Default for SamplerDesc {
+    fn default() -> Self {
+        Self {
+            mag_filter: Filter::Linear,
+            min_filter: Filter::Linear,
+            mipmap_filter: Filter::Linear,
+            address_mode: [AddressMode::Repeat; 3],
+            max_lod: br::vk::VK_LOD_CLAMP_NONE,
+        }
+    }
+}
+impl SamplerDesc {
+    /// Same filter and address mode for every direction
+    pub fn new(filter: Filter, address_mode: AddressMode) -> Self {
+        Self {
+            mag_filter: filter,
+            min_filter: filter,
+            mipmap_filter: filter,
+            address_mode: [address_mode; 3],
+            ..Default::default()
+        }
+    }
+
+    pub fn create<'d, Device: br::Device + ?Sized>(
+        &self,
+        device: &'d Device,
+    ) -> br::Result<br::SamplerObject<&'d Device>> {
+        let [u, v, w] = self.address_mode.map(AddressMode::addressing_mode);
+        let mut builder = br::SamplerBuilder::default();
+        builder
+            .mag_filter(self.mag_filter.filter_mode())
+            .min_filter(self.min_filter.filter_mode())
+            .mip_filter(self.mipmap_filter.mipmap_mode())
+            .addressing(u, v, w)
+            .border_color(br::BorderColor::OpaqueBlackF)
+            .lod_range(0.0..self.max_lod);
+
+        builder.create(device)
+    }
+}
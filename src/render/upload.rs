@@ -0,0 +1,686 @@
+//! Staging upload queue.
+//!
+//! Uploads can be requested from any task through [`UploadQueue`]. The engine packs pending
+//! requests into a ring staging buffer and submits them in one batch on [`Engine::flush_uploads`],
+//! without waiting for the completion. Completions are observed by polling fences on later flushes.
+//!
+//! [`Engine::flush_uploads`]: crate::game::Engine::flush_uploads
+
+use std::{
+    collections::VecDeque,
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
+use bedrock::{self as br, CommandBufferMut, CommandPoolMut, Status, VkHandle, VulkanStructure};
+
+use crate::error::EngineError;
+
+use super::memory::{AllocatedBuffer, MemoryAllocator, MemoryLocation};
+
+pub const DEFAULT_STAGING_BUFFER_SIZE: u64 = 16 << 20;
+/// Covers every texel/block size of uncompressed(1/2/4/8/16 bytes) and block compressed formats
+const STAGING_ALIGNMENT: u64 = 16;
+
+/// Destination of an image upload. Only the color aspect is supported.
+#[derive(Clone, Debug)]
+pub struct ImageUploadRegion {
+    pub mip_level: u32,
+    pub array_layers: Range<u32>,
+    pub offset: br::vk::VkOffset3D,
+    pub extent: br::vk::VkExtent3D,
+}
+impl ImageUploadRegion {
+    /// The whole mip level 0 of a 2D image
+    pub fn whole_2d(extent: br::vk::VkExtent2D) -> Self {
+        Self {
+            mip_level: 0,
+            array_layers: 0..1,
+            offset: br::vk::VkOffset3D { x: 0, y: 0, z: 0 },
+            extent: br::vk::VkExtent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            },
+        }
+    }
+
+    fn subresource_range(&self, mip_levels: Range<u32>) -> br::vk::VkImageSubresourceRange {
+        br::vk::VkImageSubresourceRange {
+            aspectMask: br::vk::VK_IMAGE_ASPECT_COLOR_BIT,
+            baseMipLevel: mip_levels.start,
+            levelCount: mip_levels.len() as _,
+            baseArrayLayer: self.array_layers.start,
+            layerCount: self.array_layers.len() as _,
+        }
+    }
+
+    fn subresource_layers(&self, mip_level: u32) -> br::vk::VkImageSubresourceLayers {
+        br::vk::VkImageSubresourceLayers {
+            aspectMask: br::vk::VK_IMAGE_ASPECT_COLOR_BIT,
+            mipLevel: mip_level,
+            baseArrayLayer: self.array_layers.start,
+            layerCount: self.array_layers.len() as _,
+        }
+    }
+}
+
+fn image_barrier(
+    image: br::vk::VkImage,
+    subresource_range: br::vk::VkImageSubresourceRange,
+    (old_layout, new_layout): (br::ImageLayout, br::ImageLayout),
+    (src_access, dst_access): (br::vk::VkAccessFlags, br::vk::VkAccessFlags),
+) -> br::vk::VkImageMemoryBarrier {
+    br::vk::VkImageMemoryBarrier {
+        sType: br::vk::VkImageMemoryBarrier::TYPE,
+        pNext: core::ptr::null(),
+        srcAccessMask: src_access,
+        dstAccessMask: dst_access,
+        oldLayout: old_layout as _,
+        newLayout: new_layout as _,
+        srcQueueFamilyIndex: br::vk::VK_QUEUE_FAMILY_IGNORED,
+        dstQueueFamilyIndex: br::vk::VK_QUEUE_FAMILY_IGNORED,
+        image,
+        subresourceRange: subresource_range,
+    }
+}
+
+enum UploadDestination<'d, Device: br::Device + ?Sized + 'd> {
+    Buffer {
+        buffer: Arc<AllocatedBuffer<'d, Device>>,
+        offset: u64,
+    },
+    Image {
+        image: Arc<br::ImageObject<&'d Device>>,
+        region: ImageUploadRegion,
+        old_layout: br::ImageLayout,
+        new_layout: br::ImageLayout,
+        /// Number of mip levels following the region to be generated by blits
+        generated_mip_levels: u32,
+    },
+}
+
+struct PendingUpload<'d, Device: br::Device + ?Sized + 'd> {
+    data: Vec<u8>,
+    destination: UploadDestination<'d, Device>,
+    completion: async_std::channel::Sender<()>,
+}
+
+/// Resolves when the upload has been completed on the device.
+pub struct UploadCompletion(async_std::channel::Receiver<()>);
+impl UploadCompletion {
+    /// Returns false if the upload has been abandoned(e.g. the engine has gone before submitting it).
+    pub async fn wait(self) -> bool {
+        self.0.recv().await.is_ok()
+    }
+}
+
+/// Request side of the upload service. Cheap to clone and can be sent to other tasks.
+pub struct UploadQueue<'d, Device: br::Device + ?Sized + 'd> {
+    pending: Arc<Mutex<VecDeque<PendingUpload<'d, Device>>>>,
+}
+impl<'d, Device: br::Device + ?Sized + 'd> Clone for UploadQueue<'d, Device> {
+    fn clone(&self) -> Self {
+        Self {
+            pending: self.pending.clone(),
+        }
+    }
+}
+impl<'d, Device: br::Device + ?Sized + 'd> UploadQueue<'d, Device> {
+    fn new() -> Self {
+        Self {
+            pending: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    fn push(&self, data: Vec<u8>, destination: UploadDestination<'d, Device>) -> UploadCompletion {
+        let (completion, receiver) = async_std::channel::bounded(1);
+        if data.is_empty() {
+            // nothing to copy(zero sized copies are not allowed)
+            let _ = completion.try_send(());
+            return UploadCompletion(receiver);
+        }
+
+        self.pending
+            .lock()
+            .expect("Poisoned")
+            .push_back(PendingUpload {
+                data,
+                destination,
+                completion,
+            });
+
+        UploadCompletion(receiver)
+    }
+
+    /// Writes `data` into the buffer at `offset`.
+    ///
+    /// The written range becomes visible to vertex inputs, uniform/storage reads and shader reads of
+    /// later submissions.
+    pub fn upload_buffer(
+        &self,
+        buffer: Arc<AllocatedBuffer<'d, Device>>,
+        offset: u64,
+        data: impl Into<Vec<u8>>,
+    ) -> UploadCompletion {
+        self.push(data.into(), UploadDestination::Buffer { buffer, offset })
+    }
+
+    /// Writes tightly packed texels into the region, then generates `generated_mip_levels` levels
+    /// following the region's level by successive linear blits.
+    ///
+    /// The format must support `BLIT_SRC`, `BLIT_DST` and `SAMPLED_IMAGE_FILTER_LINEAR` features,
+    /// and the image must be created with `TRANSFER_SRC` usage. Every level is transitioned from
+    /// `old_layout` to `new_layout`.
+    pub fn upload_image_with_mips(
+        &self,
+        image: Arc<br::ImageObject<&'d Device>>,
+        region: ImageUploadRegion,
+        generated_mip_levels: u32,
+        old_layout: br::ImageLayout,
+        new_layout: br::ImageLayout,
+        data: impl Into<Vec<u8>>,
+    ) -> UploadCompletion {
+        self.push(
+            data.into(),
+            UploadDestination::Image {
+                image,
+                region,
+                old_layout,
+                new_layout,
+                generated_mip_levels,
+            },
+        )
+    }
+}
+
+/// Space management of the ring staging buffer. Ranges are reserved at the head and released
+/// from the tail in the same order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct StagingRing {
+    capacity: u64,
+    head: u64,
+    tail: u64,
+    /// Reserved bytes(including the skipped end of the ring on wrap-around)
+    used: u64,
+}
+impl StagingRing {
+    const fn new(capacity: u64) -> Self {
+        Self {
+            capacity,
+            head: 0,
+            tail: 0,
+            used: 0,
+        }
+    }
+
+    /// true if a request of the size never fits in the ring
+    const fn exceeds_capacity(&self, size: u64) -> bool {
+        size.div_ceil(STAGING_ALIGNMENT) * STAGING_ALIGNMENT > self.capacity
+    }
+
+    /// Reserves a range. None if there is no room until some ranges are released.
+    fn allocate(&mut self, size: u64) -> Option<u64> {
+        let size = size.div_ceil(STAGING_ALIGNMENT) * STAGING_ALIGNMENT;
+        if self.used == 0 {
+            self.head = 0;
+            self.tail = 0;
+        }
+        if self.used + size > self.capacity {
+            return None;
+        }
+
+        if self.head >= self.tail {
+            // free: [head, capacity) and [0, tail)
+            if self.head + size <= self.capacity {
+                let offset = self.head;
+                self.head += size;
+                self.used += size;
+                return Some(offset);
+            }
+            if size <= self.tail {
+                // skip the tail end of the ring
+                self.used += self.capacity - self.head + size;
+                self.head = size;
+                return Some(0);
+            }
+
+            None
+        } else if self.head + size <= self.tail {
+            let offset = self.head;
+            self.head += size;
+            self.used += size;
+            Some(offset)
+        } else {
+            None
+        }
+    }
+
+    /// Releases the oldest `bytes` reserved bytes, which end at `end`(the head after them).
+    fn release(&mut self, end: u64, bytes: u64) {
+        self.tail = end;
+        self.used -= bytes;
+    }
+}
+
+enum StagingSource<'d, Device: br::Device + ?Sized + 'd> {
+    Ring(u64),
+    /// larger than the whole ring
+    Dedicated(AllocatedBuffer<'d, Device>),
+}
+
+struct InFlightBatch<'d, Device: br::Device + ?Sized + 'd> {
+    fence: br::FenceObject<&'d Device>,
+    _command_pool: br::CommandPoolObject<&'d Device>,
+    /// ring head after this batch
+    ring_end: u64,
+    /// ring bytes consumed by this batch(including the wrap-around padding)
+    ring_bytes: u64,
+    // Note: 転送が完了するまで転送元と転送先を生かしておく
+    _dedicated_staging: Vec<AllocatedBuffer<'d, Device>>,
+    _destinations: Vec<UploadDestination<'d, Device>>,
+    completions: Vec<async_std::channel::Sender<()>>,
+}
+
+/// Engine side of the upload service: owns the staging ring and in-flight batches.
+pub struct UploadProcessor<'d, Device: br::Device + ?Sized + 'd> {
+    device: &'d Device,
+    queue_family_index: u32,
+    allocator: MemoryAllocator<'d, Device>,
+    requests: UploadQueue<'d, Device>,
+    in_flight: VecDeque<InFlightBatch<'d, Device>>,
+    staging: AllocatedBuffer<'d, Device>,
+    ring: StagingRing,
+}
+impl<'d, Device: br::Device + ?Sized + 'd> UploadProcessor<'d, Device> {
+    pub fn new(
+        device: &'d Device,
+        queue_family_index: u32,
+        allocator: MemoryAllocator<'d, Device>,
+        staging_buffer_size: u64,
+    ) -> Result<Self, EngineError> {
+        let staging = allocator.create_buffer(
+            br::BufferDesc::new(staging_buffer_size as _, br::BufferUsage::TRANSFER_SRC),
+            MemoryLocation::HostVisible,
+        )?;
+
+        Ok(Self {
+            device,
+            queue_family_index,
+            allocator,
+            requests: UploadQueue::new(),
+            in_flight: VecDeque::new(),
+            staging,
+            ring: StagingRing::new(staging_buffer_size),
+        })
+    }
+
+    pub fn queue(&self) -> &UploadQueue<'d, Device> {
+        &self.requests
+    }
+
+    /// Retires completed batches and notifies their completions.
+    pub fn poll(&mut self) -> Result<(), EngineError> {
+        while let Some(b) = self.in_flight.front() {
+            if !b.fence.status()? {
+                break;
+            }
+
+            let b = self.in_flight.pop_front().expect("no batch");
+            // batches complete in submission order
+            self.ring.release(b.ring_end, b.ring_bytes);
+            for c in b.completions {
+                // receiver may have been dropped
+                let _ = c.try_send(());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Submits pending requests in one batch. Requests which don't fit in the staging ring are
+    /// deferred to later flushes(in order).
+    ///
+    /// If the submission fails, the requests are put back to the queue and retried on the next
+    /// flush.
+    pub fn flush(&mut self, q: &mut br::QueueObject<&'d Device>) -> Result<(), EngineError> {
+        self.poll()?;
+
+        let ring_before = self.ring;
+        let mut uploads = Vec::new();
+        let result = self
+            .stage_pending(&mut uploads)
+            .and_then(|_| self.submit_staged(q, &mut uploads, ring_before.used));
+        if result.is_err() {
+            self.ring = ring_before;
+            let mut pending = self.requests.pending.lock().expect("Poisoned");
+            for (_, p) in uploads.into_iter().rev() {
+                pending.push_front(p);
+            }
+        }
+
+        result
+    }
+
+    /// Takes pending requests and copies them into staging buffers.
+    fn stage_pending(
+        &mut self,
+        uploads: &mut Vec<(StagingSource<'d, Device>, PendingUpload<'d, Device>)>,
+    ) -> Result<(), EngineError> {
+        let pending = self.requests.pending.clone();
+        let mut pending = pending.lock().expect("Poisoned");
+        while let Some(p) = pending.front() {
+            let size = p.data.len() as u64;
+            let source = if self.ring.exceeds_capacity(size) {
+                let buffer = self.allocator.create_buffer(
+                    br::BufferDesc::new(size as _, br::BufferUsage::TRANSFER_SRC),
+                    MemoryLocation::HostVisible,
+                )?;
+                unsafe {
+                    buffer.write_mapped_bytes(0, &p.data);
+                }
+
+                StagingSource::Dedicated(buffer)
+            } else {
+                let Some(offset) = self.ring.allocate(size) else {
+                    break;
+                };
+                unsafe {
+                    self.staging.write_mapped_bytes(offset as _, &p.data);
+                }
+
+                StagingSource::Ring(offset)
+            };
+
+            let p = pending.pop_front().expect("no pending request");
+            uploads.push((source, p));
+        }
+
+        Ok(())
+    }
+
+    /// Records and submits the staged uploads. `uploads` are moved into the in-flight batch only if
+    /// the submission succeeds.
+    fn submit_staged(
+        &mut self,
+        q: &mut br::QueueObject<&'d Device>,
+        uploads: &mut Vec<(StagingSource<'d, Device>, PendingUpload<'d, Device>)>,
+        used_before: u64,
+    ) -> Result<(), EngineError> {
+        if uploads.is_empty() {
+            return Ok(());
+        }
+
+        let mut command_pool = br::CommandPoolBuilder::new(self.queue_family_index)
+            .transient()
+            .create(self.device)?;
+        let [mut cb] = command_pool.alloc_array::<1>(true)?;
+        let image_barriers = |after_copy: bool| {
+            uploads
+                .iter()
+                .flat_map(|(_, p)| match &p.destination {
+                    UploadDestination::Image {
+                        image,
+                        region,
+                        old_layout,
+                        new_layout,
+                        generated_mip_levels,
+                    } => {
+                        let last_level = region.mip_level + generated_mip_levels;
+                        if !after_copy {
+                            return vec![image_barrier(
+                                image.native_ptr(),
+                                region.subresource_range(region.mip_level..last_level + 1),
+                                (*old_layout, br::ImageLayout::TransferDestOpt),
+                                (0, br::AccessFlags::TRANSFER.write),
+                            )];
+                        }
+
+                        // levels used as blit sources have been transitioned to TransferSrcOpt
+                        let mut barriers = vec![image_barrier(
+                            image.native_ptr(),
+                            region.subresource_range(last_level..last_level + 1),
+                            (br::ImageLayout::TransferDestOpt, *new_layout),
+                            (
+                                br::AccessFlags::TRANSFER.write,
+                                br::AccessFlags::SHADER.read,
+                            ),
+                        )];
+                        if *generated_mip_levels > 0 {
+                            barriers.push(image_barrier(
+                                image.native_ptr(),
+                                region.subresource_range(region.mip_level..last_level),
+                                (br::ImageLayout::TransferSrcOpt, *new_layout),
+                                (br::AccessFlags::TRANSFER.read, br::AccessFlags::SHADER.read),
+                            ));
+                        }
+
+                        barriers
+                    }
+                    UploadDestination::Buffer { .. } => Vec::new(),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // previous reads of the destinations must be finished before overwriting
+        let mut rec = unsafe { cb.begin_once(self.device)? }.pipeline_barrier(
+            br::PipelineStageFlags::ALL_COMMANDS,
+            br::PipelineStageFlags::TRANSFER,
+            false,
+            &[],
+            &[],
+            &image_barriers(false),
+        );
+        for (source, p) in uploads.iter() {
+            let (src, src_offset) = match source {
+                StagingSource::Ring(offset) => (&self.staging, *offset),
+                StagingSource::Dedicated(buffer) => (buffer, 0),
+            };
+
+            rec = match &p.destination {
+                UploadDestination::Buffer { buffer, offset } => rec.copy_buffer(
+                    src.buffer(),
+                    buffer.buffer(),
+                    &[br::BufferCopy::new(src_offset, *offset, p.data.len() as _)],
+                ),
+                UploadDestination::Image { image, region, .. } => rec.copy_buffer_to_image(
+                    src.buffer(),
+                    &**image,
+                    br::ImageLayout::TransferDestOpt,
+                    &[br::vk::VkBufferImageCopy {
+                        bufferOffset: src_offset,
+                        // tightly packed
+                        bufferRowLength: 0,
+                        bufferImageHeight: 0,
+                        imageSubresource: region.subresource_layers(region.mip_level),
+                        imageOffset: region.offset.clone(),
+                        imageExtent: region.extent.clone(),
+                    }],
+                ),
+            };
+        }
+        for (_, p) in uploads.iter() {
+            let UploadDestination::Image {
+                image,
+                region,
+                generated_mip_levels,
+                ..
+            } = &p.destination
+            else {
+                continue;
+            };
+
+            let mut extent = (region.extent.width as i32, region.extent.height as i32);
+            for level in region.mip_level + 1..=region.mip_level + generated_mip_levels {
+                let next = ((extent.0 / 2).max(1), (extent.1 / 2).max(1));
+                rec = rec
+                    .pipeline_barrier(
+                        br::PipelineStageFlags::TRANSFER,
+                        br::PipelineStageFlags::TRANSFER,
+                        false,
+                        &[],
+                        &[],
+                        &[image_barrier(
+                            image.native_ptr(),
+                            region.subresource_range(level - 1..level),
+                            (
+                                br::ImageLayout::TransferDestOpt,
+                                br::ImageLayout::TransferSrcOpt,
+                            ),
+                            (
+                                br::AccessFlags::TRANSFER.write,
+                                br::AccessFlags::TRANSFER.read,
+                            ),
+                        )],
+                    )
+                    .blit_image(
+                        &**image,
+                        br::ImageLayout::TransferSrcOpt,
+                        &**image,
+                        br::ImageLayout::TransferDestOpt,
+                        &[br::vk::VkImageBlit {
+                            srcSubresource: region.subresource_layers(level - 1),
+                            srcOffsets: [
+                                br::vk::VkOffset3D { x: 0, y: 0, z: 0 },
+                                br::vk::VkOffset3D {
+                                    x: extent.0,
+                                    y: extent.1,
+                                    z: 1,
+                                },
+                            ],
+                            dstSubresource: region.subresource_layers(level),
+                            dstOffsets: [
+                                br::vk::VkOffset3D { x: 0, y: 0, z: 0 },
+                                br::vk::VkOffset3D {
+                                    x: next.0,
+                                    y: next.1,
+                                    z: 1,
+                                },
+                            ],
+                        }],
+                        br::FilterMode::Linear,
+                    );
+                extent = next;
+            }
+        }
+        rec.pipeline_barrier(
+            br::PipelineStageFlags::TRANSFER,
+            br::PipelineStageFlags::ALL_COMMANDS,
+            false,
+            &[br::vk::VkMemoryBarrier {
+                sType: br::vk::VkMemoryBarrier::TYPE,
+                pNext: core::ptr::null(),
+                srcAccessMask: br::AccessFlags::TRANSFER.write,
+                dstAccessMask: br::AccessFlags::VERTEX_ATTRIBUTE_READ
+                    | br::AccessFlags::INDEX_READ
+                    | br::AccessFlags::UNIFORM_READ
+                    | br::AccessFlags::SHADER.read,
+            }],
+            &[],
+            &image_barriers(true),
+        )
+        .end()?;
+
+        let mut fence = br::FenceBuilder::new().create(self.device)?;
+        q.submit_alt3(
+            &[br::SubmissionBatch3::new_wait_semaphore_array(
+                &[],
+                &[],
+                &[cb.as_transparent_ref()],
+                &[],
+            )],
+            Some(fence.as_transparent_mut_ref()),
+        )?;
+
+        let mut dedicated_staging = Vec::new();
+        let mut destinations = Vec::with_capacity(uploads.len());
+        let mut completions = Vec::with_capacity(uploads.len());
+        for (source, p) in uploads.drain(..) {
+            if let StagingSource::Dedicated(buffer) = source {
+                dedicated_staging.push(buffer);
+            }
+            destinations.push(p.destination);
+            completions.push(p.completion);
+        }
+        self.in_flight.push_back(InFlightBatch {
+            fence,
+            _command_pool: command_pool,
+            ring_end: self.ring.head,
+            ring_bytes: self.ring.used - used_before,
+            _dedicated_staging: dedicated_staging,
+            _destinations: destinations,
+            completions,
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_allocations_are_aligned() {
+        let mut ring = StagingRing::new(256);
+
+        assert_eq!(ring.allocate(10), Some(0));
+        assert_eq!(ring.allocate(20), Some(16));
+        assert_eq!(ring.allocate(16), Some(48));
+        assert_eq!(ring.used, 64);
+    }
+
+    #[test]
+    fn ring_wraps_around_after_release() {
+        let mut ring = StagingRing::new(256);
+        assert_eq!(ring.allocate(100), Some(0));
+        assert_eq!(ring.allocate(100), Some(112));
+        ring.release(112, 112);
+
+        // the end of the ring(224..256) is skipped
+        assert_eq!(ring.allocate(48), Some(0));
+        assert_eq!(ring.used, 112 + 32 + 48);
+        // up to the tail
+        assert_eq!(ring.allocate(64), Some(48));
+        assert_eq!(ring.used, 256);
+        assert_eq!(ring.allocate(1), None);
+
+        ring.release(224, 112);
+        ring.release(48, 32 + 48);
+        ring.release(112, 64);
+        assert_eq!(ring.used, 0);
+    }
+
+    #[test]
+    fn ring_rejects_requests_without_room() {
+        let mut ring = StagingRing::new(256);
+        assert_eq!(ring.allocate(128), Some(0));
+        assert_eq!(ring.allocate(64), Some(128));
+        ring.release(128, 128);
+
+        // 64 bytes left at the end, 128 bytes before the tail
+        let before = ring;
+        assert_eq!(ring.allocate(144), None);
+        assert_eq!(ring, before);
+        assert_eq!(ring.allocate(128), Some(0));
+    }
+
+    #[test]
+    fn empty_ring_restarts_from_the_beginning() {
+        let mut ring = StagingRing::new(256);
+        assert_eq!(ring.allocate(100), Some(0));
+        ring.release(112, 112);
+
+        // would not fit after the previous allocation
+        assert_eq!(ring.allocate(200), Some(0));
+    }
+
+    #[test]
+    fn oversized_requests_exceed_capacity() {
+        let ring = StagingRing::new(256);
+
+        assert!(!ring.exceeds_capacity(241));
+        assert!(!ring.exceeds_capacity(256));
+        assert!(ring.exceeds_capacity(257));
+    }
+}
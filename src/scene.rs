@@ -0,0 +1,243 @@
+//! Scene graph: a hierarchy of nodes with local transforms, mesh/material references and
+//! visibility.
+//!
+//! World transforms are cached in each node and refreshed by [`Scene::update_world_transforms`],
+//! which should be called once per frame after updating local transforms.
+
+use crate::math::{Mat4, Quat, Vec3};
+
+/// Handle of a node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId(u32);
+
+/// Mesh registered to the renderer
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MeshId(pub u32);
+
+/// Material registered to the renderer
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MaterialId(pub u32);
+
+/// Local transform relative to the parent node. Applied in scale, rotation, translation order.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+impl Default for Transform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+impl Transform {
+    pub const IDENTITY: Self = Self {
+        translation: Vec3::ZERO,
+        rotation: Quat::IDENTITY,
+        scale: Vec3::ONE,
+    };
+
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::from_trs(self.translation, self.rotation, self.scale)
+    }
+}
+
+/// A mesh drawn with a material at the node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MeshInstance {
+    pub mesh: MeshId,
+    pub material: MaterialId,
+}
+
+pub struct Node {
+    pub name: String,
+    pub transform: Transform,
+    /// Invisible nodes hide their whole subtree
+    pub visible: bool,
+    pub mesh: Option<MeshInstance>,
+    children: Vec<NodeId>,
+    world_transform: Mat4,
+}
+
+/// A visible mesh collected from the scene.
+#[derive(Clone, Copy, Debug)]
+pub struct VisibleMesh {
+    pub instance: MeshInstance,
+    pub world_transform: Mat4,
+}
+
+#[derive(Default)]
+pub struct Scene {
+    nodes: Vec<Node>,
+    roots: Vec<NodeId>,
+}
+impl Scene {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a node with identity transform. Returns None if the parent is not a node of this
+    /// scene.
+    pub fn add_node(&mut self, name: impl Into<String>, parent: Option<NodeId>) -> Option<NodeId> {
+        let id = NodeId(self.nodes.len() as _);
+        match parent {
+            Some(p) => self.node_mut(p)?.children.push(id),
+            None => self.roots.push(id),
+        }
+        self.nodes.push(Node {
+            name: name.into(),
+            transform: Transform::IDENTITY,
+            visible: true,
+            mesh: None,
+            children: Vec::new(),
+            world_transform: Mat4::IDENTITY,
+        });
+
+        Some(id)
+    }
+
+    pub fn node(&self, id: NodeId) -> Option<&Node> {
+        self.nodes.get(id.0 as usize)
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> Option<&mut Node> {
+        self.nodes.get_mut(id.0 as usize)
+    }
+
+    /// Recomputes world transforms of every node from the local transforms.
+    pub fn update_world_transforms(&mut self) {
+        let mut stack = self
+            .roots
+            .iter()
+            .map(|&id| (id, Mat4::IDENTITY))
+            .collect::<Vec<_>>();
+        while let Some((id, parent_world)) = stack.pop() {
+            let node = &mut self.nodes[id.0 as usize];
+            node.world_transform = parent_world * node.transform.matrix();
+
+            let world = node.world_transform;
+            stack.extend(node.children.iter().map(|&c| (c, world)));
+        }
+    }
+
+    /// Collects meshes of visible nodes(whose ancestors are also visible) into `out`.
+    pub fn collect_visible_meshes(&self, out: &mut Vec<VisibleMesh>) {
+        let mut stack = self.roots.clone();
+        while let Some(id) = stack.pop() {
+            let node = &self.nodes[id.0 as usize];
+            if !node.visible {
+                continue;
+            }
+
+            if let Some(instance) = node.mesh {
+                out.push(VisibleMesh {
+                    instance,
+                    world_transform: node.world_transform,
+                });
+            }
+            stack.extend_from_slice(&node.children);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Vec4;
+
+    fn translated(scene: &mut Scene, parent: Option<NodeId>, translation: Vec3) -> NodeId {
+        let id = scene.add_node("", parent).expect("no parent");
+        scene.node_mut(id).expect("no node").transform.translation = translation;
+
+        id
+    }
+
+    fn mesh(scene: &mut Scene, id: NodeId, mesh: u32) {
+        scene.node_mut(id).expect("no node").mesh = Some(MeshInstance {
+            mesh: MeshId(mesh),
+            material: MaterialId(0),
+        });
+    }
+
+    fn visible_meshes(scene: &Scene) -> Vec<(u32, Vec4)> {
+        let mut visible = Vec::new();
+        scene.collect_visible_meshes(&mut visible);
+        let mut visible = visible
+            .iter()
+            .map(|v| (v.instance.mesh.0, v.world_transform.cols[3]))
+            .collect::<Vec<_>>();
+        visible.sort_by_key(|v| v.0);
+
+        visible
+    }
+
+    #[test]
+    fn unknown_parent_is_rejected_before_adding() {
+        let mut other = Scene::new();
+        other.add_node("a", None).expect("no node");
+        let foreign = other.add_node("b", None).expect("no node");
+
+        let mut scene = Scene::new();
+        assert_eq!(scene.add_node("orphan", Some(foreign)), None);
+        // the rejected node must not occupy the handle
+        let root = scene.add_node("root", None).expect("no node");
+        assert_eq!(scene.node(root).expect("no node").name, "root");
+        assert!(scene.node(foreign).is_none());
+        assert!(visible_meshes(&scene).is_empty());
+    }
+
+    #[test]
+    fn world_transforms_are_composed_from_ancestors() {
+        let mut scene = Scene::new();
+        let root = translated(&mut scene, None, Vec3::new(1.0, 0.0, 0.0));
+        let child = translated(&mut scene, Some(root), Vec3::new(0.0, 2.0, 0.0));
+        let grandchild = translated(&mut scene, Some(child), Vec3::new(0.0, 0.0, 4.0));
+        let other_root = translated(&mut scene, None, Vec3::new(8.0, 0.0, 0.0));
+        mesh(&mut scene, root, 0);
+        mesh(&mut scene, grandchild, 1);
+        mesh(&mut scene, other_root, 2);
+
+        scene.update_world_transforms();
+        assert_eq!(
+            visible_meshes(&scene),
+            [
+                (0, Vec4::new(1.0, 0.0, 0.0, 1.0)),
+                (1, Vec4::new(1.0, 2.0, 4.0, 1.0)),
+                (2, Vec4::new(8.0, 0.0, 0.0, 1.0)),
+            ]
+        );
+
+        // cached until the next update
+        scene.node_mut(root).expect("no node").transform.translation = Vec3::ZERO;
+        assert_eq!(
+            visible_meshes(&scene)[1],
+            (1, Vec4::new(1.0, 2.0, 4.0, 1.0))
+        );
+        scene.update_world_transforms();
+        assert_eq!(
+            visible_meshes(&scene)[1],
+            (1, Vec4::new(0.0, 2.0, 4.0, 1.0))
+        );
+    }
+
+    #[test]
+    fn invisible_nodes_hide_their_subtree() {
+        let mut scene = Scene::new();
+        let root = translated(&mut scene, None, Vec3::ZERO);
+        let hidden = translated(&mut scene, Some(root), Vec3::ZERO);
+        let hidden_child = translated(&mut scene, Some(hidden), Vec3::ZERO);
+        let sibling = translated(&mut scene, Some(root), Vec3::ZERO);
+        mesh(&mut scene, root, 0);
+        mesh(&mut scene, hidden, 1);
+        mesh(&mut scene, hidden_child, 2);
+        mesh(&mut scene, sibling, 3);
+        scene.node_mut(hidden).expect("no node").visible = false;
+        scene.update_world_transforms();
+
+        let visible = visible_meshes(&scene)
+            .into_iter()
+            .map(|(m, _)| m)
+            .collect::<Vec<_>>();
+        assert_eq!(visible, [0, 3]);
+    }
+}
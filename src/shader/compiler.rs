@@ -0,0 +1,101 @@
+//! GLSL to SPIR-V compilation through `glslc`.
+//!
+//! Also included from the build script, so only std can be used here.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+    time::SystemTime,
+};
+
+#[derive(Debug)]
+pub enum CompileError {
+    /// `glslc` could not be launched(the Vulkan SDK is not installed or not in PATH)
+    Spawn(std::io::Error),
+    /// Diagnostics emitted by the compiler
+    Failed(String),
+}
+impl std::fmt::Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Spawn(e) => write!(f, "failed to launch glslc: {e}"),
+            Self::Failed(diagnostic) => f.write_str(diagnostic.trim_end()),
+        }
+    }
+}
+
+/// Path of the compiled SPIR-V. None if the extension is not a known shader stage.
+///
+/// Stages are distinguished by extensions: `test.vert` is compiled into `test.vspv`.
+pub fn spirv_path(source: &Path) -> Option<PathBuf> {
+    let extension = match source.extension()?.to_str()? {
+        "vert" => "vspv",
+        "frag" => "fspv",
+        "comp" => "cspv",
+        "geom" => "gspv",
+        "tesc" => "tcspv",
+        "tese" => "tespv",
+        _ => return None,
+    };
+
+    Some(source.with_extension(extension))
+}
+
+/// GLSL sources in the directory(not recursive)
+pub fn sources(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut sources = Vec::new();
+    for e in std::fs::read_dir(dir)? {
+        let path = e?.path();
+        if spirv_path(&path).is_some() {
+            sources.push(path);
+        }
+    }
+    sources.sort();
+
+    Ok(sources)
+}
+
+fn glslc_path() -> PathBuf {
+    let executable = if cfg!(windows) { "glslc.exe" } else { "glslc" };
+    if let Some(sdk) = std::env::var_os("VULKAN_SDK") {
+        let path = Path::new(&sdk).join("bin").join(executable);
+        if path.exists() {
+            return path;
+        }
+    }
+
+    // search in PATH
+    PathBuf::from(executable)
+}
+
+/// Compiles the source into `output`. The output is left untouched on failure.
+pub fn compile(source: &Path, output: &Path, optimize: bool) -> Result<(), CompileError> {
+    let result = Command::new(glslc_path())
+        .arg(if optimize { "-O" } else { "-g" })
+        .arg("-o")
+        .arg(output)
+        .arg(source)
+        .output()
+        .map_err(CompileError::Spawn)?;
+    if !result.status.success() {
+        return Err(CompileError::Failed(
+            String::from_utf8_lossy(&result.stderr).into_owned(),
+        ));
+    }
+
+    Ok(())
+}
+
+pub fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// true if the output is missing or older than the source
+pub fn is_outdated(source: &Path, output: &Path) -> bool {
+    match (modified_time(source), modified_time(output)) {
+        (Some(s), Some(o)) => s > o,
+        (_, None) => true,
+        // source has gone: use the output as is
+        (None, Some(_)) => false,
+    }
+}
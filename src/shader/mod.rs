@@ -0,0 +1,159 @@
+//! Shader sources in `assets/shaders` and their compiled SPIR-V.
+//!
+//! Release builds use SPIR-V precompiled and embedded by the build script. Debug builds compile
+//! outdated sources at runtime and recompile them when the sources are modified(hot reload).
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{asset, error::EngineError};
+
+use self::reflect::ShaderReflection;
+
+pub mod compiler;
+pub mod reflect;
+
+/// Minimum interval between checks of source modifications
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// (source name, SPIR-V) compiled by the build script. Empty in debug builds.
+const PRECOMPILED: &[(&str, &[u8])] = include!(concat!(env!("OUT_DIR"), "/shaders.rs"));
+
+struct ShaderEntry {
+    source: PathBuf,
+    output: PathBuf,
+    /// As of the last compilation attempt
+    source_modified: Option<SystemTime>,
+    spirv: Vec<u8>,
+}
+
+pub struct ShaderLibrary {
+    dir: PathBuf,
+    hot_reload: bool,
+    entries: HashMap<String, ShaderEntry>,
+    last_poll: Instant,
+}
+impl Default for ShaderLibrary {
+    fn default() -> Self {
+        Self {
+            dir: asset::path("shaders"),
+            hot_reload: cfg!(debug_assertions),
+            entries: HashMap::new(),
+            last_poll: Instant::now(),
+        }
+    }
+}
+impl ShaderLibrary {
+    /// Hot reload is enabled in debug builds.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads SPIR-V of a source in the shader directory(e.g. `test.vert`).
+    ///
+    /// Without hot reload, SPIR-V embedded in the executable is used if available.
+    /// With hot reload, the source is compiled first if the SPIR-V is outdated. If the compilation
+    /// fails but a previously compiled SPIR-V exists, it is used with a logged diagnostic.
+    pub fn load(&mut self, name: &str) -> Result<&[u8], EngineError> {
+        if !self.entries.contains_key(name) {
+            let source = self.dir.join(name);
+            let output = compiler::spirv_path(&source)
+                .ok_or(EngineError::Unsupported("shader stage of the extension"))?;
+            let source_modified = compiler::modified_time(&source);
+            let precompiled = PRECOMPILED
+                .iter()
+                .find(|(n, _)| *n == name)
+                .filter(|_| !self.hot_reload);
+            let spirv = match precompiled {
+                // Note: include_bytes!の中身は4バイト境界に揃っていないのでコピーして使う
+                Some((_, spirv)) => spirv.to_vec(),
+                None => {
+                    if self.hot_reload && compiler::is_outdated(&source, &output) {
+                        if let Err(e) = compiler::compile(&source, &output, false) {
+                            if !output.exists() {
+                                return Err(EngineError::ShaderCompilation {
+                                    path: source,
+                                    diagnostic: e.to_string(),
+                                });
+                            }
+
+                            eprintln!(
+                                "shader compilation failed: using previous {}\n{e}",
+                                output.display()
+                            );
+                        }
+                    }
+
+                    std::fs::read(&output).map_err(|e| EngineError::io(&output, e))?
+                }
+            };
+
+            self.entries.insert(
+                name.to_owned(),
+                ShaderEntry {
+                    source,
+                    output,
+                    source_modified,
+                    spirv,
+                },
+            );
+        }
+
+        Ok(&self.entries[name].spirv)
+    }
+
+    /// SPIR-V of a loaded shader
+    pub fn spirv(&self, name: &str) -> Option<&[u8]> {
+        self.entries.get(name).map(|e| &e.spirv[..])
+    }
+
+    /// Reflects the interface of a loaded shader.
+    pub fn reflect(&self, name: &str) -> Result<ShaderReflection, EngineError> {
+        let e = self
+            .entries
+            .get(name)
+            .ok_or(EngineError::Unsupported("reflection of unloaded shaders"))?;
+
+        ShaderReflection::parse(&e.output, &e.spirv)
+    }
+
+    /// Recompiles loaded shaders whose sources have been modified since the last check.
+    ///
+    /// Returns names of the shaders successfully reloaded. On failure the diagnostic is logged and
+    /// the previous SPIR-V is kept(retried after the next modification). Checks are throttled, so
+    /// this can be called every frame. Does nothing without hot reload.
+    pub fn poll_changes(&mut self) -> Vec<String> {
+        if !self.hot_reload || self.last_poll.elapsed() < POLL_INTERVAL {
+            return Vec::new();
+        }
+        self.last_poll = Instant::now();
+
+        let mut reloaded = Vec::new();
+        for (name, e) in &mut self.entries {
+            let modified = compiler::modified_time(&e.source);
+            if modified.is_none() || modified == e.source_modified {
+                continue;
+            }
+            e.source_modified = modified;
+
+            let result = compiler::compile(&e.source, &e.output, false)
+                .map_err(|err| err.to_string())
+                .and_then(|_| std::fs::read(&e.output).map_err(|err| err.to_string()));
+            match result {
+                Ok(spirv) => {
+                    println!("shader reloaded: {name}");
+                    e.spirv = spirv;
+                    reloaded.push(name.clone());
+                }
+                Err(diagnostic) => {
+                    eprintln!("shader compilation failed: keeping previous {name}\n{diagnostic}")
+                }
+            }
+        }
+
+        reloaded
+    }
+}
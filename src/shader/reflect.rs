@@ -0,0 +1,1303 @@
+//! SPIR-V reflection of shader interfaces.
+//!
+//! Descriptor set layouts, push constant ranges and vertex inputs are read from the compiled
+//! shaders instead of being written by hand, and Rust side layouts(vertex attributes and
+//! layout-checked structs) are checked against them before pipelines are created.
+
+use std::{
+    collections::{btree_map::Entry, BTreeMap, HashMap},
+    ops::Range,
+    path::Path,
+};
+
+use bedrock as br;
+
+use crate::{
+    error::EngineError,
+    math::{
+        layout::{align_up, FieldLayout},
+        UniformStruct,
+    },
+    render::descriptor::{LayoutBinding, SetLayoutSignature},
+};
+
+const MAGIC: u32 = 0x0723_0203;
+
+const OP_NAME: u32 = 5;
+const OP_MEMBER_NAME: u32 = 6;
+const OP_ENTRY_POINT: u32 = 15;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_ROW_MAJOR: u32 = 4;
+const DECORATION_COL_MAJOR: u32 = 5;
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_MATRIX_STRIDE: u32 = 7;
+const DECORATION_BUILT_IN: u32 = 11;
+const DECORATION_LOCATION: u32 = 30;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+const STORAGE_CLASS_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_CLASS_INPUT: u32 = 1;
+const STORAGE_CLASS_UNIFORM: u32 = 2;
+const STORAGE_CLASS_PUSH_CONSTANT: u32 = 9;
+const STORAGE_CLASS_STORAGE_BUFFER: u32 = 12;
+
+const DIM_BUFFER: u32 = 5;
+const DIM_SUBPASS_DATA: u32 = 6;
+/// `Sampled` operand of OpTypeImage for storage images
+const IMAGE_STORAGE: u32 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ShaderStage {
+    Vertex,
+    TessellationControl,
+    TessellationEvaluation,
+    Geometry,
+    Fragment,
+    Compute,
+}
+impl ShaderStage {
+    fn from_execution_model(model: u32) -> Option<Self> {
+        match model {
+            0 => Some(Self::Vertex),
+            1 => Some(Self::TessellationControl),
+            2 => Some(Self::TessellationEvaluation),
+            3 => Some(Self::Geometry),
+            4 => Some(Self::Fragment),
+            5 => Some(Self::Compute),
+            _ => None,
+        }
+    }
+
+    pub fn flags(self) -> br::ShaderStage {
+        match self {
+            Self::Vertex => br::ShaderStage::VERTEX,
+            Self::TessellationControl => br::ShaderStage::TESSELLATION_CONTROL,
+            Self::TessellationEvaluation => br::ShaderStage::TESSELLATION_EVALUATION,
+            Self::Geometry => br::ShaderStage::GEOMETRY,
+            Self::Fragment => br::ShaderStage::FRAGMENT,
+            Self::Compute => br::ShaderStage::COMPUTE,
+        }
+    }
+}
+
+/// Union of the stage bits
+pub fn stage_flags(stages: &[ShaderStage]) -> br::ShaderStage {
+    stages
+        .iter()
+        .map(|s| s.flags())
+        .reduce(|a, b| a | b)
+        .expect("no stages")
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DescriptorKind {
+    UniformBuffer,
+    StorageBuffer,
+    CombinedImageSampler,
+    SampledImage,
+    StorageImage,
+    Sampler,
+    UniformTexelBuffer,
+    StorageTexelBuffer,
+    InputAttachment,
+}
+impl DescriptorKind {
+    /// Shaders cannot tell whether buffers are bound with dynamic offsets: it is decided by the
+    /// binding side.
+    pub fn descriptor_type(self, dynamic_buffers: bool) -> br::DescriptorType {
+        match self {
+            Self::UniformBuffer if dynamic_buffers => br::DescriptorType::UniformBufferDynamic,
+            Self::UniformBuffer => br::DescriptorType::UniformBuffer,
+            Self::StorageBuffer if dynamic_buffers => br::DescriptorType::StorageBufferDynamic,
+            Self::StorageBuffer => br::DescriptorType::StorageBuffer,
+            Self::CombinedImageSampler => br::DescriptorType::CombinedImageSampler,
+            Self::SampledImage => br::DescriptorType::SampledImage,
+            Self::StorageImage => br::DescriptorType::StorageImage,
+            Self::Sampler => br::DescriptorType::Sampler,
+            Self::UniformTexelBuffer => br::DescriptorType::UniformTexelBuffer,
+            Self::StorageTexelBuffer => br::DescriptorType::StorageTexelBuffer,
+            Self::InputAttachment => br::DescriptorType::InputAttachment,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockMember {
+    pub name: String,
+    pub offset: u32,
+    pub size: u32,
+}
+
+/// Member layout of a uniform/storage/push constant block.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockLayout {
+    /// Name of the block type
+    pub name: String,
+    /// End of the last member(without trailing paddings)
+    pub size: u32,
+    pub members: Vec<BlockMember>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DescriptorBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub name: String,
+    pub kind: DescriptorKind,
+    /// Array length(1 if not an array)
+    pub count: u32,
+    /// Present for uniform/storage buffers
+    pub block: Option<BlockLayout>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VertexInput {
+    pub location: u32,
+    pub name: String,
+    pub format: br::vk::VkFormat,
+}
+
+/// Interface of an entry point of a SPIR-V module.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShaderReflection {
+    /// File name of the module(for error reports)
+    pub name: String,
+    pub stage: ShaderStage,
+    pub entry_point: String,
+    /// Empty except for vertex shaders. Built-in variables are excluded
+    pub vertex_inputs: Vec<VertexInput>,
+    /// Sorted by (set, binding)
+    pub bindings: Vec<DescriptorBinding>,
+    pub push_constants: Option<BlockLayout>,
+}
+impl ShaderReflection {
+    /// Reads the first entry point of a SPIR-V blob. `path` is used for error reports.
+    pub fn parse(path: &Path, spirv: &[u8]) -> Result<Self, EngineError> {
+        let invalid = |reason: &str| EngineError::invalid_asset(path, reason);
+
+        if spirv.len() < 20 || spirv.len() % 4 != 0 {
+            return Err(invalid("truncated SPIR-V module"));
+        }
+        let words = spirv
+            .chunks_exact(4)
+            .map(|b| u32::from_le_bytes(b.try_into().expect("size mismatch")))
+            .collect::<Vec<_>>();
+        if words[0] != MAGIC {
+            return Err(invalid("not a SPIR-V module"));
+        }
+        let module = Module::parse(&words[5..]).map_err(invalid)?;
+
+        let (model, entry_point) = module
+            .entry_point
+            .clone()
+            .ok_or_else(|| invalid("no entry points"))?;
+        let stage = ShaderStage::from_execution_model(model)
+            .ok_or_else(|| invalid("unsupported execution model"))?;
+
+        let mut vertex_inputs = Vec::new();
+        let mut bindings = Vec::new();
+        let mut push_constants = None;
+        for &(id, type_id, storage_class) in &module.variables {
+            let Some(&Type::Pointer(pointee)) = module.types.get(&type_id) else {
+                return Err(invalid("variable of a non-pointer type"));
+            };
+            let decoration = module.decorations.get(&id).cloned().unwrap_or_default();
+            let name = module.name_of(id, pointee);
+
+            match storage_class {
+                STORAGE_CLASS_INPUT if stage == ShaderStage::Vertex => {
+                    if decoration.built_in {
+                        continue;
+                    }
+                    let location = decoration
+                        .location
+                        .ok_or_else(|| invalid(&format!("vertex input {name} has no location")))?;
+                    let format = module.vertex_format(pointee).ok_or_else(|| {
+                        invalid(&format!("unsupported type of vertex input {name}"))
+                    })?;
+
+                    vertex_inputs.push(VertexInput {
+                        location,
+                        name,
+                        format,
+                    });
+                }
+                STORAGE_CLASS_UNIFORM_CONSTANT
+                | STORAGE_CLASS_UNIFORM
+                | STORAGE_CLASS_STORAGE_BUFFER => {
+                    let (Some(set), Some(binding)) = (decoration.set, decoration.binding) else {
+                        return Err(invalid(&format!("resource {name} has no set or binding")));
+                    };
+                    let (element, count) = match module.types.get(&pointee) {
+                        Some(&Type::Array { element, length }) => {
+                            let count = *module.constants.get(&length).ok_or_else(|| {
+                                invalid(&format!("array length of {name} is not a constant"))
+                            })?;
+
+                            (element, count)
+                        }
+                        Some(Type::RuntimeArray) => {
+                            return Err(invalid(&format!(
+                                "runtime sized descriptor array {name} is not supported"
+                            )))
+                        }
+                        _ => (pointee, 1),
+                    };
+                    let buffer_block = module
+                        .decorations
+                        .get(&element)
+                        .is_some_and(|d| d.buffer_block);
+                    let kind = match (storage_class, module.types.get(&element)) {
+                        (STORAGE_CLASS_UNIFORM, Some(Type::Struct)) if buffer_block => {
+                            DescriptorKind::StorageBuffer
+                        }
+                        (STORAGE_CLASS_UNIFORM, Some(Type::Struct)) => {
+                            DescriptorKind::UniformBuffer
+                        }
+                        (STORAGE_CLASS_STORAGE_BUFFER, Some(Type::Struct)) => {
+                            DescriptorKind::StorageBuffer
+                        }
+                        (_, Some(Type::SampledImage)) => DescriptorKind::CombinedImageSampler,
+                        (_, Some(Type::Sampler)) => DescriptorKind::Sampler,
+                        (_, Some(&Type::Image { dim, sampled })) => match (dim, sampled) {
+                            (DIM_SUBPASS_DATA, _) => DescriptorKind::InputAttachment,
+                            (DIM_BUFFER, IMAGE_STORAGE) => DescriptorKind::StorageTexelBuffer,
+                            (DIM_BUFFER, _) => DescriptorKind::UniformTexelBuffer,
+                            (_, IMAGE_STORAGE) => DescriptorKind::StorageImage,
+                            _ => DescriptorKind::SampledImage,
+                        },
+                        _ => return Err(invalid(&format!("unsupported type of resource {name}"))),
+                    };
+                    let block = match kind {
+                        DescriptorKind::UniformBuffer | DescriptorKind::StorageBuffer => {
+                            Some(module.block_layout(element).map_err(invalid)?)
+                        }
+                        _ => None,
+                    };
+
+                    bindings.push(DescriptorBinding {
+                        set,
+                        binding,
+                        name,
+                        kind,
+                        count,
+                        block,
+                    });
+                }
+                STORAGE_CLASS_PUSH_CONSTANT => {
+                    push_constants = Some(module.block_layout(pointee).map_err(invalid)?);
+                }
+                _ => (),
+            }
+        }
+        vertex_inputs.sort_by_key(|x| x.location);
+        bindings.sort_by_key(|x| (x.set, x.binding));
+
+        Ok(Self {
+            name: path.file_name().map_or_else(
+                || path.display().to_string(),
+                |n| n.to_string_lossy().into(),
+            ),
+            stage,
+            entry_point,
+            vertex_inputs,
+            bindings,
+            push_constants,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct InterfaceBinding {
+    binding: DescriptorBinding,
+    /// Stages referring the binding
+    stages: Vec<ShaderStage>,
+}
+
+/// Merged interface of the shader stages of a pipeline.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PipelineInterface {
+    /// Names of the shaders(for error reports)
+    shaders: String,
+    vertex_inputs: Vec<VertexInput>,
+    bindings: BTreeMap<(u32, u32), InterfaceBinding>,
+    push_constants: Vec<(ShaderStage, BlockLayout)>,
+}
+impl PipelineInterface {
+    /// Fails if the stages declare the same binding differently.
+    pub fn new(stages: &[ShaderReflection]) -> Result<Self, EngineError> {
+        let shaders = stages
+            .iter()
+            .map(|s| &s.name[..])
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let mut bindings = BTreeMap::<_, InterfaceBinding>::new();
+        let mut declared_in = HashMap::new();
+        for s in stages {
+            for b in &s.bindings {
+                match bindings.entry((b.set, b.binding)) {
+                    Entry::Vacant(e) => {
+                        e.insert(InterfaceBinding {
+                            binding: b.clone(),
+                            stages: vec![s.stage],
+                        });
+                        declared_in.insert((b.set, b.binding), &s.name);
+                    }
+                    Entry::Occupied(mut e) => {
+                        let existing = e.get_mut();
+                        if (
+                            existing.binding.kind,
+                            existing.binding.count,
+                            &existing.binding.block,
+                        ) != (b.kind, b.count, &b.block)
+                        {
+                            return Err(EngineError::ShaderInterfaceMismatch(format!(
+                                "set {} binding {} is declared differently in {} and {}",
+                                b.set,
+                                b.binding,
+                                declared_in[&(b.set, b.binding)],
+                                s.name
+                            )));
+                        }
+
+                        existing.stages.push(s.stage);
+                    }
+                }
+            }
+        }
+
+        Ok(Self {
+            shaders,
+            vertex_inputs: stages
+                .iter()
+                .find(|s| s.stage == ShaderStage::Vertex)
+                .map_or_else(Vec::new, |s| s.vertex_inputs.clone()),
+            bindings,
+            push_constants: stages
+                .iter()
+                .filter_map(|s| Some((s.stage, s.push_constants.clone()?)))
+                .collect(),
+        })
+    }
+
+    fn mismatch(&self, reason: impl std::fmt::Display) -> EngineError {
+        EngineError::ShaderInterfaceMismatch(format!("{}: {reason}", self.shaders))
+    }
+
+    pub fn vertex_inputs(&self) -> &[VertexInput] {
+        &self.vertex_inputs
+    }
+
+    /// Descriptor bindings of a set, in binding order
+    pub fn bindings(&self, set: u32) -> impl Iterator<Item = &DescriptorBinding> + '_ {
+        self.bindings
+            .range((set, 0)..=(set, u32::MAX))
+            .map(|(_, b)| &b.binding)
+    }
+
+    /// Number of descriptor sets in the pipeline layout(including empty sets in between)
+    pub fn set_count(&self) -> u32 {
+        self.bindings
+            .keys()
+            .map(|&(set, _)| set + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Bindings of a descriptor set layout, visible to the stages referring them.
+    ///
+    /// Buffers in the set are bound with dynamic offsets if `dynamic_buffers` is true.
+    pub fn set_layout_signature(&self, set: u32, dynamic_buffers: bool) -> SetLayoutSignature {
+        SetLayoutSignature::new(
+            self.bindings
+                .range((set, 0)..=(set, u32::MAX))
+                .map(|(_, b)| LayoutBinding {
+                    binding: b.binding.binding,
+                    kind: b.binding.kind,
+                    dynamic: dynamic_buffers,
+                    count: b.binding.count,
+                    stages: b.stages.clone(),
+                })
+                .collect(),
+        )
+    }
+
+    /// Stages sharing the same range are merged into one.
+    pub fn push_constant_ranges(&self) -> Vec<br::PushConstantRange> {
+        let mut ranges = Vec::<(Range<u32>, Vec<ShaderStage>)>::new();
+        for (stage, block) in &self.push_constants {
+            let start = block.members.iter().map(|m| m.offset).min().unwrap_or(0);
+            let range = start..block.size;
+            match ranges.iter_mut().find(|(r, _)| *r == range) {
+                Some((_, stages)) => stages.push(*stage),
+                None => ranges.push((range, vec![*stage])),
+            }
+        }
+
+        ranges
+            .into_iter()
+            .map(|(range, stages)| br::PushConstantRange::new(stage_flags(&stages), range))
+            .collect()
+    }
+
+    /// Every vertex input must be fed by an attribute of the same format.
+    pub fn check_vertex_attributes(
+        &self,
+        attributes: &[br::vk::VkVertexInputAttributeDescription],
+    ) -> Result<(), EngineError> {
+        for input in &self.vertex_inputs {
+            let Some(a) = attributes.iter().find(|a| a.location == input.location) else {
+                return Err(self.mismatch(format_args!(
+                    "no attribute for vertex input {}(location {})",
+                    input.name, input.location
+                )));
+            };
+            if a.format != input.format {
+                return Err(self.mismatch(format_args!(
+                    "vertex input {}(location {}) expects format {:?}, but the attribute is {:?}",
+                    input.name, input.location, input.format, a.format
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks member offsets and sizes of the uniform block at the binding against `T`.
+    pub fn check_uniform_block<T: UniformStruct>(
+        &self,
+        set: u32,
+        binding: u32,
+    ) -> Result<(), EngineError> {
+        self.check_uniform_block_fields(set, binding, T::FIELDS, std::any::type_name::<T>())
+    }
+
+    /// [`Self::check_uniform_block`] for types known only at runtime.
+    pub fn check_uniform_block_fields(
+        &self,
+        set: u32,
+        binding: u32,
+        fields: &[FieldLayout],
+        type_name: &str,
+    ) -> Result<(), EngineError> {
+        let type_name = short_type_name(type_name);
+        let block = self
+            .bindings
+            .get(&(set, binding))
+            .filter(|b| b.binding.kind == DescriptorKind::UniformBuffer)
+            .and_then(|b| b.binding.block.as_ref())
+            .ok_or_else(|| {
+                self.mismatch(format_args!(
+                    "no uniform block at set {set} binding {binding} for {type_name}"
+                ))
+            })?;
+
+        self.check_block(block, fields, type_name)
+    }
+
+    fn check_block(
+        &self,
+        block: &BlockLayout,
+        fields: &[FieldLayout],
+        type_name: &str,
+    ) -> Result<(), EngineError> {
+        if block.members.len() != fields.len() {
+            return Err(self.mismatch(format_args!(
+                "block {} has {} members, but {type_name} has {} fields",
+                block.name,
+                block.members.len(),
+                fields.len()
+            )));
+        }
+
+        for (m, f) in block.members.iter().zip(fields) {
+            let (offset, size) = (m.offset as usize, m.size as usize);
+            // Note: 16バイト境界までのパディング(vec3にVec4を当てるなど)は許容する
+            if f.offset != offset || f.size < size || f.size > align_up(size, 16) {
+                return Err(self.mismatch(format_args!(
+                    "{}.{}(offset {offset}, {size} bytes) does not match \
+                     {type_name}::{}(offset {}, {} bytes)",
+                    block.name, m.name, f.name, f.offset, f.size
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn short_type_name(name: &str) -> &str {
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Type {
+    Int {
+        width: u32,
+        signed: bool,
+    },
+    Float {
+        width: u32,
+    },
+    Vector {
+        component: u32,
+        count: u32,
+    },
+    Matrix {
+        column: u32,
+        count: u32,
+    },
+    Image {
+        dim: u32,
+        sampled: u32,
+    },
+    Sampler,
+    SampledImage,
+    Array {
+        element: u32,
+        length: u32,
+    },
+    RuntimeArray,
+    /// Members are in [`Module::struct_members`]
+    Struct,
+    Pointer(u32),
+}
+
+#[derive(Clone, Debug, Default)]
+struct Decoration {
+    location: Option<u32>,
+    binding: Option<u32>,
+    set: Option<u32>,
+    array_stride: Option<u32>,
+    buffer_block: bool,
+    built_in: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+struct MemberDecoration {
+    offset: Option<u32>,
+    matrix_stride: Option<u32>,
+    row_major: bool,
+}
+
+/// Declarations of a module needed for reflection(function bodies are ignored).
+#[derive(Default)]
+struct Module {
+    /// (execution model, name) of the first entry point
+    entry_point: Option<(u32, String)>,
+    names: HashMap<u32, String>,
+    member_names: HashMap<(u32, u32), String>,
+    decorations: HashMap<u32, Decoration>,
+    member_decorations: HashMap<(u32, u32), MemberDecoration>,
+    types: HashMap<u32, Type>,
+    struct_members: HashMap<u32, Vec<u32>>,
+    /// Lowest 32 bits of scalar constants
+    constants: HashMap<u32, u32>,
+    /// (id, pointer type, storage class)
+    variables: Vec<(u32, u32, u32)>,
+}
+impl Module {
+    fn parse(mut words: &[u32]) -> Result<Self, &'static str> {
+        let mut module = Self::default();
+
+        while let Some(&head) = words.first() {
+            let count = (head >> 16) as usize;
+            if count == 0 || count > words.len() {
+                return Err("truncated instruction");
+            }
+            let operands = &words[1..count];
+            words = &words[count..];
+            let operand = |n: usize| operands.get(n).copied().ok_or("malformed instruction");
+
+            match head & 0xffff {
+                OP_NAME => {
+                    module.names.insert(
+                        operand(0)?,
+                        literal_string(operands.get(1..).unwrap_or(&[])),
+                    );
+                }
+                OP_MEMBER_NAME => {
+                    module.member_names.insert(
+                        (operand(0)?, operand(1)?),
+                        literal_string(operands.get(2..).unwrap_or(&[])),
+                    );
+                }
+                OP_ENTRY_POINT if module.entry_point.is_none() => {
+                    module.entry_point = Some((
+                        operand(0)?,
+                        literal_string(operands.get(2..).unwrap_or(&[])),
+                    ));
+                }
+                OP_TYPE_INT => {
+                    module.types.insert(
+                        operand(0)?,
+                        Type::Int {
+                            width: operand(1)?,
+                            signed: operand(2)? != 0,
+                        },
+                    );
+                }
+                OP_TYPE_FLOAT => {
+                    module
+                        .types
+                        .insert(operand(0)?, Type::Float { width: operand(1)? });
+                }
+                OP_TYPE_VECTOR => {
+                    module.types.insert(
+                        operand(0)?,
+                        Type::Vector {
+                            component: operand(1)?,
+                            count: operand(2)?,
+                        },
+                    );
+                }
+                OP_TYPE_MATRIX => {
+                    module.types.insert(
+                        operand(0)?,
+                        Type::Matrix {
+                            column: operand(1)?,
+                            count: operand(2)?,
+                        },
+                    );
+                }
+                OP_TYPE_IMAGE => {
+                    module.types.insert(
+                        operand(0)?,
+                        Type::Image {
+                            dim: operand(2)?,
+                            sampled: operand(6)?,
+                        },
+                    );
+                }
+                OP_TYPE_SAMPLER => {
+                    module.types.insert(operand(0)?, Type::Sampler);
+                }
+                OP_TYPE_SAMPLED_IMAGE => {
+                    module.types.insert(operand(0)?, Type::SampledImage);
+                }
+                OP_TYPE_ARRAY => {
+                    module.types.insert(
+                        operand(0)?,
+                        Type::Array {
+                            element: operand(1)?,
+                            length: operand(2)?,
+                        },
+                    );
+                }
+                OP_TYPE_RUNTIME_ARRAY => {
+                    module.types.insert(operand(0)?, Type::RuntimeArray);
+                }
+                OP_TYPE_STRUCT => {
+                    let id = operand(0)?;
+                    module.types.insert(id, Type::Struct);
+                    module.struct_members.insert(id, operands[1..].to_vec());
+                }
+                OP_TYPE_POINTER => {
+                    module.types.insert(operand(0)?, Type::Pointer(operand(2)?));
+                }
+                OP_CONSTANT => {
+                    module.constants.insert(operand(1)?, operand(2)?);
+                }
+                OP_VARIABLE => {
+                    module
+                        .variables
+                        .push((operand(1)?, operand(0)?, operand(2)?));
+                }
+                OP_DECORATE => {
+                    let d = module.decorations.entry(operand(0)?).or_default();
+                    match operand(1)? {
+                        DECORATION_LOCATION => d.location = Some(operand(2)?),
+                        DECORATION_BINDING => d.binding = Some(operand(2)?),
+                        DECORATION_DESCRIPTOR_SET => d.set = Some(operand(2)?),
+                        DECORATION_ARRAY_STRIDE => d.array_stride = Some(operand(2)?),
+                        DECORATION_BUFFER_BLOCK => d.buffer_block = true,
+                        DECORATION_BUILT_IN => d.built_in = true,
+                        _ => (),
+                    }
+                }
+                OP_MEMBER_DECORATE => {
+                    let d = module
+                        .member_decorations
+                        .entry((operand(0)?, operand(1)?))
+                        .or_default();
+                    match operand(2)? {
+                        DECORATION_OFFSET => d.offset = Some(operand(3)?),
+                        DECORATION_MATRIX_STRIDE => d.matrix_stride = Some(operand(3)?),
+                        DECORATION_ROW_MAJOR => d.row_major = true,
+                        DECORATION_COL_MAJOR => d.row_major = false,
+                        _ => (),
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Ok(module)
+    }
+
+    /// Name of the variable, or the name of its type for anonymous blocks
+    fn name_of(&self, variable: u32, pointee: u32) -> String {
+        self.names
+            .get(&variable)
+            .filter(|n| !n.is_empty())
+            .or_else(|| self.names.get(&pointee))
+            .cloned()
+            .unwrap_or_else(|| format!("%{variable}"))
+    }
+
+    /// Only 32-bit scalars and vectors are supported
+    fn vertex_format(&self, type_id: u32) -> Option<br::vk::VkFormat> {
+        let (component, count) = match *self.types.get(&type_id)? {
+            Type::Vector { component, count } => (component, count),
+            _ => (type_id, 1),
+        };
+        let formats = match *self.types.get(&component)? {
+            Type::Float { width: 32 } => [
+                br::vk::VK_FORMAT_R32_SFLOAT,
+                br::vk::VK_FORMAT_R32G32_SFLOAT,
+                br::vk::VK_FORMAT_R32G32B32_SFLOAT,
+                br::vk::VK_FORMAT_R32G32B32A32_SFLOAT,
+            ],
+            Type::Int {
+                width: 32,
+                signed: true,
+            } => [
+                br::vk::VK_FORMAT_R32_SINT,
+                br::vk::VK_FORMAT_R32G32_SINT,
+                br::vk::VK_FORMAT_R32G32B32_SINT,
+                br::vk::VK_FORMAT_R32G32B32A32_SINT,
+            ],
+            Type::Int {
+                width: 32,
+                signed: false,
+            } => [
+                br::vk::VK_FORMAT_R32_UINT,
+                br::vk::VK_FORMAT_R32G32_UINT,
+                br::vk::VK_FORMAT_R32G32B32_UINT,
+                br::vk::VK_FORMAT_R32G32B32A32_UINT,
+            ],
+            _ => return None,
+        };
+
+        formats.get(count.checked_sub(1)? as usize).copied()
+    }
+
+    /// Size in a block. Matrices and arrays use the strides decorated by the compiler
+    ///
+    /// `member` is the decoration of the struct member(matrix stride and majorness)
+    /// None for unsupported types, or sizes not fitting in u32(from malformed or hostile modules)
+    fn type_size(&self, type_id: u32, member: Option<&MemberDecoration>) -> Option<u32> {
+        match *self.types.get(&type_id)? {
+            Type::Int { width, .. } | Type::Float { width } => Some(width / 8),
+            Type::Vector { component, count } => {
+                self.type_size(component, None)?.checked_mul(count)
+            }
+            Type::Matrix { column, count } => {
+                let matrix_stride = member.and_then(|d| d.matrix_stride);
+                if member.is_some_and(|d| d.row_major) {
+                    // Note: row-majorの場合はMatrixStrideが行の間隔になる
+                    let Type::Vector { count: rows, .. } = *self.types.get(&column)? else {
+                        return None;
+                    };
+
+                    matrix_stride?.checked_mul(rows)
+                } else {
+                    matrix_stride
+                        .or_else(|| self.type_size(column, None))?
+                        .checked_mul(count)
+                }
+            }
+            Type::Array { element, length } => {
+                let stride = match self.decorations.get(&type_id).and_then(|d| d.array_stride) {
+                    Some(s) => s,
+                    None => self.type_size(element, member)?,
+                };
+
+                stride.checked_mul(*self.constants.get(&length)?)
+            }
+            Type::Struct => self.block_layout(type_id).ok().map(|b| b.size),
+            _ => None,
+        }
+    }
+
+    fn block_layout(&self, struct_id: u32) -> Result<BlockLayout, &'static str> {
+        let members = self
+            .struct_members
+            .get(&struct_id)
+            .ok_or("block is not a struct")?;
+
+        let members = members
+            .iter()
+            .enumerate()
+            .map(|(n, &type_id)| {
+                let key = (struct_id, n as u32);
+                let decoration = self.member_decorations.get(&key);
+                let offset = decoration
+                    .and_then(|d| d.offset)
+                    .ok_or("block member has no offset")?;
+                let size = self
+                    .type_size(type_id, decoration)
+                    .ok_or("unsupported type of block member")?;
+                offset
+                    .checked_add(size)
+                    .ok_or("block member exceeds the address space")?;
+
+                Ok(BlockMember {
+                    name: self.member_names.get(&key).cloned().unwrap_or_default(),
+                    offset,
+                    size,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(BlockLayout {
+            name: self.names.get(&struct_id).cloned().unwrap_or_default(),
+            size: members.iter().map(|m| m.offset + m.size).max().unwrap_or(0),
+            members,
+        })
+    }
+}
+
+/// Decodes a nul-terminated UTF-8 string packed into words.
+fn literal_string(words: &[u32]) -> String {
+    let bytes = words
+        .iter()
+        .flat_map(|w| w.to_le_bytes())
+        .take_while(|&b| b != 0)
+        .collect::<Vec<_>>();
+
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FUNCTION_ID: u32 = 99;
+    const DECORATION_BLOCK: u32 = 2;
+    const BUILT_IN_VERTEX_INDEX: u32 = 42;
+    const EXECUTION_MODEL_VERTEX: u32 = 0;
+    const EXECUTION_MODEL_FRAGMENT: u32 = 4;
+
+    /// Hand assembler of SPIR-V modules(only the declarations read by the reflection)
+    struct Assembler(Vec<u32>);
+    impl Assembler {
+        fn new() -> Self {
+            Self(vec![MAGIC, 0x0001_0000, 0, 100, 0])
+        }
+
+        fn op(mut self, opcode: u32, operands: &[u32]) -> Self {
+            self.0.push(((operands.len() as u32 + 1) << 16) | opcode);
+            self.0.extend_from_slice(operands);
+            self
+        }
+
+        /// Operands with a literal string in between
+        fn op_str(self, opcode: u32, head: &[u32], s: &str, tail: &[u32]) -> Self {
+            let mut bytes = s.as_bytes().to_vec();
+            bytes.resize((bytes.len() / 4 + 1) * 4, 0);
+            let operands = head
+                .iter()
+                .copied()
+                .chain(
+                    bytes
+                        .chunks_exact(4)
+                        .map(|b| u32::from_le_bytes(b.try_into().unwrap())),
+                )
+                .chain(tail.iter().copied())
+                .collect::<Vec<_>>();
+
+            self.op(opcode, &operands)
+        }
+
+        fn entry_point(self, model: u32) -> Self {
+            self.op_str(OP_ENTRY_POINT, &[model, FUNCTION_ID], "main", &[])
+        }
+
+        /// %1 = float, %2 = vec2, %3 = vec3, %4 = vec4, %5 = mat4, %6 = int, %7 = mat4x3, %8 = uint
+        fn basic_types(self) -> Self {
+            self.op(OP_TYPE_FLOAT, &[1, 32])
+                .op(OP_TYPE_VECTOR, &[2, 1, 2])
+                .op(OP_TYPE_VECTOR, &[3, 1, 3])
+                .op(OP_TYPE_VECTOR, &[4, 1, 4])
+                .op(OP_TYPE_MATRIX, &[5, 4, 4])
+                .op(OP_TYPE_INT, &[6, 32, 1])
+                .op(OP_TYPE_MATRIX, &[7, 3, 4])
+                .op(OP_TYPE_INT, &[8, 32, 0])
+        }
+
+        /// `uniform Camera { mat4 view_projection; vec4 tint; }` at set 0 binding 0
+        fn camera_block(self) -> Self {
+            self.op_str(OP_NAME, &[30], "Camera", &[])
+                .op_str(OP_MEMBER_NAME, &[30, 0], "view_projection", &[])
+                .op_str(OP_MEMBER_NAME, &[30, 1], "tint", &[])
+                .op(OP_DECORATE, &[30, DECORATION_BLOCK])
+                .op(OP_MEMBER_DECORATE, &[30, 0, DECORATION_COL_MAJOR])
+                .op(OP_MEMBER_DECORATE, &[30, 0, DECORATION_OFFSET, 0])
+                .op(OP_MEMBER_DECORATE, &[30, 0, DECORATION_MATRIX_STRIDE, 16])
+                .op(OP_MEMBER_DECORATE, &[30, 1, DECORATION_OFFSET, 64])
+                .op(OP_DECORATE, &[32, DECORATION_DESCRIPTOR_SET, 0])
+                .op(OP_DECORATE, &[32, DECORATION_BINDING, 0])
+                .op(OP_TYPE_STRUCT, &[30, 5, 4])
+                .op(OP_TYPE_POINTER, &[31, STORAGE_CLASS_UNIFORM, 30])
+                .op(OP_VARIABLE, &[31, 32, STORAGE_CLASS_UNIFORM])
+        }
+
+        fn bytes(&self) -> Vec<u8> {
+            self.0.iter().flat_map(|w| w.to_le_bytes()).collect()
+        }
+    }
+
+    fn vertex_shader() -> Vec<u8> {
+        Assembler::new()
+            .entry_point(EXECUTION_MODEL_VERTEX)
+            .op_str(OP_NAME, &[20], "pos", &[])
+            .op_str(OP_NAME, &[21], "uv", &[])
+            .op(OP_DECORATE, &[20, DECORATION_LOCATION, 0])
+            .op(OP_DECORATE, &[21, DECORATION_LOCATION, 1])
+            .op(
+                OP_DECORATE,
+                &[22, DECORATION_BUILT_IN, BUILT_IN_VERTEX_INDEX],
+            )
+            // push constant { layout(column_major) mat4x3 a; layout(row_major) mat4x3 b; }
+            .op_str(OP_NAME, &[40], "Push", &[])
+            .op_str(OP_MEMBER_NAME, &[40, 0], "a", &[])
+            .op_str(OP_MEMBER_NAME, &[40, 1], "b", &[])
+            .op(OP_DECORATE, &[40, DECORATION_BLOCK])
+            .op(OP_MEMBER_DECORATE, &[40, 0, DECORATION_COL_MAJOR])
+            .op(OP_MEMBER_DECORATE, &[40, 0, DECORATION_OFFSET, 0])
+            .op(OP_MEMBER_DECORATE, &[40, 0, DECORATION_MATRIX_STRIDE, 16])
+            .op(OP_MEMBER_DECORATE, &[40, 1, DECORATION_ROW_MAJOR])
+            .op(OP_MEMBER_DECORATE, &[40, 1, DECORATION_OFFSET, 64])
+            .op(OP_MEMBER_DECORATE, &[40, 1, DECORATION_MATRIX_STRIDE, 16])
+            .basic_types()
+            .op(OP_TYPE_POINTER, &[10, STORAGE_CLASS_INPUT, 3])
+            .op(OP_TYPE_POINTER, &[11, STORAGE_CLASS_INPUT, 2])
+            .op(OP_TYPE_POINTER, &[12, STORAGE_CLASS_INPUT, 6])
+            // declared out of location order
+            .op(OP_VARIABLE, &[11, 21, STORAGE_CLASS_INPUT])
+            .op(OP_VARIABLE, &[10, 20, STORAGE_CLASS_INPUT])
+            .op(OP_VARIABLE, &[12, 22, STORAGE_CLASS_INPUT])
+            .op(OP_TYPE_STRUCT, &[40, 7, 7])
+            .op(OP_TYPE_POINTER, &[41, STORAGE_CLASS_PUSH_CONSTANT, 40])
+            .op(OP_VARIABLE, &[41, 42, STORAGE_CLASS_PUSH_CONSTANT])
+            .camera_block()
+            .bytes()
+    }
+
+    fn fragment_shader() -> Vec<u8> {
+        Assembler::new()
+            .entry_point(EXECUTION_MODEL_FRAGMENT)
+            .op_str(OP_NAME, &[53], "albedo", &[])
+            .op_str(OP_NAME, &[63], "layers", &[])
+            .op(OP_DECORATE, &[53, DECORATION_DESCRIPTOR_SET, 1])
+            .op(OP_DECORATE, &[53, DECORATION_BINDING, 0])
+            .op(OP_DECORATE, &[63, DECORATION_DESCRIPTOR_SET, 1])
+            .op(OP_DECORATE, &[63, DECORATION_BINDING, 1])
+            .basic_types()
+            .op(OP_TYPE_IMAGE, &[50, 1, 1, 0, 0, 0, 1, 0])
+            .op(OP_TYPE_SAMPLED_IMAGE, &[51, 50])
+            .op(OP_TYPE_POINTER, &[52, STORAGE_CLASS_UNIFORM_CONSTANT, 51])
+            .op(OP_VARIABLE, &[52, 53, STORAGE_CLASS_UNIFORM_CONSTANT])
+            .op(OP_CONSTANT, &[8, 60, 4])
+            .op(OP_TYPE_ARRAY, &[61, 51, 60])
+            .op(OP_TYPE_POINTER, &[62, STORAGE_CLASS_UNIFORM_CONSTANT, 61])
+            .op(OP_VARIABLE, &[62, 63, STORAGE_CLASS_UNIFORM_CONSTANT])
+            .camera_block()
+            .bytes()
+    }
+
+    fn reflect(name: &str, spirv: &[u8]) -> ShaderReflection {
+        ShaderReflection::parse(Path::new(name), spirv).expect("reflection failed")
+    }
+
+    fn interface() -> PipelineInterface {
+        PipelineInterface::new(&[
+            reflect("test.vert", &vertex_shader()),
+            reflect("test.frag", &fragment_shader()),
+        ])
+        .expect("interface mismatch")
+    }
+
+    fn camera_layout() -> BlockLayout {
+        BlockLayout {
+            name: "Camera".into(),
+            size: 80,
+            members: vec![
+                BlockMember {
+                    name: "view_projection".into(),
+                    offset: 0,
+                    size: 64,
+                },
+                BlockMember {
+                    name: "tint".into(),
+                    offset: 64,
+                    size: 16,
+                },
+            ],
+        }
+    }
+
+    fn attribute(
+        location: u32,
+        format: br::vk::VkFormat,
+    ) -> br::vk::VkVertexInputAttributeDescription {
+        br::vk::VkVertexInputAttributeDescription {
+            location,
+            binding: 0,
+            format,
+            offset: 0,
+        }
+    }
+
+    #[test]
+    fn vertex_inputs_are_sorted_and_exclude_built_ins() {
+        let r = reflect("test.vert", &vertex_shader());
+
+        assert_eq!(r.name, "test.vert");
+        assert_eq!(r.stage, ShaderStage::Vertex);
+        assert_eq!(r.entry_point, "main");
+        assert_eq!(
+            r.vertex_inputs,
+            [
+                VertexInput {
+                    location: 0,
+                    name: "pos".into(),
+                    format: br::vk::VK_FORMAT_R32G32B32_SFLOAT,
+                },
+                VertexInput {
+                    location: 1,
+                    name: "uv".into(),
+                    format: br::vk::VK_FORMAT_R32G32_SFLOAT,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn uniform_blocks_are_reflected() {
+        let r = reflect("test.vert", &vertex_shader());
+
+        assert_eq!(
+            r.bindings,
+            [DescriptorBinding {
+                set: 0,
+                binding: 0,
+                name: "Camera".into(),
+                kind: DescriptorKind::UniformBuffer,
+                count: 1,
+                block: Some(camera_layout()),
+            }]
+        );
+    }
+
+    #[test]
+    fn row_major_matrices_are_sized_by_rows() {
+        let push_constants = reflect("test.vert", &vertex_shader())
+            .push_constants
+            .expect("no push constants");
+
+        let sizes = push_constants
+            .members
+            .iter()
+            .map(|m| (&m.name[..], m.offset, m.size))
+            .collect::<Vec<_>>();
+        // mat4x3: 4 columns of vec3 = 3 rows of vec4
+        assert_eq!(sizes, [("a", 0, 64), ("b", 64, 48)]);
+        assert_eq!(push_constants.size, 112);
+    }
+
+    #[test]
+    fn oversized_blocks_are_rejected() {
+        // push constant { vec4 values[length]; } with the member at `offset`
+        let module = |length: u32, offset: u32| {
+            Assembler::new()
+                .entry_point(EXECUTION_MODEL_VERTEX)
+                .op(OP_DECORATE, &[71, DECORATION_ARRAY_STRIDE, 16])
+                .op(OP_DECORATE, &[72, DECORATION_BLOCK])
+                .op(OP_MEMBER_DECORATE, &[72, 0, DECORATION_OFFSET, offset])
+                .basic_types()
+                .op(OP_CONSTANT, &[8, 70, length])
+                .op(OP_TYPE_ARRAY, &[71, 4, 70])
+                .op(OP_TYPE_STRUCT, &[72, 71])
+                .op(OP_TYPE_POINTER, &[73, STORAGE_CLASS_PUSH_CONSTANT, 72])
+                .op(OP_VARIABLE, &[73, 74, STORAGE_CLASS_PUSH_CONSTANT])
+                .bytes()
+        };
+        let parse = |spirv: &[u8]| ShaderReflection::parse(Path::new("huge.spv"), spirv);
+
+        let push_constants = parse(&module(4, 16))
+            .expect("reflection failed")
+            .push_constants
+            .expect("no push constants");
+        assert_eq!(push_constants.size, 80);
+        // 16 * 2^28 = 2^32
+        assert!(matches!(
+            parse(&module(1 << 28, 0)),
+            Err(EngineError::InvalidAsset { .. })
+        ));
+        assert!(matches!(
+            parse(&module(1, u32::MAX - 8)),
+            Err(EngineError::InvalidAsset { .. })
+        ));
+    }
+
+    #[test]
+    fn image_resources_are_reflected() {
+        let r = reflect("test.frag", &fragment_shader());
+
+        assert_eq!(r.stage, ShaderStage::Fragment);
+        assert!(r.vertex_inputs.is_empty());
+        let bindings = r
+            .bindings
+            .iter()
+            .map(|b| (b.set, b.binding, &b.name[..], b.kind, b.count))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            bindings,
+            [
+                (0, 0, "Camera", DescriptorKind::UniformBuffer, 1),
+                (1, 0, "albedo", DescriptorKind::CombinedImageSampler, 1),
+                (1, 1, "layers", DescriptorKind::CombinedImageSampler, 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn stages_are_merged_into_layouts() {
+        let interface = interface();
+
+        assert_eq!(interface.set_count(), 2);
+        assert_eq!(
+            interface.set_layout_signature(0, true).bindings(),
+            [LayoutBinding {
+                binding: 0,
+                kind: DescriptorKind::UniformBuffer,
+                dynamic: true,
+                count: 1,
+                stages: vec![ShaderStage::Vertex, ShaderStage::Fragment],
+            }]
+        );
+        assert_eq!(interface.bindings(1).count(), 2);
+    }
+
+    #[test]
+    fn vertex_attributes_are_checked() {
+        let interface = interface();
+
+        let matched = [
+            attribute(0, br::vk::VK_FORMAT_R32G32B32_SFLOAT),
+            attribute(1, br::vk::VK_FORMAT_R32G32_SFLOAT),
+        ];
+        assert!(interface.check_vertex_attributes(&matched).is_ok());
+
+        let wrong_format = [
+            attribute(0, br::vk::VK_FORMAT_R32G32B32A32_SFLOAT),
+            attribute(1, br::vk::VK_FORMAT_R32G32_SFLOAT),
+        ];
+        assert!(matches!(
+            interface.check_vertex_attributes(&wrong_format),
+            Err(EngineError::ShaderInterfaceMismatch(_))
+        ));
+
+        let missing = [attribute(0, br::vk::VK_FORMAT_R32G32B32_SFLOAT)];
+        assert!(matches!(
+            interface.check_vertex_attributes(&missing),
+            Err(EngineError::ShaderInterfaceMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn uniform_block_fields_are_checked() {
+        let interface = interface();
+        let field = |name, offset, size| FieldLayout { name, offset, size };
+
+        let matched = [field("view_projection", 0, 64), field("tint", 64, 16)];
+        assert!(interface
+            .check_uniform_block_fields(0, 0, &matched, "Camera")
+            .is_ok());
+
+        let wrong_offset = [field("view_projection", 0, 64), field("tint", 80, 16)];
+        assert!(matches!(
+            interface.check_uniform_block_fields(0, 0, &wrong_offset, "Camera"),
+            Err(EngineError::ShaderInterfaceMismatch(_))
+        ));
+
+        let too_few = [field("view_projection", 0, 64)];
+        assert!(matches!(
+            interface.check_uniform_block_fields(0, 0, &too_few, "Camera"),
+            Err(EngineError::ShaderInterfaceMismatch(_))
+        ));
+
+        // not a uniform block
+        assert!(matches!(
+            interface.check_uniform_block_fields(1, 0, &matched, "Camera"),
+            Err(EngineError::ShaderInterfaceMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn conflicting_declarations_are_rejected() {
+        // declares set 1 binding 0 as a uniform block
+        let vertex = Assembler::new()
+            .entry_point(EXECUTION_MODEL_VERTEX)
+            .op(OP_DECORATE, &[30, DECORATION_BLOCK])
+            .op(OP_MEMBER_DECORATE, &[30, 0, DECORATION_OFFSET, 0])
+            .op(OP_DECORATE, &[32, DECORATION_DESCRIPTOR_SET, 1])
+            .op(OP_DECORATE, &[32, DECORATION_BINDING, 0])
+            .basic_types()
+            .op(OP_TYPE_STRUCT, &[30, 4])
+            .op(OP_TYPE_POINTER, &[31, STORAGE_CLASS_UNIFORM, 30])
+            .op(OP_VARIABLE, &[31, 32, STORAGE_CLASS_UNIFORM])
+            .bytes();
+
+        let result = PipelineInterface::new(&[
+            reflect("test.vert", &vertex),
+            reflect("test.frag", &fragment_shader()),
+        ]);
+        assert!(matches!(
+            result,
+            Err(EngineError::ShaderInterfaceMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn malformed_modules_are_rejected() {
+        let parse = |spirv: &[u8]| ShaderReflection::parse(Path::new("broken.spv"), spirv);
+
+        assert!(matches!(
+            parse(&[0; 20]),
+            Err(EngineError::InvalidAsset { .. })
+        ));
+        let mut truncated = vertex_shader();
+        truncated.truncate(truncated.len() - 4);
+        assert!(matches!(
+            parse(&truncated),
+            Err(EngineError::InvalidAsset { .. })
+        ));
+        // no entry points
+        assert!(matches!(
+            parse(&Assembler::new().basic_types().bytes()),
+            Err(EngineError::InvalidAsset { .. })
+        ));
+    }
+}
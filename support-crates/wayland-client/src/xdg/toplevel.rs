@@ -33,7 +33,7 @@ pub enum XDGToplevelResizeEdge {
 }
 
 #[repr(C)]
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum XDGToplevelState {
     Maximized = 1,
     Fullscreen = 2,
@@ -50,6 +50,23 @@ pub enum XDGToplevelState {
     /// since v6
     Suspeneded = 9,
 }
+impl XDGToplevelState {
+    /// decodes a value in the states array of `xdg_toplevel.configure`
+    pub const fn from_raw(value: c_uint) -> Option<Self> {
+        match value {
+            1 => Some(Self::Maximized),
+            2 => Some(Self::Fullscreen),
+            3 => Some(Self::Resizing),
+            4 => Some(Self::Activated),
+            5 => Some(Self::TiledLeft),
+            6 => Some(Self::TiledRight),
+            7 => Some(Self::TiledTop),
+            8 => Some(Self::TiledBottom),
+            9 => Some(Self::Suspeneded),
+            _ => None,
+        }
+    }
+}
 
 /// since v5
 #[repr(C)]